@@ -1,43 +1,239 @@
 //! Alfred daemon - keeps LLM model loaded for fast inference
 
 use anyhow::{anyhow, Context, Result};
+use clap::{Parser, ValueEnum};
 use colored::Colorize;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::StatusCode;
+use hyper_util::rt::TokioIo;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::fs;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // Import from alfred crate
-use alfred::config;
+use alfred::config::{self, Config, SamplingConfig};
+use alfred::daemon_client::{estimate_remaining, Activity, DaemonMetrics, DaemonStatus};
+use alfred::daemon_log::{self, LogEntry};
+use alfred::daemon_tls::{self, Channel, ServerChannel};
+use alfred::git;
 use alfred::llm;
 
-#[derive(Deserialize)]
+#[derive(Parser)]
+#[command(
+  name = "alferd",
+  about = "Alfred daemon - keeps the model loaded for fast inference",
+  version
+)]
+struct Args {
+  /// Run attached to the terminal: no PID file, no idle timeout, per-request logs
+  #[arg(long)]
+  foreground: bool,
+
+  /// Port to listen on; 0 picks a free one (default: daemon.port)
+  #[arg(long)]
+  port: Option<u16>,
+
+  /// Model file to load instead of the configured one
+  #[arg(long)]
+  model: Option<PathBuf>,
+
+  /// Log verbosity (default: debug with --foreground, info otherwise)
+  #[arg(long, value_enum)]
+  log_level: Option<LogLevel>,
+}
+
+impl Args {
+  /// `--log-level`, or debug in the foreground and info otherwise
+  fn level(&self) -> LogLevel {
+    self.log_level.unwrap_or(if self.foreground {
+      LogLevel::Debug
+    } else {
+      LogLevel::Info
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum LogLevel {
+  Error = 0,
+  Warn = 1,
+  Info = 2,
+  Debug = 3,
+}
+
+impl LogLevel {
+  fn name(self) -> &'static str {
+    match self {
+      LogLevel::Error => "error",
+      LogLevel::Warn => "warn",
+      LogLevel::Info => "info",
+      LogLevel::Debug => "debug",
+    }
+  }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static STARTED: OnceCell<Instant> = OnceCell::new();
+/// Set once writing the structured log has failed, so the failure is reported once
+static LOG_FILE_FAILED: AtomicBool = AtomicBool::new(false);
+
+fn logs(level: LogLevel) -> bool {
+  level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Print a log line and append it to the structured log
+fn log(level: LogLevel, msg: impl std::fmt::Display) {
+  if !logs(level) {
+    return;
+  }
+  let msg = msg.to_string();
+  print_log(level, &msg);
+  write_log(&LogEntry::new(level.name(), msg));
+}
+
+fn write_log(entry: &LogEntry) {
+  if let Err(e) = daemon_log::append(entry) {
+    if !LOG_FILE_FAILED.swap(true, Ordering::Relaxed) {
+      print_log(
+        LogLevel::Warn,
+        &format!("Can't write the log file: {:#}", e),
+      );
+    }
+  }
+}
+
+/// One console line: seconds since start, level, message
+fn print_log(level: LogLevel, msg: &str) {
+  let elapsed = STARTED.get().map_or(0.0, |t| t.elapsed().as_secs_f64());
+  let tag = match level {
+    LogLevel::Error => "ERROR".red(),
+    LogLevel::Warn => "WARN ".yellow(),
+    LogLevel::Info => "INFO ".green(),
+    LogLevel::Debug => "DEBUG".blue(),
+  };
+  let line = format!("{} {} {}", format!("{:>9.3}s", elapsed).dimmed(), tag, msg);
+  if level <= LogLevel::Warn {
+    eprintln!("{}", line);
+  } else {
+    println!("{}", line);
+  }
+}
+
+fn format_size(bytes: usize) -> String {
+  if bytes < 1024 {
+    format!("{} B", bytes)
+  } else {
+    format!("{:.1} KB", bytes as f64 / 1024.0)
+  }
+}
+
+#[derive(Clone, Deserialize)]
 struct Request {
   method: String,
   params: serde_json::Value,
   id: u64,
+  /// Must match `daemon.auth_token` when one is set
+  #[serde(default)]
+  token: Option<String>,
+}
+
+/// The error for a request without the configured auth token
+const UNAUTHORIZED: &str = "401 Unauthorized";
+
+/// Whether the request carries `daemon.auth_token`. The config is read for each request, so
+/// a rotated token applies without a restart
+fn authorized(request: &Request, cfg: &Config) -> bool {
+  let expected = match cfg.daemon.auth_token {
+    Some(ref token) if !token.is_empty() => token,
+    _ => return true,
+  };
+  let Some(ref given) = request.token else {
+    return false;
+  };
+  // Compare every byte so the time taken doesn't reveal how much of the token matched
+  given.len() == expected.len()
+    && given
+      .bytes()
+      .zip(expected.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
 }
 
 #[derive(Serialize)]
 struct Response {
-  result: Option<String>,
+  /// A string for every method except detailed `status`, so older clients keep working
+  result: Option<serde_json::Value>,
   error: Option<String>,
   id: u64,
+  /// Token counts and generation time, on requests that went through the inference queue
+  #[serde(flatten, skip_serializing_if = "Option::is_none")]
+  usage: Option<llm::Usage>,
+}
+
+/// Subjects the client's repository uses, sent with the `repo` commit style
+fn commit_examples(request: &Request) -> Option<Vec<String>> {
+  request
+    .params
+    .get("examples")
+    .and_then(|v| serde_json::from_value(v.clone()).ok())
 }
 
 fn handle_request(request: &Request) -> Response {
   let result = match request.method.as_str() {
     "ping" => Ok("pong".to_string()),
     "shutdown" => Ok("shutting_down".to_string()),
+    "gpu_layers" => Ok(
+      llm::gpu_layers()
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| "not loaded".to_string()),
+    ),
+    // Queued like a generation, so it never swaps the model out from under one
+    "reload_model" => {
+      let path = request
+        .params
+        .get("path")
+        .and_then(|v| v.as_str())
+        .map(std::path::Path::new);
+      let started = Instant::now();
+      llm::reload_model(path).map(|()| {
+        log(
+          LogLevel::Info,
+          format!(
+            "Reloaded {} in {:.1}s",
+            llm::model_name(),
+            started.elapsed().as_secs_f64()
+          ),
+        );
+        format!("Loaded {}", llm::model_name())
+      })
+    }
     "generate" => {
       let prompt = request
         .params
         .get("prompt")
         .and_then(|v| v.as_str())
         .unwrap_or("");
+      let max_prompt_bytes = config::get_daemon_config().max_prompt_bytes;
+      if prompt.len() > max_prompt_bytes {
+        return error_response(
+          request.id,
+          &format!(
+            "Prompt is {} bytes, over the daemon's limit of {} (daemon.max_prompt_bytes)",
+            prompt.len(),
+            max_prompt_bytes
+          ),
+        );
+      }
       let max_tokens = request
         .params
         .get("max_tokens")
@@ -51,7 +247,233 @@ fn handle_request(request: &Request) -> Response {
         .get("diff")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-      llm::generate_commit_message(diff)
+      let structure: Vec<git::StructuralChange> = request
+        .params
+        .get("structure")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let scope = request.params.get("scope").and_then(|v| v.as_str());
+      let issue = request.params.get("issue").and_then(|v| v.as_str());
+      let examples = commit_examples(request);
+      let hints = llm::CommitHints {
+        scope,
+        issue,
+        tests: None,
+        examples: examples.as_deref(),
+      };
+      llm::generate_commit_message(diff, &structure, hints)
+    }
+    "generate_commit_message_full" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let structure: Vec<git::StructuralChange> = request
+        .params
+        .get("structure")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let scope = request.params.get("scope").and_then(|v| v.as_str());
+      let issue = request.params.get("issue").and_then(|v| v.as_str());
+      let tests = request.params.get("tests").and_then(|v| v.as_str());
+      let examples = commit_examples(request);
+      let hints = llm::CommitHints {
+        scope,
+        issue,
+        tests,
+        examples: examples.as_deref(),
+      };
+      llm::generate_commit_message_full(diff, &structure, hints)
+    }
+    "generate_commit_candidates" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let structure: Vec<git::StructuralChange> = request
+        .params
+        .get("structure")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let scope = request.params.get("scope").and_then(|v| v.as_str());
+      let issue = request.params.get("issue").and_then(|v| v.as_str());
+      let tests = request.params.get("tests").and_then(|v| v.as_str());
+      let count = request
+        .params
+        .get("count")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(1) as usize;
+      let body = request
+        .params
+        .get("body")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+      let examples = commit_examples(request);
+      let hints = llm::CommitHints {
+        scope,
+        issue,
+        tests,
+        examples: examples.as_deref(),
+      };
+      llm::generate_commit_candidates(diff, &structure, hints, count, body)
+        .and_then(|candidates| Ok(serde_json::to_string(&candidates)?))
+    }
+    "generate_pr_description" => {
+      let commits: Vec<(String, String)> = request
+        .params
+        .get("commits")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let issue = request.params.get("issue").and_then(|v| v.as_str());
+      llm::generate_pr_description(&commits, issue)
+    }
+    "generate_changelog" => {
+      let commits: Vec<git::CommitRecord> = request
+        .params
+        .get("commits")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let format = request
+        .params
+        .get("format")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or(llm::ChangelogFormat::KeepAChangelog);
+      llm::generate_changelog(&commits, &format)
+    }
+    "find_fixup_target" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let commits: Vec<git::CommitRecord> = request
+        .params
+        .get("commits")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      llm::find_fixup_target(diff, &commits)
+    }
+    "summarize_changes" => {
+      let chunks: Vec<String> = request
+        .params
+        .get("diff_chunks")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+      llm::summarize_changes(&chunks)
+    }
+    "predict_cherry_pick_conflicts" => {
+      let patch = request
+        .params
+        .get("patch")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let files: Vec<(String, String)> = request
+        .params
+        .get("files")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      llm::predict_cherry_pick_conflicts(patch, &files)
+        .and_then(|predictions| Ok(serde_json::to_string(&predictions)?))
+    }
+    "analyze_merge" => {
+      let branch = request
+        .params
+        .get("branch")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      llm::analyze_merge(branch, diff).and_then(|analysis| Ok(serde_json::to_string(&analysis)?))
+    }
+    "classify_bisect_step" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let symptom = request
+        .params
+        .get("symptom")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      llm::classify_bisect_step(diff, symptom).map(|c| c.name().to_string())
+    }
+    "summarize_log" => {
+      let messages: Vec<String> = request
+        .params
+        .get("messages")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      llm::summarize_log(&messages)
+    }
+    "generate_stash_message" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      llm::generate_stash_message(diff)
+    }
+    "suggest_next_version" => {
+      let latest_tag = request
+        .params
+        .get("latest_tag")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let commits: Vec<String> = request
+        .params
+        .get("commits")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      llm::suggest_next_version(latest_tag, &commits)
+    }
+    "review_changes" => {
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let severity = request.params.get("severity").and_then(|v| v.as_str());
+      llm::review_changes(diff, severity).and_then(|comments| Ok(serde_json::to_string(&comments)?))
+    }
+    "review_diff" => {
+      let chunks: Vec<String> = request
+        .params
+        .get("diff_chunks")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+      let severity = request.params.get("severity").and_then(|v| v.as_str());
+      llm::review_diff(&chunks, severity).and_then(|comments| Ok(serde_json::to_string(&comments)?))
+    }
+    "explain_changes" => {
+      let messages: Vec<String> = request
+        .params
+        .get("messages")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let diff = request
+        .params
+        .get("diff")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let deleted_tests: Vec<String> = request
+        .params
+        .get("deleted_tests")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+      let short = request
+        .params
+        .get("short")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+      llm::explain_changes(&messages, diff, &deleted_tests, short)
     }
     "suggest_branch_name" => {
       let description = request
@@ -59,7 +481,8 @@ fn handle_request(request: &Request) -> Response {
         .get("description")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-      llm::suggest_branch_name(description)
+      let issue = request.params.get("issue").and_then(|v| v.as_str());
+      llm::suggest_branch_name(description, issue)
     }
     "suggest_conflict_resolution" => {
       let file = request
@@ -82,7 +505,36 @@ fn handle_request(request: &Request) -> Response {
         .get("base")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-      llm::suggest_conflict_resolution(file, ours, theirs, base)
+      let before = request
+        .params
+        .get("before")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let after = request
+        .params
+        .get("after")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+      let conflict = llm::Conflict {
+        ours,
+        theirs,
+        base,
+        before,
+        after,
+      };
+      let labels = llm::ConflictLabels {
+        ours: request
+          .params
+          .get("ours_label")
+          .and_then(|v| v.as_str())
+          .unwrap_or("current branch"),
+        theirs: request
+          .params
+          .get("theirs_label")
+          .and_then(|v| v.as_str())
+          .unwrap_or("incoming branch"),
+      };
+      llm::suggest_conflict_resolution(file, &conflict, &labels)
     }
     "suggest_rebase_strategy" => {
       let commits: Vec<String> = request
@@ -108,54 +560,822 @@ fn handle_request(request: &Request) -> Response {
 
   match result {
     Ok(r) => Response {
-      result: Some(r),
+      result: Some(serde_json::Value::String(r)),
       error: None,
       id: request.id,
+      usage: None,
     },
-    Err(e) => Response {
-      result: None,
-      error: Some(e.to_string()),
+    Err(e) => {
+      // `{:?}` includes the cause chain, and a backtrace when RUST_LIB_BACKTRACE is set
+      log(
+        LogLevel::Debug,
+        format!("{} error: {:?}", request.method, e),
+      );
+      Response {
+        result: None,
+        error: Some(e.to_string()),
+        id: request.id,
+        usage: None,
+      }
+    }
+  }
+}
+
+/// State shared between the accept loop and connection workers
+struct DaemonState {
+  start_time: Instant,
+  /// When the most recent activity happened; `None` until the first, so idle time counts
+  /// from `start_time`
+  last_activity: Mutex<Option<Instant>>,
+  shutdown_flag: AtomicBool,
+  /// Queue of the inference thread; control methods never go through it
+  jobs: mpsc::Sender<Job>,
+  /// Requests currently running or waiting for the inference lock
+  pending: AtomicUsize,
+  /// Requests answered, and how many of those returned an error
+  served: AtomicU64,
+  failed: AtomicU64,
+  /// Unix timestamp of the last request other than a health check, 0 before the first
+  last_request: AtomicU64,
+  /// How long the most recently answered request took, in milliseconds
+  last_request_ms: AtomicU64,
+  /// How long the model took to load at startup, in milliseconds
+  model_load_ms: u64,
+}
+
+impl DaemonState {
+  /// Record activity now, restarting the idle timer
+  fn touch(&self, method: &str) {
+    self.touch_at(method, Instant::now());
+  }
+
+  fn touch_at(&self, method: &str, now: Instant) {
+    *self.last_activity.lock().unwrap() = Some(now);
+    log(
+      LogLevel::Debug,
+      format!(
+        "Idle timer reset ({} at +{}s)",
+        method,
+        now.saturating_duration_since(self.start_time).as_secs()
+      ),
+    );
+  }
+
+  /// How long the daemon had gone without activity at `now`
+  fn idle_duration_at(&self, now: Instant) -> Duration {
+    let since = self
+      .last_activity
+      .lock()
+      .unwrap()
+      .unwrap_or(self.start_time);
+    now.saturating_duration_since(since)
+  }
+
+  /// Idle long enough to shut down, and not in the middle of a generation
+  fn idle_expired(&self, timeout: Duration) -> bool {
+    self.idle_expired_at(timeout, Instant::now())
+  }
+
+  fn idle_expired_at(&self, timeout: Duration, now: Instant) -> bool {
+    self.pending.load(Ordering::Relaxed) == 0 && self.idle_duration_at(now) > timeout
+  }
+
+  fn activity(&self) -> Activity {
+    let pending = self.pending.load(Ordering::Relaxed);
+    if pending == 0 {
+      return Activity::Idle;
+    }
+    let queue_depth = pending.saturating_sub(1);
+    // Nothing to estimate from between a request's generations, or before the first starts
+    let (elapsed, estimated_remaining_secs) = match llm::generation_progress() {
+      Some((elapsed, budget)) => (
+        elapsed,
+        estimate_remaining(elapsed, budget, llm::tokens_per_sec(), queue_depth),
+      ),
+      None => (Duration::ZERO, None),
+    };
+    Activity::Generating {
+      elapsed_secs: elapsed.as_secs(),
+      queue_depth,
+      estimated_remaining_secs,
+    }
+  }
+
+  fn details(&self) -> DaemonStatus {
+    let last_request = self.last_request.load(Ordering::Relaxed);
+    DaemonStatus {
+      model: llm::model_name(),
+      // Ollama and remote APIs keep their own models loaded
+      model_loaded: llm::is_loaded() || !config::get_backend().is_local(),
+      uptime_secs: self.start_time.elapsed().as_secs(),
+      requests_served: self.served.load(Ordering::Relaxed),
+      last_request: (last_request > 0).then_some(last_request),
+      memory_bytes: resident_memory(),
+      pending: self.pending.load(Ordering::Relaxed),
+      threads: config::get_backend().is_local().then(llm::threads),
+      loaded_models: llm::loaded_models(),
+      context_size: config::get_backend().is_local().then(llm::context_size),
+    }
+  }
+
+  fn metrics(&self) -> DaemonMetrics {
+    let total = self.served.load(Ordering::Relaxed);
+    let failed = self.failed.load(Ordering::Relaxed);
+    DaemonMetrics {
+      requests_total: total,
+      requests_ok: total.saturating_sub(failed),
+      requests_err: failed,
+      uptime_secs: self.start_time.elapsed().as_secs(),
+      model_load_ms: self.model_load_ms,
+      last_request_ms: self.last_request_ms.load(Ordering::Relaxed),
+    }
+  }
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+/// Resident set size of this process, in bytes
+fn resident_memory() -> Option<u64> {
+  // Linux: "VmRSS:    123456 kB"
+  if let Ok(status) = fs::read_to_string("/proc/self/status") {
+    return status
+      .lines()
+      .find_map(|l| l.strip_prefix("VmRSS:"))
+      .and_then(|v| v.split_whitespace().next()?.parse::<u64>().ok())
+      .map(|kb| kb * 1024);
+  }
+
+  // macOS and the BSDs report kilobytes
+  let output = std::process::Command::new("ps")
+    .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+    .output()
+    .ok()?;
+  String::from_utf8_lossy(&output.stdout)
+    .trim()
+    .parse::<u64>()
+    .ok()
+    .map(|kb| kb * 1024)
+}
+
+/// A request waiting for the inference thread, and where its response goes
+struct Job {
+  request: Request,
+  /// The request's config, the client's repository settings included
+  config: Arc<Config>,
+  sampling: Option<SamplingConfig>,
+  /// Model alias the client's task uses; unset means `default`
+  model: Option<String>,
+  /// Send each generated piece ahead of the response
+  stream: bool,
+  reply: mpsc::Sender<JobEvent>,
+}
+
+/// What a streaming client is told while its request is handled
+enum Progress<'a> {
+  /// Waiting behind this many requests
+  Queued(usize),
+  Token(&'a str),
+}
+
+/// What the inference thread sends back for a job
+enum JobEvent {
+  Token(String),
+  Done(Response),
+}
+
+/// Run queued requests one at a time, in the order they arrived
+fn inference_worker(jobs: mpsc::Receiver<Job>) {
+  for job in jobs {
+    let request = &job.request;
+    // A panic must not take the queue down with it
+    let run = || {
+      let (mut response, usage) = llm::measuring_usage(|| {
+        config::with_config(job.config.clone(), || {
+          llm::with_sampling(job.sampling, || {
+            llm::with_model(job.model.clone(), || handle_request(request))
+          })
+        })
+      });
+      response.usage = Some(usage);
+      response
+    };
+    let response = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      if job.stream {
+        let tokens = job.reply.clone();
+        llm::with_token_callback(
+          move |piece| {
+            let _ = tokens.send(JobEvent::Token(piece.to_string()));
+          },
+          run,
+        )
+      } else {
+        run()
+      }
+    }))
+    .unwrap_or_else(|_| error_response(request.id, "Inference panicked"));
+    // The client may have hung up while it waited
+    let _ = job.reply.send(JobEvent::Done(response));
+  }
+}
+
+fn error_response(id: u64, error: &str) -> Response {
+  Response {
+    result: None,
+    error: Some(error.to_string()),
+    id,
+    usage: None,
+  }
+}
+
+/// Methods answered immediately, even while a generation is in flight
+fn is_control_method(method: &str) -> bool {
+  matches!(
+    method,
+    "ping" | "status" | "stats" | "metrics" | "shutdown" | "gpu_layers"
+  )
+}
+
+/// `ping` and `status` answer with an object instead of a string when asked for details
+fn detailed(request: &Request) -> bool {
+  request
+    .params
+    .get("detailed")
+    .and_then(|v| v.as_bool())
+    .unwrap_or(false)
+}
+
+fn dispatch(
+  request: &Request,
+  state: &DaemonState,
+  cfg: &Arc<Config>,
+  mut on_progress: Option<&mut dyn FnMut(Progress)>,
+) -> Response {
+  if request.method == "stats" || request.method == "status" {
+    if request.method == "stats" || detailed(request) {
+      return Response {
+        result: serde_json::to_value(state.details()).ok(),
+        error: None,
+        id: request.id,
+        usage: None,
+      };
+    }
+
+    let pending = state.pending.load(Ordering::Relaxed);
+    let result = if pending == 0 {
+      "idle".to_string()
+    } else {
+      format!("busy ({} in flight)", pending)
+    };
+    return Response {
+      result: Some(serde_json::Value::String(result)),
+      error: None,
+      id: request.id,
+      usage: None,
+    };
+  }
+
+  if request.method == "metrics" {
+    return Response {
+      result: serde_json::to_value(state.metrics()).ok(),
+      error: None,
+      id: request.id,
+      usage: None,
+    };
+  }
+
+  if request.method == "ping" && detailed(request) {
+    return Response {
+      result: serde_json::to_value(state.activity()).ok(),
+      error: None,
       id: request.id,
+      usage: None,
+    };
+  }
+
+  if is_control_method(&request.method) {
+    return handle_request(request);
+  }
+
+  let sampling = request
+    .params
+    .get("sampling")
+    .and_then(|v| serde_json::from_value(v.clone()).ok());
+  let model = request
+    .params
+    .get("model")
+    .and_then(|v| v.as_str())
+    .map(str::to_string);
+
+  let ahead = state.pending.fetch_add(1, Ordering::Relaxed);
+  if let (true, Some(on_progress)) = (ahead > 0, on_progress.as_mut()) {
+    on_progress(Progress::Queued(ahead));
+  }
+  let (reply, events) = mpsc::channel();
+  let job = Job {
+    request: request.clone(),
+    config: cfg.clone(),
+    sampling,
+    model,
+    stream: on_progress.is_some(),
+    reply,
+  };
+  let stopped = || error_response(request.id, "Inference thread stopped");
+  let response = match state.jobs.send(job) {
+    Ok(()) => loop {
+      match events.recv() {
+        Ok(JobEvent::Token(piece)) => {
+          if let Some(on_progress) = on_progress.as_mut() {
+            on_progress(Progress::Token(&piece));
+          }
+        }
+        Ok(JobEvent::Done(response)) => break response,
+        Err(_) => break stopped(),
+      }
     },
+    Err(_) => stopped(),
+  };
+  // Idle time counts from when the generation finished, not when it was requested
+  state.touch(&request.method);
+  state.pending.fetch_sub(1, Ordering::Relaxed);
+  response
+}
+
+/// The daemon's config, read again for each request so edits apply without a restart
+fn daemon_config() -> Arc<Config> {
+  match config::load_daemon() {
+    Ok(cfg) => Arc::new(cfg),
+    Err(e) => {
+      log(
+        LogLevel::Warn,
+        format!("Using the config from startup: {:#}", e),
+      );
+      config::current()
+    }
   }
 }
 
-fn handle_client(
-  mut stream: TcpStream,
-  last_activity: &AtomicU64,
-  shutdown_flag: &AtomicBool,
-) -> Result<bool> {
-  stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-  stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+/// `daemon_cfg` with the settings of the client's repository, which it sends with each
+/// generation, so the daemon follows that repository's `.alfred.yaml` and not its own
+fn request_config(request: &Request, daemon_cfg: &Arc<Config>) -> Arc<Config> {
+  let Some(settings) = request.params.get("settings") else {
+    return daemon_cfg.clone();
+  };
+  match config::with_repo_settings(daemon_cfg, settings) {
+    Ok(cfg) => Arc::new(cfg),
+    Err(e) => {
+      log(LogLevel::Warn, format!("{:#}; using the daemon's", e));
+      daemon_cfg.clone()
+    }
+  }
+}
 
-  let mut reader = BufReader::new(stream.try_clone()?);
-  let mut line = String::new();
+/// The request log line: method, id, duration, sizes, token counts and any error
+fn request_summary(
+  request: &Request,
+  response: &Response,
+  elapsed: Duration,
+  in_bytes: usize,
+  out_bytes: usize,
+) -> String {
+  let mut summary = format!(
+    "{} #{} {:.3}s in={} out={}",
+    request.method,
+    request.id,
+    elapsed.as_secs_f64(),
+    format_size(in_bytes),
+    format_size(out_bytes)
+  );
+  if let Some(usage) = response.usage {
+    summary.push_str(&format!(
+      " prompt={} completion={} tok",
+      usage.prompt_tokens, usage.completion_tokens
+    ));
+  }
+  if let Some(ref error) = response.error {
+    summary.push_str(&format!(" failed: {}", error));
+  }
+  summary
+}
 
-  match reader.read_line(&mut line) {
-    Ok(0) => return Ok(false), // Connection closed
-    Ok(_) => {}
-    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
-    Err(e) => return Err(e.into()),
+/// Answer one request from either listener, keeping the counters and the request log
+fn serve(
+  request: &Request,
+  state: &DaemonState,
+  daemon_cfg: &Arc<Config>,
+  in_bytes: usize,
+  on_progress: Option<&mut dyn FnMut(Progress)>,
+) -> Response {
+  // Health checks and rejected requests don't count as activity
+  let authorized = authorized(request, daemon_cfg);
+  if authorized
+    && !matches!(
+      request.method.as_str(),
+      "ping" | "status" | "stats" | "metrics"
+    )
+  {
+    state.touch(&request.method);
+    state.last_request.store(unix_now(), Ordering::Relaxed);
+  }
+
+  let started = Instant::now();
+  let response = if authorized {
+    let cfg = request_config(request, daemon_cfg);
+    config::with_config(cfg.clone(), || dispatch(request, state, &cfg, on_progress))
+  } else {
+    error_response(request.id, UNAUTHORIZED)
+  };
+
+  let elapsed = started.elapsed();
+  state
+    .last_request_ms
+    .store(elapsed.as_millis() as u64, Ordering::Relaxed);
+  state.served.fetch_add(1, Ordering::Relaxed);
+  let out_bytes = serde_json::to_string(&response).map_or(0, |s| s.len() + 1);
+  let summary = request_summary(request, &response, elapsed, in_bytes, out_bytes);
+  let level = if response.error.is_some() {
+    state.failed.fetch_add(1, Ordering::Relaxed);
+    LogLevel::Warn
+  } else {
+    LogLevel::Debug
+  };
+  if logs(level) {
+    print_log(level, &summary);
   }
+  // Every request goes in the file, whatever the console shows
+  write_log(&LogEntry {
+    method: Some(request.method.clone()),
+    id: Some(request.id),
+    prompt_tokens: response.usage.map(|u| u.prompt_tokens),
+    completion_tokens: response.usage.map(|u| u.completion_tokens),
+    duration_ms: Some(elapsed.as_millis() as u64),
+    error: response.error.clone(),
+    ..LogEntry::new(
+      if response.error.is_some() {
+        "warn"
+      } else {
+        "info"
+      },
+      summary,
+    )
+  });
 
-  // Update last activity timestamp
-  last_activity.store(Instant::now().elapsed().as_secs(), Ordering::Relaxed);
+  response
+}
 
-  let request: Request = serde_json::from_str(&line).with_context(|| "Failed to parse request")?;
+/// A piece of a streamed generation
+#[derive(Serialize)]
+struct TokenLine<'a> {
+  token: &'a str,
+  id: u64,
+}
 
-  let should_shutdown = request.method == "shutdown";
-  let response = handle_request(&request);
+/// Sent first when a streamed generation waits behind others
+#[derive(Serialize)]
+struct QueuedLine {
+  /// Requests running or waiting ahead of this one
+  queued: usize,
+  id: u64,
+}
+
+/// The last line of a streamed generation
+#[derive(Serialize)]
+struct Done<'a> {
+  done: bool,
+  #[serde(flatten)]
+  response: &'a Response,
+}
 
-  let mut response_str = serde_json::to_string(&response)?;
-  response_str.push('\n');
-  stream.write_all(response_str.as_bytes())?;
-  stream.flush()?;
+/// Whether the client asked for a generation's text as it's produced
+fn streams(request: &Request) -> bool {
+  !is_control_method(&request.method)
+    && request
+      .params
+      .get("stream")
+      .and_then(|v| v.as_bool())
+      .unwrap_or(false)
+}
 
-  if should_shutdown {
-    shutdown_flag.store(true, Ordering::Relaxed);
+fn write_line(writer: &mut impl Write, value: &impl Serialize) -> Result<()> {
+  let mut line = serde_json::to_string(value)?;
+  line.push('\n');
+  writer.write_all(line.as_bytes())?;
+  writer.flush()?;
+  Ok(())
+}
+
+/// Room a request has beyond its prompt, for the JSON around it and the other params
+const REQUEST_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// Time a client has to send a whole request line. Each read also times out after this
+/// long, so an idle connection is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A reader that fails with `TimedOut` once `deadline` passes, however steadily bytes arrive.
+/// A read already waiting can still run for the socket's own timeout
+struct Deadline<R> {
+  inner: R,
+  deadline: Instant,
+}
+
+impl<R> Deadline<R> {
+  fn check(&self) -> std::io::Result<()> {
+    if Instant::now() >= self.deadline {
+      return Err(std::io::ErrorKind::TimedOut.into());
+    }
+    Ok(())
+  }
+}
+
+impl<R: Read> Read for Deadline<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.check()?;
+    self.inner.read(buf)
+  }
+}
+
+impl<R: BufRead> BufRead for Deadline<R> {
+  fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+    self.check()?;
+    self.inner.fill_buf()
+  }
+
+  fn consume(&mut self, amt: usize) {
+    self.inner.consume(amt)
+  }
+}
+
+/// Read one request line into `buf`, never buffering more than one byte past `max_line`
+/// and giving up at `deadline`
+fn read_request_line(
+  reader: &mut impl BufRead,
+  max_line: usize,
+  deadline: Instant,
+  buf: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+  Deadline {
+    inner: reader.take(max_line as u64 + 1),
+    deadline,
+  }
+  .read_until(b'\n', buf)
+}
+
+fn handle_client(stream: ServerChannel, state: &DaemonState) -> Result<()> {
+  // Idle connections are dropped; a client waiting on a response is never read from
+  stream.tcp().set_read_timeout(Some(REQUEST_TIMEOUT))?;
+  stream
+    .tcp()
+    .set_write_timeout(Some(Duration::from_secs(5)))?;
+
+  let mut reader = BufReader::new(stream);
+
+  loop {
+    let cfg = daemon_config();
+    let max_line = cfg.daemon.max_prompt_bytes + REQUEST_OVERHEAD_BYTES;
+    // Never buffer more than one byte past the limit or wait past the deadline, whatever
+    // the client sends or however slowly
+    let mut buf = Vec::new();
+    let read = read_request_line(
+      &mut reader,
+      max_line,
+      Instant::now() + REQUEST_TIMEOUT,
+      &mut buf,
+    );
+    let rejection = match read {
+      Ok(0) => return Ok(()), // Connection closed
+      Ok(_) if buf.len() > max_line => Some(format!(
+        "Request is over the daemon's limit of {} bytes (daemon.max_prompt_bytes)",
+        max_line
+      )),
+      Ok(_) if !buf.ends_with(b"\n") => {
+        Some("Incomplete request: no newline before EOF".to_string())
+      }
+      Ok(_) => None,
+      // TLS clients hang up without a close_notify
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+      Err(e)
+        if e.kind() == std::io::ErrorKind::WouldBlock
+          || e.kind() == std::io::ErrorKind::TimedOut =>
+      {
+        if buf.is_empty() {
+          return Ok(());
+        }
+        Some("Timed out reading the request".to_string())
+      }
+      Err(e) => return Err(e.into()),
+    };
+    // What's left of the line can't be told apart from the next request, so hang up
+    if let Some(error) = rejection {
+      log(LogLevel::Warn, format!("Rejected a request: {}", error));
+      let _ = write_line(reader.get_mut(), &error_response(0, &error));
+      return Ok(());
+    }
+
+    let line = String::from_utf8(buf).with_context(|| "Request is not valid UTF-8")?;
+    let request: Request =
+      serde_json::from_str(&line).with_context(|| "Failed to parse request")?;
+
+    let writer = reader.get_mut();
+    let response = if streams(&request) {
+      // A client that stops reading only misses the rest of the stream
+      let mut sending = true;
+      let id = request.id;
+      let mut on_progress = |progress: Progress| {
+        if sending {
+          sending = match progress {
+            Progress::Queued(queued) => write_line(writer, &QueuedLine { queued, id }),
+            Progress::Token(token) => write_line(writer, &TokenLine { token, id }),
+          }
+          .is_ok();
+        }
+      };
+      let response = serve(&request, state, &cfg, line.len(), Some(&mut on_progress));
+      write_line(
+        writer,
+        &Done {
+          done: true,
+          response: &response,
+        },
+      )?;
+      response
+    } else {
+      let response = serve(&request, state, &cfg, line.len(), None);
+      write_line(writer, &response)?;
+      response
+    };
+    let should_shutdown = request.method == "shutdown" && response.error.is_none();
+
+    if should_shutdown {
+      state.shutdown_flag.store(true, Ordering::Relaxed);
+      return Ok(());
+    }
   }
+}
+
+/// Routes of the HTTP API, each the TCP method of the same name
+const HTTP_ROUTES: &[(&str, &str)] = &[
+  ("POST", "/generate"),
+  ("POST", "/generate_commit_message"),
+  ("POST", "/suggest_branch_name"),
+  ("POST", "/suggest_conflict_resolution"),
+  ("GET", "/status"),
+  ("POST", "/shutdown"),
+];
+
+/// HTTP requests have no ids of their own; these number them in the log
+static HTTP_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+type HttpResponse = hyper::Response<Full<Bytes>>;
 
-  Ok(should_shutdown)
+fn http_reply(status: StatusCode, body: &serde_json::Value) -> HttpResponse {
+  let mut response = hyper::Response::new(Full::new(Bytes::from(body.to_string())));
+  *response.status_mut() = status;
+  response.headers_mut().insert(
+    hyper::header::CONTENT_TYPE,
+    hyper::header::HeaderValue::from_static("application/json"),
+  );
+  response
+}
+
+fn http_error(status: StatusCode, error: &str) -> HttpResponse {
+  http_reply(status, &serde_json::json!({ "error": error }))
+}
+
+/// One HTTP request: the body is the method's `params`, the reply its `result`
+async fn handle_http(
+  req: hyper::Request<Incoming>,
+  state: Arc<DaemonState>,
+) -> Result<HttpResponse, Infallible> {
+  let path = req.uri().path().to_string();
+  if !HTTP_ROUTES
+    .iter()
+    .any(|(method, route)| req.method().as_str() == *method && *route == path)
+  {
+    return Ok(http_error(StatusCode::NOT_FOUND, "Not found"));
+  }
+
+  let token = req
+    .headers()
+    .get(hyper::header::AUTHORIZATION)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.strip_prefix("Bearer "))
+    .map(|t| t.trim().to_string());
+  let cfg = daemon_config();
+  let max_body = cfg.daemon.max_prompt_bytes + REQUEST_OVERHEAD_BYTES;
+  let body = match Limited::new(req.into_body(), max_body).collect().await {
+    Ok(body) => body.to_bytes(),
+    Err(e) if e.is::<LengthLimitError>() => {
+      return Ok(http_error(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        &format!(
+          "Request is over the daemon's limit of {} bytes (daemon.max_prompt_bytes)",
+          max_body
+        ),
+      ))
+    }
+    Err(e) => return Ok(http_error(StatusCode::BAD_REQUEST, &e.to_string())),
+  };
+  let mut params = if body.iter().all(u8::is_ascii_whitespace) {
+    serde_json::json!({})
+  } else {
+    match serde_json::from_slice(&body) {
+      Ok(params @ serde_json::Value::Object(_)) => params,
+      _ => {
+        return Ok(http_error(
+          StatusCode::BAD_REQUEST,
+          "The body must be a JSON object",
+        ))
+      }
+    }
+  };
+
+  let method = path.trim_start_matches('/').to_string();
+  // Scripts want the numbers, not the one-word summary old TCP clients expect
+  if method == "status" {
+    params["detailed"] = true.into();
+  }
+  let request = Request {
+    method,
+    params,
+    id: HTTP_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+    token,
+  };
+
+  // Generations block until the inference thread answers
+  let serving = state.clone();
+  let response = match tokio::task::spawn_blocking(move || {
+    let response = serve(&request, &serving, &cfg, body.len(), None);
+    (request.method == "shutdown", response)
+  })
+  .await
+  {
+    Ok((shutdown, response)) => {
+      if shutdown && response.error.is_none() {
+        // Give the reply a moment to go out before the main loop exits
+        tokio::spawn(async move {
+          tokio::time::sleep(Duration::from_millis(200)).await;
+          state.shutdown_flag.store(true, Ordering::Relaxed);
+        });
+      }
+      response
+    }
+    Err(_) => {
+      return Ok(http_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Request panicked",
+      ))
+    }
+  };
+
+  Ok(match response.error {
+    Some(ref error) if error == UNAUTHORIZED => http_error(StatusCode::UNAUTHORIZED, error),
+    Some(ref error) => http_error(StatusCode::INTERNAL_SERVER_ERROR, error),
+    None => http_reply(
+      StatusCode::OK,
+      &response.result.unwrap_or(serde_json::Value::Null),
+    ),
+  })
+}
+
+/// Serve the HTTP API until the process exits, on a runtime of its own
+fn serve_http(listener: TcpListener, state: Arc<DaemonState>) -> Result<()> {
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()?;
+  runtime.block_on(async move {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    loop {
+      let (stream, peer) = match listener.accept().await {
+        Ok(accepted) => accepted,
+        Err(e) => {
+          log(LogLevel::Error, format!("HTTP accept error: {}", e));
+          continue;
+        }
+      };
+      log(LogLevel::Debug, format!("HTTP connection from {}", peer));
+      let state = state.clone();
+      tokio::spawn(async move {
+        let service = service_fn(move |req| handle_http(req, state.clone()));
+        if let Err(e) = http1::Builder::new()
+          .serve_connection(TokioIo::new(stream), service)
+          .await
+        {
+          log(LogLevel::Debug, format!("HTTP client error: {}", e));
+        }
+      });
+    }
+  })
+}
+
+/// Listen on `port` on the loopback interface; port 0 lets the OS pick a free one
+fn bind(port: u16) -> Result<TcpListener> {
+  let addr = format!("127.0.0.1:{}", port);
+  TcpListener::bind(&addr).with_context(|| format!("Failed to bind to {}", addr))
 }
 
 fn write_pid_file() -> Result<()> {
@@ -171,95 +1391,457 @@ fn remove_pid_file() {
 }
 
 fn main() -> Result<()> {
-  println!("{}", "Alfred Daemon starting...".cyan());
+  let args = Args::parse();
+  let _ = STARTED.set(Instant::now());
+
+  let level = args.level();
+  LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+  if level == LogLevel::Debug && std::env::var_os("RUST_LIB_BACKTRACE").is_none() {
+    std::env::set_var("RUST_LIB_BACKTRACE", "1");
+  }
+
+  log(LogLevel::Info, "Alfred Daemon starting...");
+
+  // Never route our own helper calls back through the daemon
+  llm::set_daemon_mode();
 
   // Load configuration
+  config::set_current(config::load_daemon()?);
   let daemon_config = config::get_daemon_config();
-  let port = daemon_config.port;
-  let idle_timeout = Duration::from_secs((daemon_config.idle_timeout_minutes * 60) as u64);
-  let has_timeout = daemon_config.idle_timeout_minutes > 0;
-
-  // Write PID file
-  write_pid_file()?;
+  let port = args.port.unwrap_or(daemon_config.port);
+  // A foreground instance lives as long as the terminal session
+  let idle_minutes = if args.foreground {
+    0
+  } else {
+    daemon_config.idle_timeout_minutes
+  };
+  let idle_timeout = Duration::from_secs((idle_minutes * 60) as u64);
+  let has_timeout = idle_minutes > 0;
 
   // Load model
-  println!("{}", "Loading LLM model...".cyan());
-  if let Err(e) = llm::load_model() {
-    eprintln!("{} {}", "Error loading model:".red(), e);
-    remove_pid_file();
+  log(LogLevel::Info, "Loading LLM model...");
+  let load_started = Instant::now();
+  let loaded = match args.model {
+    Some(ref path) => llm::load_model_from(path),
+    None => llm::load_model(config::DEFAULT_MODEL),
+  };
+  if let Err(e) = loaded {
+    log(LogLevel::Error, format!("Error loading model: {}", e));
     return Err(e);
   }
-  println!("{}", "Model loaded successfully!".green());
+  let model_load_ms = load_started.elapsed().as_millis() as u64;
+  log(LogLevel::Info, "Model loaded successfully!");
 
   // Bind to port
-  let addr = format!("127.0.0.1:{}", port);
-  let listener = TcpListener::bind(&addr).with_context(|| format!("Failed to bind to {}", addr))?;
+  let listener = bind(port)?;
+  let addr = listener.local_addr()?;
 
   listener.set_nonblocking(true)?;
 
-  println!("{} {}", "Listening on".green(), addr.cyan());
-  if has_timeout {
+  let tls = if daemon_config.tls {
+    daemon_tls::ensure_cert()?;
+    Some(daemon_tls::server_config()?)
+  } else {
+    None
+  };
+
+  log(LogLevel::Info, format!("Listening on {}", addr));
+  if tls.is_some() {
+    log(
+      LogLevel::Info,
+      format!("TLS with {}", config::daemon_cert_file().display()),
+    );
+  }
+  let http = match daemon_config.http_port {
+    Some(http_port) => {
+      let listener = bind(http_port)?;
+      log(
+        LogLevel::Info,
+        format!("HTTP API on http://{}", listener.local_addr()?),
+      );
+      Some(listener)
+    }
+    None => None,
+  };
+
+  // Only once the ports are ours: a daemon that loses the race to bind must not replace,
+  // then remove, the running one's PID file. Foreground instances don't claim it, so they
+  // can run beside the service
+  if !args.foreground {
+    write_pid_file()?;
+  }
+
+  if args.foreground {
     println!(
-      "{} {} {}",
-      "Idle timeout:".dimmed(),
-      daemon_config.idle_timeout_minutes,
-      "minutes".dimmed()
+      "{} {}={} alfred commit",
+      "Point a client at it with:".dimmed(),
+      config::DAEMON_PORT_ENV,
+      addr.port()
+    );
+  }
+  if has_timeout {
+    log(
+      LogLevel::Info,
+      format!("Idle timeout: {} minutes", idle_minutes),
     );
   } else {
-    println!("{}", "Idle timeout: disabled".dimmed());
+    log(LogLevel::Info, "Idle timeout: disabled");
   }
 
-  // Shutdown flag
-  let shutdown_flag = Arc::new(AtomicBool::new(false));
-  let shutdown_flag_ctrlc = shutdown_flag.clone();
+  // Connections are read on their own threads; inference happens on this one, in order
+  let (jobs, queue) = mpsc::channel();
+  std::thread::Builder::new()
+    .name("inference".to_string())
+    .spawn(move || inference_worker(queue))
+    .with_context(|| "Failed to start the inference thread")?;
+
+  let state = Arc::new(DaemonState {
+    start_time: Instant::now(),
+    last_activity: Mutex::new(None),
+    shutdown_flag: AtomicBool::new(false),
+    jobs,
+    pending: AtomicUsize::new(0),
+    served: AtomicU64::new(0),
+    failed: AtomicU64::new(0),
+    last_request: AtomicU64::new(0),
+    last_request_ms: AtomicU64::new(0),
+    model_load_ms,
+  });
+
+  if let Some(listener) = http {
+    let state = state.clone();
+    std::thread::Builder::new()
+      .name("http".to_string())
+      .spawn(move || {
+        if let Err(e) = serve_http(listener, state) {
+          log(LogLevel::Error, format!("HTTP API stopped: {}", e));
+        }
+      })
+      .with_context(|| "Failed to start the HTTP API thread")?;
+  }
 
   // Handle Ctrl+C
+  let state_ctrlc = state.clone();
   ctrlc::set_handler(move || {
-    shutdown_flag_ctrlc.store(true, Ordering::Relaxed);
+    state_ctrlc.shutdown_flag.store(true, Ordering::Relaxed);
   })?;
 
-  // Track last activity for idle timeout
-  let start_time = Instant::now();
-  let last_activity = Arc::new(AtomicU64::new(0));
-
-  // Main loop
-  while !shutdown_flag.load(Ordering::Relaxed) {
-    // Accept connections
+  // Main loop: accept here, handle each connection on its own worker thread
+  while !state.shutdown_flag.load(Ordering::Relaxed) {
     match listener.accept() {
-      Ok((stream, _addr)) => {
-        if let Err(e) = handle_client(stream, &last_activity, &shutdown_flag) {
-          eprintln!("{} {}", "Client error:".red(), e);
+      Ok((stream, peer)) => {
+        log(LogLevel::Debug, format!("Connection from {}", peer));
+        // Accepted sockets may inherit non-blocking mode from the listener. One bad socket
+        // mustn't take the daemon down
+        if let Err(e) = stream.set_nonblocking(false) {
+          log(LogLevel::Error, format!("Client socket error: {}", e));
+          continue;
         }
+        let stream = match tls {
+          Some(ref tls) => match daemon_tls::accept(stream, tls) {
+            Ok(stream) => stream,
+            Err(e) => {
+              log(LogLevel::Error, format!("TLS error: {}", e));
+              continue;
+            }
+          },
+          None => Channel::Plain(stream),
+        };
+        let state = state.clone();
+        std::thread::spawn(move || {
+          if let Err(e) = handle_client(stream, &state) {
+            log(LogLevel::Error, format!("Client error: {:?}", e));
+          }
+        });
       }
       Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
         // No connection waiting, sleep briefly
         std::thread::sleep(Duration::from_millis(50));
       }
       Err(e) => {
-        eprintln!("{} {}", "Accept error:".red(), e);
+        log(LogLevel::Error, format!("Accept error: {}", e));
       }
     }
 
     // Check idle timeout
-    if has_timeout {
-      let last = last_activity.load(Ordering::Relaxed);
-      let idle_duration = if last == 0 {
-        start_time.elapsed()
-      } else {
-        Duration::from_secs(start_time.elapsed().as_secs() - last)
+    if has_timeout && state.idle_expired(idle_timeout) {
+      log(LogLevel::Info, "Idle timeout reached, shutting down...");
+      break;
+    }
+  }
+
+  // Cleanup
+  log(LogLevel::Info, "Shutting down...");
+  if !args.foreground {
+    remove_pid_file();
+  }
+  log(
+    LogLevel::Info,
+    format!(
+      "Served {} request(s), {} failed, over {:.0}s",
+      state.served.load(Ordering::Relaxed),
+      state.failed.load(Ordering::Relaxed),
+      state.start_time.elapsed().as_secs_f64()
+    ),
+  );
+  log(LogLevel::Info, "Daemon stopped.");
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state(start_time: Instant) -> DaemonState {
+    state_with(start_time, mpsc::channel().0)
+  }
+
+  fn state_with(start_time: Instant, jobs: mpsc::Sender<Job>) -> DaemonState {
+    DaemonState {
+      start_time,
+      last_activity: Mutex::new(None),
+      shutdown_flag: AtomicBool::new(false),
+      jobs,
+      pending: AtomicUsize::new(0),
+      served: AtomicU64::new(0),
+      failed: AtomicU64::new(0),
+      last_request: AtomicU64::new(0),
+      last_request_ms: AtomicU64::new(0),
+      model_load_ms: 0,
+    }
+  }
+
+  const MINUTE: Duration = Duration::from_secs(60);
+
+  #[test]
+  fn idle_time_counts_from_start_before_any_activity() {
+    let start = Instant::now();
+    let state = state(start);
+    assert_eq!(state.idle_duration_at(start + 5 * MINUTE), 5 * MINUTE);
+    assert!(!state.idle_expired_at(30 * MINUTE, start + 30 * MINUTE));
+    assert!(state.idle_expired_at(30 * MINUTE, start + 31 * MINUTE));
+  }
+
+  #[test]
+  fn activity_restarts_the_idle_timer() {
+    let start = Instant::now();
+    let state = state(start);
+    state.touch_at("generate", start + 20 * MINUTE);
+
+    assert_eq!(state.idle_duration_at(start + 25 * MINUTE), 5 * MINUTE);
+    assert!(!state.idle_expired_at(30 * MINUTE, start + 45 * MINUTE));
+    assert!(state.idle_expired_at(30 * MINUTE, start + 51 * MINUTE));
+  }
+
+  #[test]
+  fn never_expires_during_a_generation() {
+    let start = Instant::now();
+    let state = state(start);
+    state.pending.store(1, Ordering::Relaxed);
+    assert!(!state.idle_expired_at(30 * MINUTE, start + 120 * MINUTE));
+
+    state.pending.store(0, Ordering::Relaxed);
+    assert!(state.idle_expired_at(30 * MINUTE, start + 120 * MINUTE));
+  }
+
+  #[test]
+  fn idle_time_never_underflows() {
+    let start = Instant::now();
+    let state = state(start);
+    state.touch_at("generate", start + 10 * MINUTE);
+    // A check that raced the request sees no idle time rather than a panic
+    assert_eq!(state.idle_duration_at(start + 9 * MINUTE), Duration::ZERO);
+    assert!(!state.idle_expired_at(Duration::ZERO, start));
+  }
+
+  #[test]
+  fn a_generation_holds_off_shutdown_until_it_finishes() {
+    let (jobs, queue) = mpsc::channel::<Job>();
+    let state = state_with(Instant::now(), jobs);
+    let request = Request {
+      method: "generate".to_string(),
+      params: serde_json::json!({ "prompt": "x" }),
+      id: 7,
+      token: None,
+    };
+
+    let response = std::thread::scope(|scope| {
+      let state = &state;
+      scope.spawn(move || {
+        let job = queue.recv().unwrap();
+        assert_eq!(state.pending.load(Ordering::Relaxed), 1);
+        assert!(!state.idle_expired(Duration::ZERO), "busy, so not idle");
+        let done = Response {
+          result: Some(serde_json::json!("done")),
+          error: None,
+          id: job.request.id,
+          usage: None,
+        };
+        job.reply.send(JobEvent::Done(done)).unwrap();
+      });
+      dispatch(&request, state, &Arc::new(Config::default()), None)
+    });
+
+    assert_eq!(response.id, 7);
+    assert_eq!(state.pending.load(Ordering::Relaxed), 0);
+    // The finished generation restarted the idle timer
+    assert!(state.last_activity.lock().unwrap().is_some());
+  }
+
+  #[test]
+  fn pings_report_a_generation_in_flight() {
+    let (jobs, queue) = mpsc::channel::<Job>();
+    let state = state_with(Instant::now(), jobs);
+    let cfg = Arc::new(Config::default());
+    let request = |method: &str, params| Request {
+      method: method.to_string(),
+      params,
+      id: 1,
+      token: None,
+    };
+    let ping = request("ping", serde_json::json!({ "detailed": true }));
+    let activity = |state| {
+      let response = dispatch(&ping, state, &cfg, None);
+      serde_json::from_value::<Activity>(response.result.unwrap()).unwrap()
+    };
+    assert_eq!(activity(&state), Activity::Idle);
+
+    let generate = request("generate", serde_json::json!({ "prompt": "x" }));
+    std::thread::scope(|scope| {
+      let state = &state;
+      let (generate, cfg) = (&generate, &cfg);
+      scope.spawn(move || dispatch(generate, state, cfg, None));
+      // The fake inference thread holds the job while the ping is answered
+      let job = queue.recv().unwrap();
+      assert!(matches!(
+        activity(state),
+        Activity::Generating { queue_depth: 0, .. }
+      ));
+      let done = Response {
+        result: Some(serde_json::json!("done")),
+        error: None,
+        id: job.request.id,
+        usage: None,
       };
+      job.reply.send(JobEvent::Done(done)).unwrap();
+    });
+    assert_eq!(activity(&state), Activity::Idle);
+  }
+
+  fn args(argv: &[&str]) -> Args {
+    Args::try_parse_from([&["alferd"], argv].concat()).unwrap()
+  }
+
+  #[test]
+  fn foreground_flags_parse() {
+    let args = args(&[
+      "--foreground",
+      "--port",
+      "0",
+      "--model",
+      "/tmp/m.gguf",
+      "--log-level",
+      "warn",
+    ]);
+    assert!(args.foreground);
+    assert_eq!(args.port, Some(0));
+    assert_eq!(args.model, Some(PathBuf::from("/tmp/m.gguf")));
+    assert_eq!(args.level(), LogLevel::Warn);
+  }
+
+  #[test]
+  fn log_level_defaults_to_debug_only_in_the_foreground() {
+    assert_eq!(args(&[]).level(), LogLevel::Info);
+    assert_eq!(args(&["--foreground"]).level(), LogLevel::Debug);
+    assert_eq!(args(&["--port", "7000"]).port, Some(7000));
+    assert!(Args::try_parse_from(["alferd", "--log-level", "trace"]).is_err());
+    assert!(Args::try_parse_from(["alferd", "--port", "70000"]).is_err());
+  }
+
+  #[test]
+  fn port_zero_binds_a_free_port() {
+    let first = bind(0).unwrap();
+    let second = bind(0).unwrap();
+    let (a, b) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+    assert!(a.ip().is_loopback());
+    assert_ne!(a.port(), 0);
+    assert_ne!(a.port(), b.port());
+    // The printed port is the one clients can reach
+    assert!(std::net::TcpStream::connect(a).is_ok());
+  }
+
+  #[test]
+  fn request_lines_show_sizes_tokens_and_errors() {
+    let request: Request =
+      serde_json::from_str(r#"{"method":"commit_message","params":{},"id":7}"#).unwrap();
+    let mut response = Response {
+      result: Some("feat: add x".into()),
+      error: None,
+      id: 7,
+      usage: Some(llm::Usage {
+        prompt_tokens: 812,
+        completion_tokens: 14,
+        duration_ms: 1500,
+      }),
+    };
+    assert_eq!(
+      request_summary(&request, &response, Duration::from_millis(1500), 300, 2048),
+      "commit_message #7 1.500s in=300 B out=2.0 KB prompt=812 completion=14 tok"
+    );
 
-      if idle_duration > idle_timeout {
-        println!("{}", "Idle timeout reached, shutting down...".yellow());
-        break;
+    response.usage = None;
+    response.error = Some("Model not loaded".to_string());
+    assert_eq!(
+      request_summary(&request, &response, Duration::from_millis(2), 40, 60),
+      "commit_message #7 0.002s in=40 B out=60 B failed: Model not loaded"
+    );
+  }
+
+  /// A client sending one byte at a time, each well inside the per-read timeout
+  struct Trickle(std::vec::IntoIter<u8>);
+
+  impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      std::thread::sleep(Duration::from_millis(10));
+      match self.0.next() {
+        Some(byte) if !buf.is_empty() => {
+          buf[0] = byte;
+          Ok(1)
+        }
+        _ => Ok(0),
       }
     }
   }
 
-  // Cleanup
-  println!("{}", "Shutting down...".cyan());
-  remove_pid_file();
-  println!("{}", "Daemon stopped.".green());
+  fn trickle(line: &str) -> BufReader<Trickle> {
+    BufReader::new(Trickle(line.as_bytes().to_vec().into_iter()))
+  }
 
-  Ok(())
+  #[test]
+  fn a_trickled_request_line_times_out_as_a_whole() {
+    let mut reader = trickle(&format!("{}\n", "x".repeat(100)));
+    let mut buf = Vec::new();
+    let deadline = Instant::now() + Duration::from_millis(100);
+    let err = read_request_line(&mut reader, 1024, deadline, &mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(
+      !buf.is_empty() && buf.len() < 100,
+      "read {} bytes",
+      buf.len()
+    );
+  }
+
+  #[test]
+  fn request_lines_within_the_deadline_and_limit_are_read_whole() {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut buf = Vec::new();
+    read_request_line(&mut trickle("{}\n{}\n"), 1024, deadline, &mut buf).unwrap();
+    assert_eq!(buf, b"{}\n");
+
+    // One byte past the limit is enough to reject it
+    let mut buf = Vec::new();
+    read_request_line(&mut trickle("0123456789\n"), 4, deadline, &mut buf).unwrap();
+    assert_eq!(buf, b"01234");
+  }
 }
@@ -0,0 +1,159 @@
+use anyhow::{bail, Result};
+use colored::Colorize;
+
+use crate::git::{self, BisectStep};
+use crate::llm::{self, BisectClassification};
+use crate::ui;
+
+pub struct BisectOptions {
+  /// A revision with the regression (default: HEAD)
+  pub bad: Option<String>,
+  /// A revision without it (default: the latest tag before `bad`)
+  pub good: Option<String>,
+  /// What the regression looks like, for the model
+  pub symptom: Option<String>,
+}
+
+/// What to tell git about the commit being tested, or `None` to stop
+fn ask(suggestion: BisectClassification) -> Option<&'static str> {
+  let mut options: Vec<(&str, Option<&str>)> = vec![
+    ("Mark good", Some("good")),
+    ("Mark bad", Some("bad")),
+    ("Skip this commit", Some("skip")),
+  ];
+  // The suggestion goes first, so Enter takes it
+  if let Some(i) = options
+    .iter()
+    .position(|(_, term)| *term == Some(suggestion.name()))
+  {
+    let suggested = options.remove(i);
+    options.insert(0, suggested);
+  }
+  options.push(("Stop bisecting", None));
+
+  let labels: Vec<String> = options
+    .iter()
+    .enumerate()
+    .map(|(i, (label, _))| {
+      if i == 0 && suggestion != BisectClassification::Unknown {
+        format!("{} (suggested)", label)
+      } else {
+        label.to_string()
+      }
+    })
+    .collect();
+  let choice = ui::select("What does this commit do?", &labels)?;
+  let index = labels.iter().position(|l| *l == choice)?;
+  options[index].1
+}
+
+fn subject(message: &str) -> &str {
+  message.lines().next().unwrap_or_default()
+}
+
+fn short(hash: &str) -> &str {
+  &hash[..hash.len().min(7)]
+}
+
+/// Show the commit under test and the model's verdict, and ask what it is
+fn review(hash: &str, remaining: Option<usize>, symptom: &str) -> Result<Option<&'static str>> {
+  let (message, diff) = git::show_commit(hash)?;
+  println!();
+  ui::heading(format!(
+    "Testing {} {}",
+    short(hash).yellow(),
+    subject(&message)
+  ));
+  if let Some(remaining) = remaining {
+    ui::dim(format!(
+      "About {} revision(s) left after this one",
+      remaining
+    ));
+  }
+
+  ui::info("Analyzing commit...");
+  let suggestion = match llm::classify_bisect_step(&diff, symptom) {
+    Ok(suggestion) => suggestion,
+    Err(e) => {
+      ui::warn(format!("Failed to analyze commit: {}", e));
+      BisectClassification::Unknown
+    }
+  };
+  match suggestion {
+    BisectClassification::Unknown => ui::dim("The model can't tell from this commit's changes"),
+    suggestion => ui::info(format!("AI suggests: {}", suggestion.name().bold())),
+  }
+  ui::dim("Build or test it now if you want to check before answering");
+
+  Ok(ask(suggestion))
+}
+
+/// Show the first bad commit and what it does
+fn show_culprit(hash: &str) -> Result<()> {
+  let (message, diff) = git::show_commit(hash)?;
+  println!();
+  ui::success(format!(
+    "First bad commit: {} {}",
+    short(hash).yellow(),
+    subject(&message)
+  ));
+
+  ui::info("Explaining...");
+  match llm::explain_changes(&[message], &diff, &[], true) {
+    Ok(explanation) => {
+      println!();
+      ui::separator();
+      println!("{}", explanation.trim());
+      ui::separator();
+    }
+    Err(e) => ui::warn(format!("Failed to explain: {}", e)),
+  }
+  Ok(())
+}
+
+pub fn run(opts: BisectOptions) -> Result<()> {
+  if git::bisect_in_progress() {
+    bail!("A bisect is already in progress\nFinish it with 'git bisect reset' first.");
+  }
+
+  let bad = opts.bad.unwrap_or_else(|| "HEAD".to_string());
+  let Some(good) = opts.good.or_else(|| git::latest_tag(&format!("{}^", bad))) else {
+    bail!("No tag before {} to start from. Pass --good <REF>", bad);
+  };
+  let Some(symptom) = opts
+    .symptom
+    .or_else(|| ui::prompt("Describe the regression:"))
+  else {
+    bail!("Describe the regression with --symptom");
+  };
+
+  ui::info(format!(
+    "Bisecting from {} (good) to {} (bad)",
+    good.cyan(),
+    bad.cyan()
+  ));
+  let mut step = git::bisect_start(&bad, &good)?;
+
+  loop {
+    match step {
+      BisectStep::Done(hash) => {
+        show_culprit(&hash)?;
+        break;
+      }
+      BisectStep::Commit { hash, remaining } => match review(&hash, remaining, &symptom)? {
+        Some(term) => step = git::bisect_mark(term)?,
+        None => {
+          ui::info("Stopped bisecting");
+          break;
+        }
+      },
+    }
+  }
+
+  if ui::confirm("End the bisect and check out where you started?", true) {
+    git::bisect_reset()?;
+  } else {
+    ui::dim("Run 'git bisect reset' when you're done");
+  }
+  Ok(())
+}
@@ -1,23 +1,40 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
 use colored::Colorize;
+use regex::Regex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::{git, llm, ui};
+use crate::{config, forge, git, llm, ui};
 
-pub fn new_branch(name: Option<String>) -> Result<()> {
+pub fn new_branch(
+  name: Option<String>,
+  description: Option<String>,
+  issue: Option<String>,
+  use_forge: bool,
+) -> Result<()> {
   let branch_name = match name {
     Some(n) => n,
     None => {
-      let description = match ui::prompt("Describe what this branch is for:") {
-        Some(d) => d,
-        None => {
-          ui::info("Aborted");
-          return Ok(());
-        }
+      let description =
+        match description.or_else(|| ui::prompt("Describe what this branch is for:")) {
+          Some(d) => d,
+          None if ui::non_interactive() => {
+            bail!("Pass a branch name or --description; there's no terminal to ask for one")
+          }
+          None => {
+            ui::info("Aborted");
+            return Ok(());
+          }
+        };
+
+      let context = match issue {
+        Some(ref id) => forge::issue_context(Some(id), use_forge),
+        None => None,
       };
 
       ui::info("Generating branch name...");
 
-      match llm::suggest_branch_name(&description) {
+      match llm::suggest_branch_name(&description, context.as_deref()) {
         Ok(suggested) => {
           ui::info(format!("Suggested: {}", suggested.cyan()));
 
@@ -33,6 +50,7 @@ pub fn new_branch(name: Option<String>) -> Result<()> {
             }
           }
         }
+        Err(e) if ui::non_interactive() => bail!("Failed to generate name: {}", e),
         Err(e) => {
           ui::error(format!("Failed to generate name: {}", e));
           match ui::prompt("Enter branch name:") {
@@ -52,9 +70,62 @@ pub fn new_branch(name: Option<String>) -> Result<()> {
     return Ok(());
   }
 
-  // Sanitize branch name
-  let sanitized: String = branch_name
-    .to_lowercase()
+  let cfg = config::current();
+  let ticket = if cfg.preserve_ticket_case {
+    match Regex::new(&cfg.commit.ticket_pattern) {
+      Ok(re) => Some(re),
+      Err(e) => {
+        ui::warn(format!("Ignoring invalid commit.ticket_pattern: {}", e));
+        None
+      }
+    }
+  } else {
+    None
+  };
+  let sanitized = sanitize_branch_name(&branch_name, ticket.as_ref());
+
+  // Names are usually lowercased, so `PROJ-` matches `proj-123-...`
+  let prefixes = config::get_branch_prefixes();
+  let lowered = sanitized.to_lowercase();
+  if !prefixes
+    .iter()
+    .any(|p| lowered.starts_with(&p.to_lowercase()))
+  {
+    ui::warn(format!(
+      "{} doesn't start with one of the branch prefixes: {}",
+      sanitized,
+      prefixes.join(", ")
+    ));
+  }
+
+  match git::create_branch(&sanitized) {
+    Ok(()) => {
+      ui::success(format!("Created and switched to: {}", sanitized));
+    }
+    Err(e) => {
+      ui::error(format!("Failed to create branch: {}", e));
+    }
+  }
+
+  Ok(())
+}
+
+/// Lowercase `name` and replace characters git dislikes with dashes. Matches of `ticket`
+/// keep their case, so `PROJ-123 add login` becomes `PROJ-123-add-login`
+fn sanitize_branch_name(name: &str, ticket: Option<&Regex>) -> String {
+  let mut cased = String::with_capacity(name.len());
+  let mut rest = name;
+  while let Some(m) = ticket.and_then(|re| re.find(rest)) {
+    if m.as_str().is_empty() {
+      break;
+    }
+    cased.push_str(&rest[..m.start()].to_lowercase());
+    cased.push_str(m.as_str());
+    rest = &rest[m.end()..];
+  }
+  cased.push_str(&rest.to_lowercase());
+
+  cased
     .chars()
     .map(|c| {
       if c.is_alphanumeric() || c == '/' || c == '_' || c == '-' {
@@ -67,117 +138,399 @@ pub fn new_branch(name: Option<String>) -> Result<()> {
     .split('-')
     .filter(|s| !s.is_empty())
     .collect::<Vec<&str>>()
-    .join("-");
-
-  match git::create_branch(&sanitized) {
-    Ok(()) => {
-      ui::success(format!("Created and switched to: {}", sanitized));
-    }
-    Err(e) => {
-      ui::error(format!("Failed to create branch: {}", e));
-    }
-  }
+    .join("-")
+}
 
-  Ok(())
+/// Local branches, or the remote-tracking ones with `--remote`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CleanScope {
+  Local,
+  Remote,
 }
 
-pub fn clean(force: bool) -> Result<()> {
-  let status = git::status()?;
+pub fn clean(force: bool, dry_run: bool, remote: bool) -> Result<()> {
   let branches = git::get_branches()?;
 
   // Find base branch
-  let base_branch = if branches.contains(&"main".to_string()) {
-    "main"
-  } else if branches.contains(&"master".to_string()) {
-    "master"
-  } else {
-    branches.first().map(|s| s.as_str()).unwrap_or("main")
-  };
+  let base_branch = git::default_base()
+    .or_else(|| branches.first().cloned())
+    .unwrap_or_else(|| "main".to_string());
+
+  clean_merged(CleanScope::Local, &base_branch, force, dry_run)?;
+  if remote {
+    println!();
+    clean_merged(CleanScope::Remote, &base_branch, force, dry_run)?;
+  }
+  Ok(())
+}
+
+fn clean_merged(scope: CleanScope, base_branch: &str, force: bool, dry_run: bool) -> Result<()> {
+  let status = git::status()?;
 
   ui::info(format!(
-    "Checking for branches merged into {}...",
+    "Checking for {} merged into {}...",
+    match scope {
+      CleanScope::Local => "branches",
+      CleanScope::Remote => "remote branches",
+    },
     base_branch.cyan()
   ));
 
-  let merged = git::get_merged_branches(base_branch)?;
+  let merged = match scope {
+    CleanScope::Local => git::get_merged_branches(base_branch)?,
+    CleanScope::Remote => git::get_merged_remote_branches(base_branch)?,
+  };
 
   if merged.is_empty() {
     ui::success("No merged branches to clean up!");
     return Ok(());
   }
 
-  ui::heading(format!("Found {} merged branch(es):", merged.len()));
-  for b in &merged {
-    let current = if *b == status.branch {
-      format!(" {}", "(current)".yellow())
-    } else {
-      String::new()
+  // Remote branches are protected by their name on the remote, `main` in `origin/main`
+  let cfg = config::current();
+  let (protected, merged): (Vec<_>, Vec<_>) = merged.into_iter().partition(|b| {
+    let name = match scope {
+      CleanScope::Local => b.name.as_str(),
+      CleanScope::Remote => b.name.split_once('/').map_or(b.name.as_str(), |(_, n)| n),
     };
-    ui::list_item(format!("{}{}", b, current));
+    cfg.is_protected_branch(name)
+  });
+  for b in &protected {
+    ui::dim(format!("  Skipping {} (protected)", b.name));
   }
-  println!();
 
-  let to_delete: Vec<&String> = merged.iter().filter(|b| *b != &status.branch).collect();
+  let (current, to_delete): (Vec<_>, Vec<_>) = merged
+    .into_iter()
+    .partition(|b| scope == CleanScope::Local && b.name == status.branch);
+  for b in &current {
+    ui::info(format!("Skipping {} (checked out)", b.name));
+  }
 
   if to_delete.is_empty() {
-    ui::info("All merged branches are currently checked out");
+    ui::info("No merged branches left to delete");
     return Ok(());
   }
 
-  if force {
-    for branch in to_delete {
-      git::delete_branch(branch, false)?;
-      ui::success(format!("Deleted: {}", branch));
-    }
-  } else if ui::confirm(
-    &format!("Delete {} merged branch(es)?", to_delete.len()),
-    true,
-  ) {
-    for branch in to_delete {
-      match git::delete_branch(branch, false) {
-        Ok(()) => {
-          ui::success(format!("Deleted: {}", branch));
-        }
-        Err(e) => {
-          ui::error(format!("Failed to delete {}: {}", branch, e));
-        }
+  // Same list the real run starts from, whatever --force says
+  if dry_run {
+    ui::heading(format!(
+      "Would delete {} merged branch(es), oldest first:",
+      to_delete.len()
+    ));
+    let lines: Vec<String> = to_delete
+      .iter()
+      .map(|b| {
+        format!(
+          "{} {}  {}  {}",
+          "•".dimmed(),
+          b.name,
+          b.date.dimmed(),
+          b.subject.dimmed()
+        )
+      })
+      .collect();
+    ui::list_paged(&lines, ui::PAGE_SIZE);
+    println!();
+    ui::dim("Dry run: no branches were deleted");
+    return Ok(());
+  }
+
+  ui::heading(format!(
+    "Found {} merged branch(es), oldest first:",
+    to_delete.len()
+  ));
+
+  // `--yes` asks for the same thing as `--force`
+  if force || ui::assume_yes() {
+    let lines: Vec<String> = to_delete
+      .iter()
+      .map(|b| format!("{} {}", "•".dimmed(), b.name))
+      .collect();
+    ui::list_paged(&lines, ui::PAGE_SIZE);
+    println!();
+    for branch in &to_delete {
+      match scope {
+        CleanScope::Local => git::delete_branch(&branch.name, false)?,
+        CleanScope::Remote => git::delete_remote_branch(&branch.name)?,
       }
+      ui::success(format!("Deleted: {}", branch.name));
     }
-  } else {
+    return Ok(());
+  }
+
+  let labels: Vec<String> = to_delete
+    .iter()
+    .map(|b| format!("{}  {}  {}", b.name, b.date.dimmed(), b.subject.dimmed()))
+    .collect();
+  let checked = vec![true; to_delete.len()];
+  let chosen = match ui::multi_select("Branches to delete:", &labels, &checked) {
+    Some(chosen) if !chosen.is_empty() => chosen,
+    _ => {
+      ui::info("Aborted");
+      return Ok(());
+    }
+  };
+
+  // Other people's clones lose these branches too, so ask once more
+  if scope == CleanScope::Remote
+    && !ui::confirm_destructive(
+      &format!("Delete {} branch(es) from the remote?", chosen.len()),
+      false,
+    )
+  {
     ui::info("Aborted");
+    return Ok(());
+  }
+
+  let mut deleted = 0;
+  for branch in chosen.iter().map(|&i| &to_delete[i]) {
+    let result = match scope {
+      CleanScope::Local => delete_merged(&branch.name),
+      CleanScope::Remote => git::delete_remote_branch(&branch.name).map(|()| true),
+    };
+    match result {
+      Ok(true) => {
+        ui::success(format!("Deleted: {}", branch.name));
+        deleted += 1;
+      }
+      Ok(false) => ui::info(format!("Kept: {}", branch.name)),
+      Err(e) => ui::error(format!("Failed to delete {}: {}", branch.name, e)),
+    }
   }
+  ui::info(format!(
+    "Deleted {} of {} branch(es)",
+    deleted,
+    chosen.len()
+  ));
 
   Ok(())
 }
 
-pub fn list(all: bool) -> Result<()> {
-  let status = git::status()?;
-  let branches = git::get_branches()?;
+/// Seconds in a day, for `branch stale --days`
+const DAY_SECS: i64 = 24 * 60 * 60;
 
-  ui::heading("Local branches:");
-  for branch in &branches {
-    let marker = if *branch == status.branch {
-      format!("{} ", "*".green())
+/// List local branches without a commit in the last `days` days, oldest first, and with
+/// `clean` offer to delete them
+pub fn stale(days: u32, clean: bool) -> Result<()> {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0);
+  let cutoff = now - days as i64 * DAY_SECS;
+  let stale: Vec<(String, i64)> = git::branch_last_commit_dates()?
+    .into_iter()
+    .filter(|(_, at)| *at < cutoff)
+    .collect();
+
+  if stale.is_empty() {
+    ui::success(format!("No branches older than {} days", days));
+    return Ok(());
+  }
+
+  let age = |at: i64| format!("{} days", (now - at) / DAY_SECS);
+  ui::heading(format!(
+    "Found {} branch(es) without commits in {} days, oldest first:",
+    stale.len(),
+    days
+  ));
+  let lines: Vec<String> = stale
+    .iter()
+    .map(|(name, at)| format!("{} {}  {}", "•".dimmed(), name, age(*at).dimmed()))
+    .collect();
+  ui::list_paged(&lines, ui::PAGE_SIZE);
+  println!();
+
+  if !clean {
+    ui::dim("Run 'alfred branch stale --clean' to delete them");
+    return Ok(());
+  }
+
+  let status = git::status()?;
+  let cfg = config::current();
+  let mut to_delete = Vec::new();
+  for (name, at) in stale {
+    if cfg.is_protected_branch(&name) {
+      ui::dim(format!("  Skipping {} (protected)", name));
+    } else if name == status.branch {
+      ui::info(format!("Skipping {} (checked out)", name));
     } else {
-      "  ".to_string()
-    };
-    println!("{}{}", marker, branch);
+      to_delete.push((name, at));
+    }
+  }
+
+  if to_delete.is_empty() {
+    ui::info("No stale branches left to delete");
+    return Ok(());
+  }
+
+  let labels: Vec<String> = to_delete
+    .iter()
+    .map(|(name, at)| format!("{}  {}", name, age(*at).dimmed()))
+    .collect();
+  let checked = vec![true; to_delete.len()];
+  let chosen = match ui::multi_select("Branches to delete:", &labels, &checked) {
+    Some(chosen) if !chosen.is_empty() => chosen,
+    _ => {
+      ui::info("Aborted");
+      return Ok(());
+    }
+  };
+
+  let mut deleted = 0;
+  for (name, _) in chosen.iter().map(|&i| &to_delete[i]) {
+    match delete_merged(name) {
+      Ok(true) => {
+        ui::success(format!("Deleted: {}", name));
+        deleted += 1;
+      }
+      Ok(false) => ui::info(format!("Kept: {}", name)),
+      Err(e) => ui::error(format!("Failed to delete {}: {}", name, e)),
+    }
   }
+  ui::info(format!(
+    "Deleted {} of {} branch(es)",
+    deleted,
+    chosen.len()
+  ));
 
-  if all {
-    let remote = git::get_remote_branches()?;
-    if !remote.is_empty() {
-      println!();
-      ui::heading("Remote branches:");
-      for b in &remote {
-        println!("  {}", b.red());
+  Ok(())
+}
+
+/// Delete a branch the user picked. `git branch -d` refuses one that isn't merged into its
+/// upstream or HEAD; then ask before forcing. Returns false if the user kept it
+fn delete_merged(name: &str) -> Result<bool> {
+  match git::delete_branch(name, false) {
+    Ok(()) => Ok(true),
+    Err(e) if e.to_string().contains("not fully merged") => {
+      ui::warn(format!("git says {} is not fully merged", name));
+      if !ui::confirm_destructive(&format!("Force delete {}?", name), false) {
+        return Ok(false);
       }
+      git::delete_branch(name, true)?;
+      Ok(true)
     }
+    Err(e) => Err(e),
+  }
+}
+
+/// Order of `branch list`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BranchSort {
+  /// Most recently committed first
+  Date,
+  /// Alphabetical
+  Name,
+}
+
+/// Longest subject shown before it's cut with an ellipsis
+const SUBJECT_WIDTH: usize = 50;
+
+pub fn list(all: bool, sort: BranchSort) -> Result<()> {
+  let mut branches = git::for_each_branch_info(all)?;
+  if branches.is_empty() {
+    ui::info("No branches yet");
+    return Ok(());
+  }
+
+  match sort {
+    BranchSort::Date => branches.sort_by_key(|b| std::cmp::Reverse(b.timestamp)),
+    BranchSort::Name => branches.sort_by(|a, b| a.name.cmp(&b.name)),
+  }
+  // Local branches first, then remotes, each in the chosen order
+  branches.sort_by_key(|b| b.remote);
+
+  let tracking: Vec<String> = branches.iter().map(tracking).collect();
+  let name_width = branches
+    .iter()
+    .map(|b| b.name.chars().count())
+    .max()
+    .unwrap_or(0);
+  let tracking_width = tracking
+    .iter()
+    .map(|t| t.chars().count())
+    .max()
+    .unwrap_or(0);
+  let date_width = branches
+    .iter()
+    .map(|b| b.date.chars().count())
+    .max()
+    .unwrap_or(0);
+
+  println!();
+  for (branch, tracking) in branches.iter().zip(&tracking) {
+    let marker = if branch.current {
+      "*".green().to_string()
+    } else {
+      " ".to_string()
+    };
+    let name = format!("{:<width$}", branch.name, width = name_width);
+    let name = if branch.current {
+      name.green().bold().to_string()
+    } else if branch.remote {
+      name.red().to_string()
+    } else {
+      name
+    };
+    let tracking = format!("{:<width$}", tracking, width = tracking_width);
+    let tracking = if branch.gone {
+      tracking.yellow().to_string()
+    } else {
+      tracking.dimmed().to_string()
+    };
+    let date = format!("{:<width$}", branch.date, width = date_width);
+
+    println!(
+      "{} {}  {}  {}  {}",
+      marker,
+      name,
+      tracking,
+      date.dimmed(),
+      shorten(&branch.subject, SUBJECT_WIDTH)
+    );
+  }
+
+  let gone = branches.iter().filter(|b| b.gone).count();
+  if gone > 0 {
+    println!();
+    ui::dim(format!(
+      "{} branch(es) track an upstream that was deleted; they're usually finished and safe to delete",
+      gone
+    ));
   }
 
   Ok(())
 }
 
+/// Upstream column: "origin/main ↑2 ↓1", "origin/x (gone)", or "no upstream"
+fn tracking(branch: &git::BranchInfo) -> String {
+  if branch.remote {
+    return String::new();
+  }
+  let Some(upstream) = &branch.upstream else {
+    return "no upstream".to_string();
+  };
+  if branch.gone {
+    return format!("{} (gone)", upstream);
+  }
+
+  let mut out = upstream.clone();
+  if branch.ahead > 0 {
+    out.push_str(&format!(" ↑{}", branch.ahead));
+  }
+  if branch.behind > 0 {
+    out.push_str(&format!(" ↓{}", branch.behind));
+  }
+  out
+}
+
+fn shorten(text: &str, max: usize) -> String {
+  if text.chars().count() <= max {
+    return text.to_string();
+  }
+  let cut: String = text.chars().take(max - 1).collect();
+  format!("{}…", cut.trim_end())
+}
+
 pub fn show_help() {
   println!(
     r#"
@@ -190,16 +543,20 @@ pub fn show_help() {
   {}, {}    Create new branch with AI-suggested name
   {}, {}  Delete merged branches
   {}, {}       List branches
+  {}          List branches without recent commits
 
 {}
   --all, -a    Show remote branches (for list)
   --force, -f  Delete without confirmation (for clean)
+  --days <N>   Age in days that makes a branch stale (for stale, default 30)
+  --clean      Offer to delete the stale branches (for stale)
 
 {}
   alfred branch new                 Create branch with AI name suggestion
   alfred branch new feature/auth    Create specific branch
   alfred branch clean               Clean up merged branches
   alfred branch list --all          List all branches including remotes
+  alfred branch stale --days 60     Branches untouched for two months
 "#,
     "alfred branch".bold(),
     "USAGE".bold(),
@@ -210,7 +567,108 @@ pub fn show_help() {
     "cleanup".cyan(),
     "list".cyan(),
     "ls".cyan(),
+    "stale".cyan(),
     "OPTIONS".bold(),
     "EXAMPLES".bold(),
   );
 }
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_support::{commit, enter, git, init};
+  use super::*;
+  use crate::ui::script::{self, Answer};
+  use std::sync::Arc;
+  use tempfile::TempDir;
+
+  fn ticket() -> Regex {
+    Regex::new(&config::CommitConfig::default().ticket_pattern).unwrap()
+  }
+
+  #[test]
+  fn ticket_ids_keep_their_case() {
+    assert_eq!(
+      sanitize_branch_name("PROJ-123 add login", Some(&ticket())),
+      "PROJ-123-add-login"
+    );
+    assert_eq!(
+      sanitize_branch_name("feature/PROJ-7: Fix Login Bug", Some(&ticket())),
+      "feature/PROJ-7-fix-login-bug"
+    );
+    assert_eq!(
+      sanitize_branch_name("ABC-1 and DEF-22 Merge", Some(&ticket())),
+      "ABC-1-and-DEF-22-merge"
+    );
+  }
+
+  #[test]
+  fn everything_is_lowercased_without_preserve_ticket_case() {
+    assert_eq!(
+      sanitize_branch_name("PROJ-123 add login", None),
+      "proj-123-add-login"
+    );
+  }
+
+  #[test]
+  fn characters_git_dislikes_become_single_dashes() {
+    assert_eq!(
+      sanitize_branch_name("  Fix: the \"quoted\" bug!! ", None),
+      "fix-the-quoted-bug"
+    );
+    assert_eq!(
+      sanitize_branch_name("bugfix/crash_on start", Some(&ticket())),
+      "bugfix/crash_on-start"
+    );
+  }
+
+  #[test]
+  fn preserve_ticket_case_is_off_by_default() {
+    assert!(!config::Config::default().preserve_ticket_case);
+  }
+
+  /// `main` with more merged branches than fit under the bulk-confirm threshold
+  fn merged_fixture() -> TempDir {
+    let dir = init();
+    let repo = dir.path();
+    commit(repo, "a.txt", "a\n", "Add a");
+    for i in 1..=ui::BULK_CONFIRM_THRESHOLD + 5 {
+      git(repo, &["branch", &format!("done-{}", i)]);
+    }
+    dir
+  }
+
+  fn branches(repo: &std::path::Path) -> usize {
+    git(repo, &["branch", "--format=%(refname:short)"])
+      .lines()
+      .filter(|b| b.starts_with("done-"))
+      .count()
+  }
+
+  fn clean_in(repo: &std::path::Path, dry_run: bool, answers: Vec<Answer>) -> Vec<String> {
+    let _cwd = enter(repo);
+    let cfg = Arc::new(config::Config::default());
+    let (result, asked) = script::run(true, answers, || {
+      config::with_config(cfg, || {
+        clean_merged(CleanScope::Local, "main", false, dry_run)
+      })
+    });
+    result.unwrap();
+    asked
+  }
+
+  #[test]
+  fn dry_runs_collapse_long_lists_in_a_terminal() {
+    let dir = merged_fixture();
+    let asked = clean_in(dir.path(), true, vec![Answer::No]);
+    assert_eq!(asked, ["expand"]);
+    assert_eq!(branches(dir.path()), ui::BULK_CONFIRM_THRESHOLD + 5);
+  }
+
+  #[test]
+  fn clean_offers_every_merged_branch_and_deletes_the_picked_ones() {
+    let dir = merged_fixture();
+    let asked = clean_in(dir.path(), false, vec![Answer::Picks(Some(vec![0, 1, 2]))]);
+    assert_eq!(asked, ["Branches to delete:"]);
+    assert_eq!(branches(dir.path()), ui::BULK_CONFIRM_THRESHOLD + 2);
+  }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::{config, response_cache, ui};
+
+/// Where the cache lives, how much it holds, and how long replies are kept
+pub fn status() {
+  ui::heading("Response Cache");
+  println!();
+  println!(
+    "  {} {}",
+    "File:".dimmed(),
+    response_cache::cache_path().display()
+  );
+  println!("  {} {}", "Replies:".dimmed(), response_cache::len());
+  let ttl = config::get_cache_ttl_hours();
+  println!(
+    "  {} {}",
+    "Kept for:".dimmed(),
+    if ttl > 0 {
+      format!("{} hours", ttl)
+    } else {
+      "disabled (cache_ttl_hours: 0)".to_string()
+    }
+  );
+  println!();
+  println!(
+    "  {} {}",
+    "alfred cache clear".cyan(),
+    "Forget every stored reply".dimmed()
+  );
+}
+
+pub fn clear() -> Result<()> {
+  let count = response_cache::clear()?;
+  ui::success(format!(
+    "Cleared {} cached {}",
+    count,
+    if count == 1 { "reply" } else { "replies" }
+  ));
+  Ok(())
+}
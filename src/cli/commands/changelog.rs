@@ -0,0 +1,194 @@
+use anyhow::{anyhow, bail, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::Path;
+
+use crate::git::CommitRecord;
+use crate::llm::ChangelogFormat;
+use crate::{git, llm, ui};
+
+const CHANGELOG_FILE: &str = "CHANGELOG.md";
+
+const HEADER: &str =
+  "# Changelog\n\nAll notable changes to this project will be documented in this file.\n";
+
+pub struct ChangelogOptions {
+  /// `from..to`, either side optional, instead of `from` and `to`
+  pub range: Option<String>,
+  /// Start of the range (default: latest tag)
+  pub from: Option<String>,
+  /// End of the range (default: HEAD)
+  pub to: Option<String>,
+  /// `keep-a-changelog` (default) or `conventional`
+  pub format: Option<String>,
+  /// Print instead of writing the changelog file
+  pub dry_run: bool,
+  /// File to add the entry to (default: CHANGELOG.md)
+  pub output: Option<String>,
+  /// Group commits by their conventional type instead of asking the model
+  pub no_ai: bool,
+}
+
+pub fn run(opts: ChangelogOptions) -> Result<()> {
+  let format = match opts.format.as_deref() {
+    None => ChangelogFormat::KeepAChangelog,
+    Some(name) => ChangelogFormat::parse(name).ok_or_else(|| {
+      anyhow!(
+        "Unknown changelog format '{}'. Use keep-a-changelog or conventional",
+        name
+      )
+    })?,
+  };
+
+  let (from, to) = match opts.range {
+    Some(ref range) => {
+      let (from, to) = range
+        .split_once("...")
+        .or_else(|| range.split_once(".."))
+        .ok_or_else(|| anyhow!("Expected a range like v1.0.0..HEAD, got '{}'", range))?;
+      let side = |s: &str| (!s.is_empty()).then(|| s.to_string());
+      (side(from), side(to))
+    }
+    None => (opts.from, opts.to),
+  };
+  let to = to.unwrap_or_else(|| "HEAD".to_string());
+  let from = from.or_else(|| git::latest_tag(&to)).unwrap_or_default();
+
+  let range = if from.is_empty() {
+    to.clone()
+  } else {
+    format!("{}..{}", from, to)
+  };
+  let commits =
+    git::get_commits_with_messages(&range).map_err(|_| anyhow!("Invalid range: {}", range))?;
+  if commits.is_empty() {
+    ui::info("No commits in range");
+    return Ok(());
+  }
+
+  if !opts.dry_run && !opts.no_ai {
+    let range = if from.is_empty() {
+      format!("start..{}", to)
+    } else {
+      range.clone()
+    };
+    ui::info(format!(
+      "Generating changelog for {} commit(s) in {}...",
+      commits.len(),
+      range.cyan()
+    ));
+  }
+
+  let sections = if opts.no_ai {
+    group_by_type(&commits, &format)
+  } else {
+    match llm::generate_changelog(&commits, &format) {
+      Ok(s) => s,
+      Err(e) => {
+        ui::error(format!("Failed to generate changelog: {}", e));
+        ui::dim("Make sure you have run 'alfred setup', or use --no-ai");
+        return Ok(());
+      }
+    }
+  };
+
+  // Name the release after `to` when it's a tag, otherwise it's unreleased
+  let version = if git::is_tag(&to) {
+    format!("[{}]", to)
+  } else {
+    "[Unreleased]".to_string()
+  };
+  let date = commits.first().map(|c| c.date.as_str()).unwrap_or("");
+  let entry = format!("## {} - {}\n\n{}\n", version, date, sections.trim());
+
+  if opts.dry_run {
+    println!("{}", entry);
+    return Ok(());
+  }
+
+  let path = Path::new(opts.output.as_deref().unwrap_or(CHANGELOG_FILE));
+  if path.is_dir() {
+    bail!("{} is a directory", path.display());
+  }
+  let existing = fs::read_to_string(path).unwrap_or_default();
+  fs::write(path, insert_entry(&existing, &entry))?;
+  ui::success(format!("Updated {}", path.display()));
+
+  Ok(())
+}
+
+/// The conventional type of a subject and whether it's marked breaking with `!`
+fn conventional_type(subject: &str) -> Option<(&str, bool)> {
+  // Gitmoji subjects lead with the emoji
+  let subject = match subject.split_once(' ') {
+    Some((first, rest)) if !first.chars().any(|c| c.is_ascii_alphanumeric()) => rest,
+    _ => subject,
+  };
+  let (prefix, _) = subject.split_once(": ")?;
+  let kind = prefix.trim_end_matches('!').split('(').next()?;
+  if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_lowercase()) {
+    return None;
+  }
+  Some((kind, prefix.ends_with('!')))
+}
+
+/// The heading a commit goes under without the model
+fn section(commit: &CommitRecord, format: &ChangelogFormat) -> &'static str {
+  let (kind, breaking) = conventional_type(commit.subject()).unwrap_or(("", false));
+  match format {
+    ChangelogFormat::KeepAChangelog => match kind {
+      "feat" => "Added",
+      "fix" => "Fixed",
+      _ => "Changed",
+    },
+    ChangelogFormat::ConventionalChangelog => {
+      if breaking || commit.body().contains("BREAKING CHANGE") {
+        return "BREAKING CHANGES";
+      }
+      match kind {
+        "feat" => "Features",
+        "fix" => "Bug Fixes",
+        "perf" => "Performance Improvements",
+        _ => "Other Changes",
+      }
+    }
+  }
+}
+
+/// Commits listed under the format's headings by conventional type, oldest first
+fn group_by_type(commits: &[CommitRecord], format: &ChangelogFormat) -> String {
+  let mut sections: Vec<(&str, Vec<String>)> = Vec::new();
+  for commit in commits.iter().rev() {
+    let heading = section(commit, format);
+    let bullet = format!("- {} ({})", commit.subject(), commit.short_hash());
+    match sections.iter_mut().find(|(h, _)| *h == heading) {
+      Some((_, bullets)) => bullets.push(bullet),
+      None => sections.push((heading, vec![bullet])),
+    }
+  }
+
+  sections.sort_by_key(|(heading, _)| {
+    format
+      .headings()
+      .iter()
+      .position(|h| h == heading)
+      .unwrap_or(usize::MAX)
+  });
+  sections
+    .iter()
+    .map(|(heading, bullets)| format!("### {}\n\n{}", heading, bullets.join("\n")))
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+/// Put the new entry above the newest release, keeping any intro text on top
+fn insert_entry(existing: &str, entry: &str) -> String {
+  if existing.trim().is_empty() {
+    return format!("{}\n{}", HEADER, entry);
+  }
+
+  match existing.find("\n## ") {
+    Some(pos) => format!("{}\n{}\n{}", &existing[..pos], entry, &existing[pos + 1..]),
+    None => format!("{}\n\n{}", existing.trim_end(), entry),
+  }
+}
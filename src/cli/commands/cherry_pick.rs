@@ -0,0 +1,483 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use super::resolve;
+use crate::{git, llm, ui};
+
+/// Predictions at or above this likelihood are shown in red
+const LIKELY_CONFLICT: f32 = 0.7;
+
+pub struct CherryPickOptions {
+  /// Commits or `a..b` ranges, picked in the order given
+  pub refs: Vec<String>,
+  /// Branch to check out before picking
+  pub onto: Option<String>,
+  /// Append "(cherry picked from commit ...)" to each message
+  pub record_origin: bool,
+  /// Reset to where the last sequence started
+  pub undo: bool,
+  /// Ask the model which files are likely to conflict before picking
+  pub predict_conflicts: bool,
+}
+
+enum PickOutcome {
+  Picked,
+  Skipped,
+  Stopped,
+}
+
+pub fn run(opts: CherryPickOptions) -> Result<()> {
+  if opts.undo {
+    return undo();
+  }
+
+  if git::cherry_pick_state().is_some() {
+    ui::warn("A cherry-pick is already in progress");
+    ui::dim("Run 'alfred resolve' to finish it, or 'git cherry-pick --abort' to cancel");
+    return Ok(());
+  }
+
+  let status = git::status()?;
+  if !status.staged.is_empty() || !status.unstaged.is_empty() || !status.conflicts.is_empty() {
+    ui::warn("You have uncommitted changes. Commit or stash them first.");
+    return Ok(());
+  }
+
+  // Resolve before any checkout so relative refs mean what the user typed
+  let mut commits = Vec::new();
+  for spec in &opts.refs {
+    commits.extend(git::resolve_commits(spec)?);
+  }
+
+  if commits.is_empty() {
+    ui::info("Nothing to cherry-pick");
+    return Ok(());
+  }
+
+  let target = opts.onto.clone().unwrap_or_else(|| status.branch.clone());
+  ui::heading(format!(
+    "Commits to cherry-pick onto {} ({}):",
+    target.cyan(),
+    commits.len()
+  ));
+  for commit in &commits {
+    ui::list_item(git::commit_oneline(commit));
+    let stat = git::commit_shortstat(commit);
+    if !stat.is_empty() {
+      ui::dim(format!("    {}", stat));
+    }
+  }
+  println!();
+
+  // With nothing predicted to conflict there's nothing to weigh up, so go ahead
+  let confirm =
+    !opts.predict_conflicts || predict_conflicts(&commits, opts.onto.as_deref().unwrap_or("HEAD"));
+  if confirm && !ui::confirm("Start cherry-pick?", true) {
+    ui::info("Aborted");
+    return Ok(());
+  }
+
+  if let Some(ref branch) = opts.onto {
+    if *branch != status.branch {
+      git::checkout(branch)?;
+      ui::info(format!("Switched to {}", branch.cyan()));
+    }
+  }
+
+  let start = git::SequenceStart {
+    tip: git::head()?,
+    branch: git::current_branch(),
+  };
+  git::record_sequence_start(&start)?;
+
+  let mut picked = Vec::new();
+  let mut skipped = Vec::new();
+
+  for (i, commit) in commits.iter().enumerate() {
+    match pick(commit, opts.record_origin)? {
+      PickOutcome::Picked => picked.push(commit.clone()),
+      PickOutcome::Skipped => skipped.push(commit.clone()),
+      PickOutcome::Stopped => {
+        let remaining = &commits[i..];
+        summary(&picked, &skipped, remaining);
+        stop(&start, remaining);
+        return Ok(());
+      }
+    }
+  }
+
+  summary(&picked, &skipped, &[]);
+  ui::dim("Run 'alfred cherry-pick --undo' to drop the whole sequence");
+  Ok(())
+}
+
+/// Show the files each commit is predicted to conflict in on `target`. Returns whether any
+/// are, or whether the model couldn't say
+fn predict_conflicts(commits: &[String], target: &str) -> bool {
+  ui::info("Predicting conflicts...");
+  let mut any = false;
+
+  for commit in commits {
+    let predictions = git::show_commit(commit).and_then(|(_, patch)| {
+      let files: Vec<(String, String)> = git::commit_files(commit)?
+        .into_iter()
+        .map(|path| {
+          let content =
+            git::file_at(target, &path).unwrap_or_else(|| "(not on the target branch)".to_string());
+          (path, content)
+        })
+        .collect();
+      llm::predict_cherry_pick_conflicts(&patch, &files)
+    });
+
+    match predictions {
+      Ok(predictions) if predictions.is_empty() => {}
+      Ok(predictions) => {
+        any = true;
+        ui::heading(format!(
+          "Likely conflicts in {}:",
+          git::commit_oneline(commit)
+        ));
+        for prediction in predictions {
+          let color = if prediction.likelihood >= LIKELY_CONFLICT {
+            "red"
+          } else {
+            "yellow"
+          };
+          ui::list_item_colored(
+            "!",
+            color,
+            format!(
+              "{} {:.0}% - {}",
+              prediction.file.cyan(),
+              prediction.likelihood * 100.0,
+              prediction.reason
+            ),
+          );
+        }
+      }
+      Err(e) => {
+        any = true;
+        ui::warn(format!(
+          "Couldn't predict conflicts for {}: {}",
+          git::commit_oneline(commit),
+          e
+        ));
+      }
+    }
+  }
+
+  if any {
+    println!();
+  } else {
+    ui::success("No conflicts predicted");
+  }
+  any
+}
+
+fn pick(commit: &str, record_origin: bool) -> Result<PickOutcome> {
+  let line = git::commit_oneline(commit);
+  ui::info(format!("Picking {}", line));
+
+  let err = match git::cherry_pick(commit, record_origin) {
+    Ok(()) => return Ok(PickOutcome::Picked),
+    Err(e) => e,
+  };
+
+  // Git refused before starting (e.g. a merge commit without -m)
+  if git::cherry_pick_state().is_none() {
+    ui::error(format!("Cherry-pick failed: {}", err));
+    return Ok(PickOutcome::Stopped);
+  }
+
+  if !git::status()?.conflicts.is_empty() {
+    ui::warn(format!("Conflicts picking {}", line));
+    match resolve::resolve_conflicts(None, false)? {
+      resolve::Outcome::Resolved(_) | resolve::Outcome::NoConflicts => {}
+      resolve::Outcome::Remaining(_) => return Ok(PickOutcome::Stopped),
+    }
+
+    match git::continue_operation(git::Operation::CherryPick) {
+      Ok(()) => return Ok(PickOutcome::Picked),
+      Err(e) if !is_empty_pick()? => {
+        ui::error(format!("Cherry-pick failed: {}", e));
+        return Ok(PickOutcome::Stopped);
+      }
+      Err(_) => {}
+    }
+  } else if !is_empty_pick()? {
+    ui::error(format!("Cherry-pick failed: {}", err));
+    return Ok(PickOutcome::Stopped);
+  }
+
+  // Nothing left to commit: already applied here, or emptied by the resolution
+  ui::warn(format!("{} is empty on this branch", line));
+  if ui::confirm("Skip it?", true) {
+    git::skip_cherry_pick()?;
+    Ok(PickOutcome::Skipped)
+  } else {
+    Ok(PickOutcome::Stopped)
+  }
+}
+
+/// A stopped pick with nothing to commit: no conflicts left and the index matches HEAD.
+/// Read from the repository rather than git's message, which may be translated
+fn is_empty_pick() -> Result<bool> {
+  Ok(git::status()?.conflicts.is_empty() && !git::has_staged_changes())
+}
+
+fn summary(picked: &[String], skipped: &[String], remaining: &[String]) {
+  ui::heading("Cherry-pick summary:");
+  ui::list_item_colored("✓", "green", format!("{} picked", picked.len()));
+  for commit in skipped {
+    ui::list_item_colored(
+      "-",
+      "yellow",
+      format!("skipped {}", git::commit_oneline(commit)),
+    );
+  }
+  for commit in remaining {
+    ui::list_item_colored(
+      "•",
+      "dim",
+      format!("remaining {}", git::commit_oneline(commit)),
+    );
+  }
+  println!();
+}
+
+/// Offer to roll the branch back after the sequence stops midway
+fn stop(start: &git::SequenceStart, remaining: &[String]) {
+  let short: String = start.tip.chars().take(7).collect();
+  if ui::confirm_destructive(&format!("Abort and restore the branch to {}?", short), true) {
+    match restore(start) {
+      Ok(()) => ui::success(format!("Restored to {}", short)),
+      Err(e) => ui::error(format!("Failed to restore: {}", e)),
+    }
+    return;
+  }
+
+  ui::dim("Finish the current pick with 'alfred resolve', then pick the rest:");
+  let hashes: Vec<String> = remaining
+    .iter()
+    .skip(1)
+    .map(|c| c.chars().take(7).collect())
+    .collect();
+  if !hashes.is_empty() {
+    ui::dim(format!("  alfred cherry-pick {}", hashes.join(" ")));
+  }
+  ui::dim("Or drop the whole sequence with 'alfred cherry-pick --undo'");
+}
+
+fn restore(start: &git::SequenceStart) -> Result<()> {
+  if git::cherry_pick_state().is_some() {
+    git::abort_cherry_pick()?;
+  }
+  if let Some(ref branch) = start.branch {
+    if git::current_branch().as_deref() != Some(branch.as_str()) {
+      git::checkout(branch)?;
+    }
+  }
+  git::reset_hard(&start.tip)?;
+  git::clear_sequence_start();
+  Ok(())
+}
+
+fn undo() -> Result<()> {
+  let Some(start) = git::sequence_start() else {
+    ui::info("No cherry-pick sequence to undo");
+    return Ok(());
+  };
+
+  if git::cherry_pick_state().is_none() {
+    let status = git::status()?;
+    if !status.staged.is_empty() || !status.unstaged.is_empty() {
+      ui::warn("You have uncommitted changes. Commit or stash them first.");
+      return Ok(());
+    }
+  }
+
+  let branch = start.branch.as_deref().unwrap_or("HEAD");
+  let short: String = start.tip.chars().take(7).collect();
+  ui::warn(format!(
+    "This resets {} to {} and drops everything committed since",
+    branch.cyan(),
+    short
+  ));
+
+  if !ui::confirm_destructive("Undo the cherry-pick sequence?", false) {
+    ui::info("Aborted");
+    return Ok(());
+  }
+
+  restore(&start)?;
+  ui::success(format!("Restored {} to {}", branch, short));
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_support::{commit, enter, git, init};
+  use super::*;
+  use crate::ui::script::{self, Answer};
+  use std::fs;
+  use std::path::Path;
+  use tempfile::TempDir;
+
+  /// `main` with two fixes on top of where `release` branched off; `release` is checked out.
+  /// With `diverge`, `release` changed the line the first fix changes too
+  fn fixture(diverge: bool) -> TempDir {
+    let dir = init();
+    let repo = dir.path();
+    commit(repo, "a.txt", "one\n", "Add a");
+    git(repo, &["branch", "release"]);
+    commit(repo, "a.txt", "fixed\n", "Fix a");
+    commit(repo, "b.txt", "new\n", "Add b");
+    git(repo, &["checkout", "-q", "release"]);
+    if diverge {
+      commit(repo, "a.txt", "released\n", "Change a on release");
+    }
+    dir
+  }
+
+  fn options(refs: &[&str]) -> CherryPickOptions {
+    CherryPickOptions {
+      refs: refs.iter().map(|r| r.to_string()).collect(),
+      onto: None,
+      record_origin: false,
+      undo: false,
+      predict_conflicts: false,
+    }
+  }
+
+  fn subjects(repo: &Path, range: &str) -> Vec<String> {
+    git(repo, &["log", "--reverse", "--format=%s", range])
+      .lines()
+      .map(String::from)
+      .collect()
+  }
+
+  #[test]
+  fn a_clean_range_is_picked_in_order() {
+    let dir = fixture(false);
+    let repo = dir.path();
+    let start = git(repo, &["rev-parse", "HEAD"]);
+
+    let _in_repo = enter(repo);
+    let (result, asked) = script::run(true, vec![Answer::Yes], || run(options(&["release..main"])));
+    result.unwrap();
+
+    assert_eq!(asked, ["Start cherry-pick?"]);
+    assert_eq!(
+      subjects(repo, &format!("{}..release", start)),
+      ["Fix a", "Add b"]
+    );
+    assert_eq!(fs::read_to_string(repo.join("a.txt")).unwrap(), "fixed\n");
+    assert!(git::cherry_pick_state().is_none());
+    assert_eq!(git::sequence_start().unwrap().tip, start);
+  }
+
+  #[test]
+  fn a_conflicting_pick_goes_through_resolve_and_continues() {
+    let dir = fixture(true);
+    let repo = dir.path();
+
+    let _in_repo = enter(repo);
+    let answers = vec![
+      Answer::Yes,
+      // "Apply this resolution"
+      Answer::Pick(0),
+    ];
+    let (result, asked) = script::run(true, answers, || {
+      llm::with_conflict_suggestions(
+        |conflict| Ok(format!("{}{}", conflict.ours, conflict.theirs)),
+        || run(options(&["main~1"])),
+      )
+    });
+    result.unwrap();
+
+    assert_eq!(asked, ["Start cherry-pick?", "What would you like to do?"]);
+    assert_eq!(
+      fs::read_to_string(repo.join("a.txt")).unwrap(),
+      "released\nfixed\n"
+    );
+    assert_eq!(git(repo, &["log", "-1", "--format=%s"]), "Fix a");
+    assert!(git::cherry_pick_state().is_none());
+    assert_eq!(git(repo, &["status", "--porcelain"]), "");
+  }
+
+  #[test]
+  fn a_pick_already_applied_can_be_skipped() {
+    let dir = fixture(false);
+    let repo = dir.path();
+    let fix = git(repo, &["rev-parse", "main~1"]);
+
+    let _in_repo = enter(repo);
+    let (result, asked) = script::run(true, vec![Answer::Yes, Answer::Yes], || {
+      run(options(&[&fix, &fix]))
+    });
+    result.unwrap();
+
+    assert_eq!(asked, ["Start cherry-pick?", "Skip it?"]);
+    assert_eq!(subjects(repo, "main~2..release"), ["Fix a"]);
+    assert!(git::cherry_pick_state().is_none());
+  }
+
+  #[test]
+  fn aborting_midway_restores_the_original_tip() {
+    let dir = fixture(true);
+    let repo = dir.path();
+    let start = git(repo, &["rev-parse", "HEAD"]);
+
+    let _in_repo = enter(repo);
+    let answers = vec![
+      Answer::Yes,
+      // "Skip this conflict", which leaves the pick stopped
+      Answer::Pick(4),
+      // Abort and restore
+      Answer::Yes,
+    ];
+    let (result, asked) = script::run(true, answers, || {
+      llm::with_conflict_suggestions(
+        |conflict| Ok(conflict.theirs.to_string()),
+        || run(options(&["release..main"])),
+      )
+    });
+    result.unwrap();
+
+    assert_eq!(asked.len(), 3, "{:?}", asked);
+    assert!(asked[2].starts_with("Abort and restore the branch to "));
+    assert_eq!(git(repo, &["rev-parse", "HEAD"]), start);
+    assert_eq!(git(repo, &["branch", "--show-current"]), "release");
+    assert_eq!(git(repo, &["status", "--porcelain"]), "");
+    assert!(git::cherry_pick_state().is_none());
+    assert!(git::sequence_start().is_none());
+  }
+
+  #[test]
+  fn undo_drops_the_whole_sequence() {
+    let dir = fixture(false);
+    let repo = dir.path();
+    let start = git(repo, &["rev-parse", "HEAD"]);
+
+    let _in_repo = enter(repo);
+    let (result, _) = script::run(true, vec![Answer::Yes], || run(options(&["release..main"])));
+    result.unwrap();
+    assert_ne!(git(repo, &["rev-parse", "HEAD"]), start);
+
+    let undo = || CherryPickOptions {
+      undo: true,
+      ..options(&[])
+    };
+    // Destructive, so never without a terminal
+    let (result, _) = script::run(false, vec![], || run(undo()));
+    result.unwrap();
+    assert_ne!(git(repo, &["rev-parse", "HEAD"]), start);
+
+    let (result, asked) = script::run(true, vec![Answer::Yes], || run(undo()));
+    result.unwrap();
+    assert_eq!(asked, ["Undo the cherry-pick sequence?"]);
+    assert_eq!(git(repo, &["rev-parse", "HEAD"]), start);
+    assert!(git::sequence_start().is_none());
+  }
+}
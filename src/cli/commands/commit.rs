@@ -1,38 +1,93 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
+use regex::Regex;
+use std::fs;
 
-use crate::{git, llm, ui};
+use crate::risk::{self, RiskAssessment, RiskLevel};
+use crate::{commit_examples, config, diff_filter, forge, git, llm, tests_changed, ui};
 
-pub fn run(edit: bool) -> Result<()> {
+pub struct CommitOptions {
+  /// Edit the message before committing
+  pub edit: bool,
+  /// Subject line only
+  pub short: bool,
+  /// Number of candidates to generate
+  pub count: usize,
+  /// Issue to use as context instead of the one in the branch name
+  pub issue: Option<String>,
+  /// Look up issue context on the forge
+  pub forge: bool,
+  /// Regenerate the message of the last commit instead of creating one
+  pub amend: bool,
+  /// Add a `Signed-off-by:` trailer
+  pub signoff: bool,
+  /// `Name <email>` entries for `Co-authored-by:` trailers
+  pub co_authors: Vec<String>,
+  /// Sample the `repo` commit style's examples from history again instead of the cache
+  pub refresh_style: bool,
+  /// Print token counts and generation time
+  pub verbose: bool,
+  /// Commit a high-risk change without the extra confirmation
+  pub allow_high_risk: bool,
+}
+
+pub fn run(opts: CommitOptions) -> Result<()> {
   let status = git::status()?;
+  let cfg = config::current();
+  let mut trailers = trailers(&opts, &cfg.commit)?;
+  let ticket = ticket(&status.branch, &cfg.commit);
 
-  if status.staged.is_empty() {
+  if opts.amend {
+    ui::warn(format!(
+      "Amending rewrites history: {} will be replaced",
+      git::commit_oneline("HEAD")
+    ));
+    if git::is_pushed("HEAD") {
+      ui::warn("This commit is already on a remote; you'll need to force-push afterwards");
+    }
+    if !status.staged.is_empty() {
+      ui::info(format!(
+        "Staged changes in {} file(s) will be included",
+        status.staged.len()
+      ));
+    }
+  } else if status.staged.is_empty() {
     if !status.unstaged.is_empty() || !status.untracked.is_empty() {
       ui::warn("No staged changes. Stage files first with 'git add'");
       println!();
 
-      if !status.unstaged.is_empty() {
+      let mut modified = status.unstaged.clone();
+      modified.sort();
+      let mut untracked = status.untracked.clone();
+      untracked.sort();
+      let mut fully_shown = true;
+
+      if !modified.is_empty() {
         ui::dim("Modified files:");
-        for f in &status.unstaged {
-          println!("  {} {}", "M".yellow(), f);
-        }
+        let lines: Vec<String> = modified
+          .iter()
+          .map(|f| format!("{} {}", "M".yellow(), f))
+          .collect();
+        fully_shown &= ui::list_paged(&lines, ui::PAGE_SIZE);
       }
 
-      if !status.untracked.is_empty() {
+      if !untracked.is_empty() {
         ui::dim("Untracked files:");
-        for f in &status.untracked {
-          println!("  {} {}", "?".red(), f);
-        }
+        let lines: Vec<String> = untracked
+          .iter()
+          .map(|f| format!("{} {}", "?".red(), f))
+          .collect();
+        fully_shown &= ui::list_paged(&lines, ui::PAGE_SIZE);
       }
 
       println!();
 
-      if ui::confirm("Stage all changes?", true) {
-        let all_files: Vec<String> = status
-          .unstaged
-          .into_iter()
-          .chain(status.untracked)
-          .collect();
+      let mut prospective = git::diff_stats(false).unwrap_or_default();
+      prospective.extend(git::untracked_stats(&status.untracked));
+      let stage_risk = risk::assess(&prospective, &cfg.risk);
+
+      let all_files: Vec<String> = modified.into_iter().chain(untracked).collect();
+      if confirm_stage_all(&all_files, fully_shown, &stage_risk) {
         git::add(&all_files)?;
         ui::success("Staged all changes");
       } else {
@@ -44,37 +99,152 @@ pub fn run(edit: bool) -> Result<()> {
     }
   }
 
+  let assessment = risk::assess(&git::diff_stats(true)?, &cfg.risk);
+  if let Ok(stat) = git::diff_stat(true) {
+    println!();
+    println!("{}", stat);
+  }
+  let tests = tests_changed::changes(true, &cfg.tests).unwrap_or_default();
+  match tests.line() {
+    Some(line) if tests.tests.is_empty() => println!("{}", line.yellow()),
+    Some(line) => ui::dim(line),
+    None => {}
+  }
+  ui::info(format!("Risk: {}", assessment.level));
+  if assessment.level != RiskLevel::Low {
+    for factor in &assessment.factors {
+      ui::dim(format!("  {}", factor));
+    }
+  }
+  println!();
+
+  if !confirm_risk(&assessment, opts.allow_high_risk)? {
+    ui::info("Aborted");
+    return Ok(());
+  }
+
   ui::info("Generating commit message...");
 
-  let diff = git::diff(true)?;
+  let mut diff = String::new();
+  let mut structure = Vec::new();
+  let mut files = git::staged_files().unwrap_or_default();
+  if opts.amend {
+    diff = git::diff_head()?;
+    structure = git::commit_structure("HEAD").unwrap_or_default();
+    files.extend(git::commit_files("HEAD").unwrap_or_default());
+  }
+  if !status.staged.is_empty() {
+    diff.push_str(&git::diff(true)?);
+    structure.extend(git::staged_structure().unwrap_or_default());
+  }
+  let diff = diff_filter::filter_diff(&diff, &diff_filter::ignore_patterns());
   if diff.is_empty() {
     ui::error("Could not get diff of staged changes");
     return Ok(());
   }
 
-  let mut message = match llm::generate_commit_message(&diff) {
-    Ok(msg) => msg,
-    Err(e) => {
+  let scope = llm::infer_scope(&files);
+  let issue = forge::issue_context(opts.issue.as_deref(), opts.forge);
+  let tests = tests.prompt_hint();
+  let examples = style_examples(&cfg, opts.refresh_style);
+  let hints = llm::CommitHints {
+    scope: scope.as_deref(),
+    issue: issue.as_deref(),
+    tests: tests.as_deref(),
+    examples: examples.as_deref(),
+  };
+
+  let (generated, usage) = llm::measuring_usage(|| {
+    if opts.count > 1 {
+      llm::generate_commit_candidates(&diff, &structure, hints, opts.count, !opts.short)
+    } else if opts.short {
+      ui::with_streamed_output(|| llm::generate_commit_message(&diff, &structure, hints))
+        .map(|m| vec![m])
+    } else {
+      ui::with_streamed_output(|| llm::generate_commit_message_full(&diff, &structure, hints))
+        .map(|m| vec![m])
+    }
+  });
+  if opts.verbose {
+    ui::dim(format!("Model: {}", usage));
+  }
+
+  // Renames and deletions can still be described without the model
+  let fallback = llm::is_structural_only(&diff, &structure)
+    .then(|| llm::structural_message(&structure))
+    .flatten();
+
+  let candidates = match (generated, fallback) {
+    (Ok(candidates), _) if !candidates.is_empty() => candidates,
+    (_, Some(message)) => {
+      ui::warn("Couldn't generate a message; using one derived from the file changes");
+      vec![message]
+    }
+    (Ok(_), None) => {
+      ui::error("Failed to generate message: the model returned nothing");
+      return Ok(());
+    }
+    (Err(e), None) => {
       ui::error(format!("Failed to generate message: {}", e));
       ui::dim("Make sure you have run 'alfred setup'");
       return Ok(());
     }
   };
 
-  println!();
-  ui::heading("Generated commit message:");
-  ui::separator();
-  println!("{}", message);
-  ui::separator();
-  println!();
+  // The model doesn't always follow the style instructions
+  let mut shortened = false;
+  let candidates: Vec<String> = candidates
+    .iter()
+    .map(|c| {
+      let styled = llm::apply_commit_style(c, cfg.commit_style);
+      let (message, cut) = llm::limit_subject(&styled, cfg.commit_rules.max_subject_length);
+      shortened |= cut;
+      message
+    })
+    .collect();
+  if shortened {
+    warn_shortened(cfg.commit_rules.max_subject_length);
+  }
 
-  if edit {
-    if let Some(edited) = ui::prompt("Edit message (or press Enter to keep):") {
-      message = edited;
+  let message = if candidates.len() == 1 {
+    let mut message = candidates.into_iter().next().unwrap_or_default();
+    println!();
+    ui::heading("Generated commit message:");
+    ui::separator();
+    println!("{}", message);
+    ui::separator();
+    println!();
+
+    if opts.edit {
+      if let Some(edited) = ui::prompt("Edit message (or press Enter to keep):") {
+        message = edited;
+      }
+    }
+    message
+  } else {
+    match choose(candidates, opts.edit) {
+      Some(message) => message,
+      None => {
+        ui::info("Aborted");
+        return Ok(());
+      }
     }
+  };
+
+  let message = with_ticket(message, ticket.as_deref(), &mut trailers, &cfg.commit);
+  let message = with_trailers(&message, &trailers);
+  for trailer in &trailers {
+    ui::dim(format!("  {}", trailer));
   }
 
-  if ui::confirm("Commit with this message?", true) {
+  if opts.amend {
+    if ui::confirm("Amend the last commit with this message?", true) {
+      git::amend(&message)?;
+      ui::success("Amended!");
+    } else {
+      ui::info("Aborted");
+    }
+  } else if ui::confirm("Commit with this message?", true) {
     git::commit(&message)?;
     ui::success("Committed!");
   } else {
@@ -83,3 +253,504 @@ pub fn run(edit: bool) -> Result<()> {
 
   Ok(())
 }
+
+/// Ask before staging every change, defaulting to No when that would make a high-risk commit
+fn confirm_stage_all(files: &[String], fully_shown: bool, risk: &RiskAssessment) -> bool {
+  if risk.level == RiskLevel::High {
+    ui::warn(format!(
+      "Staging everything would be a {} risk commit",
+      risk.level
+    ));
+    for factor in &risk.factors {
+      ui::dim(format!("  {}", factor));
+    }
+  }
+  ui::confirm_bulk(
+    "Stage all changes?",
+    files,
+    fully_shown,
+    risk.level != RiskLevel::High,
+  )
+}
+
+/// The extra question a high-risk commit needs; anything less risky goes ahead unasked.
+/// Without anyone to ask it's an error, so scripts see the commit didn't happen
+fn confirm_risk(assessment: &RiskAssessment, allow_high_risk: bool) -> Result<bool> {
+  if assessment.level != RiskLevel::High {
+    return Ok(true);
+  }
+  if allow_high_risk {
+    ui::dim("Committing a high-risk change (--allow-high-risk)");
+    return Ok(true);
+  }
+  if ui::non_interactive() {
+    bail!(
+      "Not committing a high-risk change without confirmation; pass --allow-high-risk to commit it"
+    );
+  }
+  Ok(ui::confirm(
+    "This is a high-risk change. Commit it anyway?",
+    false,
+  ))
+}
+
+/// Recent subjects to imitate with the `repo` commit style. `None` with other styles, or
+/// when there's no history to learn from
+fn style_examples(cfg: &config::Config, refresh: bool) -> Option<Vec<String>> {
+  if cfg.commit_style != config::CommitStyle::Repo {
+    return None;
+  }
+  let examples = commit_examples::examples(refresh);
+  (!examples.is_empty()).then_some(examples)
+}
+
+/// Whether a message file holds anything besides blank lines and git's comments
+fn has_message(content: &str) -> bool {
+  content
+    .lines()
+    .any(|l| !l.trim().is_empty() && !l.starts_with('#'))
+}
+
+/// Generate a message for staged changes straight into git's message file, without prompts.
+///
+/// Run by the prepare-commit-msg hook. Leaves the file alone when it already has a message
+pub fn prepare_message(file: &str, opts: &CommitOptions) -> Result<()> {
+  let existing = fs::read_to_string(file).unwrap_or_default();
+  if has_message(&existing) {
+    return Ok(());
+  }
+
+  let diff = git::diff_filtered(true, &diff_filter::ignore_patterns())?;
+  if diff.trim().is_empty() {
+    return Ok(());
+  }
+  let structure = git::staged_structure().unwrap_or_default();
+  let files = git::staged_files().unwrap_or_default();
+
+  let cfg = config::current();
+  let scope = llm::infer_scope(&files);
+  let issue = forge::issue_context(opts.issue.as_deref(), opts.forge);
+  let tests = tests_changed::changes(true, &cfg.tests)
+    .unwrap_or_default()
+    .prompt_hint();
+  let examples = style_examples(&cfg, opts.refresh_style);
+  let hints = llm::CommitHints {
+    scope: scope.as_deref(),
+    issue: issue.as_deref(),
+    tests: tests.as_deref(),
+    examples: examples.as_deref(),
+  };
+
+  ui::info("alfred: generating commit message...");
+  let message = match llm::generate_commit_message_full(&diff, &structure, hints) {
+    Ok(message) if !message.trim().is_empty() => message,
+    result => {
+      let fallback = llm::is_structural_only(&diff, &structure)
+        .then(|| llm::structural_message(&structure))
+        .flatten();
+      match (fallback, result) {
+        (Some(message), _) => message,
+        (None, Err(e)) => bail!("Failed to generate message: {}", e),
+        (None, Ok(_)) => bail!("Failed to generate message: the model returned nothing"),
+      }
+    }
+  };
+
+  let message = llm::apply_commit_style(&message, cfg.commit_style);
+  let (message, shortened) = llm::limit_subject(&message, cfg.commit_rules.max_subject_length);
+  if shortened {
+    warn_shortened(cfg.commit_rules.max_subject_length);
+  }
+  let mut trailers = trailers(opts, &cfg.commit)?;
+  let ticket = git::current_branch().and_then(|b| ticket(&b, &cfg.commit));
+  let message = with_ticket(message, ticket.as_deref(), &mut trailers, &cfg.commit);
+  let message = with_trailers(&message, &trailers);
+
+  // Keep git's comment block below the message for the editor
+  fs::write(file, format!("{}\n{}", message, existing))
+    .with_context(|| format!("Failed to write {}", file))?;
+  Ok(())
+}
+
+/// `Signed-off-by:` and `Co-authored-by:` lines from the flags and config
+fn trailers(opts: &CommitOptions, cfg: &config::CommitConfig) -> Result<Vec<String>> {
+  let mut trailers = Vec::new();
+  if opts.signoff || cfg.signoff {
+    trailers.push(format!("Signed-off-by: {}", git::user_identity()?));
+  }
+
+  for author in cfg.co_authors.iter().chain(&opts.co_authors) {
+    let author = author.trim();
+    if !(author.contains(" <") && author.ends_with('>')) {
+      bail!(
+        "Co-author must look like \"Name <email>\", got \"{}\"",
+        author
+      );
+    }
+    let trailer = format!("Co-authored-by: {}", author);
+    if !trailers.contains(&trailer) {
+      trailers.push(trailer);
+    }
+  }
+  Ok(trailers)
+}
+
+/// Ticket ID from the branch name, e.g. `PROJ-123` in `feature/PROJ-123-add-login`
+fn ticket(branch: &str, cfg: &config::CommitConfig) -> Option<String> {
+  if cfg.ticket_placement == config::TicketPlacement::Off {
+    return None;
+  }
+  match Regex::new(&cfg.ticket_pattern) {
+    Ok(re) => re.find(branch).map(|m| m.as_str().to_string()),
+    Err(e) => {
+      ui::warn(format!("Ignoring invalid commit.ticket_pattern: {}", e));
+      None
+    }
+  }
+}
+
+fn warn_shortened(max: usize) {
+  ui::warn(format!(
+    "The model's subject was over {} characters; shortened it (commit_rules.max_subject_length)",
+    max
+  ));
+}
+
+/// Reference the branch's ticket in the subject or a `Refs:` trailer, unless the message already does
+fn with_ticket(
+  message: String,
+  ticket: Option<&str>,
+  trailers: &mut Vec<String>,
+  cfg: &config::CommitConfig,
+) -> String {
+  match ticket {
+    Some(id) if !message.contains(id) => match cfg.ticket_placement {
+      config::TicketPlacement::Subject => with_ticket_in_subject(&message, id),
+      _ => {
+        trailers.push(format!("Refs: {}", id));
+        message
+      }
+    },
+    _ => message,
+  }
+}
+
+fn with_ticket_in_subject(message: &str, ticket: &str) -> String {
+  match message.split_once('\n') {
+    Some((subject, rest)) => format!("{} ({})\n{}", subject.trim_end(), ticket, rest),
+    None => format!("{} ({})", message.trim_end(), ticket),
+  }
+}
+
+/// Append trailers after a blank line, skipping any the message already has
+fn with_trailers(message: &str, trailers: &[String]) -> String {
+  let message = message.trim_end();
+  let missing: Vec<&str> = trailers
+    .iter()
+    .filter(|t| !message.lines().any(|line| line.trim() == t.as_str()))
+    .map(String::as_str)
+    .collect();
+  if missing.is_empty() {
+    return message.to_string();
+  }
+  format!("{}\n\n{}", message, missing.join("\n"))
+}
+
+/// Let the user pick one of several candidates, or write their own
+fn choose(candidates: Vec<String>, edit: bool) -> Option<String> {
+  println!();
+  ui::heading(format!("Generated {} commit messages:", candidates.len()));
+  for (i, candidate) in candidates.iter().enumerate() {
+    ui::separator();
+    println!("{} {}", format!("{}.", i + 1).cyan(), candidate);
+  }
+  ui::separator();
+  println!();
+
+  const WRITE_OWN: &str = "Write my own message";
+  let mut options: Vec<String> = candidates
+    .iter()
+    .enumerate()
+    .map(|(i, c)| format!("{}. {}", i + 1, c.lines().next().unwrap_or_default()))
+    .collect();
+  options.push(WRITE_OWN.to_string());
+
+  let choice = ui::select("Which message?", &options)?;
+  let index = options.iter().position(|o| *o == choice)?;
+
+  let Some(chosen) = candidates.into_iter().nth(index) else {
+    return ui::prompt("Commit message:");
+  };
+
+  if edit {
+    if let Some(edited) = ui::prompt("Edit message (or press Enter to keep):") {
+      return Some(edited);
+    }
+  }
+  Some(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ui::script::Answer;
+
+  fn options() -> CommitOptions {
+    CommitOptions {
+      edit: false,
+      short: false,
+      count: 1,
+      issue: None,
+      forge: false,
+      amend: false,
+      signoff: false,
+      co_authors: Vec::new(),
+      refresh_style: false,
+      verbose: false,
+      allow_high_risk: false,
+    }
+  }
+
+  #[test]
+  fn comments_and_blank_lines_are_not_a_message() {
+    assert!(!has_message(""));
+    assert!(!has_message("\n# Please enter the commit message\n#\n"));
+    assert!(has_message(
+      "fix: typo\n# Please enter the commit message\n"
+    ));
+    assert!(has_message("\n  wip\n"));
+  }
+
+  #[test]
+  fn prepare_message_leaves_an_existing_message_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("COMMIT_EDITMSG");
+    let content = "feat: keep me\n\n# Please enter the commit message\n";
+    fs::write(&file, content).unwrap();
+
+    prepare_message(file.to_str().unwrap(), &options()).unwrap();
+
+    assert_eq!(fs::read_to_string(&file).unwrap(), content);
+  }
+
+  fn placement(placement: config::TicketPlacement) -> config::CommitConfig {
+    config::CommitConfig {
+      ticket_placement: placement,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn tickets_come_from_the_branch_name() {
+    let cfg = placement(config::TicketPlacement::Trailer);
+    assert_eq!(
+      ticket("feature/PROJ-123-add-login", &cfg).as_deref(),
+      Some("PROJ-123")
+    );
+    assert_eq!(ticket("feature/add-login", &cfg), None);
+    assert_eq!(ticket("main", &cfg), None);
+
+    let off = placement(config::TicketPlacement::Off);
+    assert_eq!(ticket("feature/PROJ-123-add-login", &off), None);
+  }
+
+  #[test]
+  fn tickets_go_in_a_trailer_by_default() {
+    let cfg = placement(config::TicketPlacement::Trailer);
+    let mut trailers = Vec::new();
+    let message = with_ticket(
+      "feat: add login".to_string(),
+      Some("PROJ-123"),
+      &mut trailers,
+      &cfg,
+    );
+    assert_eq!(message, "feat: add login");
+    assert_eq!(trailers, ["Refs: PROJ-123"]);
+  }
+
+  #[test]
+  fn tickets_can_go_in_the_subject() {
+    let cfg = placement(config::TicketPlacement::Subject);
+    let mut trailers = Vec::new();
+    let message = with_ticket(
+      "feat: add login \n\nBody".to_string(),
+      Some("PROJ-123"),
+      &mut trailers,
+      &cfg,
+    );
+    assert_eq!(message, "feat: add login (PROJ-123)\n\nBody");
+    let message = with_ticket(
+      "feat: add login".to_string(),
+      Some("PROJ-123"),
+      &mut trailers,
+      &cfg,
+    );
+    assert_eq!(message, "feat: add login (PROJ-123)");
+    assert!(trailers.is_empty());
+  }
+
+  #[test]
+  fn tickets_already_mentioned_are_not_repeated() {
+    let cfg = placement(config::TicketPlacement::Subject);
+    let mut trailers = Vec::new();
+    let message = "fix(PROJ-123): handle empty passwords".to_string();
+    assert_eq!(
+      with_ticket(message.clone(), Some("PROJ-123"), &mut trailers, &cfg),
+      message
+    );
+    assert_eq!(
+      with_ticket(message.clone(), None, &mut trailers, &cfg),
+      message
+    );
+    assert!(trailers.is_empty());
+  }
+
+  #[test]
+  fn trailers_follow_a_blank_line_once() {
+    let trailers = [
+      "Signed-off-by: A <a@example.com>".to_string(),
+      "Co-authored-by: B <b@example.com>".to_string(),
+    ];
+    assert_eq!(
+      with_trailers("feat: add login\n\n", &trailers),
+      "feat: add login\n\nSigned-off-by: A <a@example.com>\nCo-authored-by: B <b@example.com>"
+    );
+
+    let signed = "feat: add login\n\nSigned-off-by: A <a@example.com>";
+    assert_eq!(
+      with_trailers(signed, &trailers),
+      format!("{}\n\nCo-authored-by: B <b@example.com>", signed)
+    );
+    assert_eq!(with_trailers(signed, &trailers[..1]), signed);
+    assert_eq!(with_trailers("feat: add login\n", &[]), "feat: add login");
+  }
+
+  #[test]
+  fn co_authors_come_from_flags_and_config_once() {
+    let cfg = config::CommitConfig {
+      co_authors: vec!["B <b@example.com>".to_string()],
+      ..Default::default()
+    };
+    let opts = CommitOptions {
+      co_authors: vec![
+        " C <c@example.com> ".to_string(),
+        "B <b@example.com>".to_string(),
+      ],
+      ..options()
+    };
+    assert_eq!(
+      trailers(&opts, &cfg).unwrap(),
+      [
+        "Co-authored-by: B <b@example.com>",
+        "Co-authored-by: C <c@example.com>"
+      ]
+    );
+  }
+
+  #[test]
+  fn co_authors_need_a_name_and_email() {
+    for author in ["b@example.com", "B", "B <b@example.com"] {
+      let opts = CommitOptions {
+        co_authors: vec![author.to_string()],
+        ..options()
+      };
+      let err = trailers(&opts, &config::CommitConfig::default())
+        .unwrap_err()
+        .to_string();
+      assert!(err.contains(author), "{}", err);
+    }
+  }
+
+  fn risk(level: RiskLevel) -> RiskAssessment {
+    RiskAssessment {
+      score: 0,
+      level,
+      factors: vec!["touches .github/workflows/ci.yml (+3)".to_string()],
+    }
+  }
+
+  fn files(count: usize) -> Vec<String> {
+    (1..=count).map(|i| format!("src/file{}.rs", i)).collect()
+  }
+
+  const RISK_QUESTION: &str = "This is a high-risk change. Commit it anyway?";
+
+  #[test]
+  fn only_high_risk_commits_get_the_extra_question() {
+    for level in [RiskLevel::Low, RiskLevel::Medium] {
+      let (allowed, asked) =
+        ui::script::run(true, vec![], || confirm_risk(&risk(level), false).unwrap());
+      assert!(allowed);
+      assert!(asked.is_empty(), "{:?}", asked);
+    }
+
+    for (answer, allowed) in [(Answer::Yes, true), (Answer::No, false)] {
+      let (result, asked) = ui::script::run(true, vec![answer], || {
+        confirm_risk(&risk(RiskLevel::High), false).unwrap()
+      });
+      assert_eq!(result, allowed);
+      assert_eq!(asked, [RISK_QUESTION]);
+    }
+  }
+
+  #[test]
+  fn high_risk_commits_fail_without_a_terminal() {
+    let (result, asked) = ui::script::run(false, vec![], || {
+      confirm_risk(&risk(RiskLevel::High), false)
+    });
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("--allow-high-risk"), "{}", err);
+    assert!(asked.is_empty(), "{:?}", asked);
+
+    let (result, _) = ui::script::run(false, vec![], || confirm_risk(&risk(RiskLevel::Low), false));
+    assert!(result.unwrap());
+  }
+
+  #[test]
+  fn allow_high_risk_skips_the_question() {
+    for interactive in [true, false] {
+      let (allowed, asked) = ui::script::run(interactive, vec![], || {
+        confirm_risk(&risk(RiskLevel::High), true).unwrap()
+      });
+      assert!(allowed);
+      assert!(asked.is_empty(), "{:?}", asked);
+    }
+  }
+
+  #[test]
+  fn stage_all_defaults_to_no_when_it_would_be_high_risk() {
+    for (level, staged) in [
+      (RiskLevel::Low, true),
+      (RiskLevel::Medium, true),
+      (RiskLevel::High, false),
+    ] {
+      let (result, asked) = ui::script::run(false, vec![], || {
+        confirm_stage_all(&files(3), true, &risk(level))
+      });
+      assert_eq!(result, staged, "{:?}", level);
+      assert_eq!(asked, ["Stage all changes?"]);
+    }
+
+    // A terminal user can still stage a high-risk change
+    let (result, _) = ui::script::run(true, vec![Answer::Yes], || {
+      confirm_stage_all(&files(3), true, &risk(RiskLevel::High))
+    });
+    assert!(result);
+  }
+
+  #[test]
+  fn stage_all_writes_a_long_collapsed_file_list_for_review() {
+    let many = files(ui::BULK_CONFIRM_THRESHOLD + 1);
+    let written = std::env::temp_dir().join(format!("alfred-list-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&written);
+
+    let (result, asked) = ui::script::run(true, vec![Answer::Yes], || {
+      confirm_stage_all(&many, false, &risk(RiskLevel::Low))
+    });
+    assert!(result);
+    assert_eq!(asked, ["Stage all changes?"]);
+    let content = std::fs::read_to_string(&written).unwrap();
+    let _ = std::fs::remove_file(&written);
+    assert_eq!(content.lines().collect::<Vec<_>>(), many);
+  }
+}
@@ -1,39 +1,329 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
+use std::fs;
+use std::io::IsTerminal;
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::{config, ui};
+use crate::{config, daemon_client, llm, prompt_template, ui};
 
-pub fn run(model_path: Option<String>, reset: bool) -> Result<()> {
-  if reset {
+/// Smallest context window alfred accepts; the prompts alone need most of it
+const MIN_CONTEXT_SIZE: u32 = 512;
+
+/// `--threads`: a thread count, or `auto` for half the logical CPUs
+#[derive(Debug, Clone, Copy)]
+pub enum ThreadsArg {
+  Auto,
+  Count(u32),
+}
+
+impl FromStr for ThreadsArg {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    if s.eq_ignore_ascii_case("auto") {
+      return Ok(ThreadsArg::Auto);
+    }
+    match s.parse::<u32>() {
+      Ok(0) => Err("must be at least 1".to_string()),
+      Ok(n) => Ok(ThreadsArg::Count(n)),
+      Err(_) => Err("expected a number of threads or 'auto'".to_string()),
+    }
+  }
+}
+
+/// Half the logical CPUs, leaving the rest for the editor and everything else running
+fn auto_threads() -> u32 {
+  std::thread::available_parallelism()
+    .map(|n| (n.get() as u32 / 2).max(1))
+    .unwrap_or(1)
+}
+
+/// Have a running daemon load the newly configured model
+fn reload_daemon_model() {
+  let Ok(mut client) = daemon_client::connect() else {
+    return;
+  };
+  ui::info("Reloading the model in the daemon...");
+  match client.reload_model(None) {
+    Ok(()) => ui::success("Daemon is using the new model"),
+    Err(e) => {
+      ui::warn(format!("The daemon couldn't load the new model: {}", e));
+      ui::dim(
+        "Restart it with 'alfred daemon stop' and 'alfred daemon start' once the model is in place",
+      );
+    }
+  }
+}
+
+/// `--context-size`: a token count, or `auto` to size it from free memory
+#[derive(Debug, Clone, Copy)]
+pub enum ContextSizeArg {
+  Auto,
+  Tokens(u32),
+}
+
+impl FromStr for ContextSizeArg {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    if s.eq_ignore_ascii_case("auto") {
+      return Ok(ContextSizeArg::Auto);
+    }
+    match s.parse::<u32>() {
+      Ok(n) if n >= MIN_CONTEXT_SIZE => Ok(ContextSizeArg::Tokens(n)),
+      Ok(_) => Err(format!("must be at least {}", MIN_CONTEXT_SIZE)),
+      Err(_) => Err("expected a number of tokens or 'auto'".to_string()),
+    }
+  }
+}
+
+/// `--task`: `<task>=<alias>`, the model alias a task generates with
+#[derive(Debug, Clone)]
+pub struct TaskModelArg {
+  pub task: config::Task,
+  pub alias: String,
+}
+
+impl FromStr for TaskModelArg {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    let Some((task, alias)) = s.split_once('=') else {
+      return Err("expected <task>=<model alias>, e.g. resolve=coder".to_string());
+    };
+    let task = config::Task::parse(task.trim()).ok_or_else(|| {
+      let names: Vec<&str> = config::Task::ALL.iter().map(|t| t.name()).collect();
+      format!(
+        "unknown task '{}'. Tasks: {}",
+        task.trim(),
+        names.join(", ")
+      )
+    })?;
+    let alias = alias.trim();
+    if alias.is_empty() {
+      return Err("the model alias is empty".to_string());
+    }
+    Ok(TaskModelArg {
+      task,
+      alias: alias.to_string(),
+    })
+  }
+}
+
+pub struct ConfigOptions {
+  pub model: Option<String>,
+  pub gpu_layers: Option<u32>,
+  pub context_size: Option<ContextSizeArg>,
+  pub threads: Option<ThreadsArg>,
+  pub temperature: Option<f64>,
+  pub top_k: Option<u32>,
+  pub top_p: Option<f64>,
+  pub seed: Option<u32>,
+  pub repeat_penalty: Option<f64>,
+  /// Model aliases for tasks
+  pub tasks: Vec<TaskModelArg>,
+  pub reset: bool,
+  /// Print the resolved configuration as JSON instead
+  pub json: bool,
+  /// Open this prompt template in `$EDITOR`
+  pub edit_prompt: Option<String>,
+}
+
+impl ConfigOptions {
+  fn sets_anything(&self) -> bool {
+    self.model.is_some()
+      || self.gpu_layers.is_some()
+      || self.context_size.is_some()
+      || self.threads.is_some()
+      || self.temperature.is_some()
+      || self.top_k.is_some()
+      || self.top_p.is_some()
+      || self.seed.is_some()
+      || self.repeat_penalty.is_some()
+      || !self.tasks.is_empty()
+  }
+}
+
+pub fn run(opts: ConfigOptions) -> Result<()> {
+  if let Some(name) = opts.edit_prompt {
+    return edit_prompt(&name);
+  }
+
+  if opts.reset {
     config::save(&config::Config::default())?;
     ui::success("Configuration reset to defaults");
     return Ok(());
   }
 
-  if let Some(path) = model_path {
-    let mut cfg = config::load()?;
-    cfg.model_path = Some(path.clone());
+  if opts.sets_anything() {
+    let mut cfg = config::load_global()?;
+
+    if let Some(ref path) = opts.model {
+      cfg.model_path = Some(path.clone());
+      ui::success(format!("Model path set to: {}", path));
+    }
+
+    if let Some(layers) = opts.gpu_layers {
+      cfg.n_gpu_layers = Some(layers);
+      ui::success(format!("GPU layers set to: {}", layers));
+    }
+
+    match opts.context_size {
+      Some(ContextSizeArg::Tokens(n)) => {
+        cfg.context_size = n;
+        ui::success(format!("Context size set to: {}", n));
+      }
+      Some(ContextSizeArg::Auto) => {
+        cfg.context_size = llm::detect_safe_context_size();
+        ui::success(format!(
+          "Context size set to: {} (picked from available memory)",
+          cfg.context_size
+        ));
+      }
+      None => {}
+    }
+
+    if let Some(threads) = opts.threads {
+      let n = match threads {
+        ThreadsArg::Count(n) => n,
+        ThreadsArg::Auto => auto_threads(),
+      };
+      cfg.n_threads = Some(n);
+      ui::success(format!("Inference threads set to: {}", n));
+    }
+
+    if let Some(temperature) = opts.temperature {
+      if !(0.0..=2.0).contains(&temperature) {
+        bail!("--temperature must be between 0 and 2");
+      }
+      cfg.sampling.temperature = temperature;
+      ui::success(format!("Temperature set to: {}", temperature));
+    }
+
+    if let Some(top_k) = opts.top_k {
+      cfg.sampling.top_k = top_k;
+      ui::success(format!("Top-k set to: {}", top_k));
+    }
+
+    if let Some(top_p) = opts.top_p {
+      if !(top_p > 0.0 && top_p <= 1.0) {
+        bail!("--top-p must be above 0 and at most 1");
+      }
+      cfg.sampling.top_p = top_p;
+      ui::success(format!("Top-p set to: {}", top_p));
+    }
+
+    if let Some(seed) = opts.seed {
+      cfg.sampling.seed = seed;
+      ui::success(format!("Seed set to: {}", seed));
+    }
+
+    if let Some(penalty) = opts.repeat_penalty {
+      if penalty <= 0.0 {
+        bail!("--repeat-penalty must be above 0");
+      }
+      cfg.sampling.repeat_penalty = penalty;
+      ui::success(format!("Repeat penalty set to: {}", penalty));
+    }
+
+    for arg in &opts.tasks {
+      // Catch a typo now rather than on the task's next run
+      cfg.model_spec(&arg.alias)?;
+      cfg.tasks.set(arg.task, &arg.alias);
+      ui::success(format!(
+        "Task {} set to model: {}",
+        arg.task.name(),
+        arg.alias
+      ));
+    }
+
     config::save(&cfg)?;
-    ui::success(format!("Model path set to: {}", path));
+
+    // A running daemon would otherwise keep the old model until it restarts
+    if opts.model.is_some() && cfg.backend.is_local() {
+      reload_daemon_model();
+    }
     return Ok(());
   }
 
+  if opts.json {
+    return print_json();
+  }
+
   // Show current config
-  let cfg = config::load()?;
+  let cfg = config::load_global()?;
 
   ui::heading("Alfred Configuration");
   println!(
     "{}",
     format!("Config file: {}", config::config_path().display()).dimmed()
   );
+  if let Some(repo) = std::env::current_dir()
+    .ok()
+    .and_then(|dir| config::repo_config_path(&dir))
+  {
+    println!(
+      "{}",
+      format!("Repository overrides: {}", repo.display()).dimmed()
+    );
+  }
+  let env = config::env_overrides();
+  if !env.is_empty() {
+    println!(
+      "{}",
+      format!(
+        "Environment overrides: {} (not shown below)",
+        env.join(", ")
+      )
+      .dimmed()
+    );
+  }
   println!();
 
+  ui::info(format!("Backend: {}", cfg.backend.name()));
+  let s = &cfg.sampling;
+  ui::info(format!(
+    "Sampling: temperature {}, top-k {}, top-p {}, seed {}, repeat penalty {}",
+    s.temperature, s.top_k, s.top_p, s.seed, s.repeat_penalty
+  ));
+  match cfg.backend {
+    config::Backend::Ollama => {
+      ui::info(format!(
+        "Ollama: {} at {}",
+        cfg.ollama_model.as_deref().unwrap_or("(no model set)"),
+        cfg.ollama_url
+      ));
+      print_options();
+      return Ok(());
+    }
+    config::Backend::Remote => {
+      ui::info(format!(
+        "API: {}",
+        cfg.remote.api_base.as_deref().unwrap_or("(not set)")
+      ));
+      ui::info(format!(
+        "Model: {}",
+        cfg.remote.model.as_deref().unwrap_or("(not set)")
+      ));
+      match cfg.remote.key() {
+        Some(key) => ui::info(format!("API key: {}", mask(&key))),
+        None => ui::warn(format!(
+          "API key: not set (use {} or remote.api_key)",
+          config::API_KEY_ENV
+        )),
+      }
+      print_options();
+      return Ok(());
+    }
+    config::Backend::Local => {}
+  }
+
   // Check model
   let model_path = cfg
-    .model_path
-    .map(|p| Path::new(&p).to_path_buf())
-    .unwrap_or_else(config::default_model_path);
+    .model_spec(config::DEFAULT_MODEL)
+    .map(|spec| spec.path)
+    .unwrap_or_else(|_| config::default_model_path());
 
   if model_path.exists() {
     let size_mb = std::fs::metadata(&model_path)
@@ -44,22 +334,204 @@ pub fn run(model_path: Option<String>, reset: bool) -> Result<()> {
       model_path.display(),
       size_mb
     ));
+  } else if config::part_path(&model_path).exists() {
+    ui::warn(format!(
+      "Model download incomplete: {}",
+      model_path.display()
+    ));
+    ui::dim("Run 'alfred setup --resume' to finish it");
   } else {
     ui::warn(format!("Model not found: {}", model_path.display()));
     ui::dim("Run 'alfred setup' to download a model");
   }
 
+  match cfg.n_gpu_layers {
+    Some(0) => ui::info("GPU offload: disabled (CPU only)"),
+    Some(n) => ui::info(format!("GPU offload: {} layers", n)),
+    None => ui::info("GPU offload: auto (all layers when CUDA/Metal is available)"),
+  }
+  ui::info(format!("Context size: {} tokens", cfg.context_size));
+  print_model_profiles(&cfg);
+  match cfg.n_threads {
+    Some(n) => ui::info(format!("Threads: {}", n)),
+    None => ui::info("Threads: llama.cpp default"),
+  }
+
   // Check library (not needed for Rust version with llama-cpp-2)
   ui::info("Using llama-cpp-2 Rust bindings (no external library required)");
 
+  print_options();
+  Ok(())
+}
+
+/// Each alias in `models` with the tasks using it, flagging files that aren't there
+fn print_model_profiles(cfg: &config::Config) {
+  let tasks_using = |alias: &str| -> Vec<&str> {
+    config::Task::ALL
+      .iter()
+      .filter(|task| cfg.tasks.get(**task) == alias)
+      .map(|task| task.name())
+      .collect()
+  };
+
+  if !cfg.models.is_empty() {
+    ui::info("Model profiles:");
+  }
+  for (alias, profile) in &cfg.models {
+    let mut line = format!("{}: {}", alias, profile.path);
+    if let Some(n) = profile.context_size {
+      line.push_str(&format!(", {} tokens", n));
+    }
+    let tasks = tasks_using(alias);
+    if !tasks.is_empty() {
+      line.push_str(&format!(" ({})", tasks.join(", ")));
+    }
+    if Path::new(&profile.path).exists() {
+      ui::list_item_colored("✓", "green", line);
+    } else {
+      ui::list_item_colored("✗", "red", format!("{} {}", line, "(file not found)".red()));
+    }
+  }
+
+  for task in config::Task::ALL {
+    let alias = cfg.tasks.get(task);
+    if alias != config::DEFAULT_MODEL && !cfg.models.contains_key(alias) {
+      ui::warn(format!(
+        "tasks.{} uses '{}', which isn't in models",
+        task.name(),
+        alias
+      ));
+    }
+  }
+}
+
+/// The configuration in effect here, repository overrides included, for scripts and CI
+fn print_json() -> Result<()> {
+  let cfg = config::load()?;
+  let repo_config = std::env::current_dir()
+    .ok()
+    .and_then(|dir| config::repo_config_path(&dir));
+  let model_path = cfg
+    .model_spec(config::DEFAULT_MODEL)
+    .map(|spec| spec.path)
+    .unwrap_or_else(|_| config::default_model_path());
+  let model_size = std::fs::metadata(&model_path).ok().map(|m| m.len());
+
+  let out = serde_json::json!({
+    "config_file": config::config_path(),
+    "repo_config": repo_config,
+    "backend": cfg.backend.name(),
+    "model": {
+      "path": model_path,
+      "exists": model_size.is_some(),
+      "size_bytes": model_size,
+      "download_incomplete": model_size.is_none() && config::part_path(&model_path).exists(),
+    },
+    "ollama": {
+      "url": cfg.ollama_url,
+      "model": cfg.ollama_model,
+    },
+    "remote": {
+      "api_base": cfg.remote.api_base,
+      "model": cfg.remote.model,
+      "api_key_set": cfg.remote.key().is_some(),
+    },
+    "context_size": cfg.context_size,
+    "models": cfg.models.iter().map(|(alias, profile)| {
+      (alias.clone(), serde_json::json!({
+        "path": profile.path,
+        "context_size": profile.context_size.unwrap_or(cfg.context_size),
+        "exists": Path::new(&profile.path).exists(),
+      }))
+    }).collect::<serde_json::Map<_, _>>(),
+    "tasks": config::Task::ALL
+      .iter()
+      .map(|task| (task.name().to_string(), cfg.tasks.get(*task).into()))
+      .collect::<serde_json::Map<_, _>>(),
+    "max_diff_chars": cfg.max_diff_chars.unwrap_or(config::DEFAULT_MAX_DIFF_CHARS),
+    "gpu_layers": cfg.n_gpu_layers,
+    "threads": cfg.n_threads,
+    "sampling": cfg.sampling,
+    "daemon": {
+      "port": cfg.daemon.port,
+      "idle_timeout_minutes": cfg.daemon.idle_timeout_minutes,
+    },
+  });
+
+  println!("{}", serde_json::to_string_pretty(&out)?);
+  Ok(())
+}
+
+/// Open a prompt template in `$EDITOR`, copying the built-in one out first
+fn edit_prompt(name: &str) -> Result<()> {
+  let builtin = prompt_template::builtin(name)?;
+  let path = match prompt_template::override_path(name) {
+    Some(path) => path,
+    None => {
+      let dir = prompt_template::prompts_dir();
+      fs::create_dir_all(&dir)?;
+      let path = dir.join(format!("{}.txt", name));
+      fs::write(&path, format!("{}\n", builtin))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+      ui::info(format!(
+        "Copied the built-in template to {}",
+        path.display()
+      ));
+      path
+    }
+  };
+
+  if !std::io::stdin().is_terminal() {
+    bail!("No terminal to edit in; edit {} directly", path.display());
+  }
+
+  ui::open_in_editor(&path)?;
+
+  let edited = fs::read_to_string(&path)?;
+  let used = prompt_template::placeholders(&edited);
+  let missing: Vec<&str> = prompt_template::placeholders(builtin)
+    .into_iter()
+    .filter(|p| !used.contains(p))
+    .collect();
+  if !missing.is_empty() {
+    ui::warn(format!(
+      "The template no longer uses {}",
+      missing
+        .iter()
+        .map(|p| format!("{{{{{}}}}}", p))
+        .collect::<Vec<_>>()
+        .join(", ")
+    ));
+  }
+
+  ui::success(format!("Saved {}", path.display()));
+  ui::dim("Delete the file to go back to the built-in prompt");
+  Ok(())
+}
+
+/// Show only enough of a secret to tell keys apart
+fn mask(key: &str) -> String {
+  let chars: Vec<char> = key.chars().collect();
+  if chars.len() <= 8 {
+    return "****".to_string();
+  }
+  let head: String = chars[..4].iter().collect();
+  let tail: String = chars[chars.len() - 4..].iter().collect();
+  format!("{}…{}", head, tail)
+}
+
+fn print_options() {
   println!(
     r#"
 {}
   alfred config --model=/path/to/model.gguf
+  alfred config --gpu-layers=99
+  alfred config --context-size=auto
+  alfred config --threads=auto
+  alfred config --temperature=0.2 --seed=12345
+  alfred config --edit-prompt=commit_message
   alfred config --reset
 "#,
     "Options:".bold()
   );
-
-  Ok(())
 }
@@ -3,11 +3,15 @@
 use anyhow::{anyhow, Context, Result};
 use colored::Colorize;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::config;
-use crate::daemon_client;
+use crate::daemon_client::{self, DaemonMetrics, DaemonStatus};
 use crate::ui;
+use crate::{daemon_log, daemon_tls};
 
 pub fn show_help() {
   ui::heading("Daemon Commands");
@@ -27,6 +31,26 @@ pub fn show_help() {
     "alfred daemon status".cyan(),
     "Check daemon status".dimmed()
   );
+  println!(
+    "  {} {}",
+    "alfred daemon run".cyan(),
+    "Run in the foreground with request logs".dimmed()
+  );
+  println!(
+    "  {} {}",
+    "alfred daemon logs".cyan(),
+    "Show or follow the daemon log".dimmed()
+  );
+  println!(
+    "  {} {}",
+    "alfred daemon regen-cert".cyan(),
+    "Replace the TLS certificate".dimmed()
+  );
+  println!(
+    "  {} {}",
+    "alfred daemon rotate-token".cyan(),
+    "Replace the auth token".dimmed()
+  );
   println!(
     "  {} {}",
     "alfred daemon install".cyan(),
@@ -51,7 +75,7 @@ pub fn start() -> Result<()> {
   ui::info("Starting alfred daemon...");
 
   // Find alferd binary - check same directory as alfred first
-  let alferd_path = find_alferd_binary()?;
+  let alferd_path = daemon_client::find_alferd_binary()?;
 
   // Start daemon in background
   let child = Command::new(&alferd_path)
@@ -64,6 +88,38 @@ pub fn start() -> Result<()> {
   Ok(())
 }
 
+/// Run alferd attached to this terminal, replacing this process where possible
+pub fn run_foreground(port: u16, model: Option<String>, log_level: &str) -> Result<i32> {
+  let alferd_path = daemon_client::find_alferd_binary()?;
+
+  let mut cmd = Command::new(&alferd_path);
+  cmd.args(["--foreground", "--port", &port.to_string()]);
+  cmd.args(["--log-level", log_level]);
+  if let Some(ref model) = model {
+    cmd.args(["--model", model]);
+  }
+
+  // exec so Ctrl+C reaches alferd alone and the shell waits for its summary
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::CommandExt;
+    let err = cmd.exec();
+    Err(anyhow!(
+      "Failed to run daemon from {}: {}",
+      alferd_path.display(),
+      err
+    ))
+  }
+
+  #[cfg(not(unix))]
+  {
+    let status = cmd
+      .status()
+      .with_context(|| format!("Failed to run daemon from {}", alferd_path.display()))?;
+    Ok(status.code().unwrap_or(1))
+  }
+}
+
 pub fn stop() -> Result<()> {
   if !daemon_client::is_daemon_running() {
     ui::warn("Daemon is not running");
@@ -86,6 +142,49 @@ pub fn stop() -> Result<()> {
   Ok(())
 }
 
+/// Replace the TLS certificate. Once clients trust the new one they can't reach a daemon
+/// serving the old one, so a running daemon is stopped first and started again after
+pub fn regen_cert() -> Result<()> {
+  let was_running = daemon_client::is_daemon_running();
+  if was_running {
+    stop()?;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while daemon_client::is_daemon_running() {
+      if std::time::Instant::now() > deadline {
+        return Err(anyhow!(
+          "The daemon didn't stop; stop it and run this again"
+        ));
+      }
+      std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+  }
+
+  daemon_tls::generate_cert()?;
+  ui::success(format!(
+    "New certificate: {}",
+    config::daemon_cert_file().display()
+  ));
+  if !config::get_daemon_config().tls {
+    ui::dim("TLS is off; set daemon.tls: true to use it");
+  }
+
+  if was_running {
+    start()?;
+  }
+  Ok(())
+}
+
+/// Store a new auth token. The daemon reads it on every request, so a running one switches
+/// over at once
+pub fn rotate_token() -> Result<()> {
+  config::set_daemon_auth_token(&daemon_tls::generate_token()?)?;
+  ui::success(format!(
+    "New auth token saved to {}",
+    config::config_path().display()
+  ));
+  Ok(())
+}
+
 pub fn status() -> Result<()> {
   let daemon_config = config::get_daemon_config();
 
@@ -99,11 +198,38 @@ pub fn status() -> Result<()> {
     if let Ok(pid) = read_pid_file() {
       println!("  {} {}", "PID:".dimmed(), pid);
     }
+
+    if let Ok(mut client) = daemon_client::connect() {
+      match client.stats().or_else(|_| client.status_details()) {
+        Ok(details) => print_details(&details),
+        Err(e) => ui::dim(format!("  {}", e)),
+      }
+      // Older daemons don't keep metrics
+      if let Ok(metrics) = client.metrics() {
+        print_metrics(&metrics);
+      }
+      if let Ok(layers) = client.gpu_layers() {
+        println!("  {} {}", "GPU layers:".dimmed(), layers);
+      }
+    }
   } else {
     println!("  {} {}", "Status:".dimmed(), "Stopped".red());
   }
 
   println!("  {} {}", "Port:".dimmed(), daemon_config.port);
+  if daemon_config.tls {
+    println!(
+      "  {} {}",
+      "TLS:".dimmed(),
+      config::daemon_cert_file().display()
+    );
+  }
+  if let Some(http_port) = daemon_config.http_port {
+    println!("  {} http://127.0.0.1:{}", "HTTP API:".dimmed(), http_port);
+  }
+  if daemon_config.auth_token.is_some() {
+    println!("  {} token required", "Auth:".dimmed());
+  }
   println!(
     "  {} {}",
     "Idle timeout:".dimmed(),
@@ -129,8 +255,253 @@ pub fn status() -> Result<()> {
   Ok(())
 }
 
+fn print_details(details: &DaemonStatus) {
+  println!(
+    "  {} {} {}",
+    "Model:".dimmed(),
+    details.model,
+    if details.model_loaded {
+      "(loaded)".green().to_string()
+    } else {
+      "(not loaded)".yellow().to_string()
+    }
+  );
+  // Other aliases in `models` load as their tasks ask for them
+  if details.loaded_models.len() > 1 {
+    println!(
+      "  {} {}",
+      "Loaded models:".dimmed(),
+      details.loaded_models.join(", ")
+    );
+  }
+  if let Some(n_ctx) = details.context_size {
+    println!("  {} {} tokens", "Context size:".dimmed(), n_ctx);
+  }
+  println!(
+    "  {} {}",
+    "Uptime:".dimmed(),
+    format_duration(details.uptime_secs)
+  );
+  println!(
+    "  {} {} served, {} in flight",
+    "Requests:".dimmed(),
+    details.requests_served,
+    details.pending
+  );
+
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  println!(
+    "  {} {}",
+    "Last request:".dimmed(),
+    match details.last_request {
+      Some(at) => format!("{} ago", format_duration(now.saturating_sub(at))),
+      None => "none yet".to_string(),
+    }
+  );
+
+  if let Some(bytes) = details.memory_bytes {
+    println!(
+      "  {} {:.0} MB",
+      "Memory:".dimmed(),
+      bytes as f64 / (1024.0 * 1024.0)
+    );
+  }
+
+  if let Some(threads) = details.threads {
+    println!("  {} {}", "Threads:".dimmed(), threads);
+  }
+}
+
+fn print_metrics(metrics: &DaemonMetrics) {
+  println!(
+    "  {} {} ok, {} failed",
+    "Outcomes:".dimmed(),
+    metrics.requests_ok,
+    metrics.requests_err
+  );
+  println!(
+    "  {} {}",
+    "Model load:".dimmed(),
+    format_millis(metrics.model_load_ms)
+  );
+  if metrics.requests_total > 0 {
+    println!(
+      "  {} {}",
+      "Last request took:".dimmed(),
+      format_millis(metrics.last_request_ms)
+    );
+  }
+}
+
+/// "850 ms", "2.4s"
+fn format_millis(ms: u64) -> String {
+  if ms < 1000 {
+    format!("{} ms", ms)
+  } else {
+    format!("{:.1}s", ms as f64 / 1000.0)
+  }
+}
+
+/// "2h 5m", "3m 12s", "40s"
+fn format_duration(secs: u64) -> String {
+  let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+  if h > 0 {
+    format!("{}h {}m", h, m)
+  } else if m > 0 {
+    format!("{}m {}s", m, s)
+  } else {
+    format!("{}s", s)
+  }
+}
+
+/// How often `logs --follow` checks the file for new lines
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+/// Print the last `lines` entries of the daemon log, then keep printing new ones with
+/// `follow`. Without a structured log yet, shows the service's own output instead
+pub fn logs(lines: usize, follow: bool, json: bool) -> Result<()> {
+  let path = config::daemon_log_file();
+  if !path.exists() {
+    if json {
+      return Err(anyhow!(
+        "No structured log at {} yet. The daemon writes it once it has started",
+        path.display()
+      ));
+    }
+    return service_logs(lines, follow);
+  }
+
+  let content = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+  let text = String::from_utf8_lossy(&content);
+  let all: Vec<&str> = text.lines().collect();
+  for line in &all[all.len().saturating_sub(lines)..] {
+    print_log_line(line, json);
+  }
+
+  if follow {
+    follow_log(&path, content.len() as u64, json)?;
+  }
+  Ok(())
+}
+
+/// Print new lines as they're appended, starting over when the log is rotated
+fn follow_log(path: &Path, mut offset: u64, json: bool) -> Result<()> {
+  let mut partial = String::new();
+  loop {
+    std::thread::sleep(FOLLOW_POLL);
+    let len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if len < offset {
+      offset = 0;
+      partial.clear();
+    }
+    if len == offset {
+      continue;
+    }
+
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    offset += bytes.len() as u64;
+
+    partial.push_str(&String::from_utf8_lossy(&bytes));
+    // A line still being written waits for the rest of it
+    while let Some(end) = partial.find('\n') {
+      print_log_line(&partial[..end], json);
+      partial.drain(..=end);
+    }
+  }
+}
+
+/// One log entry as `2026-10-16 13:45:01 INFO  message`, or verbatim with `json`.
+/// Lines that aren't entries, such as output from older daemons, are shown as they are
+/// but left out of `json`
+fn print_log_line(line: &str, json: bool) {
+  let Ok(entry) = serde_json::from_str::<daemon_log::LogEntry>(line) else {
+    if !json && !line.trim().is_empty() {
+      println!("{}", line);
+    }
+    return;
+  };
+  if json {
+    println!("{}", line);
+    return;
+  }
+
+  let level = match entry.level.as_str() {
+    "error" => "ERROR".red(),
+    "warn" => "WARN ".yellow(),
+    "info" => "INFO ".green(),
+    _ => "DEBUG".blue(),
+  };
+  println!(
+    "{} {} {}",
+    daemon_log::format_timestamp(entry.ts).dimmed(),
+    level,
+    entry.message
+  );
+}
+
+/// The output a launchd or systemd service captured, for daemons that haven't written the
+/// structured log
+fn service_logs(lines: usize, follow: bool) -> Result<()> {
+  #[cfg(target_os = "linux")]
+  {
+    if systemd_service_path().exists() {
+      let mut cmd = Command::new("journalctl");
+      cmd.args([
+        "--user",
+        "-u",
+        "alfred",
+        "--no-pager",
+        "-n",
+        &lines.to_string(),
+      ]);
+      if follow {
+        cmd.arg("-f");
+      }
+      cmd.status().with_context(|| "Failed to run journalctl")?;
+      return Ok(());
+    }
+  }
+
+  let files: Vec<PathBuf> = ["alferd.out.log", "alferd.error.log"]
+    .iter()
+    .map(|name| config::alfred_dir().join(name))
+    .filter(|path| path.exists())
+    .collect();
+  if files.is_empty() {
+    ui::warn("No daemon logs found");
+    ui::dim("The daemon writes them once it has started: 'alfred daemon start'");
+    return Ok(());
+  }
+
+  let mut cmd = Command::new("tail");
+  cmd.args(["-n", &lines.to_string()]);
+  if follow {
+    cmd.arg("-F");
+  }
+  cmd
+    .args(&files)
+    .status()
+    .with_context(|| "Failed to run tail")?;
+  Ok(())
+}
+
 pub fn install() -> Result<()> {
-  let alferd_path = find_alferd_binary()?;
+  let alferd_path = daemon_client::find_alferd_binary()?;
+
+  // A service outlives the terminal that started it, so don't leave it open to every process
+  if config::get_daemon_config().auth_token.is_none() {
+    config::set_daemon_auth_token(&daemon_tls::generate_token()?)?;
+    ui::dim(format!(
+      "Generated a daemon auth token in {}",
+      config::config_path().display()
+    ));
+  }
 
   #[cfg(target_os = "macos")]
   {
@@ -176,46 +547,6 @@ pub fn uninstall() -> Result<()> {
 
 // --- Helper functions ---
 
-fn find_alferd_binary() -> Result<std::path::PathBuf> {
-  // Check same directory as current executable
-  if let Ok(current_exe) = std::env::current_exe() {
-    let dir = current_exe.parent().unwrap();
-    let alferd = dir.join("alferd");
-    if alferd.exists() {
-      return Ok(alferd);
-    }
-  }
-
-  // Check PATH
-  if let Ok(output) = Command::new("which").arg("alferd").output() {
-    if output.status.success() {
-      let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-      if !path.is_empty() {
-        return Ok(std::path::PathBuf::from(path));
-      }
-    }
-  }
-
-  // Check common locations
-  let home = dirs::home_dir().unwrap_or_default();
-  let common_paths: Vec<std::path::PathBuf> = vec![
-    std::path::PathBuf::from("/usr/local/bin/alferd"),
-    std::path::PathBuf::from("/usr/bin/alferd"),
-    home.join(".cargo/bin/alferd"),
-  ];
-
-  for path in common_paths {
-    if path.exists() {
-      return Ok(path);
-    }
-  }
-
-  Err(anyhow!(
-    "Could not find alferd binary.\n\
-         Make sure it's installed in the same directory as alfred or in your PATH."
-  ))
-}
-
 fn read_pid_file() -> Result<u32> {
   let pid_path = config::pid_file();
   let content = fs::read_to_string(&pid_path).with_context(|| "No PID file found")?;
@@ -296,7 +627,7 @@ fn install_launchd(alferd_path: &std::path::Path) -> Result<()> {
     <key>KeepAlive</key>
     <false/>
     <key>StandardOutPath</key>
-    <string>{}/alferd.log</string>
+    <string>{}/alferd.out.log</string>
     <key>StandardErrorPath</key>
     <string>{}/alferd.error.log</string>
 </dict>
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::{config, git, llm, tests_changed, ui};
+
+pub struct ExplainOptions {
+  /// A commit, or a range such as `main..feature`
+  pub target: String,
+  /// One paragraph instead of the full breakdown
+  pub short: bool,
+}
+
+/// What's being explained, gathered from git
+struct Changes {
+  heading: String,
+  stat: String,
+  /// Commit messages, oldest first
+  messages: Vec<String>,
+  diff: String,
+}
+
+pub fn run(opts: ExplainOptions) -> Result<()> {
+  let changes = collect(&opts.target)?;
+
+  println!();
+  ui::heading(&changes.heading);
+  if !changes.stat.is_empty() {
+    ui::dim(&changes.stat);
+  }
+  println!();
+
+  if changes.diff.is_empty() {
+    ui::info("No files changed");
+  }
+
+  let cfg = config::current();
+  let deleted_tests = tests_changed::deleted_tests(&changes.diff, &cfg.tests);
+  if !deleted_tests.is_empty() {
+    ui::warn(format!("Deletes tests: {}", deleted_tests.join(", ")));
+  }
+
+  ui::info("Explaining...");
+  match llm::explain_changes(&changes.messages, &changes.diff, &deleted_tests, opts.short) {
+    Ok(explanation) => {
+      println!();
+      ui::separator();
+      println!("{}", explanation.trim());
+      ui::separator();
+    }
+    Err(e) => {
+      ui::error(format!("Failed to explain: {}", e));
+      ui::dim("Make sure you have run 'alfred setup'");
+    }
+  }
+
+  Ok(())
+}
+
+/// `a..b` or `a...b`; an empty side means HEAD, as in git
+fn split_range<'a>(target: &'a str) -> Option<(&'a str, &'a str)> {
+  let (base, head) = target
+    .split_once("...")
+    .or_else(|| target.split_once(".."))?;
+  let or_head = |side: &'a str| if side.is_empty() { "HEAD" } else { side };
+  Some((or_head(base), or_head(head)))
+}
+
+fn collect(target: &str) -> Result<Changes> {
+  if let Some((base, head)) = split_range(target) {
+    return collect_range(target, base, head);
+  }
+
+  let hash = git::resolve_commits(target)?
+    .pop()
+    .ok_or_else(|| anyhow!("Unknown commit: {}", target))?;
+  let parents = git::commit_parents(&hash)?;
+  let (message, diff) = git::show_commit(&hash)?;
+
+  // A merge is explained as the whole branch it brought in
+  if let [mainline, _, ..] = parents.as_slice() {
+    let mut messages = vec![message];
+    messages.extend(branch_messages(mainline, &hash)?);
+    return Ok(Changes {
+      heading: format!(
+        "{} (merges {} commit(s))",
+        git::commit_oneline(&hash),
+        messages.len() - 1
+      ),
+      stat: git::diff_range_shortstat(mainline, &hash),
+      messages,
+      diff: git::diff_range(mainline, &hash)?,
+    });
+  }
+
+  Ok(Changes {
+    heading: git::commit_oneline(&hash),
+    stat: git::commit_shortstat(&hash),
+    messages: vec![message],
+    diff,
+  })
+}
+
+fn collect_range(target: &str, base: &str, head: &str) -> Result<Changes> {
+  let messages = branch_messages(base, head).map_err(|_| anyhow!("Invalid range: {}", target))?;
+  if messages.is_empty() {
+    bail!("No commits in {}", target);
+  }
+
+  Ok(Changes {
+    heading: format!("{} commit(s) in {}", messages.len(), target),
+    stat: git::diff_range_shortstat(base, head),
+    messages,
+    diff: git::diff_range(base, head)?,
+  })
+}
+
+/// Messages of the commits in `head` but not `base`, oldest first
+fn branch_messages(base: &str, head: &str) -> Result<Vec<String>> {
+  let mut commits = git::get_commits_between(base, head)?;
+  commits.reverse();
+  Ok(commits.into_iter().map(|c| c.message).collect())
+}
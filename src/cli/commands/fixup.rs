@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+
+use crate::git::CommitRecord;
+use crate::{git, llm, ui};
+
+pub struct FixupOptions {
+  /// Branch the current one started from (default: main or master)
+  pub base: Option<String>,
+}
+
+pub fn run(opts: FixupOptions) -> Result<()> {
+  let diff = git::diff(true)?;
+  if diff.trim().is_empty() {
+    ui::warn("No staged changes. Stage the fix first with 'git add'");
+    return Ok(());
+  }
+
+  let base = opts
+    .base
+    .or_else(git::default_base)
+    .ok_or_else(|| anyhow!("No main or master branch found. Pass --base"))?;
+  let merge_base = git::merge_base(&base, "HEAD")?;
+  let commits = git::get_commits_between(&merge_base, "HEAD")?;
+  if commits.is_empty() {
+    ui::info(format!("No commits on this branch since {}", base));
+    return Ok(());
+  }
+
+  // Commits that touched the staged files are the likely targets
+  let staged = git::staged_files().unwrap_or_default();
+  let touching: Vec<CommitRecord> = commits
+    .iter()
+    .filter(|c| {
+      git::commit_files(&c.hash)
+        .unwrap_or_default()
+        .iter()
+        .any(|f| staged.contains(f))
+    })
+    .cloned()
+    .collect();
+  let candidates = if touching.is_empty() {
+    &commits
+  } else {
+    &touching
+  };
+
+  let suggested = if candidates.len() == 1 {
+    ui::info("Only one commit on this branch touches these files");
+    Some(candidates[0].hash.clone())
+  } else {
+    ui::info(format!(
+      "Finding the fixup target among {} commit(s)...",
+      candidates.len()
+    ));
+    match llm::find_fixup_target(&diff, candidates) {
+      Ok(hash) => Some(hash),
+      Err(e) => {
+        ui::error(format!("Failed to find the target: {}", e));
+        None
+      }
+    }
+  };
+
+  let target = match suggested {
+    Some(hash) => {
+      println!();
+      ui::heading("Fixup target:");
+      ui::list_item(git::commit_oneline(&hash));
+      let stat = git::commit_shortstat(&hash);
+      if !stat.is_empty() {
+        ui::dim(format!("    {}", stat));
+      }
+      println!();
+
+      if ui::confirm("Create a fixup commit for it?", true) {
+        Some(hash)
+      } else {
+        pick(&commits)
+      }
+    }
+    None => pick(&commits),
+  };
+
+  let Some(target) = target else {
+    ui::info("Aborted");
+    return Ok(());
+  };
+
+  git::commit_fixup(&target)?;
+  ui::success(format!(
+    "Created fixup for {}",
+    git::commit_oneline(&target)
+  ));
+  ui::dim(format!(
+    "Squash it in with 'git rebase -i --autosquash {}'",
+    &merge_base[..merge_base.len().min(7)]
+  ));
+  Ok(())
+}
+
+/// Let the user choose the target from the branch's commits, newest first
+fn pick(commits: &[CommitRecord]) -> Option<String> {
+  let lines: Vec<String> = commits
+    .iter()
+    .map(|c| git::commit_oneline(&c.hash))
+    .collect();
+  let chosen = ui::select("Pick the commit to fix:", &lines)?;
+  let index = lines.iter().position(|l| *l == chosen)?;
+  commits.get(index).map(|c| c.hash.clone())
+}
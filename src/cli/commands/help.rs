@@ -16,16 +16,30 @@ pub fn run() {
   {}    Generate AI commit message from staged changes
   {}    Interactive rebase with AI suggestions
   {}   AI-assisted merge conflict resolution
+  {}    Find the commit behind a regression
+  {} Cherry-pick commits with conflict assistance
+  {}   Explain what a commit or range does and why
+  {}     Commit a fix as a fixup of the commit it fixes
+  {}    Review uncommitted changes for mistakes
+  {}     Stash changes with an AI-generated description
   {}    Smart branch management
+  {}        Generate pull request descriptions
+  {} Summarize everything this branch changes
+  {}       Brief you on recent commits, with --ai
+  {}     Assess a branch before merging it, with --ai
+  {} Generate CHANGELOG.md entries
+  {}       Tag a release with a suggested version
+  {}     Check commit messages in a git hook
   {}    Configure alfred settings
+  {}     Show or clear cached model replies
   {}      Show this help message
 
 {}
-  All other commands are passed directly to git:
+  All other commands are passed directly to git ('alfred git <cmd>' forces it):
   {}            → git status
   {}              → git push
   {}     → git log --oneline
-  {}             → git stash
+  {}              → git diff
 
 {}
   alfred setup               Download AI model (run this first!)
@@ -33,8 +47,16 @@ pub fn run() {
   alfred commit --edit       Generate and edit before committing
   alfred rebase main         Rebase onto main with AI suggestions
   alfred resolve             Resolve all conflicts with AI assistance
+  alfred cherry-pick a..b    Pick a range of commits onto this branch
+  alfred explain HEAD        Explain the last commit
+  alfred review --staged     Review staged changes before committing
+  alfred stash               Stash changes with a descriptive message
   alfred branch new          Create branch with AI-suggested name
   alfred branch clean        Clean up merged branches
+  alfred pr describe         Describe this branch as a pull request
+  alfred summarize           Summarize this branch's changes since main
+  alfred log --ai            Summarize the last 10 commits
+  alfred merge feature --ai  Assess a branch, then merge it
 
 {}
   Run 'alfred setup' to download llama.cpp and a local AI model.
@@ -48,14 +70,28 @@ pub fn run() {
     "commit".cyan(),
     "rebase".cyan(),
     "resolve".cyan(),
+    "bisect".cyan(),
+    "cherry-pick".cyan(),
+    "explain".cyan(),
+    "fixup".cyan(),
+    "review".cyan(),
+    "stash".cyan(),
     "branch".cyan(),
+    "pr".cyan(),
+    "summarize".cyan(),
+    "log".cyan(),
+    "merge".cyan(),
+    "changelog".cyan(),
+    "tag".cyan(),
+    "hooks".cyan(),
     "config".cyan(),
+    "cache".cyan(),
     "help".cyan(),
     "GIT PASSTHROUGH".bold(),
     "alfred status".dimmed(),
     "alfred push".dimmed(),
     "alfred log --oneline".dimmed(),
-    "alfred stash".dimmed(),
+    "alfred diff".dimmed(),
     "EXAMPLES".bold(),
     "GETTING STARTED".bold(),
   );
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{config, git, ui};
+
+/// Marks hooks alfred wrote, so it never overwrites or removes anyone else's
+const MARKER: &str = "# Installed by alfred";
+
+const COMMIT_MSG_HOOK: &str = r#"#!/bin/sh
+# Installed by alfred: checks commit messages against commit_rules
+if ! command -v alfred >/dev/null 2>&1; then
+  echo "alfred not found on PATH, skipping commit message checks" >&2
+  exit 0
+fi
+exec alfred lint-commit "$1"
+"#;
+
+const PREPARE_COMMIT_MSG_HOOK: &str = r#"#!/bin/sh
+# Installed by alfred: drafts a message for plain 'git commit'
+# $2 is where the message came from; only generate when git has none yet
+case "$2" in
+  message|merge|squash|commit) exit 0 ;;
+esac
+command -v alfred >/dev/null 2>&1 || exit 0
+# Never block a commit because generation failed
+alfred commit --hook "$1" || true
+"#;
+
+/// Git hooks alfred can install
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Hook {
+  /// Check messages against commit_rules
+  CommitMsg,
+  /// Draft a message for `git commit` from the staged diff
+  PrepareCommitMsg,
+}
+
+const ALL_HOOKS: [Hook; 2] = [Hook::CommitMsg, Hook::PrepareCommitMsg];
+
+impl Hook {
+  fn name(&self) -> &'static str {
+    match self {
+      Hook::CommitMsg => "commit-msg",
+      Hook::PrepareCommitMsg => "prepare-commit-msg",
+    }
+  }
+
+  fn script(&self) -> &'static str {
+    match self {
+      Hook::CommitMsg => COMMIT_MSG_HOOK,
+      Hook::PrepareCommitMsg => PREPARE_COMMIT_MSG_HOOK,
+    }
+  }
+}
+
+fn hook_path(hook: Hook) -> Result<PathBuf> {
+  let dir = git::hooks_dir().ok_or_else(|| anyhow!("Could not find the git hooks directory"))?;
+  Ok(dir.join(hook.name()))
+}
+
+fn is_ours(path: &Path) -> bool {
+  fs::read_to_string(path).is_ok_and(|c| c.contains(MARKER))
+}
+
+/// Install `hooks`, or just commit-msg when none are given. Generating on every plain
+/// `git commit` is opt-in
+pub fn install(hooks: Vec<Hook>, force: bool) -> Result<()> {
+  let hooks = if hooks.is_empty() {
+    vec![Hook::CommitMsg]
+  } else {
+    hooks
+  };
+
+  for hook in hooks {
+    let path = hook_path(hook)?;
+
+    if path.exists() && !is_ours(&path) && !force {
+      ui::warn(format!("{} already exists", path.display()));
+      ui::dim("Pass --force to replace it");
+      continue;
+    }
+
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, hook.script())
+      .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    ui::success(format!(
+      "Installed {} hook at {}",
+      hook.name(),
+      path.display()
+    ));
+    if hook == Hook::CommitMsg {
+      ui::dim("Bypass the message check once with 'git commit --no-verify'");
+    }
+  }
+
+  Ok(())
+}
+
+/// Remove every hook alfred installed, leaving anyone else's alone
+pub fn uninstall() -> Result<()> {
+  let mut removed = 0;
+
+  for hook in ALL_HOOKS {
+    let path = hook_path(hook)?;
+    if !path.exists() {
+      continue;
+    }
+    if !is_ours(&path) {
+      ui::warn(format!(
+        "{} wasn't installed by alfred, leaving it",
+        path.display()
+      ));
+      continue;
+    }
+
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    ui::success(format!("Removed {} hook", hook.name()));
+    removed += 1;
+  }
+
+  if removed == 0 {
+    ui::info("No alfred hooks installed");
+  }
+  Ok(())
+}
+
+/// Check the message in `file`. Exits with 1 when it breaks a rule, which makes git abort the commit
+pub fn lint_commit(file: &str) -> Result<i32> {
+  let message = fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+  let cfg = config::current();
+
+  let errors = git::validate_commit_message(&message, &cfg.commit_rules);
+  if errors.is_empty() {
+    return Ok(0);
+  }
+
+  for e in &errors {
+    ui::error(format!(
+      "{} {}",
+      format!("line {}:", e.line).dimmed(),
+      e.message
+    ));
+  }
+  ui::dim("Fix the message, or bypass the check with 'git commit --no-verify'");
+  Ok(1)
+}
+
+pub fn show_help() {
+  ui::heading("Hook Commands");
+  println!();
+  println!(
+    "  {} {}",
+    "alfred hooks install".cyan(),
+    "Check messages against commit_rules".dimmed()
+  );
+  println!(
+    "  {} {}",
+    "alfred hooks install prepare-commit-msg".cyan(),
+    "Also draft messages for plain 'git commit'".dimmed()
+  );
+  println!(
+    "  {} {}",
+    "alfred hooks uninstall".cyan(),
+    "Remove alfred's hooks".dimmed()
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::process::Command;
+
+  /// Run the prepare-commit-msg script with `source` as git's second argument, against an
+  /// `alfred` that only records it was called. Returns whether it was
+  #[cfg(unix)]
+  fn hook_generates(source: &[&str]) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let called = dir.path().join("called");
+    let alfred = dir.path().join("alfred");
+    fs::write(
+      &alfred,
+      format!(
+        "#!/bin/sh
+touch '{}'
+",
+        called.display()
+      ),
+    )
+    .unwrap();
+    fs::set_permissions(&alfred, fs::Permissions::from_mode(0o755)).unwrap();
+    let hook = dir.path().join("prepare-commit-msg");
+    fs::write(&hook, PREPARE_COMMIT_MSG_HOOK).unwrap();
+
+    let path = format!(
+      "{}:{}",
+      dir.path().display(),
+      std::env::var("PATH").unwrap_or_default()
+    );
+    let status = Command::new("sh")
+      .arg(&hook)
+      .arg(dir.path().join("COMMIT_EDITMSG"))
+      .args(source)
+      .env("PATH", path)
+      .status()
+      .unwrap();
+    assert!(status.success());
+    called.exists()
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn prepare_hook_skips_messages_git_already_has() {
+    for source in ["message", "merge", "squash", "commit"] {
+      assert!(!hook_generates(&[source, "HEAD"]), "{}", source);
+    }
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn prepare_hook_generates_for_plain_commits() {
+    assert!(hook_generates(&[]));
+    assert!(hook_generates(&["template"]));
+  }
+
+  #[test]
+  fn hooks_are_marked_as_ours() {
+    for hook in ALL_HOOKS {
+      assert!(hook.script().contains(MARKER), "{}", hook.name());
+    }
+  }
+}
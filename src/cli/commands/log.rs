@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::io::IsTerminal;
+
+use crate::{git, llm, ui};
+
+pub struct LogOptions {
+  /// Commits to show and summarize
+  pub count: usize,
+  /// Summarize the commits after the log
+  pub ai: bool,
+  /// `git log --format` for the log itself
+  pub format: Option<String>,
+}
+
+pub fn run(opts: LogOptions) -> Result<i32> {
+  // No pager, so the summary can follow the log
+  let mut args = vec![
+    "--no-pager".to_string(),
+    "log".to_string(),
+    format!("-{}", opts.count),
+  ];
+  if let Some(format) = &opts.format {
+    args.push(format!("--format={}", format));
+  }
+  if std::io::stdout().is_terminal() {
+    args.push("--color=always".to_string());
+  }
+  let code = git::passthrough(&args)?;
+  if code != 0 || !opts.ai {
+    return Ok(code);
+  }
+
+  let messages = git::recent_commit_messages(opts.count)?;
+  if messages.is_empty() {
+    return Ok(0);
+  }
+
+  println!();
+  ui::info(format!(
+    "Summarizing the last {} commit(s)...",
+    messages.len()
+  ));
+  let summary = match llm::summarize_log(&messages) {
+    Ok(summary) => summary.trim().to_string(),
+    Err(e) => {
+      ui::error(format!("Failed to summarize the log: {}", e));
+      ui::dim("Make sure you have run 'alfred setup'");
+      return Ok(1);
+    }
+  };
+
+  ui::heading("Summary:");
+  ui::separator();
+  println!("{}", summary);
+  ui::separator();
+
+  Ok(0)
+}
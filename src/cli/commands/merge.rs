@@ -0,0 +1,91 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use super::resolve;
+use crate::risk::RiskLevel;
+use crate::{git, llm, ui};
+
+pub struct MergeOptions {
+  /// Branch to merge into the current one
+  pub branch: String,
+  /// Assess the merge with the model before running it
+  pub ai: bool,
+  /// Merge strategy passed to `git merge --strategy`
+  pub strategy: Option<String>,
+}
+
+pub fn run(opts: MergeOptions) -> Result<i32> {
+  if !opts.ai {
+    return merge(&opts.branch, opts.strategy.as_deref());
+  }
+
+  if git::merge_in_progress() {
+    ui::warn("A merge is already in progress");
+    ui::dim("Run 'alfred resolve' to finish it, or 'git merge --abort' to cancel");
+    return Ok(1);
+  }
+
+  let diff = git::diff_range("HEAD", &opts.branch)?;
+  if diff.trim().is_empty() {
+    ui::info(format!("{} brings in no changes", opts.branch.cyan()));
+    return merge(&opts.branch, opts.strategy.as_deref());
+  }
+
+  let shortstat = git::diff_range_shortstat("HEAD", &opts.branch);
+  ui::info(format!(
+    "Analyzing {} ({})...",
+    opts.branch.cyan(),
+    shortstat.trim()
+  ));
+  let analysis = match llm::analyze_merge(&opts.branch, &diff) {
+    Ok(analysis) => analysis,
+    Err(e) => {
+      ui::error(format!("Failed to analyze the merge: {}", e));
+      ui::dim("Make sure you have run 'alfred setup', or merge with 'git merge'");
+      return Ok(1);
+    }
+  };
+
+  ui::heading("Merge analysis:");
+  ui::info(format!("Risk: {}", analysis.risk));
+  if analysis.conflict_files.is_empty() {
+    ui::info("Likely conflicts: none");
+  } else {
+    ui::info("Likely conflicts:");
+    for file in &analysis.conflict_files {
+      ui::list_item_colored("!", "yellow", file);
+    }
+  }
+  println!();
+  println!("{}", analysis.summary);
+  println!();
+
+  let question = format!("Merge {} into the current branch?", opts.branch);
+  if !ui::confirm(&question, analysis.risk != RiskLevel::High) {
+    ui::info("Aborted");
+    return Ok(0);
+  }
+
+  let code = merge(&opts.branch, opts.strategy.as_deref())?;
+  if code == 0 || git::status()?.conflicts.is_empty() {
+    return Ok(code);
+  }
+
+  println!();
+  ui::warn("The merge stopped on conflicts");
+  resolve::run(resolve::ResolveOptions {
+    file: None,
+    whole_file: false,
+    check: false,
+  })
+}
+
+/// `git merge`, with git's own output
+fn merge(branch: &str, strategy: Option<&str>) -> Result<i32> {
+  let mut args = vec!["merge".to_string()];
+  if let Some(strategy) = strategy {
+    args.push(format!("--strategy={}", strategy));
+  }
+  args.push(branch.to_string());
+  git::passthrough(&args)
+}
@@ -1,8 +1,25 @@
+pub mod bisect;
 pub mod branch;
+pub mod cache;
+pub mod changelog;
+pub mod cherry_pick;
 pub mod commit;
 pub mod config;
 pub mod daemon;
+pub mod explain;
+pub mod fixup;
 pub mod help;
+pub mod hooks;
+pub mod log;
+pub mod merge;
+pub mod pr;
 pub mod rebase;
 pub mod resolve;
+pub mod review;
 pub mod setup;
+pub mod stash;
+pub mod summarize;
+pub mod tag;
+
+#[cfg(test)]
+mod test_support;
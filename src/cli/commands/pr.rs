@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::{forge, git, llm, ui};
+
+pub struct PrOptions {
+  /// Branch to compare against
+  pub base: Option<String>,
+  /// Issue to use as context instead of the one in the branch name
+  pub issue: Option<String>,
+  /// Look up issue context on the forge
+  pub forge: bool,
+}
+
+/// Forge CLI used to open the pull/merge request
+enum Forge {
+  GitHub,
+  GitLab,
+}
+
+impl Forge {
+  fn detect() -> Forge {
+    match git::remote_url("origin") {
+      Some(url) if url.contains("gitlab") => Forge::GitLab,
+      _ => Forge::GitHub,
+    }
+  }
+
+  fn cli(&self) -> &'static str {
+    match self {
+      Forge::GitHub => "gh",
+      Forge::GitLab => "glab",
+    }
+  }
+}
+
+pub fn describe(opts: PrOptions, write_file: bool, copy: bool) -> Result<()> {
+  // Keep stdout clean when piping into `gh pr create --body-file -`
+  let interactive = std::io::stdout().is_terminal();
+
+  let Some((_, description)) = generate(opts, interactive)? else {
+    return Ok(());
+  };
+
+  println!("{}", description);
+
+  if write_file {
+    let path = write_temp(&description)?;
+    eprintln!("{} Written to {}", "i".blue(), path.display());
+  }
+
+  if copy {
+    match copy_to_clipboard(&description) {
+      Ok(tool) => eprintln!("{} Copied to the clipboard with {}", "i".blue(), tool),
+      Err(e) => eprintln!("{} {}", "!".yellow(), e),
+    }
+  }
+
+  Ok(())
+}
+
+pub fn create(opts: PrOptions, draft: bool) -> Result<()> {
+  let Some((base, description)) = generate(opts, true)? else {
+    return Ok(());
+  };
+  let (title, body) = split_title(&description);
+
+  println!();
+  ui::heading("Generated description:");
+  ui::separator();
+  println!("{}", description);
+  ui::separator();
+  println!();
+
+  let forge = Forge::detect();
+  if !ui::confirm(
+    &format!("Open a pull request into {} with '{}'?", base, forge.cli()),
+    true,
+  ) {
+    ui::info("Aborted");
+    return Ok(());
+  }
+
+  let body_file = write_temp(&body)?;
+  let body_file = body_file.to_string_lossy().to_string();
+
+  let mut cmd = Command::new(forge.cli());
+  match forge {
+    Forge::GitHub => {
+      cmd.args(["pr", "create", "--base", &base, "--title", &title]);
+      cmd.args(["--body-file", &body_file]);
+    }
+    Forge::GitLab => {
+      cmd.args(["mr", "create", "--target-branch", &base, "--title", &title]);
+      cmd.args(["--description", &body]);
+    }
+  }
+  if draft {
+    cmd.arg("--draft");
+  }
+
+  let status = cmd
+    .status()
+    .with_context(|| format!("Failed to run '{}'. Is it installed?", forge.cli()))?;
+
+  if status.success() {
+    ui::success("Pull request created");
+  } else {
+    ui::error(format!("'{}' exited with {}", forge.cli(), status));
+    ui::dim(format!("The description is saved in {}", body_file));
+  }
+
+  Ok(())
+}
+
+/// Collect the branch's commits and generate a description. Returns the base used
+fn generate(opts: PrOptions, verbose: bool) -> Result<Option<(String, String)>> {
+  let base = opts
+    .base
+    .or_else(git::default_base)
+    .ok_or_else(|| anyhow!("No main or master branch found. Pass --base"))?;
+
+  let commits = git::get_branch_commits_with_diffs(&base)?;
+  if commits.is_empty() {
+    ui::info(format!("No commits ahead of {}", base));
+    return Ok(None);
+  }
+
+  if verbose {
+    ui::info(format!(
+      "Describing {} commit(s) since {}...",
+      commits.len(),
+      base.cyan()
+    ));
+  }
+
+  let issue = forge::issue_context(opts.issue.as_deref(), opts.forge);
+  match llm::generate_pr_description(&commits, issue.as_deref()) {
+    Ok(description) => Ok(Some((base, description.trim().to_string()))),
+    Err(e) => {
+      ui::error(format!("Failed to generate description: {}", e));
+      ui::dim("Make sure you have run 'alfred setup'");
+      Ok(None)
+    }
+  }
+}
+
+/// Split "# Title\n\nbody" into its title and body
+fn split_title(description: &str) -> (String, String) {
+  let mut lines = description.lines();
+  let first = lines.next().unwrap_or("").trim();
+  match first.strip_prefix("# ") {
+    Some(title) => (
+      title.trim().to_string(),
+      lines.collect::<Vec<_>>().join("\n").trim().to_string(),
+    ),
+    None => (first.to_string(), description.to_string()),
+  }
+}
+
+/// Clipboard tools to try, in order, with their arguments
+const CLIPBOARD_TOOLS: &[(&str, &[&str])] = &[
+  ("pbcopy", &[]),
+  ("wl-copy", &[]),
+  ("xclip", &["-selection", "clipboard"]),
+  ("xsel", &["--clipboard", "--input"]),
+  ("clip.exe", &[]),
+];
+
+/// Pipe `content` into the first clipboard tool that runs. Returns the tool's name
+fn copy_to_clipboard(content: &str) -> Result<&'static str> {
+  for (tool, args) in CLIPBOARD_TOOLS {
+    let Ok(mut child) = Command::new(tool)
+      .args(*args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn()
+    else {
+      continue;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(content.as_bytes())?;
+    }
+    if child.wait()?.success() {
+      return Ok(tool);
+    }
+  }
+  Err(anyhow!(
+    "No clipboard tool found; install pbcopy, wl-copy, xclip or xsel"
+  ))
+}
+
+fn write_temp(content: &str) -> Result<PathBuf> {
+  let branch = git::current_branch().unwrap_or_else(|| "HEAD".to_string());
+  let path = std::env::temp_dir().join(format!("alfred-pr-{}.md", branch.replace('/', "-")));
+  fs::write(&path, content)?;
+  Ok(path)
+}
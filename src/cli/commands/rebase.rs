@@ -3,7 +3,7 @@ use colored::Colorize;
 
 use crate::{git, llm, ui};
 
-pub fn run(onto: Option<String>, use_ai: bool) -> Result<()> {
+pub fn run(onto: Option<String>, use_ai: bool, verbose: bool) -> Result<()> {
   let status = git::status()?;
 
   if !status.staged.is_empty() || !status.unstaged.is_empty() {
@@ -64,7 +64,12 @@ pub fn run(onto: Option<String>, use_ai: bool) -> Result<()> {
   if use_ai {
     ui::info("Getting AI suggestions...");
 
-    match llm::suggest_rebase_strategy(&commits, &onto) {
+    let (suggestion, usage) =
+      llm::measuring_usage(|| llm::suggest_rebase_strategy(&commits, &onto));
+    if verbose {
+      ui::dim(format!("Model: {}", usage));
+    }
+    match suggestion {
       Ok(suggestion) => {
         println!();
         ui::heading("AI Suggestion:");
@@ -85,7 +90,12 @@ pub fn run(onto: Option<String>, use_ai: bool) -> Result<()> {
     "Abort",
   ];
 
-  let action = ui::select("How would you like to proceed?", &options).unwrap_or("Abort");
+  // An interactive rebase needs someone at the editor
+  let action = if ui::non_interactive() {
+    options[1]
+  } else {
+    ui::select("How would you like to proceed?", &options).unwrap_or("Abort")
+  };
 
   if action == "Abort" {
     ui::info("Aborted");
@@ -1,27 +1,123 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::Colorize;
 use std::fs;
+use std::path::Path;
 
-use crate::{git, llm, ui};
+use crate::conflict::{self, ConflictHunk, ConflictSegment};
+use crate::{config, git, llm, ui};
 
-pub fn run(target_file: Option<String>) -> Result<()> {
+/// Result of walking through the conflicted files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  NoConflicts,
+  Resolved(git::Operation),
+  Remaining(usize),
+}
+
+pub struct ResolveOptions {
+  /// Only this conflicted file
+  pub file: Option<String>,
+  /// Send whole files to the model instead of one conflict region at a time
+  pub whole_file: bool,
+  /// Only print what the model proposes, leaving every file as it is
+  pub check: bool,
+}
+
+/// Exit code of `--check` when the model had no candidate for some conflict
+const CHECK_INCOMPLETE: i32 = 2;
+
+pub fn run(opts: ResolveOptions) -> Result<i32> {
+  if opts.check {
+    return check(opts.file.as_deref(), opts.whole_file);
+  }
+
+  let operation = match resolve_conflicts(opts.file.as_deref(), opts.whole_file)? {
+    Outcome::Resolved(operation) => operation,
+    Outcome::NoConflicts | Outcome::Remaining(_) => return Ok(0),
+  };
+
+  if operation == git::Operation::Stash {
+    ui::dim("Resolved changes are left in the working tree; drop the stash once you're happy");
+    return Ok(0);
+  }
+
+  if ui::confirm(&format!("Continue {}?", operation.name()), true) {
+    complete(operation);
+  }
+
+  Ok(0)
+}
+
+/// Finish the operation after its conflicts are resolved, reporting what happened
+pub fn complete(operation: git::Operation) -> bool {
+  match git::continue_operation(operation) {
+    Ok(()) => {
+      ui::success(format!("Continued {}", operation.name()));
+      true
+    }
+    Err(e) => {
+      let msg = e.to_string();
+      if msg.contains("conflict") {
+        ui::warn("More conflicts encountered");
+        ui::dim("Run 'alfred resolve' again");
+      } else {
+        ui::error(msg);
+      }
+      false
+    }
+  }
+}
+
+/// The conflicted files to work through, in order, and what their sides are called
+struct Plan {
+  files: Vec<String>,
+  operation: git::Operation,
+  ours: String,
+  theirs: String,
+}
+
+impl Plan {
+  fn labels(&self) -> llm::ConflictLabels<'_> {
+    llm::ConflictLabels {
+      ours: &self.ours,
+      theirs: &self.theirs,
+    }
+  }
+}
+
+/// List the conflicts, pick the files to work through and load the model. `Err` is the
+/// outcome when there's nothing the model can be asked about
+fn plan(target_file: Option<&str>) -> Result<std::result::Result<Plan, Outcome>> {
   let status = git::status()?;
 
   if status.conflicts.is_empty() {
     ui::success("No conflicts to resolve!");
-    return Ok(());
+    return Ok(Err(Outcome::NoConflicts));
   }
 
+  // Simplest files first, so quick wins don't wait behind a hard one
+  let mut conflicts: Vec<(usize, String)> = status
+    .conflicts
+    .iter()
+    .map(|f| (git::conflict_hunks(f), f.clone()))
+    .collect();
+  conflicts.sort();
+
   ui::heading(format!(
-    "Found {} conflicted file(s):",
-    status.conflicts.len()
+    "Found {} conflicted file(s), fewest conflicts first:",
+    conflicts.len()
   ));
-  for f in &status.conflicts {
-    println!("  {} {}", "!".red(), f);
-  }
+  let lines: Vec<String> = conflicts
+    .iter()
+    .map(|(hunks, f)| match hunks {
+      0 => format!("{} {}", "!".red(), f),
+      n => format!("{} {} {}", "!".red(), f, format!("({})", n).dimmed()),
+    })
+    .collect();
+  ui::list_paged(&lines, ui::PAGE_SIZE);
   println!();
 
-  let files_to_resolve: Vec<String> = if let Some(ref target) = target_file {
+  let files_to_resolve: Vec<String> = if let Some(target) = target_file {
     let matching: Vec<String> = status
       .conflicts
       .iter()
@@ -31,111 +127,597 @@ pub fn run(target_file: Option<String>) -> Result<()> {
 
     if matching.is_empty() {
       ui::error(format!("File not in conflict: {}", target));
-      return Ok(());
+      return Ok(Err(Outcome::Remaining(status.conflicts.len())));
     }
     matching
   } else {
-    status.conflicts.clone()
+    conflicts.into_iter().map(|(_, f)| f).collect()
   };
 
+  let sides = git::conflict_sides();
+  let ours_label = sides.ours_label();
+  let theirs_label = sides.theirs_label();
+  ui::info(format!(
+    "Conflicts from {}: {} vs {}",
+    sides.operation.name(),
+    ours_label.cyan(),
+    theirs_label.cyan()
+  ));
+  if sides.operation == git::Operation::Rebase {
+    ui::dim("During a rebase, \"ours\" is the branch being rebased onto");
+  }
+  println!();
+
   ui::info("Loading AI model...");
-  if let Err(e) = llm::load_model() {
+  if let Err(e) = llm::load_model(&config::get_task_model(config::Task::Resolve)) {
     ui::error(format!("Failed to load model: {}", e));
     ui::dim("Make sure you have run 'alfred setup'");
-    return Ok(());
+    return Ok(Err(Outcome::Remaining(status.conflicts.len())));
   }
 
-  for file in &files_to_resolve {
+  Ok(Ok(Plan {
+    files: files_to_resolve,
+    operation: sides.operation,
+    ours: ours_label,
+    theirs: theirs_label,
+  }))
+}
+
+/// The conflict regions of a file, for resolving one at a time. `None` with `whole_file`,
+/// or for conflicts such as modify/delete that leave no markers
+fn conflict_segments(file: &str, whole_file: bool) -> Option<Vec<ConflictSegment>> {
+  if whole_file {
+    return None;
+  }
+  fs::read_to_string(file)
+    .ok()
+    .and_then(|content| conflict::split_conflicts(&content).ok())
+    .filter(|segments| {
+      segments
+        .iter()
+        .any(|s| matches!(s, ConflictSegment::Conflict(_)))
+    })
+}
+
+/// Walk the user through each conflicted file (or just `target_file`)
+pub fn resolve_conflicts(target_file: Option<&str>, whole_file: bool) -> Result<Outcome> {
+  let plan = match plan(target_file)? {
+    Ok(plan) => plan,
+    Err(outcome) => return Ok(outcome),
+  };
+  let labels = plan.labels();
+
+  for file in &plan.files {
     ui::heading(format!("Resolving: {}", file));
 
-    let conflict_info = match git::get_conflict_info(file) {
-      Ok(info) => info,
-      Err(e) => {
-        ui::warn(format!(
-          "Cannot extract conflict versions for {}: {}",
-          file, e
-        ));
-        continue;
-      }
-    };
+    match conflict_segments(file, whole_file) {
+      Some(segments) => resolve_hunks(file, segments, &labels)?,
+      None => resolve_whole_file(file, &labels)?,
+    }
+  }
+
+  // Check remaining conflicts
+  let remaining = git::status()?;
+  if remaining.conflicts.is_empty() {
+    ui::success("All conflicts resolved!");
+    Ok(Outcome::Resolved(plan.operation))
+  } else {
+    ui::warn(format!(
+      "{} conflict(s) remaining",
+      remaining.conflicts.len()
+    ));
+    Ok(Outcome::Remaining(remaining.conflicts.len()))
+  }
+}
 
-    if conflict_info.ours.is_empty() && conflict_info.theirs.is_empty() {
-      ui::warn(format!("Cannot extract conflict versions for {}", file));
-      continue;
+/// What the user picked for a conflict
+enum Choice {
+  Use(String),
+  Skip,
+}
+
+/// Ask the model to merge one conflict, show its answer, and let the user pick a version
+fn choose(
+  file: &str,
+  conflict: &llm::Conflict,
+  labels: &llm::ConflictLabels,
+  skip: &str,
+) -> Choice {
+  ui::info("Analyzing conflict...");
+
+  let resolution = match llm::suggest_conflict_resolution(file, conflict, labels) {
+    Ok(r) => r,
+    Err(e) => {
+      ui::error(format!("Failed to analyze conflict: {}", e));
+      return Choice::Skip;
     }
+  };
+
+  // Diffs show which lines came from each side and which the model wrote itself
+  let shown = with_newline(&resolution);
+  println!();
+  ui::heading(format!("AI Suggested Resolution, against {}:", labels.ours));
+  ui::print_diff(
+    labels.ours,
+    "suggestion",
+    &with_newline(conflict.ours),
+    &shown,
+  );
+  println!();
+  ui::heading(format!("Against {}:", labels.theirs));
+  ui::print_diff(
+    labels.theirs,
+    "suggestion",
+    &with_newline(conflict.theirs),
+    &shown,
+  );
+  println!();
+
+  let keep_ours = format!("Keep changes from {}", labels.ours);
+  let keep_theirs = format!("Keep changes from {}", labels.theirs);
+  let options = [
+    "Apply this resolution".to_string(),
+    "Edit in $EDITOR".to_string(),
+    keep_ours.clone(),
+    keep_theirs.clone(),
+    skip.to_string(),
+  ];
 
-    ui::info("Analyzing conflict...");
+  let action = ui::select("What would you like to do?", &options).unwrap_or_default();
 
-    let resolution = match llm::suggest_conflict_resolution(
-      file,
-      &conflict_info.ours,
-      &conflict_info.theirs,
-      &conflict_info.base,
-    ) {
-      Ok(r) => r,
+  if action == options[0] {
+    Choice::Use(resolution)
+  } else if action == options[1] {
+    let suffix = Path::new(file)
+      .extension()
+      .map(|ext| format!(".{}", ext.to_string_lossy()))
+      .unwrap_or_default();
+    match ui::edit_text(&shown, &suffix) {
+      Ok(edited) => Choice::Use(edited),
       Err(e) => {
-        ui::error(format!("Failed to analyze conflict: {}", e));
+        ui::error(format!("Failed to edit the resolution: {}", e));
+        Choice::Skip
+      }
+    }
+  } else if action == keep_ours {
+    Choice::Use(conflict.ours.to_string())
+  } else if action == keep_theirs {
+    Choice::Use(conflict.theirs.to_string())
+  } else {
+    Choice::Skip
+  }
+}
+
+/// `text` ending in a newline, so a missing final one doesn't show up as a change
+fn with_newline(text: &str) -> String {
+  if text.is_empty() || text.ends_with('\n') {
+    text.to_string()
+  } else {
+    format!("{}\n", text)
+  }
+}
+
+/// Stage a file once no conflict markers are left in it; a suggestion or an edit can bring
+/// them back
+fn stage_resolved(file: &str) -> Result<bool> {
+  let content = fs::read_to_string(file)?;
+  if conflict::has_markers(&content) {
+    ui::warn(format!(
+      "{} still has conflict markers; not staging it",
+      file
+    ));
+    ui::dim("Fix them and run 'alfred resolve' again");
+    return Ok(false);
+  }
+  git::stage_file(file)?;
+  ui::success(format!("Resolved: {}", file));
+  Ok(true)
+}
+
+/// Resolve each conflict region on its own and splice the results back between the
+/// unconflicted text. Skipped regions keep their markers, and the file is only staged once
+/// none are left
+fn resolve_hunks(
+  file: &str,
+  segments: Vec<ConflictSegment>,
+  labels: &llm::ConflictLabels,
+) -> Result<()> {
+  let total = segments
+    .iter()
+    .filter(|s| matches!(s, ConflictSegment::Conflict(_)))
+    .count();
+  let mut content = String::new();
+  let mut index = 0;
+  let mut left = 0;
+
+  for segment in segments {
+    let hunk = match segment {
+      ConflictSegment::Text(text) => {
+        content.push_str(&text);
         continue;
       }
+      ConflictSegment::Conflict(hunk) => hunk,
     };
 
-    println!();
-    ui::heading("AI Suggested Resolution:");
-    ui::separator();
-    println!("{}", resolution);
-    ui::separator();
-    println!();
+    index += 1;
+    ui::info(format!(
+      "Conflict {} of {} {}",
+      index,
+      total,
+      format!("(line {})", hunk.line).dimmed()
+    ));
 
-    let options = [
-      "Apply this resolution",
-      "Keep ours (current branch)",
-      "Keep theirs (incoming)",
-      "Skip this file",
-    ];
+    match choose(file, &region(&hunk), labels, "Skip this conflict") {
+      // Spliced between lines the file already has, so it takes their line endings
+      Choice::Use(resolved) => content.push_str(&hunk.fit(&resolved)),
+      Choice::Skip => {
+        content.push_str(&hunk.raw);
+        left += 1;
+      }
+    }
+  }
 
-    let action = ui::select("What would you like to do?", &options).unwrap_or("Skip this file");
+  if left == total {
+    return Ok(());
+  }
 
-    let content = match action {
-      "Apply this resolution" => resolution,
-      "Keep ours (current branch)" => conflict_info.ours,
-      "Keep theirs (incoming)" => conflict_info.theirs,
-      _ => continue,
-    };
+  fs::write(file, &content)?;
+  if left == 0 {
+    stage_resolved(file)?;
+  } else {
+    ui::warn(format!(
+      "{} of {} conflict(s) left in {}",
+      left, total, file
+    ));
+  }
+  Ok(())
+}
+
+/// What the model is asked about for one conflict region
+fn region(hunk: &ConflictHunk) -> llm::Conflict<'_> {
+  llm::Conflict {
+    ours: &hunk.ours,
+    theirs: &hunk.theirs,
+    base: hunk.base.as_deref().unwrap_or_default(),
+    before: &hunk.before,
+    after: &hunk.after,
+  }
+}
+
+/// Both sides and the base of a whole file, from the index stages or else the markers
+fn whole_file_versions(file: &str) -> Result<git::ConflictInfo> {
+  // Some tools leave conflicts without index stages; the markers in the file still say it all
+  match git::get_conflict_info(file) {
+    Ok(info) if !(info.ours.is_empty() && info.theirs.is_empty()) => Ok(info),
+    staged => match git::parse_conflict_markers(file) {
+      Ok(info) => {
+        ui::dim("No conflict stages in the index; using the conflict markers in the file");
+        Ok(info)
+      }
+      Err(e) => Err(anyhow!(
+        "Cannot extract conflict versions for {}: {}",
+        file,
+        staged.err().unwrap_or(e)
+      )),
+    },
+  }
+}
+
+fn whole_file_conflict(info: &git::ConflictInfo) -> llm::Conflict<'_> {
+  llm::Conflict {
+    ours: &info.ours,
+    theirs: &info.theirs,
+    base: &info.base,
+    before: "",
+    after: "",
+  }
+}
 
+/// Resolve the whole file in one suggestion
+fn resolve_whole_file(file: &str, labels: &llm::ConflictLabels) -> Result<()> {
+  let conflict_info = match whole_file_versions(file) {
+    Ok(info) => info,
+    Err(e) => {
+      ui::warn(e.to_string());
+      return Ok(());
+    }
+  };
+
+  let choice = choose(
+    file,
+    &whole_file_conflict(&conflict_info),
+    labels,
+    "Skip this file",
+  );
+  if let Choice::Use(content) = choice {
     fs::write(file, &content)?;
-    git::stage_file(file)?;
-    ui::success(format!("Resolved: {}", file));
+    stage_resolved(file)?;
   }
+  Ok(())
+}
 
-  // Check remaining conflicts
-  let remaining = git::status()?;
-  if remaining.conflicts.is_empty() {
-    ui::success("All conflicts resolved!");
+/// The model's answer for one conflict region, or for a whole file
+pub struct Proposal {
+  /// Line of the region's `<<<<<<<` marker, from 1; `None` for a whole-file suggestion
+  pub line: Option<usize>,
+  /// The suggested text, or why there isn't one
+  pub resolution: Result<String>,
+}
+
+/// What the model proposes for one conflicted file
+pub struct FileProposals {
+  pub file: String,
+  pub proposals: Vec<Proposal>,
+  /// Why the model couldn't be asked at all, such as no readable versions of the file
+  pub error: Option<String>,
+}
+
+impl FileProposals {
+  /// Conflicts the model produced a candidate for
+  pub fn resolved(&self) -> usize {
+    self
+      .proposals
+      .iter()
+      .filter(|p| p.resolution.is_ok())
+      .count()
+  }
+
+  /// Conflicts left without a candidate; a file that couldn't be asked about counts as one
+  pub fn punted(&self) -> usize {
+    self.proposals.len() - self.resolved() + usize::from(self.error.is_some())
+  }
+}
+
+/// Ask the model about every conflict in a file without touching it
+pub fn propose(file: &str, whole_file: bool, labels: &llm::ConflictLabels) -> FileProposals {
+  propose_with(file, whole_file, |conflict| {
+    llm::suggest_conflict_resolution(file, conflict, labels)
+  })
+}
+
+/// `propose`, getting each suggestion from `suggest`
+fn propose_with(
+  file: &str,
+  whole_file: bool,
+  suggest: impl Fn(&llm::Conflict) -> Result<String>,
+) -> FileProposals {
+  let ask = |conflict: &llm::Conflict| {
+    suggest(conflict).and_then(|resolution| {
+      if conflict::has_markers(&resolution) {
+        Err(anyhow!("The suggestion still has conflict markers"))
+      } else {
+        Ok(resolution)
+      }
+    })
+  };
 
-    if ui::confirm("Continue rebase/merge?", true) {
-      match git::continue_rebase() {
-        Ok(()) => {
-          ui::success("Rebase continued successfully");
+  let mut proposals = FileProposals {
+    file: file.to_string(),
+    proposals: Vec::new(),
+    error: None,
+  };
+  match conflict_segments(file, whole_file) {
+    Some(segments) => {
+      for segment in &segments {
+        if let ConflictSegment::Conflict(hunk) = segment {
+          proposals.proposals.push(Proposal {
+            line: Some(hunk.line),
+            resolution: ask(&region(hunk)),
+          });
         }
-        Err(e) => {
-          let msg = e.to_string();
-          if msg.contains("No rebase") {
-            ui::dim("No active rebase. If this was a merge, run 'git commit' to complete.");
-          } else if msg.contains("conflict") {
-            ui::warn("More conflicts encountered");
-            ui::dim("Run 'alfred resolve' again");
-          } else {
-            ui::error(msg);
-          }
+      }
+    }
+    None => match whole_file_versions(file) {
+      Ok(info) => proposals.proposals.push(Proposal {
+        line: None,
+        resolution: ask(&whole_file_conflict(&info)),
+      }),
+      Err(e) => proposals.error = Some(e.to_string()),
+    },
+  }
+  proposals
+}
+
+/// `--check`: print what the model would do with each conflict and a summary, writing and
+/// staging nothing. Exits with `CHECK_INCOMPLETE` unless every conflict got a candidate
+fn check(target_file: Option<&str>, whole_file: bool) -> Result<i32> {
+  let plan = match plan(target_file)? {
+    Ok(plan) => plan,
+    Err(Outcome::NoConflicts) => return Ok(0),
+    Err(_) => return Ok(CHECK_INCOMPLETE),
+  };
+  let labels = plan.labels();
+
+  let mut results = Vec::new();
+  for file in &plan.files {
+    ui::heading(format!("Checking: {}", file));
+    ui::info("Analyzing conflicts...");
+    let proposals = propose(file, whole_file, &labels);
+
+    if let Some(error) = &proposals.error {
+      ui::warn(error);
+    }
+    for proposal in &proposals.proposals {
+      if let Some(line) = proposal.line {
+        ui::info(format!("Conflict at line {}", line));
+      }
+      match &proposal.resolution {
+        Ok(resolution) => {
+          ui::separator();
+          print!("{}", with_newline(resolution));
+          ui::separator();
         }
+        Err(e) => ui::warn(format!("No resolution: {}", e)),
       }
     }
+    println!();
+    results.push(proposals);
+  }
+
+  let width = results
+    .iter()
+    .map(|r| r.file.len())
+    .chain(std::iter::once("File".len()))
+    .max()
+    .unwrap_or_default();
+  ui::heading("Summary:");
+  println!(
+    "  {:<width$}  {:>8}  {:>6}",
+    "File",
+    "Resolved",
+    "Punted",
+    width = width
+  );
+  for result in &results {
+    let punted = format!("{:>6}", result.punted());
+    println!(
+      "  {:<width$}  {:>8}  {}",
+      result.file,
+      result.resolved(),
+      if result.punted() == 0 {
+        punted.normal()
+      } else {
+        punted.yellow()
+      },
+      width = width
+    );
+  }
+  println!();
+  ui::dim("Nothing was written or staged");
+
+  Ok(check_exit_code(&results))
+}
+
+/// 0 when every conflict got a candidate, else `CHECK_INCOMPLETE`
+fn check_exit_code(results: &[FileProposals]) -> i32 {
+  if results.iter().all(|r| r.punted() == 0) {
+    0
   } else {
-    ui::warn(format!(
-      "{} conflict(s) remaining",
-      remaining.conflicts.len()
-    ));
+    CHECK_INCOMPLETE
   }
+}
 
-  Ok(())
+#[cfg(test)]
+mod tests {
+  use super::super::test_support::{enter, git, init};
+  use super::*;
+  use crate::ui::script::{self, Answer};
+
+  const CONFLICTED: &str = "\
+fn a() {}
+<<<<<<< HEAD
+let x = 1;
+=======
+let x = 2;
+>>>>>>> feature
+fn b() {}
+<<<<<<< HEAD
+let y = 1;
+=======
+let y = 2;
+>>>>>>> feature
+";
+
+  fn conflicted_file() -> (tempfile::TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lib.rs");
+    fs::write(&path, CONFLICTED).unwrap();
+    (dir, path.to_string_lossy().into_owned())
+  }
+
+  #[test]
+  fn every_region_gets_a_proposal_and_the_file_is_untouched() {
+    let (_dir, file) = conflicted_file();
+    let proposals = propose_with(&file, false, |conflict| {
+      Ok(conflict.theirs.replace('2', "3"))
+    });
+    assert_eq!(proposals.error, None);
+    let lines: Vec<_> = proposals.proposals.iter().map(|p| p.line).collect();
+    assert_eq!(lines, [Some(2), Some(8)]);
+    assert_eq!(
+      proposals.proposals[1].resolution.as_deref().unwrap(),
+      "let y = 3;\n"
+    );
+    assert_eq!((proposals.resolved(), proposals.punted()), (2, 0));
+    assert_eq!(fs::read_to_string(&file).unwrap(), CONFLICTED);
+    assert_eq!(check_exit_code(&[proposals]), 0);
+  }
+
+  #[test]
+  fn failures_and_leftover_markers_are_punted() {
+    let (_dir, file) = conflicted_file();
+    let proposals = propose_with(&file, false, |conflict| {
+      if conflict.ours.contains('x') {
+        Err(anyhow!("model not loaded"))
+      } else {
+        Ok("<<<<<<< HEAD\nlet y = 1;\n".to_string())
+      }
+    });
+    assert_eq!((proposals.resolved(), proposals.punted()), (0, 2));
+    let second = proposals.proposals[1].resolution.as_ref().unwrap_err();
+    assert!(second.to_string().contains("still has conflict markers"));
+    assert_eq!(fs::read_to_string(&file).unwrap(), CONFLICTED);
+    assert_eq!(check_exit_code(&[proposals]), CHECK_INCOMPLETE);
+  }
+
+  #[test]
+  fn one_incomplete_file_fails_the_check() {
+    let done = FileProposals {
+      file: "a.rs".to_string(),
+      proposals: vec![Proposal {
+        line: None,
+        resolution: Ok("merged".to_string()),
+      }],
+      error: None,
+    };
+    let unreadable = FileProposals {
+      file: "b.rs".to_string(),
+      proposals: Vec::new(),
+      error: Some("Cannot extract conflict versions for b.rs".to_string()),
+    };
+    assert_eq!((unreadable.resolved(), unreadable.punted()), (0, 1));
+    assert_eq!(check_exit_code(std::slice::from_ref(&done)), 0);
+    assert_eq!(check_exit_code(&[done, unreadable]), CHECK_INCOMPLETE);
+    assert_eq!(check_exit_code(&[]), 0);
+  }
+
+  #[test]
+  fn long_conflict_lists_collapse_with_the_simplest_files_first() {
+    let dir = init();
+    let repo = dir.path();
+    let middle = "keep\n".repeat(10);
+    let write_all = |side: &str| {
+      for i in 1..=ui::PAGE_SIZE + 1 {
+        fs::write(repo.join(format!("f{:02}.txt", i)), format!("{}\n", side)).unwrap();
+      }
+      fs::write(
+        repo.join("big.txt"),
+        format!("{}\n{}{}\n", side, middle, side),
+      )
+      .unwrap();
+      git(repo, &["add", "-A"]);
+      git(repo, &["commit", "-qm", side]);
+    };
+    write_all("base");
+    git(repo, &["checkout", "-qb", "other"]);
+    write_all("theirs");
+    git(repo, &["checkout", "-q", "main"]);
+    write_all("ours");
+    let _ = std::process::Command::new("git")
+      .current_dir(repo)
+      .args(["merge", "-q", "other"])
+      .output();
+
+    let _cwd = enter(repo);
+    // The model is only loaded here, never asked
+    let unused = |_: &llm::Conflict| unreachable!();
+    let (plan, asked) = script::run(true, vec![Answer::No], || {
+      llm::with_conflict_suggestions(unused, || plan(None))
+    });
+    let plan = plan.unwrap().expect("conflicts to resolve");
+    assert_eq!(asked, ["expand"]);
+    assert_eq!(plan.files.len(), ui::PAGE_SIZE + 2);
+    assert_eq!(plan.files[0], "f01.txt");
+    assert_eq!(plan.files.last().unwrap(), "big.txt");
+  }
 }
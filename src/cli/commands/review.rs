@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+
+use crate::llm::{ReviewComment, Severity};
+use crate::risk::{self, RiskAssessment, RiskLevel};
+use crate::tests_changed::{self, TestSummary};
+use crate::{config, diff_filter, git, llm, ui};
+
+pub struct ReviewOptions {
+  /// Review only what's staged, instead of all uncommitted changes
+  pub staged: bool,
+  /// Review everything the branch changed since it left this base instead
+  pub branch: Option<String>,
+  /// Lowest severity to report: error, warn or info
+  pub severity: Option<String>,
+  /// Also flag high-risk changes that come without test changes
+  pub strict: bool,
+}
+
+/// Review the changes. Exits with 1 when any error is reported, for pre-commit hooks
+pub fn run(opts: ReviewOptions) -> Result<i32> {
+  if let Some(ref name) = opts.severity {
+    if Severity::parse(name).is_none() {
+      return Err(anyhow!(
+        "Unknown severity '{}'. Use error, warn or info",
+        name
+      ));
+    }
+  }
+
+  let ignore = diff_filter::ignore_patterns();
+  let diff = if let Some(ref base) = opts.branch {
+    let diff =
+      git::diff_range(base, "HEAD").with_context(|| format!("Cannot diff against '{}'", base))?;
+    diff_filter::filter_diff(&diff, &ignore)
+  } else if opts.staged {
+    git::diff_filtered(true, &ignore)?
+  } else {
+    diff_filter::filter_diff(&git::diff_worktree()?, &ignore)
+  };
+  if diff.trim().is_empty() {
+    ui::info(match opts.branch {
+      Some(ref base) => format!("No changes since {} to review", base),
+      None if opts.staged => "No staged changes to review".to_string(),
+      None => "No uncommitted changes to review".to_string(),
+    });
+    return Ok(0);
+  }
+
+  let mut comments = Vec::new();
+  let mut minimum = opts.severity.as_deref().and_then(Severity::parse);
+  if opts.strict {
+    let (level, findings) = strict_findings(opts.staged)?;
+    comments.extend(findings);
+    let lowered = strict_minimum(minimum, level);
+    if lowered != minimum {
+      if let Some(lowered) = lowered {
+        ui::dim(format!(
+          "High-risk change: reporting {} and above",
+          lowered.name()
+        ));
+      }
+      minimum = lowered;
+    }
+  }
+
+  // Big diffs are reviewed a few files at a time rather than cut to fit
+  let chunks = llm::chunk_diff(&diff);
+  let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+  if chunks.len() > 1 {
+    ui::info(format!("Reviewing changes in {} parts...", chunks.len()));
+  } else {
+    ui::info("Reviewing changes...");
+  }
+  match llm::review_diff(&chunks, minimum.map(|s| s.name())) {
+    Ok(found) => comments.extend(found),
+    Err(e) => {
+      ui::error(format!("Failed to review changes: {}", e));
+      ui::dim("Make sure you have run 'alfred setup'");
+      if comments.is_empty() {
+        return Ok(0);
+      }
+    }
+  }
+
+  if comments.is_empty() {
+    ui::success("No problems found");
+    return Ok(0);
+  }
+
+  render(&comments);
+
+  let count = |severity| comments.iter().filter(|c| c.severity == severity).count();
+  let errors = count(Severity::Error);
+  println!(
+    "{} error(s), {} warning(s), {} note(s)",
+    errors,
+    count(Severity::Warn),
+    count(Severity::Info)
+  );
+
+  Ok(if errors > 0 { 1 } else { 0 })
+}
+
+/// Deterministic checks for `--strict`: a high-risk change must come with test changes.
+/// Returns the change's risk level with the findings
+fn strict_findings(staged: bool) -> Result<(RiskLevel, Vec<ReviewComment>)> {
+  let cfg = config::current();
+  let mut changes = git::diff_stats(true)?;
+  if !staged {
+    changes.extend(git::diff_stats(false)?);
+  }
+
+  let assessment = risk::assess(&changes, &cfg.risk);
+  // Only a high-risk change needs the test detector
+  let tests = if assessment.level == RiskLevel::High {
+    tests_changed::changes(staged, &cfg.tests)?
+  } else {
+    TestSummary::default()
+  };
+  Ok((
+    assessment.level,
+    untested_risk(&assessment, &tests).into_iter().collect(),
+  ))
+}
+
+/// The `--severity` minimum under `--strict`: one step lower for a high-risk change, so its
+/// review reports more. Without a minimum everything is reported already
+fn strict_minimum(minimum: Option<Severity>, level: RiskLevel) -> Option<Severity> {
+  if level != RiskLevel::High {
+    return minimum;
+  }
+  minimum.map(|severity| match severity {
+    Severity::Error => Severity::Warn,
+    Severity::Warn | Severity::Info => Severity::Info,
+  })
+}
+
+/// The `--strict` finding for a high-risk change without test changes
+fn untested_risk(assessment: &RiskAssessment, tests: &TestSummary) -> Option<ReviewComment> {
+  if assessment.level != RiskLevel::High || !tests.tests.is_empty() {
+    return None;
+  }
+  Some(ReviewComment {
+    file: "(all changes)".to_string(),
+    line: None,
+    severity: Severity::Error,
+    message: format!(
+      "High-risk change (score {}) without test changes: {}",
+      assessment.score,
+      assessment.factors.join(", ")
+    ),
+  })
+}
+
+/// Comments grouped by file, in the order files first appear
+fn render(comments: &[ReviewComment]) {
+  let mut files: Vec<&str> = Vec::new();
+  for comment in comments {
+    if !files.contains(&comment.file.as_str()) {
+      files.push(&comment.file);
+    }
+  }
+
+  println!();
+  for file in files {
+    ui::heading(file);
+    for comment in comments.iter().filter(|c| c.file == file) {
+      let line = comment
+        .line
+        .map(|n| format!("{:>5}", n))
+        .unwrap_or_else(|| " ".repeat(5));
+      let severity = match comment.severity {
+        Severity::Error => comment.severity.name().red().bold(),
+        Severity::Warn => comment.severity.name().yellow(),
+        Severity::Info => comment.severity.name().blue(),
+      };
+      println!("{}  {:<5}  {}", line.dimmed(), severity, comment.message);
+    }
+    println!();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assessment(level: RiskLevel) -> RiskAssessment {
+    RiskAssessment {
+      score: 9,
+      level,
+      factors: vec!["touches src/auth.rs (+3)".to_string()],
+    }
+  }
+
+  fn summary(tests: &[&str]) -> TestSummary {
+    TestSummary {
+      production: vec!["src/auth.rs".to_string()],
+      tests: tests.iter().map(|t| t.to_string()).collect(),
+      covered: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn strict_flags_high_risk_changes_without_tests() {
+    let finding = untested_risk(&assessment(RiskLevel::High), &summary(&[])).unwrap();
+    assert_eq!(finding.severity, Severity::Error);
+    assert_eq!(finding.line, None);
+    assert_eq!(
+      finding.message,
+      "High-risk change (score 9) without test changes: touches src/auth.rs (+3)"
+    );
+  }
+
+  #[test]
+  fn strict_accepts_tested_or_lower_risk_changes() {
+    let tested = summary(&["tests/auth_test.rs"]);
+    assert!(untested_risk(&assessment(RiskLevel::High), &tested).is_none());
+    assert!(untested_risk(&assessment(RiskLevel::Medium), &summary(&[])).is_none());
+    assert!(untested_risk(&assessment(RiskLevel::Low), &summary(&[])).is_none());
+  }
+
+  #[test]
+  fn strict_lowers_the_minimum_for_high_risk_changes() {
+    let high = RiskLevel::High;
+    assert_eq!(
+      strict_minimum(Some(Severity::Error), high),
+      Some(Severity::Warn)
+    );
+    assert_eq!(
+      strict_minimum(Some(Severity::Warn), high),
+      Some(Severity::Info)
+    );
+    assert_eq!(
+      strict_minimum(Some(Severity::Info), high),
+      Some(Severity::Info)
+    );
+    assert_eq!(strict_minimum(None, high), None);
+  }
+
+  #[test]
+  fn strict_keeps_the_minimum_below_high_risk() {
+    for level in [RiskLevel::Low, RiskLevel::Medium] {
+      assert_eq!(
+        strict_minimum(Some(Severity::Error), level),
+        Some(Severity::Error)
+      );
+      assert_eq!(strict_minimum(None, level), None);
+    }
+  }
+}
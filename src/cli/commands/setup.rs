@@ -2,56 +2,271 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::{config, ui};
+use crate::{config, ollama, ui};
 
-struct ModelInfo {
-  name: &'static str,
-  size: &'static str,
-  url: &'static str,
-  filename: &'static str,
+/// Menu entry that points alfred at an existing Ollama server instead of downloading
+const USE_OLLAMA: &str = "Use existing Ollama";
+
+/// A model setup can install; the built-in ones are `'static`, `--url` ones borrow the
+/// command line
+struct ModelInfo<'a> {
+  name: &'a str,
+  alias: &'a str,
+  size: &'a str,
+  url: &'a str,
+  filename: &'a str,
+  /// Expected SHA256 of the file, as published by the model's host
+  sha256: Option<&'a str>,
 }
 
-const MODELS: &[ModelInfo] = &[
+impl<'a> ModelInfo<'a> {
+  /// A model from `--url`, known only by where it comes from and where it goes
+  fn custom(url: &'a str, filename: &'a str) -> Self {
+    Self {
+      name: filename,
+      alias: "",
+      size: "",
+      url,
+      filename,
+      sha256: None,
+    }
+  }
+}
+
+const MODELS: &[ModelInfo<'static>] = &[
     ModelInfo {
         name: "Phi-3 Mini 4K (Q4) - Recommended",
+        alias: "phi3",
         size: "2.4 GB",
         url: "https://huggingface.co/microsoft/Phi-3-mini-4k-instruct-gguf/resolve/main/Phi-3-mini-4k-instruct-q4.gguf",
         filename: "phi-3-mini-q4.gguf",
+        sha256: None,
     },
     ModelInfo {
         name: "Phi-3 Mini 4K (Q8)",
+        alias: "phi3-q8",
         size: "4.1 GB",
         url: "https://huggingface.co/microsoft/Phi-3-mini-4k-instruct-gguf/resolve/main/Phi-3-mini-4k-instruct-q8.gguf",
         filename: "phi-3-mini-q8.gguf",
+        sha256: None,
     },
     ModelInfo {
         name: "Qwen2.5-Coder 1.5B (Q4)",
+        alias: "qwen-coder",
         size: "1.0 GB",
         url: "https://huggingface.co/Qwen/Qwen2.5-Coder-1.5B-Instruct-GGUF/resolve/main/qwen2.5-coder-1.5b-instruct-q4_k_m.gguf",
         filename: "qwen2.5-coder-1.5b-q4.gguf",
+        sha256: None,
     },
 ];
 
-async fn download_with_progress(url: &str, dest_path: &std::path::Path) -> Result<()> {
+/// Download attempts before giving up in non-interactive mode
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// How often (in bytes) download progress is recorded in the state file
+const STATE_SAVE_INTERVAL: u64 = 16 * 1024 * 1024;
+
+pub struct SetupOptions {
+  /// Model filename or alias to install without prompting
+  pub model: Option<String>,
+  /// Skip all prompts
+  pub yes: bool,
+  /// Resume an interrupted setup without asking
+  pub resume: bool,
+  /// Offload all layers to the GPU
+  pub gpu: bool,
+  /// Download a GGUF model from this URL instead of the built-in list
+  pub url: Option<String>,
+  /// Filename to save a `--url` model as (default: the URL's last path segment)
+  pub name: Option<String>,
+}
+
+/// Failure categories reported in non-interactive mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorCategory {
+  UnknownModel,
+  Download,
+  Verify,
+  Config,
+}
+
+impl ErrorCategory {
+  fn as_str(&self) -> &'static str {
+    match self {
+      ErrorCategory::UnknownModel => "unknown_model",
+      ErrorCategory::Download => "download",
+      ErrorCategory::Verify => "verify",
+      ErrorCategory::Config => "config",
+    }
+  }
+
+  fn exit_code(&self) -> i32 {
+    match self {
+      ErrorCategory::UnknownModel => 2,
+      ErrorCategory::Download => 3,
+      ErrorCategory::Verify => 4,
+      ErrorCategory::Config => 5,
+    }
+  }
+}
+
+#[derive(Debug)]
+struct SetupError {
+  category: ErrorCategory,
+  source: anyhow::Error,
+}
+
+impl fmt::Display for SetupError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:#}", self.source)
+  }
+}
+
+impl std::error::Error for SetupError {}
+
+fn fail(category: ErrorCategory) -> impl FnOnce(anyhow::Error) -> SetupError {
+  move |source| SetupError { category, source }
+}
+
+/// Progress of a setup run, persisted so an interrupted setup can pick up where it stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Stage {
+  Downloading,
+  Downloaded,
+  Verified,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SetupState {
+  /// Filename of the selected model
+  model: String,
+  stage: Stage,
+  #[serde(default)]
+  bytes_downloaded: u64,
+  #[serde(default)]
+  total_bytes: u64,
+  /// Source of a model installed with `--url`; built-in models are looked up by filename
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  url: Option<String>,
+}
+
+impl SetupState {
+  fn new(model: &ModelInfo<'_>) -> Self {
+    Self {
+      model: model.filename.to_string(),
+      stage: Stage::Downloading,
+      bytes_downloaded: 0,
+      total_bytes: 0,
+      url: None,
+    }
+  }
+
+  fn load() -> Option<Self> {
+    let content = fs::read_to_string(config::setup_state_file()).ok()?;
+    serde_json::from_str(&content).ok()
+  }
+
+  fn save(&self) -> Result<()> {
+    let content = serde_json::to_string_pretty(self)?;
+    fs::write(config::setup_state_file(), content)
+      .with_context(|| "Failed to write setup state file")?;
+    Ok(())
+  }
+
+  fn clear() {
+    let _ = fs::remove_file(config::setup_state_file());
+  }
+
+  /// Reconcile the recorded stage with what is actually on disk
+  fn reconcile(&mut self, model_path: &Path) {
+    let part = config::part_path(model_path);
+    if let Ok(meta) = fs::metadata(&part) {
+      self.stage = Stage::Downloading;
+      self.bytes_downloaded = meta.len();
+    } else if model_path.exists() {
+      if self.stage == Stage::Downloading {
+        self.stage = Stage::Downloaded;
+      }
+    } else {
+      self.stage = Stage::Downloading;
+      self.bytes_downloaded = 0;
+    }
+  }
+
+  fn describe(&self) -> String {
+    match self.stage {
+      Stage::Downloading if self.total_bytes > 0 => format!(
+        "{}: downloaded {} of {}",
+        self.model,
+        format_bytes(self.bytes_downloaded),
+        format_bytes(self.total_bytes)
+      ),
+      Stage::Downloading => format!(
+        "{}: downloaded {}",
+        self.model,
+        format_bytes(self.bytes_downloaded)
+      ),
+      Stage::Downloaded => format!("{}: download complete, not yet verified", self.model),
+      Stage::Verified => format!("{}: verified, configuration not yet saved", self.model),
+    }
+  }
+}
+
+fn format_bytes(bytes: u64) -> String {
+  format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Match a model by filename, filename without extension, or alias
+fn find_model(query: &str) -> Option<&'static ModelInfo<'static>> {
+  let query = query.trim().to_lowercase();
+  MODELS.iter().find(|m| {
+    m.filename == query || m.filename.trim_end_matches(".gguf") == query || m.alias == query
+  })
+}
+
+/// Download into a `.part` file, resuming from any bytes already on disk
+async fn download_with_progress(url: &str, dest_path: &Path, state: &mut SetupState) -> Result<()> {
+  let part = config::part_path(dest_path);
+  let existing = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
   let client = reqwest::Client::new();
-  let response = client
-    .get(url)
+  let mut request = client.get(url);
+  if existing > 0 {
+    request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+  }
+
+  let response = request
     .send()
     .await
     .with_context(|| format!("Failed to download from {}", url))?;
 
+  // The part file already holds the whole model
+  if existing > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+    fs::rename(&part, dest_path)?;
+    return Ok(());
+  }
+
   if !response.status().is_success() {
-    return Err(anyhow::anyhow!(
-      "Download failed: {} {}",
-      response.status(),
-      response.status().canonical_reason().unwrap_or("")
-    ));
+    // The status prints with its reason phrase, e.g. "404 Not Found"
+    return Err(anyhow::anyhow!("Download failed: {}", response.status()));
   }
 
-  let total_size = response.content_length().unwrap_or(0);
+  // Servers that ignore the range request send the whole file again
+  let resumed = existing > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+  let offset = if resumed { existing } else { 0 };
+  let total_size = response.content_length().map(|l| l + offset).unwrap_or(0);
+
+  state.total_bytes = total_size;
+  state.bytes_downloaded = offset;
+  state.save()?;
 
   let pb = ProgressBar::new(total_size);
   pb.set_style(
@@ -60,12 +275,20 @@ async fn download_with_progress(url: &str, dest_path: &std::path::Path) -> Resul
       .unwrap()
       .progress_chars("█▓░"),
   );
+  pb.set_position(offset);
 
-  let mut file = fs::File::create(dest_path)
-    .with_context(|| format!("Failed to create file {}", dest_path.display()))?;
+  let mut file = if resumed {
+    fs::OpenOptions::new()
+      .append(true)
+      .open(&part)
+      .with_context(|| format!("Failed to open file {}", part.display()))?
+  } else {
+    fs::File::create(&part).with_context(|| format!("Failed to create file {}", part.display()))?
+  };
 
   let mut stream = response.bytes_stream();
-  let mut downloaded: u64 = 0;
+  let mut downloaded = offset;
+  let mut last_saved = offset;
 
   while let Some(chunk) = stream.next().await {
     let chunk = chunk.with_context(|| "Failed to read response chunk")?;
@@ -74,13 +297,306 @@ async fn download_with_progress(url: &str, dest_path: &std::path::Path) -> Resul
       .with_context(|| "Failed to write to file")?;
     downloaded += chunk.len() as u64;
     pb.set_position(downloaded);
+
+    if downloaded - last_saved >= STATE_SAVE_INTERVAL {
+      state.bytes_downloaded = downloaded;
+      state.save()?;
+      last_saved = downloaded;
+    }
   }
 
+  file.flush()?;
   pb.finish_and_clear();
+
+  if total_size > 0 && downloaded < total_size {
+    state.bytes_downloaded = downloaded;
+    state.save()?;
+    return Err(anyhow::anyhow!(
+      "Download interrupted at {} of {}",
+      format_bytes(downloaded),
+      format_bytes(total_size)
+    ));
+  }
+
+  fs::rename(&part, dest_path)
+    .with_context(|| format!("Failed to move download to {}", dest_path.display()))?;
+  Ok(())
+}
+
+/// Hex SHA256 of a file, read in chunks so multi-gigabyte models never sit in memory
+fn sha256_file(path: &Path) -> Result<String> {
+  let mut file =
+    fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+  let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+  let mut buf = vec![0u8; 1024 * 1024];
+  loop {
+    let n = file
+      .read(&mut buf)
+      .with_context(|| format!("Failed to read {}", path.display()))?;
+    if n == 0 {
+      break;
+    }
+    ctx.update(&buf[..n]);
+  }
+  Ok(
+    ctx
+      .finish()
+      .as_ref()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect(),
+  )
+}
+
+/// Check that the file is a complete GGUF model with the expected checksum
+fn verify_model(model_path: &Path, expected_size: u64, sha256: Option<&str>) -> Result<()> {
+  let mut magic = [0u8; 4];
+  fs::File::open(model_path)
+    .and_then(|mut f| f.read_exact(&mut magic))
+    .with_context(|| format!("Failed to read {}", model_path.display()))?;
+
+  if &magic != b"GGUF" {
+    return Err(anyhow::anyhow!(
+      "{} is not a GGUF model file",
+      model_path.display()
+    ));
+  }
+
+  let size = fs::metadata(model_path)?.len();
+  if expected_size > 0 && size != expected_size {
+    return Err(anyhow::anyhow!(
+      "{} is {} but {} was expected",
+      model_path.display(),
+      format_bytes(size),
+      format_bytes(expected_size)
+    ));
+  }
+
+  if let Some(expected) = sha256 {
+    let pb = ProgressBar::new_spinner();
+    pb.set_message("Checking SHA256...");
+    pb.enable_steady_tick(Duration::from_millis(100));
+    let actual = sha256_file(model_path);
+    pb.finish_and_clear();
+    let actual = actual?;
+    if !actual.eq_ignore_ascii_case(expected) {
+      return Err(anyhow::anyhow!(
+        "{} has SHA256 {} but {} was expected",
+        model_path.display(),
+        actual,
+        expected
+      ));
+    }
+  }
+
   Ok(())
 }
 
-pub async fn run() -> Result<()> {
+/// Drive the setup state machine from the recorded stage to completion
+async fn install(
+  model: &ModelInfo<'_>,
+  state: &mut SetupState,
+  opts: &SetupOptions,
+) -> std::result::Result<PathBuf, SetupError> {
+  let attempts = if opts.yes { DOWNLOAD_ATTEMPTS } else { 1 };
+  let model_path = config::models_dir().join(model.filename);
+  state.reconcile(&model_path);
+
+  if state.stage == Stage::Downloading {
+    if state.bytes_downloaded > 0 {
+      ui::info(format!(
+        "Resuming download of {} at {}...",
+        model.name,
+        format_bytes(state.bytes_downloaded)
+      ));
+    } else {
+      ui::info(format!("Downloading {}...", model.name));
+    }
+
+    let mut attempt = 1;
+    loop {
+      match download_with_progress(model.url, &model_path, state).await {
+        Ok(()) => break,
+        Err(e) if attempt < attempts => {
+          ui::warn(format!("Download failed ({}), retrying...", e));
+          tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+          attempt += 1;
+        }
+        Err(e) => return Err(fail(ErrorCategory::Download)(e)),
+      }
+    }
+
+    state.stage = Stage::Downloaded;
+    state.save().map_err(fail(ErrorCategory::Download))?;
+    ui::success("Model downloaded!");
+  } else {
+    ui::success(format!("Model already downloaded: {}", model.filename));
+  }
+
+  if state.stage == Stage::Downloaded {
+    if let Err(e) = verify_model(&model_path, state.total_bytes, model.sha256) {
+      // A corrupt model would only fail later, and less clearly, when it is loaded
+      let _ = fs::remove_file(&model_path);
+      state.stage = Stage::Downloading;
+      state.bytes_downloaded = 0;
+      state.save().map_err(fail(ErrorCategory::Verify))?;
+      return Err(fail(ErrorCategory::Verify)(e));
+    }
+    state.stage = Stage::Verified;
+    state.save().map_err(fail(ErrorCategory::Verify))?;
+    ui::success("Model verified");
+  }
+
+  let mut cfg = config::load_global().map_err(fail(ErrorCategory::Config))?;
+  cfg.model_path = Some(model_path.to_string_lossy().to_string());
+  cfg.backend = config::Backend::Local;
+  if opts.gpu {
+    cfg.n_gpu_layers = Some(config::ALL_GPU_LAYERS);
+  }
+  config::save(&cfg).map_err(fail(ErrorCategory::Config))?;
+
+  SetupState::clear();
+  Ok(model_path)
+}
+
+/// Decide whether to pick up an interrupted setup
+fn pending_state(opts: &SetupOptions) -> Result<Option<SetupState>> {
+  let state = match SetupState::load() {
+    Some(s) => s,
+    None => {
+      if opts.resume {
+        return Err(anyhow::anyhow!("No interrupted setup to resume"));
+      }
+      return Ok(None);
+    }
+  };
+
+  // An explicitly requested different model starts over
+  if let Some(requested) = opts.model.as_deref().and_then(find_model) {
+    if requested.filename != state.model {
+      return Ok(None);
+    }
+  }
+  if opts.url.is_some() && opts.url != state.url {
+    return Ok(None);
+  }
+
+  if state.url.is_none() && find_model(&state.model).is_none() {
+    SetupState::clear();
+    return Ok(None);
+  }
+
+  if opts.resume || opts.yes {
+    return Ok(Some(state));
+  }
+
+  ui::info(format!("Found interrupted setup: {}", state.describe()));
+  if ui::confirm("Resume where it left off?", true) {
+    Ok(Some(state))
+  } else {
+    SetupState::clear();
+    Ok(None)
+  }
+}
+
+/// Check a `--url` download and work out the filename to save it as
+fn custom_filename(url: &str, name: Option<&str>) -> std::result::Result<String, SetupError> {
+  let invalid = |msg: String| fail(ErrorCategory::UnknownModel)(anyhow::anyhow!(msg));
+
+  if !url.starts_with("https://") && !url.starts_with("http://") {
+    return Err(invalid(format!("Not an http(s) URL: {}", url)));
+  }
+  // Hosts like Hugging Face take query strings such as `?download=true`
+  let path = url.split(['?', '#']).next().unwrap_or(url);
+  if !path.ends_with(".gguf") {
+    return Err(invalid(format!("URL must point at a .gguf file: {}", url)));
+  }
+
+  let name = name.unwrap_or_else(|| path.rsplit('/').next().unwrap_or(path));
+  if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+    return Err(invalid(format!("Invalid model filename: {}", name)));
+  }
+  if name.ends_with(".gguf") {
+    Ok(name.to_string())
+  } else {
+    Ok(format!("{}.gguf", name))
+  }
+}
+
+/// Pick a model to download, or `None` to use Ollama instead
+fn select_model(
+  opts: &SetupOptions,
+) -> std::result::Result<Option<&'static ModelInfo<'static>>, SetupError> {
+  if let Some(query) = &opts.model {
+    return find_model(query).map(Some).ok_or_else(|| {
+      let known: Vec<&str> = MODELS.iter().map(|m| m.alias).collect();
+      fail(ErrorCategory::UnknownModel)(anyhow::anyhow!(
+        "Unknown model '{}'. Available: {}",
+        query,
+        known.join(", ")
+      ))
+    });
+  }
+
+  if opts.yes {
+    return Ok(Some(&MODELS[0]));
+  }
+
+  println!("Available models:");
+  for (i, model) in MODELS.iter().enumerate() {
+    println!(
+      "  {}. {} ({}) {}",
+      (i + 1).to_string().cyan(),
+      model.name,
+      model.size,
+      format!("[{}]", model.alias).dimmed()
+    );
+  }
+  println!();
+
+  let mut model_names: Vec<&str> = MODELS.iter().map(|m| m.name).collect();
+  model_names.push(USE_OLLAMA);
+  let selected = ui::select("Select a model to download:", &model_names)
+    .ok_or_else(|| fail(ErrorCategory::UnknownModel)(anyhow::anyhow!("No model selected")))?;
+
+  if selected == USE_OLLAMA {
+    return Ok(None);
+  }
+
+  MODELS
+    .iter()
+    .find(|m| m.name == selected)
+    .map(Some)
+    .ok_or_else(|| fail(ErrorCategory::UnknownModel)(anyhow::anyhow!("Invalid selection")))
+}
+
+/// Point alfred at a model served by Ollama; nothing is downloaded
+fn setup_ollama() -> Result<String> {
+  let mut cfg = config::load_global()?;
+
+  let url = ui::prompt(&format!("Ollama URL ({}):", cfg.ollama_url))
+    .unwrap_or_else(|| cfg.ollama_url.clone());
+
+  ui::info("Checking Ollama...");
+  let models = ollama::list_models(&url)?;
+  if models.is_empty() {
+    return Err(anyhow::anyhow!(
+      "Ollama has no models yet. Pull one first, e.g. 'ollama pull phi3'"
+    ));
+  }
+
+  let model = ui::select("Select an Ollama model:", &models)
+    .ok_or_else(|| anyhow::anyhow!("No model selected"))?;
+
+  cfg.backend = config::Backend::Ollama;
+  cfg.ollama_url = url;
+  cfg.ollama_model = Some(model.clone());
+  config::save(&cfg)?;
+
+  Ok(format!("{} (Ollama)", model))
+}
+
+pub async fn run(opts: SetupOptions) -> Result<i32> {
   ui::heading("Alfred Setup");
   println!();
   println!(
@@ -114,42 +630,67 @@ pub async fn run() -> Result<()> {
     println!();
   }
 
-  println!("Available models:");
-  for (i, model) in MODELS.iter().enumerate() {
-    println!(
-      "  {}. {} ({})",
-      (i + 1).to_string().cyan(),
-      model.name,
-      model.size
-    );
-  }
-  println!();
-
-  let model_names: Vec<&str> = MODELS.iter().map(|m| m.name).collect();
-  let selected = ui::select("Select a model to download:", &model_names)
-    .ok_or_else(|| anyhow::anyhow!("No model selected"))?;
-
-  let model = MODELS
-    .iter()
-    .find(|m| m.name == selected)
-    .ok_or_else(|| anyhow::anyhow!("Invalid selection"))?;
-
-  let model_path = models_dir.join(model.filename);
-
-  if model_path.exists() {
-    ui::success(format!("Model already downloaded: {}", model.filename));
-  } else {
-    ui::info(format!("Downloading {}...", model.name));
+  let result = match pending_state(&opts)? {
+    Some(mut state) => {
+      let (filename, url) = (state.model.clone(), state.url.clone());
+      let custom;
+      let model = match &url {
+        Some(url) => {
+          custom = ModelInfo::custom(url, &filename);
+          &custom
+        }
+        None => find_model(&filename).expect("state model was validated"),
+      };
+      install(model, &mut state, &opts).await
+    }
+    None => match &opts.url {
+      Some(url) => match custom_filename(url, opts.name.as_deref()) {
+        Ok(filename) => {
+          let model = ModelInfo::custom(url, &filename);
+          let mut state = SetupState::new(&model);
+          state.url = Some(url.clone());
+          install(&model, &mut state, &opts).await
+        }
+        Err(e) => Err(e),
+      },
+      None => match select_model(&opts) {
+        Ok(Some(model)) => {
+          let mut state = SetupState::new(model);
+          install(model, &mut state, &opts).await
+        }
+        Ok(None) => {
+          let model = setup_ollama()?;
+          print_complete(&model);
+          return Ok(0);
+        }
+        Err(e) => Err(e),
+      },
+    },
+  };
 
-    download_with_progress(model.url, &model_path).await?;
-    ui::success("Model downloaded!");
-  }
+  let model_path = match result {
+    Ok(path) => path,
+    Err(e) if opts.yes => {
+      eprintln!("setup-error: {}: {}", e.category.as_str(), e);
+      return Ok(e.category.exit_code());
+    }
+    Err(e) => {
+      match e.category {
+        ErrorCategory::Download => ui::dim("Run 'alfred setup' again to resume the download"),
+        ErrorCategory::Verify => {
+          ui::dim("The bad download was deleted; run 'alfred setup' to download it again")
+        }
+        _ => {}
+      }
+      return Err(e.into());
+    }
+  };
 
-  // Save config
-  let mut cfg = config::load()?;
-  cfg.model_path = Some(model_path.to_string_lossy().to_string());
-  config::save(&cfg)?;
+  print_complete(&model_path.display().to_string());
+  Ok(0)
+}
 
+fn print_complete(model: &str) {
   println!();
   ui::heading("Setup Complete!");
   println!(
@@ -166,7 +707,7 @@ pub async fn run() -> Result<()> {
     {}       - Same as git status
     {}         - Same as git push
 "#,
-    model_path.display(),
+    model,
     "alfred commit".cyan(),
     "alfred rebase".cyan(),
     "alfred resolve".cyan(),
@@ -174,6 +715,164 @@ pub async fn run() -> Result<()> {
     "alfred status".cyan(),
     "alfred push".cyan(),
   );
+}
 
-  Ok(())
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state(stage: Stage) -> SetupState {
+    SetupState {
+      stage,
+      ..SetupState::new(&MODELS[0])
+    }
+  }
+
+  #[test]
+  fn models_are_found_by_filename_stem_or_alias() {
+    for query in [
+      "qwen2.5-coder-1.5b-q4.gguf",
+      "qwen2.5-coder-1.5b-q4",
+      "qwen-coder",
+    ] {
+      assert_eq!(
+        find_model(query).map(|m| m.alias),
+        Some("qwen-coder"),
+        "{}",
+        query
+      );
+    }
+    assert_eq!(find_model(" PHI3 ").map(|m| m.alias), Some("phi3"));
+    assert!(find_model("phi").is_none());
+    assert!(find_model("").is_none());
+  }
+
+  #[test]
+  fn aliases_and_filenames_are_unique() {
+    for (i, a) in MODELS.iter().enumerate() {
+      for b in &MODELS[i + 1..] {
+        assert_ne!(a.alias, b.alias);
+        assert_ne!(a.filename, b.filename);
+      }
+    }
+  }
+
+  #[test]
+  fn state_file_round_trips_and_tolerates_missing_counts() {
+    let mut saved = state(Stage::Downloading);
+    saved.bytes_downloaded = 10;
+    saved.total_bytes = 20;
+    let json = serde_json::to_string(&saved).unwrap();
+    assert!(json.contains(r#""stage":"downloading""#), "{}", json);
+    assert!(!json.contains("url"), "{}", json);
+
+    let loaded: SetupState = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+      (loaded.stage, loaded.bytes_downloaded, loaded.total_bytes),
+      (Stage::Downloading, 10, 20)
+    );
+
+    let minimal: SetupState =
+      serde_json::from_str(r#"{"model": "phi-3-mini-q4.gguf", "stage": "verified"}"#).unwrap();
+    assert_eq!(minimal.stage, Stage::Verified);
+    assert_eq!(minimal.bytes_downloaded, 0);
+    assert!(minimal.url.is_none());
+  }
+
+  #[test]
+  fn reconcile_resumes_a_partial_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model.gguf");
+    fs::write(config::part_path(&model), [0u8; 42]).unwrap();
+
+    let mut s = state(Stage::Verified);
+    s.reconcile(&model);
+    assert_eq!((s.stage, s.bytes_downloaded), (Stage::Downloading, 42));
+  }
+
+  #[test]
+  fn reconcile_keeps_a_finished_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model.gguf");
+    fs::write(&model, b"GGUF").unwrap();
+
+    let mut s = state(Stage::Downloading);
+    s.reconcile(&model);
+    assert_eq!(s.stage, Stage::Downloaded);
+
+    // Only writing the config failed: the verified download isn't checked again
+    let mut s = state(Stage::Verified);
+    s.reconcile(&model);
+    assert_eq!(s.stage, Stage::Verified);
+  }
+
+  #[test]
+  fn reconcile_starts_over_when_the_file_is_gone() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = state(Stage::Verified);
+    s.bytes_downloaded = 99;
+    s.reconcile(&dir.path().join("model.gguf"));
+    assert_eq!((s.stage, s.bytes_downloaded), (Stage::Downloading, 0));
+  }
+
+  #[test]
+  fn error_categories_have_distinct_names_and_exit_codes() {
+    let all = [
+      ErrorCategory::UnknownModel,
+      ErrorCategory::Download,
+      ErrorCategory::Verify,
+      ErrorCategory::Config,
+    ];
+    for (i, a) in all.iter().enumerate() {
+      assert_ne!(a.exit_code(), 0);
+      assert_ne!(a.exit_code(), 1, "1 is left for unexpected errors");
+      for b in &all[i + 1..] {
+        assert_ne!(a.as_str(), b.as_str());
+        assert_ne!(a.exit_code(), b.exit_code());
+      }
+    }
+  }
+
+  #[test]
+  fn verify_rejects_files_that_are_not_models() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.gguf");
+    fs::write(&path, b"<html>").unwrap();
+    assert!(verify_model(&path, 0, None).is_err());
+
+    fs::write(&path, b"GGUF....").unwrap();
+    assert!(verify_model(&path, 0, None).is_ok());
+    assert!(verify_model(&path, 9, None).is_err());
+    assert!(verify_model(&path, 0, Some(&"0".repeat(64))).is_err());
+  }
+
+  #[test]
+  fn sha256_matches_known_vectors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs::write(&path, b"").unwrap();
+    assert_eq!(
+      sha256_file(&path).unwrap(),
+      "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    fs::write(&path, b"abc").unwrap();
+    assert_eq!(
+      sha256_file(&path).unwrap(),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+  }
+
+  #[test]
+  fn sha256_of_a_file_larger_than_one_read_covers_every_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    let content: Vec<u8> = (0..3 * 1024 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &content).unwrap();
+    let expected: String = ring::digest::digest(&ring::digest::SHA256, &content)
+      .as_ref()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect();
+    assert_eq!(sha256_file(&path).unwrap(), expected);
+  }
 }
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::{git, llm, ui};
+
+pub fn push(message: Option<String>) -> Result<()> {
+  let message = match message {
+    Some(m) => m,
+    None => {
+      let diff = git::diff_worktree()?;
+      if diff.is_empty() {
+        ui::info("No local changes to stash");
+        return Ok(());
+      }
+
+      ui::info("Generating stash message...");
+      let suggested = match llm::generate_stash_message(&diff) {
+        Ok(m) if !m.is_empty() => m,
+        Ok(_) => {
+          ui::error("Failed to generate message: the model returned nothing");
+          return Ok(());
+        }
+        Err(e) => {
+          ui::error(format!("Failed to generate message: {}", e));
+          ui::dim("Make sure you have run 'alfred setup', or pass a message with -m");
+          return Ok(());
+        }
+      };
+
+      ui::info(format!("Suggested: {}", suggested.cyan()));
+      if ui::confirm("Stash with this message?", true) {
+        suggested
+      } else {
+        match ui::prompt("Stash message:") {
+          Some(m) => m,
+          None => {
+            ui::info("Aborted");
+            return Ok(());
+          }
+        }
+      }
+    }
+  };
+
+  git::stash_push(&message)?;
+  ui::success(format!("Stashed: {}", message));
+  Ok(())
+}
+
+pub fn list(use_ai: bool) -> Result<()> {
+  let entries = git::stash_list()?;
+  if entries.is_empty() {
+    ui::info("No stash entries");
+    return Ok(());
+  }
+
+  ui::heading(format!("Stash entries ({}):", entries.len()));
+  for entry in &entries {
+    ui::list_item(format!(
+      "{} {} {}",
+      entry.name.cyan(),
+      entry.description,
+      format!("({})", entry.age).dimmed()
+    ));
+    if use_ai {
+      match git::stash_diff(&entry.name).and_then(|diff| llm::generate_stash_message(&diff)) {
+        Ok(summary) => ui::dim(format!("    {}", summary)),
+        Err(e) => ui::dim(format!("    (no summary: {})", e)),
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Changed lines of a patch containing `text`, ignoring case
+fn matching_lines<'a>(diff: &'a str, text: &str) -> Vec<&'a str> {
+  let needle = text.to_lowercase();
+  diff
+    .lines()
+    .filter(|l| {
+      (l.starts_with('+') || l.starts_with('-')) && !l.starts_with("+++") && !l.starts_with("---")
+    })
+    .filter(|l| l[1..].to_lowercase().contains(&needle))
+    .collect()
+}
+
+/// Lines shown for each matching entry
+const FIND_PREVIEW_LINES: usize = 3;
+
+/// List the stash entries whose changes add or remove a line containing `text`
+pub fn find(text: &str) -> Result<()> {
+  let entries = git::stash_list()?;
+  if entries.is_empty() {
+    ui::info("No stash entries");
+    return Ok(());
+  }
+
+  let mut found = 0;
+  for entry in &entries {
+    let diff = git::stash_diff(&entry.name)?;
+    let lines = matching_lines(&diff, text);
+    if lines.is_empty() {
+      continue;
+    }
+    found += 1;
+    ui::list_item(format!(
+      "{} {} {}",
+      entry.name.cyan(),
+      entry.description,
+      format!("({})", entry.age).dimmed()
+    ));
+    for line in lines.iter().take(FIND_PREVIEW_LINES) {
+      ui::dim(format!("    {}", line.trim_end()));
+    }
+    if lines.len() > FIND_PREVIEW_LINES {
+      ui::dim(format!("    ... {} more", lines.len() - FIND_PREVIEW_LINES));
+    }
+  }
+
+  if found == 0 {
+    ui::info(format!(
+      "No stash entry changes a line containing \"{}\"",
+      text
+    ));
+  }
+  Ok(())
+}
+
+pub fn pop() -> Result<()> {
+  let entries = git::stash_list()?;
+  let entry = match entries.len() {
+    0 => {
+      ui::info("No stash entries");
+      return Ok(());
+    }
+    1 => entries[0].clone(),
+    _ => {
+      let options: Vec<String> = entries
+        .iter()
+        .map(|e| format!("{} {}", e.name, e.description))
+        .collect();
+      let Some(choice) = ui::select("Pop which stash?", &options) else {
+        ui::info("Aborted");
+        return Ok(());
+      };
+      let index = options.iter().position(|o| *o == choice).unwrap_or(0);
+      entries[index].clone()
+    }
+  };
+
+  ui::info(format!("{} {}", entry.name.cyan(), entry.description));
+  if let Ok(stat) = git::stash_stat(&entry.name) {
+    println!("{}", stat);
+  }
+
+  let diff = git::stash_diff(&entry.name)?;
+  match llm::generate_stash_message(&diff) {
+    Ok(summary) => ui::info(format!("Restores: {}", summary)),
+    Err(e) => ui::dim(format!("No summary available: {}", e)),
+  }
+  println!();
+
+  if !ui::confirm(&format!("Pop {}?", entry.name), true) {
+    ui::info("Aborted");
+    return Ok(());
+  }
+
+  match git::stash_pop(&entry.name) {
+    Ok(()) => ui::success(format!("Restored {}", entry.name)),
+    Err(e) if !git::status()?.conflicts.is_empty() => {
+      ui::warn(format!("Conflicts restoring {}: {}", entry.name, e));
+      ui::dim("Run 'alfred resolve' to fix them; the stash entry was kept");
+    }
+    Err(e) => return Err(e),
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::matching_lines;
+
+  const DIFF: &str = "\
+diff --git a/src/login.rs b/src/login.rs
+--- a/src/login.rs
++++ b/src/login.rs
+@@ -1,3 +1,3 @@
+ fn login() {
+-  check_password(user);
++  check_token(user);
+ }
+";
+
+  #[test]
+  fn find_matches_changed_lines_ignoring_case() {
+    assert_eq!(
+      matching_lines(DIFF, "CHECK_"),
+      ["-  check_password(user);", "+  check_token(user);"]
+    );
+    assert_eq!(matching_lines(DIFF, "token"), ["+  check_token(user);"]);
+  }
+
+  #[test]
+  fn find_skips_context_and_file_headers() {
+    assert!(matching_lines(DIFF, "fn login").is_empty());
+    assert!(matching_lines(DIFF, "login.rs").is_empty());
+    assert!(matching_lines("", "anything").is_empty());
+  }
+}
@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::io::IsTerminal;
+
+use crate::{diff_filter, git, llm, ui};
+
+pub struct SummarizeOptions {
+  /// Branch to compare against (default: main or master)
+  pub base: Option<String>,
+  /// Show the diffstat and how the diff was split
+  pub verbose: bool,
+  /// Also write the summary to this file
+  pub output: Option<String>,
+}
+
+pub fn run(opts: SummarizeOptions) -> Result<()> {
+  // Keep stdout clean when piping the summary elsewhere
+  let interactive = std::io::stdout().is_terminal();
+
+  let base = opts
+    .base
+    .or_else(git::default_base)
+    .ok_or_else(|| anyhow!("No main or master branch found. Pass --base"))?;
+
+  let diff = diff_filter::filter_diff(
+    &git::diff_range(&base, "HEAD")?,
+    &diff_filter::ignore_patterns(),
+  );
+  if diff.is_empty() {
+    ui::info(format!("No changes since {}", base));
+    return Ok(());
+  }
+
+  let chunks = llm::chunk_diff(&diff);
+  if opts.verbose && interactive {
+    if let Ok(stat) = git::diff_range_stat(&base, "HEAD") {
+      println!("{}", stat);
+      println!();
+    }
+    ui::dim(format!(
+      "{} of diff in {} chunk(s)",
+      format_size(diff.len()),
+      chunks.len()
+    ));
+  }
+  if interactive {
+    ui::info(format!("Summarizing changes since {}...", base.cyan()));
+  }
+
+  let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+  let summary = match llm::summarize_changes(&chunks) {
+    Ok(summary) => summary.trim().to_string(),
+    Err(e) => {
+      ui::error(format!("Failed to summarize changes: {}", e));
+      ui::dim("Make sure you have run 'alfred setup'");
+      return Ok(());
+    }
+  };
+
+  if interactive {
+    println!();
+    ui::separator();
+    println!("{}", summary);
+    ui::separator();
+  } else {
+    println!("{}", summary);
+  }
+
+  if let Some(path) = opts.output {
+    fs::write(&path, format!("{}\n", summary))
+      .with_context(|| format!("Failed to write {}", path))?;
+    if interactive {
+      ui::success(format!("Written to {}", path));
+    }
+  }
+
+  Ok(())
+}
+
+fn format_size(bytes: usize) -> String {
+  if bytes < 1024 {
+    format!("{} B", bytes)
+  } else {
+    format!("{:.1} KB", bytes as f64 / 1024.0)
+  }
+}
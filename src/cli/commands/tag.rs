@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+
+use crate::{git, llm, ui};
+
+/// Treated as the previous release when the repository has no tags yet
+const INITIAL_TAG: &str = "v0.0.0";
+
+pub struct TagOptions {
+  /// Tag name; suggested from the commits when absent
+  pub name: Option<String>,
+  /// Annotation message (default: "Release <name>")
+  pub message: Option<String>,
+  /// GPG-sign the tag
+  pub sign: bool,
+}
+
+pub fn run(opts: TagOptions) -> Result<()> {
+  let name = match opts.name {
+    Some(name) => name,
+    None => match suggest()? {
+      Some(name) => name,
+      None => return Ok(()),
+    },
+  };
+
+  if git::is_tag(&name) {
+    return Err(anyhow!("Tag {} already exists", name));
+  }
+
+  let message = opts.message.unwrap_or_else(|| format!("Release {}", name));
+  git::create_tag(&name, &message, opts.sign)?;
+
+  ui::success(format!(
+    "Created {}tag {}",
+    if opts.sign { "signed " } else { "" },
+    name.cyan()
+  ));
+  ui::dim(format!("Push it with 'git push origin {}'", name));
+  Ok(())
+}
+
+/// Ask the model for the next version and let the user confirm or replace it
+fn suggest() -> Result<Option<String>> {
+  let latest = git::get_latest_tag().ok();
+  let from = latest.clone().unwrap_or_default();
+  let commits = git::get_commits_between(&from, "HEAD")?;
+
+  if commits.is_empty() {
+    ui::info(format!(
+      "No commits since {}",
+      latest.as_deref().unwrap_or("the start")
+    ));
+    return Ok(None);
+  }
+
+  match latest {
+    Some(ref tag) => ui::info(format!("{} commit(s) since {}", commits.len(), tag.cyan())),
+    None => ui::info(format!("No tags yet; {} commit(s) so far", commits.len())),
+  }
+  ui::info("Suggesting next version...");
+
+  let messages: Vec<String> = commits.iter().map(|c| c.message.clone()).collect();
+  let previous = latest.as_deref().unwrap_or(INITIAL_TAG);
+  let suggested = match llm::suggest_next_version(previous, &messages) {
+    Ok(version) => version,
+    Err(e) => {
+      ui::error(format!("Failed to suggest a version: {}", e));
+      ui::dim("Make sure you have run 'alfred setup', or pass the tag name");
+      return Ok(None);
+    }
+  };
+
+  ui::info(format!("Suggested: {}", suggested.cyan()));
+  if ui::confirm(&format!("Create tag {}?", suggested), true) {
+    return Ok(Some(suggested));
+  }
+
+  match ui::prompt("Tag name:") {
+    Some(name) => Ok(Some(name)),
+    None => {
+      ui::info("Aborted");
+      Ok(None)
+    }
+  }
+}
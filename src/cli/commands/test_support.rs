@@ -0,0 +1,69 @@
+//! Fixture repositories for testing commands, which run in the current directory
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
+use tempfile::TempDir;
+
+/// The current directory is shared by the whole process, so tests that change it take turns
+static CWD: Mutex<()> = Mutex::new(());
+
+/// The current directory moved into a fixture until dropped
+pub struct InRepo {
+  previous: PathBuf,
+  _turn: MutexGuard<'static, ()>,
+}
+
+impl Drop for InRepo {
+  fn drop(&mut self) {
+    let _ = std::env::set_current_dir(&self.previous);
+  }
+}
+
+/// Make `repo` the current directory, waiting for other tests doing the same to finish
+pub fn enter(repo: &Path) -> InRepo {
+  let turn = CWD.lock().unwrap_or_else(|e| e.into_inner());
+  let previous = std::env::current_dir().unwrap();
+  std::env::set_current_dir(repo).unwrap();
+  InRepo {
+    previous,
+    _turn: turn,
+  }
+}
+
+/// Run git in `repo`, returning its trimmed output
+pub fn git(repo: &Path, args: &[&str]) -> String {
+  let output = Command::new("git")
+    .current_dir(repo)
+    .args(["-c", "init.defaultBranch=main"])
+    .args(args)
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "git {:?}: {}",
+    args,
+    String::from_utf8_lossy(&output.stderr)
+  );
+  String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// An empty repository on `main`, with an identity for the commits alfred makes itself
+pub fn init() -> TempDir {
+  let dir = tempfile::tempdir().unwrap();
+  let repo = dir.path();
+  git(repo, &["init", "-q"]);
+  git(repo, &["config", "user.name", "Test"]);
+  git(repo, &["config", "user.email", "test@example.com"]);
+  git(repo, &["config", "commit.gpgsign", "false"]);
+  dir
+}
+
+/// Commit `content` as `file`, returning the new commit's hash
+pub fn commit(repo: &Path, file: &str, content: &str, subject: &str) -> String {
+  fs::write(repo.join(file), content).unwrap();
+  git(repo, &["add", file]);
+  git(repo, &["commit", "-qm", subject]);
+  git(repo, &["rev-parse", "HEAD"])
+}
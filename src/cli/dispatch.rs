@@ -0,0 +1,208 @@
+//! Decide whether a command line is for alfred or for git, before clap sees it.
+//!
+//! Alfred subcommands always win. Everything else, and anything after `alfred git`
+//! or `alfred --`, goes to git untouched.
+
+use anyhow::{bail, Result};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Dispatch {
+  /// Parse with clap. Git's global options that came first are split out
+  Alfred {
+    args: Vec<String>,
+    globals: Vec<GlobalOption>,
+  },
+  /// Run git with exactly these arguments
+  Git(Vec<String>),
+}
+
+/// A git option that goes before the subcommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalOption {
+  /// `-c name=value`
+  Config(String),
+  /// `-C <path>`
+  Dir(String),
+  /// `--git-dir=<path>`
+  GitDir(String),
+  /// `--work-tree=<path>`
+  WorkTree(String),
+  /// `--no-pager` / `-P`
+  NoPager,
+  /// Anything else git accepts there, kept verbatim (value included)
+  Other(Vec<String>),
+}
+
+/// Long global options that take a value, as `--opt value` or `--opt=value`
+const VALUE_OPTIONS: &[&str] = &[
+  "--git-dir",
+  "--work-tree",
+  "--namespace",
+  "--config-env",
+  "--super-prefix",
+  "--list-cmds",
+  "--attr-source",
+];
+
+/// Global options without a value
+const FLAG_OPTIONS: &[&str] = &[
+  "--no-pager",
+  "-P",
+  "-p",
+  "--paginate",
+  "--bare",
+  "--exec-path",
+  "--no-replace-objects",
+  "--no-lazy-fetch",
+  "--no-optional-locks",
+  "--no-advice",
+  "--literal-pathspecs",
+  "--glob-pathspecs",
+  "--noglob-pathspecs",
+  "--icase-pathspecs",
+];
+
+/// Flags alfred answers itself when they come first
+const ALFRED_FLAGS: &[&str] = &["-h", "--help", "-V", "--version"];
+
+/// Git commands alfred only takes over when given its own flag; otherwise they go to git
+/// untouched, so `alfred log --oneline` stays `git log --oneline`
+const CLAIMED_WITH_FLAG: &[(&str, &str)] = &[("log", "--ai"), ("merge", "--ai")];
+
+/// Git commands alfred takes over only without a git subcommand after them, so
+/// `alfred bisect good` stays `git bisect good`
+const CLAIMED_WITHOUT_SUBCOMMAND: &[&str] = &["bisect"];
+
+/// Alfred's global flags, accepted before the subcommand
+const ALFRED_GLOBALS: &[&str] = &["-y", "--yes"];
+
+/// Split leading git global options off `args`, returning them and the index of the subcommand
+fn take_globals(args: &[String]) -> (Vec<GlobalOption>, usize) {
+  let mut globals = Vec::new();
+  let mut i = 0;
+
+  while let Some(arg) = args.get(i) {
+    let next = args.get(i + 1).cloned();
+    let (option, used) = match (arg.as_str(), next) {
+      ("-c", Some(value)) => (GlobalOption::Config(value), 2),
+      ("-C", Some(value)) => (GlobalOption::Dir(value), 2),
+      ("--no-pager" | "-P", _) => (GlobalOption::NoPager, 1),
+      (flag, _) if FLAG_OPTIONS.contains(&flag) || flag.starts_with("--exec-path=") => {
+        (GlobalOption::Other(vec![flag.to_string()]), 1)
+      }
+      (name, Some(value)) if VALUE_OPTIONS.contains(&name) => {
+        let raw = vec![name.to_string(), value.clone()];
+        (with_value(name, value, raw), 2)
+      }
+      (long, _) => match long.split_once('=') {
+        Some((name, value)) if VALUE_OPTIONS.contains(&name) => (
+          with_value(name, value.to_string(), vec![long.to_string()]),
+          1,
+        ),
+        _ => break,
+      },
+    };
+    globals.push(option);
+    i += used;
+  }
+
+  (globals, i)
+}
+
+fn with_value(name: &str, value: String, raw: Vec<String>) -> GlobalOption {
+  match name {
+    "--git-dir" => GlobalOption::GitDir(value),
+    "--work-tree" => GlobalOption::WorkTree(value),
+    _ => GlobalOption::Other(raw),
+  }
+}
+
+/// Route `args` (without the program name) given alfred's subcommand names and aliases
+pub fn dispatch(args: Vec<String>, commands: &[String]) -> Dispatch {
+  match args.first().map(String::as_str) {
+    None => {
+      return Dispatch::Alfred {
+        args,
+        globals: Vec::new(),
+      }
+    }
+    // Escape hatches that always reach git
+    Some("git") | Some("--") => return Dispatch::Git(args[1..].to_vec()),
+    Some(first) if ALFRED_FLAGS.contains(&first) => {
+      return Dispatch::Alfred {
+        args,
+        globals: Vec::new(),
+      }
+    }
+    _ => {}
+  }
+
+  // `alfred -y commit`: alfred's own flags, kept for clap and never passed to git
+  let own = args
+    .iter()
+    .take_while(|a| ALFRED_GLOBALS.contains(&a.as_str()))
+    .count();
+  let (globals, index) = take_globals(&args[own..]);
+  let index = own + index;
+  match args.get(index) {
+    Some(command) if commands.contains(command) && claimed(command, &args[index + 1..]) => {
+      Dispatch::Alfred {
+        args: args[..own].iter().chain(&args[index..]).cloned().collect(),
+        globals,
+      }
+    }
+    None if own > 0 => Dispatch::Alfred { args, globals },
+    _ => Dispatch::Git(args[own..].to_vec()),
+  }
+}
+
+/// Whether alfred handles `command` given the arguments after it
+fn claimed(command: &str, rest: &[String]) -> bool {
+  if CLAIMED_WITHOUT_SUBCOMMAND.contains(&command) {
+    return rest.first().is_none_or(|a| a.starts_with('-'));
+  }
+  match CLAIMED_WITH_FLAG.iter().find(|(c, _)| *c == command) {
+    Some((_, flag)) => rest
+      .iter()
+      .take_while(|a| a.as_str() != "--")
+      .any(|a| a == flag),
+    None => true,
+  }
+}
+
+/// Make git options given before an alfred command apply to every git call it makes
+pub fn apply(globals: &[GlobalOption]) -> Result<()> {
+  let mut configs = Vec::new();
+
+  for option in globals {
+    match option {
+      GlobalOption::Config(pair) => configs.push(pair),
+      GlobalOption::Dir(path) => std::env::set_current_dir(path)
+        .map_err(|e| anyhow::anyhow!("Cannot change to '{}': {}", path, e))?,
+      GlobalOption::GitDir(path) => std::env::set_var("GIT_DIR", path),
+      GlobalOption::WorkTree(path) => std::env::set_var("GIT_WORK_TREE", path),
+      GlobalOption::NoPager => std::env::set_var("GIT_PAGER", "cat"),
+      GlobalOption::Other(raw) => bail!(
+        "'{}' isn't supported before alfred commands. Use 'alfred git {} ...' to run git directly",
+        raw.join(" "),
+        raw.join(" ")
+      ),
+    }
+  }
+
+  // `-c` for child processes, the same way git passes it to its own subprocesses
+  if !configs.is_empty() {
+    let offset: usize = std::env::var("GIT_CONFIG_COUNT")
+      .ok()
+      .and_then(|n| n.parse().ok())
+      .unwrap_or(0);
+    for (n, pair) in configs.iter().enumerate() {
+      let (key, value) = pair.split_once('=').unwrap_or((pair.as_str(), "true"));
+      std::env::set_var(format!("GIT_CONFIG_KEY_{}", offset + n), key);
+      std::env::set_var(format!("GIT_CONFIG_VALUE_{}", offset + n), value);
+    }
+    std::env::set_var("GIT_CONFIG_COUNT", (offset + configs.len()).to_string());
+  }
+
+  Ok(())
+}
@@ -0,0 +1,215 @@
+//! Recent commit subjects picked as examples for the `repo` commit style, cached in the git
+//! directory
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::git;
+
+/// Subjects read from history when sampling
+const HISTORY: usize = 200;
+
+/// Examples put in the prompt
+const EXAMPLES: usize = 8;
+
+/// `alfred-style-cache` in the git directory, one subject per line
+const CACHE_FILE: &str = "alfred-style-cache";
+
+/// How long sampled examples are reused before history is read again
+const CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Prefixes of subjects that say nothing about the repository's style
+const NOISE_PREFIXES: &[&str] = &["Merge ", "Revert \"", "fixup!", "squash!", "amend!"];
+
+/// Merges, reverts, autosquash commits and work in progress follow git's or a tool's wording
+fn is_noise(subject: &str) -> bool {
+  let lower = subject.to_lowercase();
+  subject.is_empty()
+    || NOISE_PREFIXES.iter().any(|p| subject.starts_with(p))
+    || lower == "wip"
+    || lower.starts_with("wip:")
+    || lower.starts_with("wip ")
+}
+
+/// Up to `n` subjects worth imitating, each once, spread evenly over `subjects` so one busy
+/// stretch of history doesn't set the style
+pub fn select_examples(subjects: &[String], n: usize) -> Vec<String> {
+  let mut seen = HashSet::new();
+  let candidates: Vec<&str> = subjects
+    .iter()
+    .map(|s| s.trim())
+    .filter(|s| !is_noise(s))
+    .filter(|s| seen.insert(s.to_lowercase()))
+    .collect();
+
+  if candidates.len() <= n {
+    return candidates.into_iter().map(String::from).collect();
+  }
+  (0..n)
+    .map(|i| candidates[i * candidates.len() / n].to_string())
+    .collect()
+}
+
+fn read_cache(path: &Path) -> Option<Vec<String>> {
+  let age = fs::metadata(path)
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+  if age > CACHE_MAX_AGE {
+    return None;
+  }
+  let examples: Vec<String> = fs::read_to_string(path)
+    .ok()?
+    .lines()
+    .filter(|l| !l.trim().is_empty())
+    .map(String::from)
+    .collect();
+  (!examples.is_empty()).then_some(examples)
+}
+
+/// Examples for the prompt, from the cache unless `refresh` is set or it's gone stale.
+/// Empty in a repository without usable history
+pub fn examples(refresh: bool) -> Vec<String> {
+  examples_with(git::git_path(CACHE_FILE).as_deref(), refresh, || {
+    git::recent_commit_subjects(HISTORY).unwrap_or_default()
+  })
+}
+
+/// `examples` with the cache at `cache` and history read by `history`
+fn examples_with(
+  cache: Option<&Path>,
+  refresh: bool,
+  history: impl FnOnce() -> Vec<String>,
+) -> Vec<String> {
+  if !refresh {
+    if let Some(examples) = cache.and_then(read_cache) {
+      return examples;
+    }
+  }
+
+  let examples = select_examples(&history(), EXAMPLES);
+  if !examples.is_empty() {
+    if let Some(path) = cache {
+      let _ = fs::write(path, examples.join("\n") + "\n");
+    }
+  }
+  examples
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{examples_with, is_noise, select_examples, CACHE_MAX_AGE};
+  use std::fs;
+  use std::time::{Duration, SystemTime};
+
+  fn subjects(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn noise_is_tool_wording() {
+    for subject in [
+      "",
+      "Merge branch 'main' into feature",
+      "Merge pull request #12 from a/b",
+      "Revert \"Add login\"",
+      "fixup! Add login",
+      "squash! Add login",
+      "amend! Add login",
+      "WIP",
+      "wip: half done",
+      "WIP on the parser",
+    ] {
+      assert!(is_noise(subject), "{:?}", subject);
+    }
+    for subject in [
+      "Add login",
+      "Merged state is kept",
+      "Wipe the cache",
+      "Reverting is safe",
+    ] {
+      assert!(!is_noise(subject), "{:?}", subject);
+    }
+  }
+
+  #[test]
+  fn examples_skip_noise_and_repeats() {
+    let history = subjects(&[
+      "Merge branch 'main' into feature",
+      "Add login",
+      "fixup! Add login",
+      "  add login  ",
+      "Revert \"Add login\"",
+      "Fix the parser",
+      "wip",
+      "Fix the parser",
+    ]);
+    assert_eq!(
+      select_examples(&history, 8),
+      ["Add login", "Fix the parser"]
+    );
+  }
+
+  #[test]
+  fn examples_spread_over_history() {
+    let history: Vec<String> = (0..20).map(|i| format!("Change {}", i)).collect();
+    assert_eq!(
+      select_examples(&history, 4),
+      ["Change 0", "Change 5", "Change 10", "Change 15"]
+    );
+    assert!(select_examples(&history, 0).is_empty());
+    assert!(select_examples(&[], 4).is_empty());
+  }
+
+  #[test]
+  fn the_cache_is_reused_until_refreshed() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("alfred-style-cache");
+
+    let first = examples_with(Some(&cache), false, || subjects(&["Add login"]));
+    assert_eq!(first, ["Add login"]);
+    assert_eq!(fs::read_to_string(&cache).unwrap(), "Add login\n");
+
+    let cached = examples_with(Some(&cache), false, || panic!("history read"));
+    assert_eq!(cached, ["Add login"]);
+
+    let refreshed = examples_with(Some(&cache), true, || subjects(&["Fix the parser"]));
+    assert_eq!(refreshed, ["Fix the parser"]);
+    assert_eq!(fs::read_to_string(&cache).unwrap(), "Fix the parser\n");
+  }
+
+  #[test]
+  fn stale_or_empty_caches_are_replaced() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("alfred-style-cache");
+
+    fs::write(&cache, "Old style\n").unwrap();
+    let stale = SystemTime::now() - CACHE_MAX_AGE - Duration::from_secs(60);
+    fs::File::options()
+      .write(true)
+      .open(&cache)
+      .unwrap()
+      .set_modified(stale)
+      .unwrap();
+    let examples = examples_with(Some(&cache), false, || subjects(&["Add login"]));
+    assert_eq!(examples, ["Add login"]);
+
+    fs::write(&cache, "\n  \n").unwrap();
+    let examples = examples_with(Some(&cache), false, || subjects(&["Fix the parser"]));
+    assert_eq!(examples, ["Fix the parser"]);
+  }
+
+  #[test]
+  fn empty_history_leaves_the_cache_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("alfred-style-cache");
+    assert!(examples_with(Some(&cache), true, Vec::new).is_empty());
+    assert!(!cache.exists());
+    assert_eq!(
+      examples_with(None, false, || subjects(&["Add login"])),
+      ["Add login"]
+    );
+  }
+}
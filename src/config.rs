@@ -1,7 +1,10 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DaemonConfig {
@@ -11,12 +14,36 @@ pub struct DaemonConfig {
   pub idle_timeout_minutes: u32,
   #[serde(default)]
   pub auto_start: bool,
+  /// Longest a command waits for a daemon it started to load the model and listen; 0 waits
+  /// for as long as the daemon is still starting
+  #[serde(default = "default_auto_start_timeout")]
+  pub auto_start_timeout_secs: u64,
+  /// Encrypt the local TCP channel with a self-signed certificate
+  #[serde(default)]
+  pub tls: bool,
+  /// Secret every request must carry; unset, any local process can use the daemon
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub auth_token: Option<String>,
+  /// Also serve a plain HTTP API on this port
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub http_port: Option<u16>,
+  /// Largest prompt a request may carry; longer requests are refused unread
+  #[serde(default = "default_max_prompt_bytes")]
+  pub max_prompt_bytes: usize,
 }
 
 fn default_port() -> u16 {
   7654
 }
 
+fn default_auto_start_timeout() -> u64 {
+  120
+}
+
+fn default_max_prompt_bytes() -> usize {
+  4 * 1024 * 1024
+}
+
 fn default_idle_timeout() -> u32 {
   30
 }
@@ -27,16 +54,619 @@ impl Default for DaemonConfig {
       port: default_port(),
       idle_timeout_minutes: default_idle_timeout(),
       auto_start: false,
+      auto_start_timeout_secs: default_auto_start_timeout(),
+      tls: false,
+      auth_token: None,
+      http_port: None,
+      max_prompt_bytes: default_max_prompt_bytes(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskConfig {
+  /// Glob patterns for paths that make any change riskier
+  #[serde(default = "default_sensitive_paths")]
+  pub sensitive_paths: Vec<String>,
+  /// Minimum score classified as medium risk
+  #[serde(default = "default_medium_threshold")]
+  pub medium_threshold: u32,
+  /// Minimum score classified as high risk
+  #[serde(default = "default_high_threshold")]
+  pub high_threshold: u32,
+}
+
+fn default_sensitive_paths() -> Vec<String> {
+  [
+    ".github/**",
+    ".gitlab-ci.yml",
+    ".circleci/**",
+    "Jenkinsfile",
+    "**/migrations/**",
+    "**/*auth*",
+    "**/*crypto*",
+    "**/*secret*",
+    "**/*.pem",
+  ]
+  .iter()
+  .map(|s| s.to_string())
+  .collect()
+}
+
+fn default_medium_threshold() -> u32 {
+  3
+}
+
+fn default_high_threshold() -> u32 {
+  6
+}
+
+impl Default for RiskConfig {
+  fn default() -> Self {
+    Self {
+      sensitive_paths: default_sensitive_paths(),
+      medium_threshold: default_medium_threshold(),
+      high_threshold: default_high_threshold(),
+    }
+  }
+}
+
+/// Issue lookups on GitHub/GitLab used as prompt context
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForgeConfig {
+  #[serde(default = "default_true")]
+  pub enabled: bool,
+  /// API root, for self-hosted forges the origin URL doesn't identify
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub api_url: Option<String>,
+  /// Used when GITHUB_TOKEN / GH_TOKEN / GITLAB_TOKEN are unset
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub token: Option<String>,
+  /// How much of the issue description to include in prompts
+  #[serde(default = "default_description_chars")]
+  pub description_chars: usize,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn default_description_chars() -> usize {
+  500
+}
+
+impl Default for ForgeConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      api_url: None,
+      token: None,
+      description_chars: default_description_chars(),
+    }
+  }
+}
+
+/// Extra conventions for recognizing test files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TestsConfig {
+  /// Glob patterns for test paths, on top of the built-in conventions
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub patterns: Vec<String>,
+}
+
+/// Where a ticket reference found in the branch name goes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketPlacement {
+  /// A `Refs: PROJ-123` trailer
+  #[default]
+  Trailer,
+  /// Appended to the subject: `feat: add login (PROJ-123)`
+  Subject,
+  /// Don't add ticket references
+  Off,
+}
+
+/// Trailers and references added to every commit `alfred commit` makes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitConfig {
+  /// Add `Signed-off-by:` with the git user's identity (DCO)
+  #[serde(default)]
+  pub signoff: bool,
+  /// `Name <email>` entries added as `Co-authored-by:`
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub co_authors: Vec<String>,
+  /// Regex matching a ticket ID in the branch name
+  #[serde(default = "default_ticket_pattern")]
+  pub ticket_pattern: String,
+  #[serde(default)]
+  pub ticket_placement: TicketPlacement,
+}
+
+fn default_ticket_pattern() -> String {
+  r"[A-Z]+-\d+".to_string()
+}
+
+impl Default for CommitConfig {
+  fn default() -> Self {
+    Self {
+      signoff: false,
+      co_authors: Vec::new(),
+      ticket_pattern: default_ticket_pattern(),
+      ticket_placement: TicketPlacement::default(),
+    }
+  }
+}
+
+/// What `alfred lint-commit` (and the commit-msg hook) enforces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitRules {
+  /// Longest allowed subject line, in characters
+  #[serde(default = "default_max_subject_length")]
+  pub max_subject_length: usize,
+  /// Longest allowed body line; 0 disables the check
+  #[serde(default)]
+  pub max_body_line_length: usize,
+  /// Require a `type(scope): description` subject
+  #[serde(default)]
+  pub require_conventional: bool,
+  /// Types accepted when `require_conventional` is set
+  #[serde(default = "default_commit_types")]
+  pub types: Vec<String>,
+}
+
+fn default_max_subject_length() -> usize {
+  72
+}
+
+fn default_commit_types() -> Vec<String> {
+  [
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+  ]
+  .iter()
+  .map(|s| s.to_string())
+  .collect()
+}
+
+impl Default for CommitRules {
+  fn default() -> Self {
+    Self {
+      max_subject_length: default_max_subject_length(),
+      max_body_line_length: 0,
+      require_conventional: false,
+      types: default_commit_types(),
+    }
+  }
+}
+
+/// Whether generation may fall back to loading the model in this process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreferDaemon {
+  /// Use the daemon when it is free soon enough, otherwise generate locally
+  #[default]
+  Auto,
+  /// Only ever generate through the daemon; for machines that can't hold two models
+  Strict,
+}
+
+/// How clients share the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LlmConfig {
+  /// Longest a client waits for a busy daemon before generating locally
+  #[serde(default = "default_busy_wait_secs")]
+  pub busy_wait_secs: u64,
+  #[serde(default)]
+  pub prefer_daemon: PreferDaemon,
+}
+
+fn default_busy_wait_secs() -> u64 {
+  10
+}
+
+impl Default for LlmConfig {
+  fn default() -> Self {
+    Self {
+      busy_wait_secs: default_busy_wait_secs(),
+      prefer_daemon: PreferDaemon::default(),
+    }
+  }
+}
+
+/// How the model picks each token. Lower temperature and a fixed seed make output repeatable
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SamplingConfig {
+  #[serde(default = "default_temperature")]
+  pub temperature: f64,
+  #[serde(default = "default_top_k")]
+  pub top_k: u32,
+  #[serde(default = "default_top_p")]
+  pub top_p: f64,
+  #[serde(default = "default_seed")]
+  pub seed: u32,
+  /// Above 1.0 discourages repeating recent tokens; 1.0 turns it off
+  #[serde(default = "default_repeat_penalty")]
+  pub repeat_penalty: f64,
+}
+
+fn default_temperature() -> f64 {
+  0.7
+}
+
+fn default_top_k() -> u32 {
+  40
+}
+
+fn default_top_p() -> f64 {
+  0.9
+}
+
+fn default_seed() -> u32 {
+  42
+}
+
+fn default_repeat_penalty() -> f64 {
+  1.0
+}
+
+impl Default for SamplingConfig {
+  fn default() -> Self {
+    Self {
+      temperature: default_temperature(),
+      top_k: default_top_k(),
+      top_p: default_top_p(),
+      seed: default_seed(),
+      repeat_penalty: default_repeat_penalty(),
+    }
+  }
+}
+
+/// Alias of the model used by every task that `tasks` doesn't point elsewhere
+pub const DEFAULT_MODEL: &str = "default";
+
+/// A model file under an alias in `models`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelProfile {
+  pub path: String,
+  /// Context window for this model; unset uses `context_size`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub context_size: Option<u32>,
+}
+
+/// What generates text, for picking a model per task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+  Commit,
+  Branch,
+  Resolve,
+  Rebase,
+}
+
+impl Task {
+  pub const ALL: [Task; 4] = [Task::Commit, Task::Branch, Task::Resolve, Task::Rebase];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Task::Commit => "commit",
+      Task::Branch => "branch",
+      Task::Resolve => "resolve",
+      Task::Rebase => "rebase",
+    }
+  }
+
+  pub fn parse(name: &str) -> Option<Task> {
+    Task::ALL.into_iter().find(|task| task.name() == name)
+  }
+}
+
+/// The model alias each task uses; unset tasks use `default`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskModels {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub commit: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub branch: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub resolve: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rebase: Option<String>,
+}
+
+impl TaskModels {
+  pub fn get(&self, task: Task) -> &str {
+    let alias = match task {
+      Task::Commit => &self.commit,
+      Task::Branch => &self.branch,
+      Task::Resolve => &self.resolve,
+      Task::Rebase => &self.rebase,
+    };
+    alias.as_deref().unwrap_or(DEFAULT_MODEL)
+  }
+
+  /// Point `task` at `alias`; `default` clears the entry, since unset tasks use it
+  pub fn set(&mut self, task: Task, alias: &str) {
+    let alias = (alias != DEFAULT_MODEL).then(|| alias.to_string());
+    match task {
+      Task::Commit => self.commit = alias,
+      Task::Branch => self.branch = alias,
+      Task::Resolve => self.resolve = alias,
+      Task::Rebase => self.rebase = alias,
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    *self == TaskModels::default()
+  }
+}
+
+/// A model alias resolved to the file to load and the context to run it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelSpec {
+  pub path: PathBuf,
+  pub context_size: u32,
+}
+
+/// How generated commit subjects are formatted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStyle {
+  /// `feat(scope): description`
+  #[default]
+  Conventional,
+  /// `✨ feat(scope): description`
+  Gitmoji,
+  /// A plain imperative sentence with no type prefix
+  Plain,
+  /// Whatever format the repository's recent commit subjects use
+  Repo,
+}
+
+/// Where generation runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+  /// Bundled llama.cpp with a downloaded GGUF model
+  #[default]
+  Local,
+  /// An existing Ollama server
+  Ollama,
+  /// An OpenAI-compatible chat-completions API
+  Remote,
+}
+
+impl Backend {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Backend::Local => "local",
+      Backend::Ollama => "ollama",
+      Backend::Remote => "remote",
     }
   }
+
+  /// Whether generation happens in this process (so the daemon is worth keeping warm)
+  pub fn is_local(&self) -> bool {
+    *self == Backend::Local
+  }
+}
+
+/// Environment variable that overrides `remote.api_key`
+pub const API_KEY_ENV: &str = "ALFRED_API_KEY";
+
+/// OpenAI-compatible API used by the `remote` backend
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteConfig {
+  /// Base URL including the version, e.g. `https://api.openai.com/v1`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub api_base: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub api_key: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub model: Option<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+impl RemoteConfig {
+  /// The API key, preferring the environment over the config file
+  pub fn key(&self) -> Option<String> {
+    std::env::var(API_KEY_ENV)
+      .ok()
+      .or_else(|| self.api_key.clone())
+      .filter(|k| !k.is_empty())
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+  #[serde(default)]
+  pub backend: Backend,
+  #[serde(default = "default_ollama_url")]
+  pub ollama_url: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ollama_model: Option<String>,
+  #[serde(default)]
+  pub remote: RemoteConfig,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub model_path: Option<String>,
+  #[serde(default = "default_context_size")]
+  pub context_size: u32,
+  /// Model files by alias; `default` overrides `model_path`
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub models: BTreeMap<String, ModelProfile>,
+  /// Which model alias commit, branch, resolve and rebase use
+  #[serde(default, skip_serializing_if = "TaskModels::is_empty")]
+  pub tasks: TaskModels,
+  /// Most diff characters put in a prompt; unset uses 8000
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_diff_chars: Option<usize>,
+  /// Extra attempts, with a new seed, when the model replies with nothing
+  #[serde(default = "default_generation_retries")]
+  pub generation_retries: u32,
+  /// Hours a model reply is reused for the same prompt; 0 turns the cache off
+  #[serde(default = "default_cache_ttl_hours")]
+  pub cache_ttl_hours: u32,
+  /// Hold commit subjects and branch names to their format with a grammar (local backend)
+  #[serde(default = "default_true")]
+  pub grammar: bool,
+  /// Layers to offload to the GPU; unset auto-detects GPU support
+  #[serde(
+    default,
+    rename = "gpu_layers",
+    skip_serializing_if = "Option::is_none",
+    deserialize_with = "deserialize_gpu_layers"
+  )]
+  pub n_gpu_layers: Option<u32>,
+  /// Threads for local inference; unset uses llama.cpp's default
+  #[serde(default, rename = "threads", skip_serializing_if = "Option::is_none")]
+  pub n_threads: Option<u32>,
+  #[serde(default)]
+  pub risk: RiskConfig,
+  #[serde(default)]
+  pub forge: ForgeConfig,
+  #[serde(default)]
+  pub tests: TestsConfig,
+  #[serde(default)]
+  pub commit_style: CommitStyle,
+  /// Integration branch for `pr`, `summarize` and `branch clean` (default: main, then master)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub base_branch: Option<String>,
+  /// Glob patterns for branches alfred never deletes; a repository's list adds to the global one
+  #[serde(default = "default_protected_branches")]
+  pub protected_branches: Vec<String>,
+  /// Prefixes suggested branch names start with, separator included (`feature/`, `PROJ-`)
+  #[serde(default = "default_branch_prefixes")]
+  pub branch_prefixes: Vec<String>,
+  /// Keep matches of `commit.ticket_pattern` uppercase when sanitizing branch names
+  #[serde(default)]
+  pub preserve_ticket_case: bool,
+  #[serde(default)]
+  pub commit: CommitConfig,
+  #[serde(default)]
+  pub commit_rules: CommitRules,
   #[serde(default)]
   pub daemon: DaemonConfig,
+  #[serde(default)]
+  pub llm: LlmConfig,
+  #[serde(default)]
+  pub sampling: SamplingConfig,
+}
+
+fn default_ollama_url() -> String {
+  "http://localhost:11434".to_string()
+}
+
+fn default_context_size() -> u32 {
+  2048
+}
+
+/// Diff characters allowed in a prompt when `max_diff_chars` is unset
+pub const DEFAULT_MAX_DIFF_CHARS: usize = 8000;
+
+fn default_cache_ttl_hours() -> u32 {
+  24
+}
+
+fn default_protected_branches() -> Vec<String> {
+  ["main", "master", "develop", "release/*"]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+fn default_branch_prefixes() -> Vec<String> {
+  ["feature/", "bugfix/", "hotfix/", "chore/"]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+fn default_generation_retries() -> u32 {
+  2
+}
+
+/// Layer count that offloads the whole model for any model alfred ships with
+pub const ALL_GPU_LAYERS: u32 = 99;
+
+/// Accepts older configs where `-1` meant "offload all layers"
+fn deserialize_gpu_layers<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  let value: Option<i64> = Option::deserialize(deserializer)?;
+  Ok(value.map(|n| {
+    if n < 0 {
+      ALL_GPU_LAYERS
+    } else {
+      n.min(u32::MAX as i64) as u32
+    }
+  }))
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      backend: Backend::default(),
+      ollama_url: default_ollama_url(),
+      ollama_model: None,
+      remote: RemoteConfig::default(),
+      model_path: None,
+      context_size: default_context_size(),
+      models: BTreeMap::new(),
+      tasks: TaskModels::default(),
+      max_diff_chars: None,
+      generation_retries: default_generation_retries(),
+      cache_ttl_hours: default_cache_ttl_hours(),
+      grammar: true,
+      n_gpu_layers: None,
+      n_threads: None,
+      risk: RiskConfig::default(),
+      forge: ForgeConfig::default(),
+      tests: TestsConfig::default(),
+      commit_style: CommitStyle::default(),
+      base_branch: None,
+      protected_branches: default_protected_branches(),
+      branch_prefixes: default_branch_prefixes(),
+      preserve_ticket_case: false,
+      commit: CommitConfig::default(),
+      commit_rules: CommitRules::default(),
+      daemon: DaemonConfig::default(),
+      llm: LlmConfig::default(),
+      sampling: SamplingConfig::default(),
+    }
+  }
+}
+
+impl Config {
+  /// The file and context size behind a model alias. Without a `models` entry, `default`
+  /// means `model_path` and `context_size`
+  pub fn model_spec(&self, alias: &str) -> Result<ModelSpec> {
+    if let Some(profile) = self.models.get(alias) {
+      return Ok(ModelSpec {
+        path: PathBuf::from(&profile.path),
+        context_size: profile.context_size.unwrap_or(self.context_size),
+      });
+    }
+    if alias != DEFAULT_MODEL {
+      bail!(
+        "Unknown model '{}'. Add it under 'models' in {}",
+        alias,
+        config_path().display()
+      );
+    }
+    Ok(ModelSpec {
+      path: self
+        .model_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(default_model_path),
+      context_size: self.context_size,
+    })
+  }
+
+  /// Whether `branch` matches one of `protected_branches`
+  pub fn is_protected_branch(&self, branch: &str) -> bool {
+    self.protected_branches.iter().any(|pattern| {
+      pattern == branch || glob::Pattern::new(pattern).is_ok_and(|p| p.matches(branch))
+    })
+  }
 }
 
 pub fn alfred_dir() -> PathBuf {
@@ -61,11 +691,168 @@ pub fn default_model_path() -> PathBuf {
   models_dir().join("phi-3-mini-q4.gguf")
 }
 
+/// Where a model is downloaded before it's complete
+pub fn part_path(model_path: &Path) -> PathBuf {
+  let mut name = model_path.as_os_str().to_os_string();
+  name.push(".part");
+  PathBuf::from(name)
+}
+
 pub fn pid_file() -> PathBuf {
   alfred_dir().join("alferd.pid")
 }
 
+/// The daemon's structured log, written by `daemon_log`
+pub fn daemon_log_file() -> PathBuf {
+  alfred_dir().join("alferd.log")
+}
+
+/// Certificate the daemon serves TLS with and clients trust
+pub fn daemon_cert_file() -> PathBuf {
+  alfred_dir().join("daemon.crt")
+}
+
+pub fn daemon_key_file() -> PathBuf {
+  alfred_dir().join("daemon.key")
+}
+
+pub fn setup_state_file() -> PathBuf {
+  alfred_dir().join("setup-state.json")
+}
+
+pub fn forge_cache_file() -> PathBuf {
+  alfred_dir().join("forge-cache.json")
+}
+
+/// Per-repository overrides, at the top of the working tree
+pub const REPO_CONFIG_FILE: &str = ".alfred.yaml";
+
+/// Settings tied to this machine or its credentials, which a repository can't override.
+///
+/// `ollama_model`, `context_size` and `max_diff_chars` are left out on purpose: they only pick
+/// between models already pulled and size the prompt, so a project may tune them.
+const GLOBAL_ONLY: &[&str] = &[
+  "backend",
+  "ollama_url",
+  "remote",
+  "model_path",
+  "models",
+  "gpu_layers",
+  "threads",
+  "forge",
+  "daemon",
+  "llm",
+];
+
+/// The config `current` returns outside `with_config`, loaded once per process
+static CURRENT: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+thread_local! {
+  /// The config of the daemon request being handled on this thread
+  static SCOPED: RefCell<Option<Arc<Config>>> = const { RefCell::new(None) };
+}
+
+/// The config in effect: the daemon request's inside `with_config`, otherwise the one
+/// `set_current` installed, or `load`'s, read on first use and kept for the rest of the
+/// command. The `get_*` helpers all read this, so files are read once, not per setting
+pub fn current() -> Arc<Config> {
+  if let Some(cfg) = SCOPED.with(|cell| cell.borrow().clone()) {
+    return cfg;
+  }
+  if let Some(cfg) = CURRENT.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+    return cfg.clone();
+  }
+  let mut current = CURRENT.write().unwrap_or_else(|e| e.into_inner());
+  current
+    .get_or_insert_with(|| Arc::new(load().unwrap_or_default()))
+    .clone()
+}
+
+/// Use `cfg` as the process's config from now on, e.g. the daemon's, which never reads a
+/// repository's settings from its own working directory
+pub fn set_current(cfg: Config) {
+  *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(cfg));
+}
+
+/// Read the files again on the next `current`, after they changed
+pub fn reload() {
+  *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Run `f` with `cfg` as `current` on this thread. The daemon wraps each request in it
+pub fn with_config<T>(cfg: Arc<Config>, f: impl FnOnce() -> T) -> T {
+  let previous = SCOPED.with(|cell| cell.replace(Some(cfg)));
+  let result = f();
+  SCOPED.with(|cell| *cell.borrow_mut() = previous);
+  result
+}
+
+/// The effective config: the global file, the current repository's `.alfred.yaml` over it,
+/// and the `ALFRED_*` environment variables over both
 pub fn load() -> Result<Config> {
+  let mut cfg = match std::env::current_dir() {
+    Ok(dir) => load_for_repo(&dir)?,
+    Err(_) => load_global()?,
+  };
+  apply_env(&mut cfg)?;
+  Ok(cfg)
+}
+
+/// The global config with the `.alfred.yaml` of the repository containing `path` merged over it
+pub fn load_for_repo(path: &Path) -> Result<Config> {
+  let Some(repo_file) = repo_config_path(path) else {
+    return load_global();
+  };
+
+  overlay(read_yaml(&config_path())?, read_yaml(&repo_file)?)
+    .with_context(|| format!("Invalid settings in {}", repo_file.display()))
+}
+
+/// `global` with the settings a repository may override taken from `overrides`
+fn overlay(global: serde_yaml::Value, mut overrides: serde_yaml::Value) -> Result<Config> {
+  if let serde_yaml::Value::Mapping(ref mut map) = overrides {
+    for key in GLOBAL_ONLY {
+      map.remove(*key);
+    }
+  }
+  let mut merged = global;
+  extend_protected_branches(&merged, &mut overrides);
+  merge_yaml(&mut merged, overrides);
+  Ok(serde_yaml::from_value(merged)?)
+}
+
+/// The daemon's own config: the global file and the `ALFRED_*` variables, never the
+/// `.alfred.yaml` of whatever directory it was started in
+pub fn load_daemon() -> Result<Config> {
+  let mut cfg = load_global()?;
+  apply_env(&mut cfg)?;
+  Ok(cfg)
+}
+
+/// The settings of `cfg` a repository may override, which clients send with daemon requests
+/// so the daemon generates the way the client's repository is configured
+pub fn repo_settings(cfg: &Config) -> serde_json::Value {
+  let mut settings = serde_json::to_value(cfg).unwrap_or_default();
+  if let serde_json::Value::Object(ref mut map) = settings {
+    for key in GLOBAL_ONLY {
+      map.remove(*key);
+    }
+  }
+  settings
+}
+
+/// The daemon's config for one request: `daemon`'s own, with the client's `repo_settings`
+/// over it. Machine settings such as the backend, models and daemon section stay the daemon's
+pub fn with_repo_settings(daemon: &Config, settings: &serde_json::Value) -> Result<Config> {
+  overlay(
+    serde_yaml::to_value(daemon)?,
+    serde_yaml::to_value(settings)?,
+  )
+  .with_context(|| "Invalid settings from the client")
+}
+
+/// Only the global `~/.alfred/config.yaml`; edit this one, so repository values never leak into it
+pub fn load_global() -> Result<Config> {
   let path = config_path();
 
   if !path.exists() {
@@ -81,6 +868,84 @@ pub fn load() -> Result<Config> {
   Ok(config)
 }
 
+/// `.alfred.yaml` at the top of the repository containing `path`, if there is one
+pub fn repo_config_path(path: &Path) -> Option<PathBuf> {
+  let output = std::process::Command::new("git")
+    .arg("-C")
+    .arg(path)
+    .args(["rev-parse", "--show-toplevel"])
+    .stderr(std::process::Stdio::null())
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+
+  let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+  let file = Path::new(&root).join(REPO_CONFIG_FILE);
+  file.exists().then_some(file)
+}
+
+fn read_yaml(path: &Path) -> Result<serde_yaml::Value> {
+  if !path.exists() {
+    return Ok(serde_yaml::Value::Mapping(Default::default()));
+  }
+  let content = fs::read_to_string(path)
+    .with_context(|| format!("Failed to read config from {}", path.display()))?;
+  let value: serde_yaml::Value = serde_yaml::from_str(&content)
+    .with_context(|| format!("Failed to parse {}", path.display()))?;
+  // An empty file parses as null
+  Ok(match value {
+    serde_yaml::Value::Null => serde_yaml::Value::Mapping(Default::default()),
+    value => value,
+  })
+}
+
+/// Turn a repository's `protected_branches` into the global list plus its own, so a project
+/// can protect more branches but never unprotect the ones every repository gets
+fn extend_protected_branches(global: &serde_yaml::Value, overrides: &mut serde_yaml::Value) {
+  let serde_yaml::Value::Mapping(map) = overrides else {
+    return;
+  };
+  let Some(serde_yaml::Value::Sequence(extra)) = map.get("protected_branches") else {
+    return;
+  };
+
+  let mut list = match global.get("protected_branches") {
+    Some(serde_yaml::Value::Sequence(list)) => list.clone(),
+    _ => default_protected_branches()
+      .into_iter()
+      .map(serde_yaml::Value::String)
+      .collect(),
+  };
+  for pattern in extra {
+    if !list.contains(pattern) {
+      list.push(pattern.clone());
+    }
+  }
+  map.insert(
+    "protected_branches".into(),
+    serde_yaml::Value::Sequence(list),
+  );
+}
+
+/// Merge `over` into `base`: mappings key by key, anything else replaced
+fn merge_yaml(base: &mut serde_yaml::Value, over: serde_yaml::Value) {
+  match (base, over) {
+    (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(over)) => {
+      for (key, value) in over {
+        match base.get_mut(&key) {
+          Some(existing) => merge_yaml(existing, value),
+          None => {
+            base.insert(key, value);
+          }
+        }
+      }
+    }
+    (base, over) => *base = over,
+  }
+}
+
 pub fn save(config: &Config) -> Result<()> {
   let dir = alfred_dir();
   fs::create_dir_all(&dir)
@@ -90,20 +955,391 @@ pub fn save(config: &Config) -> Result<()> {
   let content =
     serde_yaml::to_string(config).with_context(|| "Failed to serialize config to YAML")?;
 
-  fs::write(&path, content)
-    .with_context(|| format!("Failed to write config to {}", path.display()))?;
+  // The auth token is a secret, so a config holding one is only readable by the user
+  if config.daemon.auth_token.is_some() {
+    write_private(&path, content.as_bytes())?;
+  } else {
+    fs::write(&path, content)
+      .with_context(|| format!("Failed to write config to {}", path.display()))?;
+  }
 
+  reload();
   Ok(())
 }
 
+/// Write a file only the user can read. A new file is created with that mode before any
+/// bytes go in, then renamed over `path`, so the contents are never readable by others
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+  use std::io::Write;
+
+  let mut tmp = path.as_os_str().to_os_string();
+  tmp.push(".tmp");
+  let tmp = PathBuf::from(tmp);
+  // A leftover from an interrupted write may have any mode, so never reuse it
+  match fs::remove_file(&tmp) {
+    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+      return Err(e).with_context(|| format!("Failed to remove {}", tmp.display()))
+    }
+    _ => {}
+  }
+
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  let mut file = options
+    .open(&tmp)
+    .with_context(|| format!("Failed to create {}", tmp.display()))?;
+  file
+    .write_all(contents)
+    .with_context(|| format!("Failed to write {}", tmp.display()))?;
+  fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+  Ok(())
+}
+
+pub fn get_backend() -> Backend {
+  current().backend
+}
+
 pub fn get_model_path() -> PathBuf {
-  load()
-    .ok()
-    .and_then(|c| c.model_path)
+  current()
+    .model_path
+    .as_ref()
     .map(PathBuf::from)
     .unwrap_or_else(default_model_path)
 }
 
+pub fn get_context_size() -> u32 {
+  current().context_size
+}
+
+/// The model alias `task` uses
+pub fn get_task_model(task: Task) -> String {
+  current().tasks.get(task).to_string()
+}
+
+pub fn get_model_spec(alias: &str) -> Result<ModelSpec> {
+  current().model_spec(alias)
+}
+
+pub fn get_max_diff_chars() -> usize {
+  current().max_diff_chars.unwrap_or(DEFAULT_MAX_DIFF_CHARS)
+}
+
+pub fn get_gpu_layers() -> Option<u32> {
+  current().n_gpu_layers
+}
+
+pub fn get_threads() -> Option<u32> {
+  current().n_threads
+}
+
+pub fn get_cache_ttl_hours() -> u32 {
+  current().cache_ttl_hours
+}
+
+pub fn get_grammar() -> bool {
+  current().grammar
+}
+
+/// `branch_prefixes` without empty entries, falling back to the defaults when none are left
+pub fn get_branch_prefixes() -> Vec<String> {
+  let prefixes: Vec<String> = current()
+    .branch_prefixes
+    .iter()
+    .map(|p| p.trim().to_string())
+    .filter(|p| !p.is_empty())
+    .collect();
+  if prefixes.is_empty() {
+    default_branch_prefixes()
+  } else {
+    prefixes
+  }
+}
+
+pub fn get_generation_retries() -> u32 {
+  current().generation_retries
+}
+
+pub fn get_commit_style() -> CommitStyle {
+  current().commit_style
+}
+
+pub fn get_max_subject_length() -> usize {
+  current().commit_rules.max_subject_length
+}
+
+pub fn get_llm_config() -> LlmConfig {
+  current().llm.clone()
+}
+
+/// Environment variable that overrides `daemon.port`, e.g. to reach a foreground dev daemon
+pub const DAEMON_PORT_ENV: &str = "ALFRED_DAEMON_PORT";
+
+/// Environment variable that overrides `model_path`
+pub const MODEL_PATH_ENV: &str = "ALFRED_MODEL_PATH";
+
+/// Environment variable that overrides `daemon.idle_timeout_minutes`
+pub const IDLE_TIMEOUT_ENV: &str = "ALFRED_IDLE_TIMEOUT";
+
+/// A set, non-blank environment variable
+fn env_value(name: &str) -> Option<String> {
+  non_blank(std::env::var(name).ok())
+}
+
+fn non_blank(value: Option<String>) -> Option<String> {
+  value
+    .map(|v| v.trim().to_string())
+    .filter(|v| !v.is_empty())
+}
+
+fn parse_env<T: std::str::FromStr>(
+  name: &str,
+  value: Option<String>,
+  expected: &str,
+) -> Result<Option<T>> {
+  let Some(value) = non_blank(value) else {
+    return Ok(None);
+  };
+  value
+    .parse()
+    .map(Some)
+    .map_err(|_| anyhow!("{} must be {}, got '{}'", name, expected, value))
+}
+
+/// Layer the `ALFRED_*` overrides over a loaded config. They are never saved back to a file
+fn apply_env(cfg: &mut Config) -> Result<()> {
+  apply_env_from(cfg, |name| std::env::var(name).ok())
+}
+
+/// `apply_env` with the variables read through `var`
+fn apply_env_from(cfg: &mut Config, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+  if let Some(path) = non_blank(var(MODEL_PATH_ENV)) {
+    if let Some(profile) = cfg.models.get_mut(DEFAULT_MODEL) {
+      profile.path = path.clone();
+    }
+    cfg.model_path = Some(path);
+  }
+  let port = var(DAEMON_PORT_ENV);
+  match parse_env::<u16>(DAEMON_PORT_ENV, port, "a port number from 1 to 65535")? {
+    Some(0) => bail!(
+      "{} must be a port number from 1 to 65535, got '0'",
+      DAEMON_PORT_ENV
+    ),
+    Some(port) => cfg.daemon.port = port,
+    None => {}
+  }
+  let minutes = var(IDLE_TIMEOUT_ENV);
+  if let Some(minutes) = parse_env(IDLE_TIMEOUT_ENV, minutes, "a whole number of minutes")? {
+    cfg.daemon.idle_timeout_minutes = minutes;
+  }
+  Ok(())
+}
+
+/// The `ALFRED_*` overrides that are set, for `alfred config` to point out
+pub fn env_overrides() -> Vec<&'static str> {
+  [MODEL_PATH_ENV, DAEMON_PORT_ENV, IDLE_TIMEOUT_ENV]
+    .into_iter()
+    .filter(|name| env_value(name).is_some())
+    .collect()
+}
+
+/// Fail early on an `ALFRED_*` variable that can't be parsed, instead of ignoring it
+pub fn check_env() -> Result<()> {
+  apply_env(&mut Config::default())
+}
+
+pub fn get_sampling() -> SamplingConfig {
+  current().sampling
+}
+
+/// Store a new daemon auth token in the global config, which `save` keeps private to the user
+pub fn set_daemon_auth_token(token: &str) -> Result<()> {
+  let mut cfg = load_global()?;
+  cfg.daemon.auth_token = Some(token.to_string());
+  save(&cfg)
+}
+
 pub fn get_daemon_config() -> DaemonConfig {
-  load().ok().map(|c| c.daemon).unwrap_or_default()
+  current().daemon.clone()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    |name| {
+      vars
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| v.to_string())
+    }
+  }
+
+  #[test]
+  fn daemon_port_env_points_clients_at_another_daemon() {
+    let mut cfg = Config::default();
+    apply_env_from(&mut cfg, env(&[(DAEMON_PORT_ENV, " 52311 ")])).unwrap();
+    assert_eq!(cfg.daemon.port, 52311);
+  }
+
+  #[test]
+  fn unset_or_blank_overrides_keep_the_config() {
+    let mut cfg = Config::default();
+    let port = cfg.daemon.port;
+    apply_env_from(&mut cfg, env(&[])).unwrap();
+    apply_env_from(&mut cfg, env(&[(DAEMON_PORT_ENV, "  ")])).unwrap();
+    assert_eq!(cfg.daemon.port, port);
+  }
+
+  #[test]
+  fn invalid_ports_are_rejected() {
+    for value in ["0", "70000", "seven"] {
+      let err = apply_env_from(&mut Config::default(), env(&[(DAEMON_PORT_ENV, value)]))
+        .unwrap_err()
+        .to_string();
+      assert!(err.starts_with(DAEMON_PORT_ENV), "{}", err);
+      assert!(err.contains(value), "{}", err);
+    }
+  }
+
+  #[test]
+  fn model_path_and_idle_timeout_overrides() {
+    let mut cfg = Config::default();
+    apply_env_from(
+      &mut cfg,
+      env(&[(MODEL_PATH_ENV, "/m/x.gguf"), (IDLE_TIMEOUT_ENV, "0")]),
+    )
+    .unwrap();
+    assert_eq!(cfg.model_path.as_deref(), Some("/m/x.gguf"));
+    assert_eq!(cfg.daemon.idle_timeout_minutes, 0);
+  }
+
+  #[test]
+  fn generation_retries_default_to_two() {
+    assert_eq!(Config::default().generation_retries, 2);
+    let cfg: Config = serde_yaml::from_str("commit_style: conventional").unwrap();
+    assert_eq!(cfg.generation_retries, 2);
+    let cfg: Config = serde_yaml::from_str("generation_retries: 0").unwrap();
+    assert_eq!(cfg.generation_retries, 0);
+  }
+
+  fn yaml(text: &str) -> serde_yaml::Value {
+    serde_yaml::from_str(text).unwrap()
+  }
+
+  #[test]
+  fn merging_keeps_sibling_keys_and_replaces_leaves() {
+    let mut base =
+      yaml("commit_rules:\n  max_subject_length: 72\n  types: [feat, fix]\ngrammar: true\n");
+    merge_yaml(
+      &mut base,
+      yaml("commit_rules:\n  types: [chore]\ngrammar: false\nbase_branch: trunk\n"),
+    );
+    assert_eq!(
+      base,
+      yaml("commit_rules:\n  max_subject_length: 72\n  types: [chore]\ngrammar: false\nbase_branch: trunk\n")
+    );
+  }
+
+  #[test]
+  fn repositories_override_project_settings_but_not_machine_ones() {
+    let global = yaml(
+      "backend: ollama\nollama_url: http://gpu:11434\ncommit_style: conventional\n\
+       commit_rules:\n  max_subject_length: 60\n  require_conventional: true\n",
+    );
+    let repo = yaml(
+      "backend: remote\nollama_url: http://evil\ndaemon:\n  port: 1\n\
+       commit_rules:\n  max_subject_length: 50\n",
+    );
+    let cfg = overlay(global, repo).unwrap();
+    assert_eq!(cfg.backend, Backend::Ollama);
+    assert_eq!(cfg.ollama_url, "http://gpu:11434");
+    assert_eq!(cfg.daemon.port, DaemonConfig::default().port);
+    assert_eq!(cfg.commit_style, CommitStyle::Conventional);
+    assert_eq!(cfg.commit_rules.max_subject_length, 50);
+    assert!(cfg.commit_rules.require_conventional);
+  }
+
+  #[test]
+  fn invalid_repository_settings_are_errors() {
+    let global = yaml("commit_style: conventional\n");
+    assert!(overlay(global, yaml("generation_retries: lots\n")).is_err());
+  }
+
+  #[test]
+  fn empty_files_read_as_empty_mappings() {
+    let dir = tempfile::tempdir().unwrap();
+    let empty = dir.path().join("empty.yaml");
+    fs::write(&empty, "").unwrap();
+    assert_eq!(read_yaml(&empty).unwrap(), yaml("{}"));
+    assert_eq!(
+      read_yaml(&dir.path().join("missing.yaml")).unwrap(),
+      yaml("{}")
+    );
+
+    let broken = dir.path().join("broken.yaml");
+    fs::write(&broken, "commit_rules: [").unwrap();
+    let err = format!("{:#}", read_yaml(&broken).unwrap_err());
+    assert!(err.contains("broken.yaml"), "{}", err);
+  }
+
+  #[test]
+  fn repository_config_is_found_from_subdirectories() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    let status = std::process::Command::new("git")
+      .args(["init", "-q"])
+      .current_dir(repo)
+      .status()
+      .unwrap();
+    assert!(status.success());
+    fs::create_dir_all(repo.join("src/deep")).unwrap();
+    assert_eq!(repo_config_path(&repo.join("src/deep")), None);
+
+    fs::write(repo.join(REPO_CONFIG_FILE), "grammar: false\n").unwrap();
+    let found = repo_config_path(&repo.join("src/deep")).unwrap();
+    assert_eq!(
+      found.canonicalize().unwrap(),
+      repo.join(REPO_CONFIG_FILE).canonicalize().unwrap()
+    );
+  }
+
+  #[test]
+  fn repositories_add_protected_branches_without_dropping_any() {
+    let global = yaml("protected_branches: [main, \"release/*\"]\n");
+    let cfg = overlay(global, yaml("protected_branches: [staging, main]\n")).unwrap();
+    assert_eq!(cfg.protected_branches, ["main", "release/*", "staging"]);
+
+    let cfg = overlay(yaml("{}"), yaml("protected_branches: []\n")).unwrap();
+    assert_eq!(cfg.protected_branches, default_protected_branches());
+
+    let cfg = overlay(yaml("{}"), yaml("protected_branches: [qa]\n")).unwrap();
+    assert_eq!(
+      cfg.protected_branches,
+      ["main", "master", "develop", "release/*", "qa"]
+    );
+  }
+
+  #[test]
+  fn protected_branches_match_names_and_globs() {
+    let cfg = Config::default();
+    for branch in ["main", "master", "develop", "release/1.2"] {
+      assert!(cfg.is_protected_branch(branch), "{}", branch);
+    }
+    for branch in ["feature/main", "mainline", "release", "release-1.2"] {
+      assert!(!cfg.is_protected_branch(branch), "{}", branch);
+    }
+
+    let cfg = Config {
+      protected_branches: vec!["[".to_string(), "hotfix/**".to_string()],
+      ..Config::default()
+    };
+    assert!(cfg.is_protected_branch("["));
+    assert!(cfg.is_protected_branch("hotfix/a/b"));
+  }
 }
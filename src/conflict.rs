@@ -0,0 +1,339 @@
+//! Parsing the conflict markers git leaves in working-tree files
+
+use anyhow::{anyhow, Result};
+
+/// Unconflicted lines kept on each side of a region, to show the model where it sits
+const CONTEXT_LINES: usize = 3;
+
+/// One side-by-side conflict region from the markers in a working-tree file
+#[derive(Debug, Clone, Default)]
+pub struct ConflictHunk {
+  pub ours: String,
+  /// `None` without a `|||||||` section, i.e. outside `merge.conflictStyle=diff3`
+  pub base: Option<String>,
+  pub theirs: String,
+  /// The region as written, markers included, for putting it back unresolved
+  pub raw: String,
+  /// Up to `CONTEXT_LINES` unconflicted lines just before and after the region
+  pub before: String,
+  pub after: String,
+  /// Line of the `<<<<<<<` marker, from 1
+  pub line: usize,
+}
+
+impl ConflictHunk {
+  /// The line ending the region was written with, so spliced text matches the file
+  pub fn line_ending(&self) -> &'static str {
+    if self.raw.contains("\r\n") {
+      "\r\n"
+    } else {
+      "\n"
+    }
+  }
+
+  /// `text` with the region's line endings, ending in one when the region did
+  pub fn fit(&self, text: &str) -> String {
+    let ending = self.line_ending();
+    let mut out: String = text
+      .split_inclusive('\n')
+      .map(|line| match line.strip_suffix('\n') {
+        Some(line) => format!("{}{}", line.trim_end_matches('\r'), ending),
+        None => line.to_string(),
+      })
+      .collect();
+    // The model tends to drop the final newline the region had
+    if !out.is_empty() && !out.ends_with('\n') && self.raw.ends_with('\n') {
+      out.push_str(ending);
+    }
+    out
+  }
+}
+
+/// A conflicted file as text outside conflicts and conflict regions, in file order
+#[derive(Debug, Clone)]
+pub enum ConflictSegment {
+  Text(String),
+  Conflict(ConflictHunk),
+}
+
+/// Whether `line` is a conflict marker. Git writes seven marker characters at the start of
+/// the line followed by a space or the line ending, so a `<<<<<<<` inside a string literal
+/// or after indentation is content
+fn is_marker(line: &str, marker: &str) -> bool {
+  line.starts_with(marker)
+    && line[marker.len()..]
+      .chars()
+      .next()
+      .is_none_or(|c| c.is_whitespace())
+}
+
+/// Split file content at its conflict markers. Errors on a region that is never closed
+pub fn split_conflicts(content: &str) -> Result<Vec<ConflictSegment>> {
+  #[derive(PartialEq)]
+  enum Section {
+    Outside,
+    Ours,
+    Base,
+    Theirs,
+  }
+
+  let mut segments = Vec::new();
+  let mut text = String::new();
+  let mut hunk = ConflictHunk::default();
+  let mut section = Section::Outside;
+
+  for (number, line) in content.split_inclusive('\n').enumerate() {
+    let marker = |m: &str| is_marker(line, m);
+    if section != Section::Outside {
+      hunk.raw.push_str(line);
+    }
+    match section {
+      Section::Outside if marker("<<<<<<<") => {
+        if !text.is_empty() {
+          segments.push(ConflictSegment::Text(std::mem::take(&mut text)));
+        }
+        hunk.raw.push_str(line);
+        hunk.line = number + 1;
+        section = Section::Ours;
+      }
+      Section::Ours if marker("|||||||") => {
+        hunk.base = Some(String::new());
+        section = Section::Base;
+      }
+      Section::Ours | Section::Base if marker("=======") => section = Section::Theirs,
+      Section::Theirs if marker(">>>>>>>") => {
+        segments.push(ConflictSegment::Conflict(std::mem::take(&mut hunk)));
+        section = Section::Outside;
+      }
+      Section::Outside => text.push_str(line),
+      Section::Ours => hunk.ours.push_str(line),
+      Section::Base => hunk.base.get_or_insert_with(String::new).push_str(line),
+      Section::Theirs => hunk.theirs.push_str(line),
+    }
+  }
+
+  if section != Section::Outside {
+    return Err(anyhow!(
+      "Unterminated conflict region starting on line {}",
+      hunk.line
+    ));
+  }
+  if !text.is_empty() {
+    segments.push(ConflictSegment::Text(text));
+  }
+
+  add_context(&mut segments);
+  Ok(segments)
+}
+
+/// Fill in each region's `before` and `after` from the text around it
+fn add_context(segments: &mut [ConflictSegment]) {
+  for i in 0..segments.len() {
+    let before = match i.checked_sub(1).map(|j| &segments[j]) {
+      Some(ConflictSegment::Text(text)) => last_lines(text, CONTEXT_LINES).to_string(),
+      _ => String::new(),
+    };
+    let after = match segments.get(i + 1) {
+      Some(ConflictSegment::Text(text)) => first_lines(text, CONTEXT_LINES).to_string(),
+      _ => String::new(),
+    };
+    if let ConflictSegment::Conflict(hunk) = &mut segments[i] {
+      hunk.before = before;
+      hunk.after = after;
+    }
+  }
+}
+
+fn first_lines(text: &str, n: usize) -> &str {
+  let end = text
+    .match_indices('\n')
+    .nth(n.saturating_sub(1))
+    .map_or(text.len(), |(i, _)| i + 1);
+  &text[..end]
+}
+
+fn last_lines(text: &str, n: usize) -> &str {
+  let body = text.strip_suffix('\n').unwrap_or(text);
+  let start = body
+    .rmatch_indices('\n')
+    .nth(n.saturating_sub(1))
+    .map_or(0, |(i, _)| i + 1);
+  &text[start..]
+}
+
+/// Number of conflict regions opened in file content
+pub fn count(content: &str) -> usize {
+  content
+    .lines()
+    .filter(|line| is_marker(line, "<<<<<<<"))
+    .count()
+}
+
+/// Whether any conflict marker line is left. A lone `=======` isn't counted, since Markdown
+/// and reStructuredText underline headings with it
+pub fn has_markers(content: &str) -> bool {
+  content.lines().any(|line| {
+    ["<<<<<<<", "|||||||", ">>>>>>>"]
+      .iter()
+      .any(|m| is_marker(line, m))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hunks(content: &str) -> Vec<ConflictHunk> {
+    split_conflicts(content)
+      .unwrap()
+      .into_iter()
+      .filter_map(|s| match s {
+        ConflictSegment::Conflict(hunk) => Some(hunk),
+        ConflictSegment::Text(_) => None,
+      })
+      .collect()
+  }
+
+  /// The file put back together with every region unresolved
+  fn rejoin(segments: &[ConflictSegment]) -> String {
+    segments
+      .iter()
+      .map(|s| match s {
+        ConflictSegment::Text(text) => text.as_str(),
+        ConflictSegment::Conflict(hunk) => hunk.raw.as_str(),
+      })
+      .collect()
+  }
+
+  const TWO_CONFLICTS: &str = "\
+fn a() {}
+<<<<<<< HEAD
+let x = 1;
+=======
+let x = 2;
+>>>>>>> feature
+fn b() {}
+fn c() {}
+fn d() {}
+fn e() {}
+<<<<<<< HEAD
+let y = \"ours\";
+||||||| base
+let y = \"base\";
+=======
+let y = \"theirs\";
+>>>>>>> feature
+fn f() {}
+";
+
+  #[test]
+  fn markers_start_the_line_and_end_at_a_space() {
+    assert!(is_marker("<<<<<<< HEAD\n", "<<<<<<<"));
+    assert!(is_marker("=======\n", "======="));
+    assert!(is_marker("=======\r\n", "======="));
+    assert!(is_marker(">>>>>>>", ">>>>>>>"));
+    assert!(!is_marker("<<<<<<<< eight", "<<<<<<<"));
+    assert!(!is_marker("  <<<<<<< indented", "<<<<<<<"));
+    assert!(!is_marker("let s = \"<<<<<<< HEAD\";", "<<<<<<<"));
+    assert!(!is_marker("=======heading", "======="));
+  }
+
+  #[test]
+  fn multiple_conflicts_split_with_their_context() {
+    let segments = split_conflicts(TWO_CONFLICTS).unwrap();
+    assert_eq!(segments.len(), 5);
+    assert_eq!(rejoin(&segments), TWO_CONFLICTS);
+
+    let hunks = hunks(TWO_CONFLICTS);
+    assert_eq!(hunks.len(), 2);
+    assert_eq!(hunks[0].line, 2);
+    assert_eq!(hunks[0].ours, "let x = 1;\n");
+    assert_eq!(hunks[0].theirs, "let x = 2;\n");
+    assert_eq!(hunks[0].base, None);
+    assert_eq!(hunks[0].before, "fn a() {}\n");
+    assert_eq!(hunks[0].after, "fn b() {}\nfn c() {}\nfn d() {}\n");
+
+    assert_eq!(hunks[1].line, 11);
+    assert_eq!(hunks[1].base.as_deref(), Some("let y = \"base\";\n"));
+    assert_eq!(hunks[1].before, "fn c() {}\nfn d() {}\nfn e() {}\n");
+    assert_eq!(hunks[1].after, "fn f() {}\n");
+    assert_eq!(count(TWO_CONFLICTS), 2);
+  }
+
+  #[test]
+  fn markers_inside_strings_are_content() {
+    let content = "\
+const OPEN: &str = \"<<<<<<< HEAD\";
+<<<<<<< HEAD
+let sep = \"=======\";
+  >>>>>>> not a marker
+=======
+let sep = \"-------\";
+>>>>>>> feature
+";
+    let hunks = hunks(content);
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(
+      hunks[0].ours,
+      "let sep = \"=======\";\n  >>>>>>> not a marker\n"
+    );
+    assert_eq!(hunks[0].theirs, "let sep = \"-------\";\n");
+    assert_eq!(hunks[0].before, "const OPEN: &str = \"<<<<<<< HEAD\";\n");
+  }
+
+  #[test]
+  fn crlf_files_keep_their_line_endings() {
+    let content = "a\r\n<<<<<<< HEAD\r\nours\r\n=======\r\ntheirs\r\n>>>>>>> b\r\nz\r\n";
+    let segments = split_conflicts(content).unwrap();
+    assert_eq!(rejoin(&segments), content);
+    let hunk = &hunks(content)[0];
+    assert_eq!(hunk.ours, "ours\r\n");
+    assert_eq!(hunk.theirs, "theirs\r\n");
+    assert_eq!(hunk.line_ending(), "\r\n");
+    // A model's LF reply is written back with the file's CRLF, final newline restored
+    assert_eq!(hunk.fit("merged\nlines"), "merged\r\nlines\r\n");
+    assert_eq!(hunk.fit("one\r\n"), "one\r\n");
+  }
+
+  #[test]
+  fn lf_regions_fit_replies_as_is() {
+    let hunk = &hunks(TWO_CONFLICTS)[0];
+    assert_eq!(hunk.line_ending(), "\n");
+    assert_eq!(hunk.fit("let x = 3;"), "let x = 3;\n");
+    assert_eq!(hunk.fit(""), "");
+  }
+
+  #[test]
+  fn a_conflict_at_the_end_without_a_final_newline() {
+    let content = "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> b";
+    let segments = split_conflicts(content).unwrap();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(rejoin(&segments), content);
+    let hunk = &hunks(content)[0];
+    assert_eq!((hunk.before.as_str(), hunk.after.as_str()), ("", ""));
+    assert_eq!(hunk.fit("merged"), "merged");
+  }
+
+  #[test]
+  fn unterminated_regions_are_an_error() {
+    let err = split_conflicts("a\n<<<<<<< HEAD\nours\n=======\ntheirs\n")
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("line 2"), "{}", err);
+  }
+
+  #[test]
+  fn files_without_conflicts_are_one_segment() {
+    let segments = split_conflicts("plain\ntext\n").unwrap();
+    assert_eq!(rejoin(&segments), "plain\ntext\n");
+    assert!(hunks("plain\ntext\n").is_empty());
+  }
+
+  #[test]
+  fn leftover_markers_ignore_heading_underlines() {
+    assert!(!has_markers("Title\n=======\n\nText\n"));
+    assert!(has_markers("a\n>>>>>>> feature\n"));
+    assert!(has_markers("||||||| base\n"));
+    assert_eq!(count("Title\n=======\n"), 0);
+  }
+}
@@ -1,39 +1,230 @@
 use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
 use std::time::Duration;
 
-use crate::config;
+use crate::daemon_tls::{self, Channel, ClientChannel};
+use crate::git::{CommitRecord, StructuralChange};
+use crate::llm::{
+  ChangelogFormat, CommitHints, Conflict, ConflictLabels, ConflictPrediction, MergeAnalysis,
+  ReviewComment,
+};
+use crate::{config, llm};
+
+/// Read timeout for control methods the daemon answers immediately
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read timeout for inference, which may queue behind other clients' generations
+const GENERATE_TIMEOUT: Duration = Duration::from_secs(600);
 
 #[derive(Serialize)]
 struct Request {
   method: String,
   params: serde_json::Value,
   id: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  token: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct Response {
-  result: Option<String>,
+  /// Usually a string; structured methods such as `status` return an object
+  result: Option<serde_json::Value>,
   error: Option<String>,
   #[allow(dead_code)]
   id: u64,
+  /// Sent by daemons since token counts were added, for generations
+  #[serde(flatten)]
+  usage: Option<llm::Usage>,
+}
+
+/// What the daemon's `stats` method, or `status` when asked for details, reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+  /// Model file name, or the model name on the Ollama and remote backends
+  pub model: String,
+  pub model_loaded: bool,
+  pub uptime_secs: u64,
+  pub requests_served: u64,
+  /// Unix timestamp of the last request, not counting health checks
+  pub last_request: Option<u64>,
+  /// Approximate resident memory, when the platform reports it
+  pub memory_bytes: Option<u64>,
+  /// Requests running or waiting for the model
+  pub pending: usize,
+  /// Inference threads, on the local backend
+  #[serde(default)]
+  pub threads: Option<u32>,
+  /// File names of the local models in memory
+  #[serde(default)]
+  pub loaded_models: Vec<String>,
+  /// Context window of the default model, on the local backend
+  #[serde(default)]
+  pub context_size: Option<u32>,
+}
+
+/// Request counters and timings from the daemon's `metrics` method
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonMetrics {
+  /// Requests answered, health checks included
+  pub requests_total: u64,
+  pub requests_ok: u64,
+  pub requests_err: u64,
+  pub uptime_secs: u64,
+  /// How long the model took to load at startup
+  pub model_load_ms: u64,
+  /// How long the most recent request took to answer, 0 before the first
+  pub last_request_ms: u64,
+}
+
+/// What the daemon is doing, from a detailed `ping`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Activity {
+  Idle,
+  Generating {
+    /// How long the current request has held the model
+    elapsed_secs: u64,
+    /// Requests waiting behind it
+    queue_depth: usize,
+    /// Until the model is free, when the daemon has measured its speed
+    estimated_remaining_secs: Option<u64>,
+  },
+}
+
+/// Seconds until a busy daemon should be free.
+///
+/// The current generation is assumed to use its whole token budget, and each queued request
+/// the same budget again. `None` until a generation speed has been observed.
+pub fn estimate_remaining(
+  elapsed: Duration,
+  max_tokens: u32,
+  tokens_per_sec: Option<f64>,
+  queue_depth: usize,
+) -> Option<u64> {
+  let rate = tokens_per_sec.filter(|r| *r > 0.0)?;
+  let per_request = max_tokens as f64 / rate;
+  let current = (per_request - elapsed.as_secs_f64()).max(0.0);
+  Some((current + per_request * queue_depth as f64).ceil() as u64)
+}
+
+/// What a client does after asking the daemon what it's doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusyDecision {
+  /// Send the request now
+  Use,
+  /// Poll again shortly
+  Wait,
+  /// Generate in this process instead
+  FallBack,
+}
+
+/// Decide between the daemon and local generation, having already waited `waited`.
+///
+/// A busy daemon is waited for while its estimate fits within `busy_wait`. Without an
+/// estimate, the client polls until `busy_wait` runs out.
+pub fn decide(activity: &Activity, waited: Duration, busy_wait: Duration) -> BusyDecision {
+  match activity {
+    Activity::Idle => BusyDecision::Use,
+    Activity::Generating {
+      estimated_remaining_secs: Some(remaining),
+      ..
+    } => {
+      if waited + Duration::from_secs(*remaining) <= busy_wait {
+        BusyDecision::Wait
+      } else {
+        BusyDecision::FallBack
+      }
+    }
+    Activity::Generating { .. } if waited < busy_wait => BusyDecision::Wait,
+    Activity::Generating { .. } => BusyDecision::FallBack,
+  }
+}
+
+/// A result as text; structured results come back as JSON
+fn text(value: serde_json::Value) -> String {
+  match value {
+    serde_json::Value::String(s) => s,
+    other => other.to_string(),
+  }
 }
 
 pub struct DaemonClient {
-  stream: TcpStream,
+  stream: ClientChannel,
   request_id: u64,
+  /// `daemon.auth_token`, sent with every request
+  token: Option<String>,
 }
 
 impl DaemonClient {
   fn send_request(&mut self, method: &str, params: serde_json::Value) -> Result<String> {
+    Ok(text(self.send_request_value(method, params)?))
+  }
+
+  /// Streams the text as it's generated when the caller set up `llm::with_token_callback`
+  fn send_request_value(
+    &mut self,
+    method: &str,
+    params: serde_json::Value,
+  ) -> Result<serde_json::Value> {
+    if llm::streaming() {
+      self.exchange(
+        method,
+        params,
+        Some(&mut |piece: &str| llm::emit_token(piece)),
+      )
+    } else {
+      self.exchange(method, params, None)
+    }
+  }
+
+  /// Send a request and wait for its response, passing streamed pieces to `on_token`
+  fn exchange(
+    &mut self,
+    method: &str,
+    params: serde_json::Value,
+    mut on_token: Option<&mut dyn FnMut(&str)>,
+  ) -> Result<serde_json::Value> {
     self.request_id += 1;
 
+    let control = matches!(
+      method,
+      "ping" | "status" | "stats" | "metrics" | "shutdown" | "gpu_layers"
+    );
+    let timeout = if control {
+      CONTROL_TIMEOUT
+    } else {
+      GENERATE_TIMEOUT
+    };
+    self.stream.tcp().set_read_timeout(Some(timeout))?;
+
+    // The daemon samples with the client's settings, not the ones it started with, on the
+    // model alias the task picked, following this repository's `.alfred.yaml`
+    let mut params = params;
+    if let (false, serde_json::Value::Object(map)) = (control, &mut params) {
+      map.insert(
+        "sampling".to_string(),
+        serde_json::to_value(config::get_sampling())?,
+      );
+      map.insert("model".to_string(), llm::model_alias().into());
+      map.insert(
+        "settings".to_string(),
+        config::repo_settings(&config::current()),
+      );
+      if on_token.is_some() {
+        map.insert("stream".to_string(), true.into());
+      }
+    }
+
     let request = Request {
       method: method.to_string(),
       params,
       id: self.request_id,
+      token: self.token.clone(),
     };
 
     let mut request_str = serde_json::to_string(&request)?;
@@ -46,15 +237,33 @@ impl DaemonClient {
 
     self.stream.flush()?;
 
-    let mut reader = BufReader::new(&self.stream);
-    let mut response_str = String::new();
-    reader
-      .read_line(&mut response_str)
-      .with_context(|| "Failed to read response from daemon")?;
-
-    let response: Response =
-      serde_json::from_str(&response_str).with_context(|| "Failed to parse daemon response")?;
+    // A streamed generation is a line per piece, then the response with `done` set. The
+    // timeout applies to each line, so a long generation is fine as long as it keeps coming
+    let mut reader = BufReader::new(&mut self.stream);
+    let response = loop {
+      let mut line = String::new();
+      reader
+        .read_line(&mut line)
+        .with_context(|| "Failed to read response from daemon")?;
+      let value: serde_json::Value =
+        serde_json::from_str(&line).with_context(|| "Failed to parse daemon response")?;
+      // A generation waiting behind others says so first
+      if value.get("queued").is_some() {
+        continue;
+      }
+      match (value.get("token").and_then(|t| t.as_str()), &mut on_token) {
+        (Some(piece), Some(on_token)) => on_token(piece),
+        (Some(_), None) => {}
+        (None, _) => {
+          break serde_json::from_value::<Response>(value)
+            .with_context(|| "Failed to parse daemon response")?
+        }
+      }
+    };
 
+    if let Some(usage) = response.usage {
+      llm::add_usage(usage);
+    }
     if let Some(error) = response.error {
       return Err(anyhow!("Daemon error: {}", error));
     }
@@ -68,6 +277,47 @@ impl DaemonClient {
     self.send_request("ping", serde_json::json!({}))
   }
 
+  /// Daemons that predate detailed pings answer "pong", which reads as idle
+  pub fn activity(&mut self) -> Result<Activity> {
+    let value = self.send_request_value("ping", serde_json::json!({ "detailed": true }))?;
+    Ok(serde_json::from_value(value).unwrap_or(Activity::Idle))
+  }
+
+  /// Short summary: "idle" or how many requests are in flight
+  pub fn status(&mut self) -> Result<String> {
+    self.send_request("status", serde_json::json!({}))
+  }
+
+  /// Model, uptime and memory details. Daemons that predate it answer with a plain string
+  pub fn status_details(&mut self) -> Result<DaemonStatus> {
+    let value = self.send_request_value("status", serde_json::json!({ "detailed": true }))?;
+    serde_json::from_value(value).with_context(|| "The daemon doesn't report details; restart it")
+  }
+
+  /// Uptime, requests served, model, context size and last activity, for monitoring. Daemons
+  /// that predate it answer "Unknown method"; `status_details` has the same fields
+  pub fn stats(&mut self) -> Result<DaemonStatus> {
+    let value = self.send_request_value("stats", serde_json::json!({}))?;
+    serde_json::from_value(value).with_context(|| "Invalid stats from daemon")
+  }
+
+  /// Request counts and timings. Daemons that predate it answer "Unknown method"
+  pub fn metrics(&mut self) -> Result<DaemonMetrics> {
+    let value = self.send_request_value("metrics", serde_json::json!({}))?;
+    serde_json::from_value(value).with_context(|| "Invalid metrics from daemon")
+  }
+
+  pub fn gpu_layers(&mut self) -> Result<String> {
+    self.send_request("gpu_layers", serde_json::json!({}))
+  }
+
+  /// Swap in the model at `path`, or whatever the daemon's config now names. Waits for
+  /// queued generations to finish first
+  pub fn reload_model(&mut self, path: Option<&str>) -> Result<()> {
+    self.send_request("reload_model", serde_json::json!({ "path": path }))?;
+    Ok(())
+  }
+
   pub fn shutdown(&mut self) -> Result<String> {
     self.send_request("shutdown", serde_json::json!({}))
   }
@@ -82,20 +332,250 @@ impl DaemonClient {
     )
   }
 
-  pub fn generate_commit_message(&mut self, diff: &str) -> Result<String> {
+  /// Like `generate`, passing the text to `on_token` as the daemon produces it
+  pub fn generate_streaming(
+    &mut self,
+    prompt: &str,
+    max_tokens: u32,
+    mut on_token: impl FnMut(&str),
+  ) -> Result<String> {
+    let value = self.exchange(
+      "generate",
+      serde_json::json!({
+          "prompt": prompt,
+          "max_tokens": max_tokens
+      }),
+      Some(&mut on_token),
+    )?;
+    Ok(text(value))
+  }
+
+  pub fn generate_commit_message(
+    &mut self,
+    diff: &str,
+    structure: &[StructuralChange],
+    hints: CommitHints,
+  ) -> Result<String> {
     self.send_request(
       "generate_commit_message",
       serde_json::json!({
+          "diff": diff,
+          "structure": structure,
+          "scope": hints.scope,
+          "issue": hints.issue,
+          "examples": hints.examples
+      }),
+    )
+  }
+
+  pub fn generate_commit_message_full(
+    &mut self,
+    diff: &str,
+    structure: &[StructuralChange],
+    hints: CommitHints,
+  ) -> Result<String> {
+    self.send_request(
+      "generate_commit_message_full",
+      serde_json::json!({
+          "diff": diff,
+          "structure": structure,
+          "scope": hints.scope,
+          "issue": hints.issue,
+          "tests": hints.tests,
+          "examples": hints.examples
+      }),
+    )
+  }
+
+  pub fn generate_commit_candidates(
+    &mut self,
+    diff: &str,
+    structure: &[StructuralChange],
+    hints: CommitHints,
+    count: usize,
+    body: bool,
+  ) -> Result<Vec<String>> {
+    let result = self.send_request(
+      "generate_commit_candidates",
+      serde_json::json!({
+          "diff": diff,
+          "structure": structure,
+          "scope": hints.scope,
+          "issue": hints.issue,
+          "tests": hints.tests,
+          "examples": hints.examples,
+          "count": count,
+          "body": body
+      }),
+    )?;
+    serde_json::from_str(&result).with_context(|| "Invalid candidate list from daemon")
+  }
+
+  pub fn generate_pr_description(
+    &mut self,
+    commits: &[(String, String)],
+    issue: Option<&str>,
+  ) -> Result<String> {
+    self.send_request(
+      "generate_pr_description",
+      serde_json::json!({
+          "commits": commits,
+          "issue": issue
+      }),
+    )
+  }
+
+  pub fn generate_changelog(
+    &mut self,
+    commits: &[CommitRecord],
+    format: &ChangelogFormat,
+  ) -> Result<String> {
+    self.send_request(
+      "generate_changelog",
+      serde_json::json!({
+          "commits": commits,
+          "format": format
+      }),
+    )
+  }
+
+  pub fn find_fixup_target(&mut self, diff: &str, commits: &[CommitRecord]) -> Result<String> {
+    self.send_request(
+      "find_fixup_target",
+      serde_json::json!({
+          "diff": diff,
+          "commits": commits
+      }),
+    )
+  }
+
+  pub fn summarize_changes(&mut self, diff_chunks: &[&str]) -> Result<String> {
+    self.send_request(
+      "summarize_changes",
+      serde_json::json!({
+          "diff_chunks": diff_chunks
+      }),
+    )
+  }
+
+  pub fn predict_cherry_pick_conflicts(
+    &mut self,
+    patch: &str,
+    current_files: &[(String, String)],
+  ) -> Result<Vec<ConflictPrediction>> {
+    let result = self.send_request(
+      "predict_cherry_pick_conflicts",
+      serde_json::json!({
+          "patch": patch,
+          "files": current_files
+      }),
+    )?;
+    serde_json::from_str(&result).with_context(|| "Invalid conflict prediction from daemon")
+  }
+
+  pub fn analyze_merge(&mut self, branch: &str, diff: &str) -> Result<MergeAnalysis> {
+    let result = self.send_request(
+      "analyze_merge",
+      serde_json::json!({
+          "branch": branch,
           "diff": diff
       }),
+    )?;
+    serde_json::from_str(&result).with_context(|| "Invalid merge analysis from daemon")
+  }
+
+  pub fn classify_bisect_step(&mut self, diff: &str, symptom: &str) -> Result<String> {
+    self.send_request(
+      "classify_bisect_step",
+      serde_json::json!({
+          "diff": diff,
+          "symptom": symptom
+      }),
     )
   }
 
-  pub fn suggest_branch_name(&mut self, description: &str) -> Result<String> {
+  pub fn summarize_log(&mut self, messages: &[String]) -> Result<String> {
+    self.send_request(
+      "summarize_log",
+      serde_json::json!({
+          "messages": messages
+      }),
+    )
+  }
+
+  pub fn generate_stash_message(&mut self, diff: &str) -> Result<String> {
+    self.send_request(
+      "generate_stash_message",
+      serde_json::json!({
+          "diff": diff
+      }),
+    )
+  }
+
+  pub fn suggest_next_version(&mut self, latest_tag: &str, commits: &[String]) -> Result<String> {
+    self.send_request(
+      "suggest_next_version",
+      serde_json::json!({
+          "latest_tag": latest_tag,
+          "commits": commits
+      }),
+    )
+  }
+
+  pub fn review_changes(
+    &mut self,
+    diff: &str,
+    severity: Option<&str>,
+  ) -> Result<Vec<ReviewComment>> {
+    let result = self.send_request(
+      "review_changes",
+      serde_json::json!({
+          "diff": diff,
+          "severity": severity
+      }),
+    )?;
+    serde_json::from_str(&result).with_context(|| "Invalid review from daemon")
+  }
+
+  pub fn review_diff(
+    &mut self,
+    diff_chunks: &[&str],
+    severity: Option<&str>,
+  ) -> Result<Vec<ReviewComment>> {
+    let result = self.send_request(
+      "review_diff",
+      serde_json::json!({
+          "diff_chunks": diff_chunks,
+          "severity": severity
+      }),
+    )?;
+    serde_json::from_str(&result).with_context(|| "Invalid review from daemon")
+  }
+
+  pub fn explain_changes(
+    &mut self,
+    messages: &[String],
+    diff: &str,
+    deleted_tests: &[String],
+    short: bool,
+  ) -> Result<String> {
+    self.send_request(
+      "explain_changes",
+      serde_json::json!({
+          "messages": messages,
+          "diff": diff,
+          "deleted_tests": deleted_tests,
+          "short": short
+      }),
+    )
+  }
+
+  pub fn suggest_branch_name(&mut self, description: &str, issue: Option<&str>) -> Result<String> {
     self.send_request(
       "suggest_branch_name",
       serde_json::json!({
-          "description": description
+          "description": description,
+          "issue": issue
       }),
     )
   }
@@ -103,17 +583,20 @@ impl DaemonClient {
   pub fn suggest_conflict_resolution(
     &mut self,
     file: &str,
-    ours: &str,
-    theirs: &str,
-    base: &str,
+    conflict: &Conflict,
+    labels: &ConflictLabels,
   ) -> Result<String> {
     self.send_request(
       "suggest_conflict_resolution",
       serde_json::json!({
           "file": file,
-          "ours": ours,
-          "theirs": theirs,
-          "base": base
+          "ours": conflict.ours,
+          "theirs": conflict.theirs,
+          "base": conflict.base,
+          "before": conflict.before,
+          "after": conflict.after,
+          "ours_label": labels.ours,
+          "theirs_label": labels.theirs
       }),
     )
   }
@@ -140,12 +623,17 @@ pub fn connect() -> Result<DaemonClient> {
   let stream = TcpStream::connect_timeout(&addr.parse().unwrap(), Duration::from_millis(100))
     .with_context(|| "Daemon not running")?;
 
-  stream.set_read_timeout(Some(Duration::from_secs(120)))?;
   stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+  let stream = if daemon_config.tls {
+    daemon_tls::connect(stream)?
+  } else {
+    Channel::Plain(stream)
+  };
 
   let mut client = DaemonClient {
     stream,
     request_id: 0,
+    token: daemon_config.auth_token,
   };
 
   // Verify connection with ping
@@ -153,3 +641,268 @@ pub fn connect() -> Result<DaemonClient> {
 
   Ok(client)
 }
+
+/// `alferd` next to this executable, else on `PATH`, else in a common install location
+pub fn find_alferd_binary() -> Result<PathBuf> {
+  // Check same directory as current executable
+  if let Ok(current_exe) = std::env::current_exe() {
+    let dir = current_exe.parent().unwrap();
+    let alferd = dir.join("alferd");
+    if alferd.exists() {
+      return Ok(alferd);
+    }
+  }
+
+  // Check PATH
+  if let Ok(output) = Command::new("which").arg("alferd").output() {
+    if output.status.success() {
+      let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+      if !path.is_empty() {
+        return Ok(PathBuf::from(path));
+      }
+    }
+  }
+
+  // Check common locations
+  let home = dirs::home_dir().unwrap_or_default();
+  let common_paths: Vec<PathBuf> = vec![
+    PathBuf::from("/usr/local/bin/alferd"),
+    PathBuf::from("/usr/bin/alferd"),
+    home.join(".cargo/bin/alferd"),
+  ];
+
+  for path in common_paths {
+    if path.exists() {
+      return Ok(path);
+    }
+  }
+
+  Err(anyhow!(
+    "Could not find alferd binary.\n\
+         Make sure it's installed in the same directory as alfred or in your PATH."
+  ))
+}
+
+/// How often a client that started the daemon checks whether it's listening
+const AUTO_START_POLL: Duration = Duration::from_millis(250);
+
+/// Start `alferd` in the background, detached so it outlives this process and its terminal
+pub fn spawn_detached() -> Result<Child> {
+  let alferd_path = find_alferd_binary()?;
+  let mut cmd = Command::new(&alferd_path);
+  cmd
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+
+  // Its own process group, so Ctrl+C in this terminal doesn't reach it
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+  }
+
+  cmd
+    .spawn()
+    .with_context(|| format!("Failed to start daemon from {}", alferd_path.display()))
+}
+
+/// Connect, first starting the daemon when it isn't running and `daemon.auto_start` is on
+pub fn connect_or_start() -> Result<DaemonClient> {
+  let daemon_config = config::get_daemon_config();
+  match connect() {
+    Ok(client) => return Ok(client),
+    Err(e) if !daemon_config.auto_start => return Err(e),
+    Err(_) => {}
+  }
+  // Loading a large model from a cold disk takes a while, so by default this is generous
+  let timeout = Duration::from_secs(daemon_config.auto_start_timeout_secs);
+
+  eprintln!("{}", "Starting daemon...".dimmed());
+  let mut child = spawn_detached()?;
+
+  let started = std::time::Instant::now();
+  loop {
+    std::thread::sleep(AUTO_START_POLL);
+    match connect() {
+      Ok(client) => return Ok(client),
+      // Usually a model that failed to load; alferd prints why when run by hand
+      Err(_) if child.try_wait().is_ok_and(|status| status.is_some()) => {
+        return Err(anyhow!(
+          "The daemon exited during startup. Run 'alfred daemon run' to see why"
+        ))
+      }
+      Err(e) if !timeout.is_zero() && started.elapsed() >= timeout => {
+        return Err(e.context(format!(
+          "The daemon didn't start listening within {}s",
+          timeout.as_secs()
+        )))
+      }
+      Err(_) => {}
+    }
+  }
+}
+
+/// How often a client waiting on a busy daemon asks again
+const BUSY_POLL: Duration = Duration::from_millis(500);
+
+/// Connect, waiting while the daemon is busy but should be free within `busy_wait`.
+///
+/// `Ok(None)` means generate locally instead. In `strict` mode a busy daemon is never given
+/// up on: the request queues behind the running generation. With `daemon.auto_start` on, a
+/// daemon that isn't running is started first.
+pub fn connect_when_ready(busy_wait: Duration, strict: bool) -> Result<Option<DaemonClient>> {
+  let mut client = connect_or_start()?;
+  let free = wait_until_free(|| client.activity(), busy_wait, strict, BUSY_POLL)?;
+  Ok(free.then_some(client))
+}
+
+/// Poll `activity` every `poll` until `decide` says to use the daemon (`true`) or to
+/// generate locally (`false`)
+fn wait_until_free(
+  mut activity: impl FnMut() -> Result<Activity>,
+  busy_wait: Duration,
+  strict: bool,
+  poll: Duration,
+) -> Result<bool> {
+  let started = std::time::Instant::now();
+
+  loop {
+    let activity = activity()?;
+    match decide(&activity, started.elapsed(), busy_wait) {
+      BusyDecision::Use => return Ok(true),
+      BusyDecision::FallBack if strict => return Ok(true),
+      BusyDecision::FallBack => {
+        if let Activity::Generating {
+          estimated_remaining_secs: Some(remaining),
+          ..
+        } = activity
+        {
+          eprintln!(
+            "{}",
+            format!("Daemon busy for ~{}s, generating locally", remaining).dimmed()
+          );
+        } else {
+          eprintln!("{}", "Daemon still busy, generating locally".dimmed());
+        }
+        return Ok(false);
+      }
+      BusyDecision::Wait => std::thread::sleep(poll),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+
+  const SEC: Duration = Duration::from_secs(1);
+
+  fn generating(remaining: Option<u64>) -> Activity {
+    Activity::Generating {
+      elapsed_secs: 3,
+      queue_depth: 0,
+      estimated_remaining_secs: remaining,
+    }
+  }
+
+  #[test]
+  fn estimates_need_an_observed_speed() {
+    assert_eq!(estimate_remaining(SEC, 200, None, 0), None);
+    assert_eq!(estimate_remaining(SEC, 200, Some(0.0), 0), None);
+  }
+
+  #[test]
+  fn estimates_count_the_rest_of_the_budget_and_the_queue() {
+    // 200 tokens at 40/s is 5s per request
+    assert_eq!(estimate_remaining(2 * SEC, 200, Some(40.0), 0), Some(3));
+    assert_eq!(estimate_remaining(2 * SEC, 200, Some(40.0), 2), Some(13));
+    // A generation past its estimate only waits on the queue
+    assert_eq!(estimate_remaining(60 * SEC, 200, Some(40.0), 0), Some(0));
+    assert_eq!(estimate_remaining(60 * SEC, 200, Some(40.0), 1), Some(5));
+    assert_eq!(estimate_remaining(SEC / 2, 100, Some(30.0), 0), Some(3));
+  }
+
+  #[test]
+  fn decisions() {
+    let wait = 10 * SEC;
+    assert_eq!(decide(&Activity::Idle, 30 * SEC, wait), BusyDecision::Use);
+    assert_eq!(decide(&generating(Some(4)), SEC, wait), BusyDecision::Wait);
+    assert_eq!(decide(&generating(Some(9)), SEC, wait), BusyDecision::Wait);
+    assert_eq!(
+      decide(&generating(Some(10)), SEC, wait),
+      BusyDecision::FallBack
+    );
+    assert_eq!(
+      decide(&generating(Some(120)), Duration::ZERO, wait),
+      BusyDecision::FallBack
+    );
+    assert_eq!(decide(&generating(None), 9 * SEC, wait), BusyDecision::Wait);
+    assert_eq!(
+      decide(&generating(None), wait, wait),
+      BusyDecision::FallBack
+    );
+    // A busy_wait of 0 never waits on an unknown generation
+    assert_eq!(
+      decide(&generating(None), Duration::ZERO, Duration::ZERO),
+      BusyDecision::FallBack
+    );
+  }
+
+  #[test]
+  fn activity_reads_the_daemons_ping() {
+    let idle: Activity = serde_json::from_str(r#"{"state":"idle"}"#).unwrap();
+    assert_eq!(idle, Activity::Idle);
+    let busy: Activity = serde_json::from_str(
+      r#"{"state":"generating","elapsed_secs":3,"queue_depth":0,"estimated_remaining_secs":null}"#,
+    )
+    .unwrap();
+    assert_eq!(busy, generating(None));
+    assert_eq!(serde_json::to_value(&busy).unwrap()["state"], "generating");
+  }
+
+  #[test]
+  fn waits_for_a_daemon_about_to_be_free() {
+    let polls = Cell::new(0);
+    let ping = || {
+      polls.set(polls.get() + 1);
+      Ok(if polls.get() <= 3 {
+        generating(Some(1))
+      } else {
+        Activity::Idle
+      })
+    };
+    let poll = Duration::from_millis(5);
+    assert!(wait_until_free(ping, 10 * SEC, false, poll).unwrap());
+    assert_eq!(polls.get(), 4);
+  }
+
+  #[test]
+  fn falls_back_once_the_wait_runs_out() {
+    let polls = Cell::new(0);
+    let ping = || {
+      polls.set(polls.get() + 1);
+      Ok(generating(None))
+    };
+    let started = std::time::Instant::now();
+    let wait = Duration::from_millis(50);
+    assert!(!wait_until_free(ping, wait, false, Duration::from_millis(5)).unwrap());
+    assert!(started.elapsed() >= wait);
+    assert!(polls.get() > 1);
+  }
+
+  #[test]
+  fn long_generations_fall_back_without_waiting_unless_strict() {
+    let ping = || Ok(generating(Some(300)));
+    assert!(!wait_until_free(ping, 10 * SEC, false, 10 * SEC).unwrap());
+    // Strict mode queues behind the generation instead
+    assert!(wait_until_free(ping, 10 * SEC, true, 10 * SEC).unwrap());
+  }
+
+  #[test]
+  fn ping_errors_are_reported() {
+    let ping = || Err(anyhow!("connection reset"));
+    assert!(wait_until_free(ping, 10 * SEC, false, SEC).is_err());
+  }
+}
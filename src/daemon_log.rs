@@ -0,0 +1,104 @@
+//! The daemon's structured log: one JSON entry per line in `~/.alfred/alferd.log`, rotated
+//! by size so it never grows without bound
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+
+/// Size at which the log is moved aside to `alferd.log.1` and started afresh
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Keeps one process's threads from interleaving or rotating under each other
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogEntry {
+  /// Unix timestamp in seconds
+  pub ts: u64,
+  /// `error`, `warn`, `info` or `debug`
+  pub level: String,
+  pub message: String,
+  /// Request fields, only on entries for a served request
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub method: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<u64>,
+  /// Tokens sent to and generated by the model; estimated on the Ollama and remote backends
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub prompt_tokens: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub completion_tokens: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration_ms: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+impl LogEntry {
+  pub fn new(level: &str, message: impl Into<String>) -> Self {
+    LogEntry {
+      ts: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0),
+      level: level.to_string(),
+      message: message.into(),
+      ..Default::default()
+    }
+  }
+}
+
+fn rotated_file() -> PathBuf {
+  config::alfred_dir().join("alferd.log.1")
+}
+
+/// Append an entry, first rotating the log once it's reached `MAX_LOG_BYTES`
+pub fn append(entry: &LogEntry) -> Result<()> {
+  let _lock = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+  let path = config::daemon_log_file();
+  if fs::metadata(&path).is_ok_and(|m| m.len() >= MAX_LOG_BYTES) {
+    fs::rename(&path, rotated_file())
+      .with_context(|| format!("Failed to rotate {}", path.display()))?;
+  }
+
+  let mut line = serde_json::to_string(entry)?;
+  line.push('\n');
+  let mut file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .with_context(|| format!("Failed to open {}", path.display()))?;
+  file.write_all(line.as_bytes())?;
+  Ok(())
+}
+
+/// "2026-10-16 13:45:01" in UTC
+pub fn format_timestamp(ts: u64) -> String {
+  let days = (ts / 86_400) as i64;
+  let secs = ts % 86_400;
+  // Civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+    year,
+    month,
+    day,
+    secs / 3600,
+    secs % 3600 / 60,
+    secs % 60
+  )
+}
@@ -0,0 +1,157 @@
+//! TLS for the daemon's local TCP channel, with a self-signed certificate in `~/.alfred/`,
+//! and the random auth token clients prove themselves with
+
+use anyhow::{anyhow, Context, Result};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use crate::config;
+
+/// Name the certificate is issued to and clients check it against
+const SERVER_NAME: &str = "localhost";
+
+/// A connection to or from the daemon, encrypted when `daemon.tls` is on
+pub enum Channel<C> {
+  Plain(TcpStream),
+  Tls(Box<rustls::StreamOwned<C, TcpStream>>),
+}
+
+pub type ClientChannel = Channel<ClientConnection>;
+pub type ServerChannel = Channel<ServerConnection>;
+
+impl<C> Channel<C> {
+  /// The socket underneath, for timeouts
+  pub fn tcp(&self) -> &TcpStream {
+    match self {
+      Channel::Plain(stream) => stream,
+      Channel::Tls(stream) => &stream.sock,
+    }
+  }
+}
+
+impl<C, S> Read for Channel<C>
+where
+  C: std::ops::DerefMut + std::ops::Deref<Target = rustls::ConnectionCommon<S>>,
+  S: rustls::SideData,
+{
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    match self {
+      Channel::Plain(stream) => stream.read(buf),
+      Channel::Tls(stream) => stream.read(buf),
+    }
+  }
+}
+
+impl<C, S> Write for Channel<C>
+where
+  C: std::ops::DerefMut + std::ops::Deref<Target = rustls::ConnectionCommon<S>>,
+  S: rustls::SideData,
+{
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match self {
+      Channel::Plain(stream) => stream.write(buf),
+      Channel::Tls(stream) => stream.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    match self {
+      Channel::Plain(stream) => stream.flush(),
+      Channel::Tls(stream) => stream.flush(),
+    }
+  }
+}
+
+/// Write a new self-signed certificate and key, replacing any there are
+pub fn generate_cert() -> Result<()> {
+  let certified =
+    rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string(), "127.0.0.1".to_string()])
+      .with_context(|| "Failed to generate the daemon certificate")?;
+
+  fs::create_dir_all(config::alfred_dir())?;
+  let cert_path = config::daemon_cert_file();
+  let key_path = config::daemon_key_file();
+  fs::write(&cert_path, certified.cert.pem())
+    .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+  // Anyone who can read the key can pose as the daemon
+  config::write_private(&key_path, certified.key_pair.serialize_pem().as_bytes())?;
+
+  Ok(())
+}
+
+/// Generate the certificate on the daemon's first start with TLS on
+pub fn ensure_cert() -> Result<()> {
+  if config::daemon_cert_file().exists() && config::daemon_key_file().exists() {
+    return Ok(());
+  }
+  generate_cert()
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+  Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// 32 random bytes as hex, for `daemon.auth_token`
+pub fn generate_token() -> Result<String> {
+  let mut bytes = [0u8; 32];
+  provider()
+    .secure_random
+    .fill(&mut bytes)
+    .map_err(|_| anyhow!("Failed to generate a random token"))?;
+  Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn read_cert() -> Result<CertificateDer<'static>> {
+  let path = config::daemon_cert_file();
+  CertificateDer::from_pem_file(&path).map_err(|e| {
+    anyhow!(
+      "Failed to read the daemon certificate {}: {}\n\
+       Start the daemon with daemon.tls on to create it",
+      path.display(),
+      e
+    )
+  })
+}
+
+/// What the daemon accepts connections with
+pub fn server_config() -> Result<Arc<ServerConfig>> {
+  let key_path = config::daemon_key_file();
+  let key = PrivateKeyDer::from_pem_file(&key_path)
+    .map_err(|e| anyhow!("Failed to read {}: {}", key_path.display(), e))?;
+
+  let config = ServerConfig::builder_with_provider(provider())
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(vec![read_cert()?], key)
+    .with_context(|| "The daemon certificate and key don't match")?;
+  Ok(Arc::new(config))
+}
+
+/// Encrypt a connection to the daemon, trusting only its certificate
+pub fn connect(stream: TcpStream) -> Result<ClientChannel> {
+  let mut roots = RootCertStore::empty();
+  roots.add(read_cert()?)?;
+
+  let config = ClientConfig::builder_with_provider(provider())
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+  let name = ServerName::try_from(SERVER_NAME)?;
+  let conn = ClientConnection::new(Arc::new(config), name)?;
+  Ok(Channel::Tls(Box::new(rustls::StreamOwned::new(
+    conn, stream,
+  ))))
+}
+
+/// Encrypt a connection the daemon accepted
+pub fn accept(stream: TcpStream, config: &Arc<ServerConfig>) -> Result<ServerChannel> {
+  let conn = ServerConnection::new(config.clone())?;
+  Ok(Channel::Tls(Box::new(rustls::StreamOwned::new(
+    conn, stream,
+  ))))
+}
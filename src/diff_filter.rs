@@ -0,0 +1,112 @@
+//! `.alfredignore`: files whose changes are never sent to the model
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{config, git};
+
+/// Ignore file at the repository root
+pub const IGNORE_FILE: &str = ".alfredignore";
+
+/// Patterns that apply to every repository
+pub fn global_ignore_path() -> PathBuf {
+  config::alfred_dir().join("ignore")
+}
+
+/// Patterns from `~/.alfred/ignore` and the repository's `.alfredignore`.
+///
+/// One glob per line; blank lines and `#` comments are skipped. A pattern without a `/`
+/// matches a file or directory name anywhere, and one with a `/` matches paths from the
+/// repository root. A trailing `/` matches everything below a directory.
+pub fn load_ignore_patterns(repo_root: &Path) -> Vec<glob::Pattern> {
+  [global_ignore_path(), repo_root.join(IGNORE_FILE)]
+    .iter()
+    .filter_map(|path| fs::read_to_string(path).ok())
+    .flat_map(|content| {
+      content
+        .lines()
+        .filter_map(parse_pattern)
+        .collect::<Vec<_>>()
+    })
+    .collect()
+}
+
+/// Patterns for the repository containing the working directory
+pub fn ignore_patterns() -> Vec<glob::Pattern> {
+  load_ignore_patterns(&git::repo_root().unwrap_or_default())
+}
+
+fn parse_pattern(line: &str) -> Option<glob::Pattern> {
+  let line = line.trim();
+  if line.is_empty() || line.starts_with('#') {
+    return None;
+  }
+
+  let pattern = match line.strip_suffix('/') {
+    // A bare directory name is matched against every directory in the path
+    Some(dir) if !dir.contains('/') => dir.to_string(),
+    Some(dir) => format!("{}/**", dir),
+    None => line.to_string(),
+  };
+  glob::Pattern::new(&pattern).ok()
+}
+
+/// Whether `path` (relative to the repository root) matches any pattern
+pub fn is_ignored(path: &str, patterns: &[glob::Pattern]) -> bool {
+  let name = path.rsplit('/').next().unwrap_or(path);
+  let options = glob::MatchOptions {
+    require_literal_separator: true,
+    ..Default::default()
+  };
+
+  patterns.iter().any(|p| {
+    let pattern = p.as_str();
+    if pattern.starts_with('/') {
+      p.matches_with(&format!("/{}", path), options)
+    } else if pattern.contains('/') {
+      p.matches_with(path, options)
+    } else {
+      // `dist` also covers everything below a `dist` directory
+      p.matches_with(name, options)
+        || path
+          .split('/')
+          .rev()
+          .skip(1)
+          .any(|dir| p.matches_with(dir, options))
+    }
+  })
+}
+
+/// Old and new paths from a `diff --git a/<old> b/<new>` header
+fn header_paths(line: &str) -> Option<(&str, &str)> {
+  let rest = line.strip_prefix("diff --git a/")?;
+  rest.split_once(" b/")
+}
+
+/// Drop the sections of a unified diff whose files match `patterns`.
+///
+/// A rename is dropped only when both of its paths match. If every file would be
+/// dropped, the diff is returned unchanged so there is still something to describe.
+pub fn filter_diff(diff: &str, patterns: &[glob::Pattern]) -> String {
+  if patterns.is_empty() {
+    return diff.to_string();
+  }
+
+  let mut out = String::with_capacity(diff.len());
+  let mut keep = true;
+  let mut dropped = false;
+  for line in diff.split_inclusive('\n') {
+    if let Some((old, new)) = header_paths(line.trim_end()) {
+      keep = !(is_ignored(old, patterns) && is_ignored(new, patterns));
+      dropped |= !keep;
+    }
+    if keep {
+      out.push_str(line);
+    }
+  }
+
+  if dropped && out.trim().is_empty() {
+    return diff.to_string();
+  }
+  out
+}
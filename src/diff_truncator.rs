@@ -0,0 +1,137 @@
+//! Fitting diffs into a prompt by keeping the hunks that change the most
+
+/// One file's section of a unified diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFile {
+  /// `diff --git` line and the file lines before the first hunk (`index` lines dropped)
+  pub header: String,
+  /// Each hunk from its `@@` line through its last context line
+  pub hunks: Vec<String>,
+}
+
+/// Split a unified diff into files and hunks. Text before the first `diff --git` line is
+/// kept as a header-only file.
+pub fn parse_diff(diff: &str) -> Vec<DiffFile> {
+  let mut files: Vec<DiffFile> = Vec::new();
+
+  for line in diff.split_inclusive('\n') {
+    if line.starts_with("diff --git ") || files.is_empty() {
+      files.push(DiffFile {
+        header: String::new(),
+        hunks: Vec::new(),
+      });
+    }
+    let file = files.last_mut().expect("a file was just pushed");
+
+    if line.starts_with("@@") {
+      file.hunks.push(line.to_string());
+    } else if let Some(hunk) = file.hunks.last_mut() {
+      hunk.push_str(line);
+    } else if !line.starts_with("index ") {
+      // The blob hashes on `index` lines mean nothing to the model
+      file.header.push_str(line);
+    }
+  }
+
+  files
+}
+
+/// Added and removed lines in a hunk
+fn changed_lines(hunk: &str) -> usize {
+  hunk
+    .lines()
+    .skip(1)
+    .filter(|l| l.starts_with('+') || l.starts_with('-'))
+    .count()
+}
+
+/// Cut `text` to at most `max` bytes at a line boundary
+fn cut_lines(text: &str, max: usize) -> &str {
+  let mut end = max.min(text.len());
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+  match text[..end].rfind('\n') {
+    Some(newline) => &text[..newline + 1],
+    None => "",
+  }
+}
+
+/// Fit a diff into about `max_chars` bytes.
+///
+/// Hunks are packed greedily, most changed lines first, and each file's header is paid for
+/// with its first hunk; what fits is printed in the original order. Files that got nothing
+/// are counted on a closing `... (N files omitted)` line. When not even one hunk fits, the
+/// biggest one is cut at a line boundary so the model still sees part of the change.
+pub fn truncate_diff(diff: &str, max_chars: usize) -> String {
+  if diff.len() <= max_chars {
+    return diff.to_string();
+  }
+
+  let files = parse_diff(diff);
+
+  // (file, hunk, changed lines); files without hunks (binary, renames) rank last
+  let mut order: Vec<(usize, Option<usize>, usize)> = Vec::new();
+  for (f, file) in files.iter().enumerate() {
+    if file.hunks.is_empty() {
+      order.push((f, None, 0));
+    }
+    for (h, hunk) in file.hunks.iter().enumerate() {
+      order.push((f, Some(h), changed_lines(hunk)));
+    }
+  }
+  order.sort_by_key(|&(_, _, changed)| std::cmp::Reverse(changed));
+
+  let mut kept = vec![Vec::new(); files.len()];
+  let mut included = vec![false; files.len()];
+  let mut used = 0;
+  for &(f, h, _) in &order {
+    let header = if included[f] {
+      0
+    } else {
+      files[f].header.len()
+    };
+    let cost = header + h.map_or(0, |h| files[f].hunks[h].len());
+    if used + cost > max_chars {
+      continue;
+    }
+    used += cost;
+    included[f] = true;
+    kept[f].extend(h);
+  }
+
+  let mut out = String::with_capacity(max_chars + 64);
+  if included.contains(&true) {
+    for (f, file) in files.iter().enumerate() {
+      if !included[f] {
+        continue;
+      }
+      out.push_str(&file.header);
+      kept[f].sort_unstable();
+      for &h in &kept[f] {
+        out.push_str(&file.hunks[h]);
+      }
+    }
+  } else if let Some(&(f, h, _)) = order.first() {
+    let file = &files[f];
+    out.push_str(cut_lines(&file.header, max_chars));
+    if let Some(h) = h {
+      let hunk = &file.hunks[h];
+      // Always show at least the hunk's `@@` line
+      let at_least = hunk.find('\n').map_or(hunk.len(), |i| i + 1);
+      let room = max_chars.saturating_sub(file.header.len()).max(at_least);
+      out.push_str(cut_lines(hunk, room));
+      out.push_str("[hunk truncated]\n");
+    }
+    included[f] = true;
+  }
+
+  let omitted = included.iter().filter(|&&inc| !inc).count();
+  if omitted > 0 {
+    if !out.is_empty() && !out.ends_with('\n') {
+      out.push('\n');
+    }
+    out.push_str(&format!("... ({} files omitted)\n", omitted));
+  }
+  out
+}
@@ -0,0 +1,516 @@
+//! Read-only issue lookups on GitHub and GitLab, used as prompt context
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{self, ForgeConfig};
+use crate::{git, ui};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgeKind {
+  GitHub,
+  GitLab,
+}
+
+/// Where a repository's issues live
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+  pub kind: ForgeKind,
+  pub host: String,
+  /// API root, e.g. `https://api.github.com` or `https://gitlab.com/api/v4`
+  pub api_base: String,
+  /// `owner/repo`, or `group/subgroup/project` on GitLab
+  pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Issue {
+  pub id: String,
+  pub title: String,
+  pub description: String,
+}
+
+impl Issue {
+  /// Text added to prompts as the authoritative statement of intent
+  pub fn prompt_context(&self) -> String {
+    let mut out = format!("#{} {}", self.id, self.title);
+    if !self.description.is_empty() {
+      out.push_str("\n\n");
+      out.push_str(&self.description);
+    }
+    out
+  }
+}
+
+/// Parse an origin URL (scp-style, ssh:// or https://) into a forge remote
+pub fn parse_remote(url: &str, api_override: Option<&str>) -> Option<Remote> {
+  let url = url.trim();
+  let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+    // https://host/owner/repo, ssh://git@host:22/owner/repo
+    let (authority, path) = rest.split_once('/')?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    (host.split(':').next()?, path)
+  } else {
+    // git@host:owner/repo.git
+    let (authority, path) = url.split_once(':')?;
+    (
+      authority.rsplit_once('@').map_or(authority, |(_, h)| h),
+      path,
+    )
+  };
+
+  let path = path
+    .trim_end_matches('/')
+    .trim_end_matches(".git")
+    .to_string();
+  if host.is_empty() || !path.contains('/') {
+    return None;
+  }
+
+  let kind = if host.contains("gitlab") {
+    ForgeKind::GitLab
+  } else if host.contains("github") {
+    ForgeKind::GitHub
+  } else if api_override.is_some_and(|api| api.contains("/api/v4")) {
+    ForgeKind::GitLab
+  } else if api_override.is_some() {
+    ForgeKind::GitHub
+  } else {
+    return None;
+  };
+
+  let api_base = match (api_override, kind) {
+    (Some(api), _) => api.trim_end_matches('/').to_string(),
+    (None, ForgeKind::GitHub) if host == "github.com" => "https://api.github.com".to_string(),
+    // GitHub Enterprise
+    (None, ForgeKind::GitHub) => format!("https://{}/api/v3", host),
+    (None, ForgeKind::GitLab) => format!("https://{}/api/v4", host),
+  };
+
+  Some(Remote {
+    kind,
+    host: host.to_string(),
+    api_base,
+    path,
+  })
+}
+
+/// Find an issue number in a branch name: `feature/123-login`, `fix/gh-42`, `issue-7`
+pub fn issue_from_branch(branch: &str) -> Option<String> {
+  let name = branch.rsplit('/').next()?;
+  name
+    .split(['-', '_', '#'])
+    .find(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    .map(String::from)
+}
+
+fn token(kind: ForgeKind, cfg: &ForgeConfig) -> Option<String> {
+  let vars: &[&str] = match kind {
+    ForgeKind::GitHub => &["GITHUB_TOKEN", "GH_TOKEN"],
+    ForgeKind::GitLab => &["GITLAB_TOKEN"],
+  };
+  vars
+    .iter()
+    .find_map(|v| std::env::var(v).ok())
+    .or_else(|| cfg.token.clone())
+    .filter(|t| !t.is_empty())
+}
+
+fn issue_url(remote: &Remote, id: &str) -> String {
+  match remote.kind {
+    // The issues endpoint also answers for pull requests
+    ForgeKind::GitHub => format!("{}/repos/{}/issues/{}", remote.api_base, remote.path, id),
+    ForgeKind::GitLab => format!(
+      "{}/projects/{}/issues/{}",
+      remote.api_base,
+      remote.path.replace('/', "%2F"),
+      id
+    ),
+  }
+}
+
+#[derive(Deserialize)]
+struct GitHubIssue {
+  title: String,
+  body: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitLabIssue {
+  title: String,
+  description: Option<String>,
+}
+
+/// Turn an API response body into an issue, keeping the first `max_chars` of its description
+pub fn parse_issue(kind: ForgeKind, id: &str, body: &str, max_chars: usize) -> Result<Issue> {
+  let (title, description) = match kind {
+    ForgeKind::GitHub => {
+      let issue: GitHubIssue = serde_json::from_str(body)?;
+      (issue.title, issue.body)
+    }
+    ForgeKind::GitLab => {
+      let issue: GitLabIssue = serde_json::from_str(body)?;
+      (issue.title, issue.description)
+    }
+  };
+
+  Ok(Issue {
+    id: id.to_string(),
+    title: title.trim().to_string(),
+    description: description
+      .unwrap_or_default()
+      .trim()
+      .chars()
+      .take(max_chars)
+      .collect(),
+  })
+}
+
+fn fetch(remote: &Remote, id: &str, token: &str, max_chars: usize) -> Result<Issue> {
+  let url = issue_url(remote, id);
+  let kind = remote.kind;
+  let token = token.to_string();
+
+  // reqwest's blocking client must not run on a tokio worker
+  let body = std::thread::spawn(move || -> Result<String> {
+    let client = reqwest::blocking::Client::builder()
+      .timeout(REQUEST_TIMEOUT)
+      .user_agent("alfred")
+      .build()?;
+    let request = match kind {
+      ForgeKind::GitHub => client
+        .get(&url)
+        .bearer_auth(&token)
+        .header("Accept", "application/vnd.github+json"),
+      ForgeKind::GitLab => client.get(&url).header("PRIVATE-TOKEN", &token),
+    };
+    Ok(request.send()?.error_for_status()?.text()?)
+  })
+  .join()
+  .map_err(|_| anyhow!("Forge request thread panicked"))??;
+
+  parse_issue(kind, id, &body, max_chars)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+  /// Days since the Unix epoch when the issue was fetched
+  day: u64,
+  issue: Issue,
+}
+
+fn today() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() / 86_400)
+    .unwrap_or(0)
+}
+
+fn cache_key(remote: &Remote, id: &str) -> String {
+  format!("{}/{}#{}", remote.host, remote.path, id)
+}
+
+fn load_cache(path: &Path) -> HashMap<String, CacheEntry> {
+  fs::read_to_string(path)
+    .ok()
+    .and_then(|c| serde_json::from_str(&c).ok())
+    .unwrap_or_default()
+}
+
+fn save_cache(path: &Path, cache: &HashMap<String, CacheEntry>) -> Result<()> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  fs::write(path, serde_json::to_string(cache)?)
+    .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Fetch an issue, reusing today's copy from the cache at `cache_file`
+fn lookup(
+  cache_file: &Path,
+  remote: &Remote,
+  id: &str,
+  cfg: &ForgeConfig,
+) -> Result<Option<Issue>> {
+  let key = cache_key(remote, id);
+  let mut cache = load_cache(cache_file);
+  let day = today();
+
+  if let Some(entry) = cache.get(&key).filter(|e| e.day == day) {
+    return Ok(Some(entry.issue.clone()));
+  }
+
+  let Some(token) = token(remote.kind, cfg) else {
+    return Ok(None);
+  };
+
+  let issue = fetch(remote, id, &token, cfg.description_chars)?;
+  cache.retain(|_, e| e.day == day);
+  cache.insert(
+    key,
+    CacheEntry {
+      day,
+      issue: issue.clone(),
+    },
+  );
+  let _ = save_cache(cache_file, &cache);
+  Ok(Some(issue))
+}
+
+/// Dim status line, suppressed when stdout is piped (e.g. `alfred pr describe | gh ...`)
+fn note(msg: String) {
+  if std::io::stdout().is_terminal() {
+    ui::dim(msg);
+  }
+}
+
+/// Issue context for prompts, from `--issue` or the current branch name.
+///
+/// Never fails: missing tokens, unknown remotes and network errors all yield `None`.
+pub fn issue_context(explicit: Option<&str>, enabled: bool) -> Option<String> {
+  let cfg = config::current().forge.clone();
+  if !enabled || !cfg.enabled {
+    return None;
+  }
+
+  let id = match explicit {
+    Some(id) => id.trim_start_matches('#').to_string(),
+    None => issue_from_branch(&git::current_branch()?)?,
+  };
+  let remote = parse_remote(&git::remote_url("origin")?, cfg.api_url.as_deref())?;
+
+  match lookup(&config::forge_cache_file(), &remote, &id, &cfg) {
+    Ok(Some(issue)) => {
+      note(format!("Using issue #{}: {}", issue.id, issue.title));
+      Some(issue.prompt_context())
+    }
+    Ok(None) => None,
+    Err(e) => {
+      note(format!("Issue #{} context unavailable: {}", id, e));
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Read, Write};
+  use std::net::TcpListener;
+  use std::thread::JoinHandle;
+
+  const GITHUB_ISSUE: &str = r#"{
+    "number": 42,
+    "title": "  Login fails with SSO  ",
+    "body": "Steps:\n1. Sign in with SSO\n2. See a 500",
+    "state": "open",
+    "user": {"login": "octocat"}
+  }"#;
+
+  const GITLAB_ISSUE: &str = r#"{
+    "iid": 7,
+    "title": "Export to CSV",
+    "description": null,
+    "state": "opened"
+  }"#;
+
+  /// Answer one HTTP request with `body`, handing back the request that came in
+  fn serve_once(body: &'static str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut request = Vec::new();
+      let mut buf = [0u8; 1024];
+      while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).unwrap();
+        if n == 0 {
+          break;
+        }
+        request.extend_from_slice(&buf[..n]);
+      }
+      write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+      )
+      .unwrap();
+      String::from_utf8_lossy(&request).to_lowercase()
+    });
+    (base, server)
+  }
+
+  fn remote(kind: ForgeKind, api_base: &str, path: &str) -> Remote {
+    Remote {
+      kind,
+      host: "example.com".to_string(),
+      api_base: api_base.to_string(),
+      path: path.to_string(),
+    }
+  }
+
+  #[test]
+  fn remotes_map_to_api_roots() {
+    let github = parse_remote("git@github.com:wess/alfred.git", None).unwrap();
+    assert_eq!(github.kind, ForgeKind::GitHub);
+    assert_eq!(github.api_base, "https://api.github.com");
+    assert_eq!(github.path, "wess/alfred");
+    assert_eq!(
+      parse_remote("https://github.com/wess/alfred/", None),
+      Some(github)
+    );
+
+    let gitlab = parse_remote(
+      "ssh://git@gitlab.example.com:2222/group/sub/project.git",
+      None,
+    )
+    .unwrap();
+    assert_eq!(gitlab.kind, ForgeKind::GitLab);
+    assert_eq!(gitlab.host, "gitlab.example.com");
+    assert_eq!(gitlab.api_base, "https://gitlab.example.com/api/v4");
+    assert_eq!(gitlab.path, "group/sub/project");
+
+    let enterprise = parse_remote("https://github.corp.com/team/app", None).unwrap();
+    assert_eq!(enterprise.api_base, "https://github.corp.com/api/v3");
+  }
+
+  #[test]
+  fn unknown_hosts_need_a_configured_api() {
+    assert_eq!(parse_remote("git@git.corp.com:team/app.git", None), None);
+
+    let gitlab = parse_remote(
+      "git@git.corp.com:team/app.git",
+      Some("https://git.corp.com/api/v4/"),
+    )
+    .unwrap();
+    assert_eq!(gitlab.kind, ForgeKind::GitLab);
+    assert_eq!(gitlab.api_base, "https://git.corp.com/api/v4");
+
+    let github = parse_remote(
+      "git@git.corp.com:team/app.git",
+      Some("https://git.corp.com/api"),
+    )
+    .unwrap();
+    assert_eq!(github.kind, ForgeKind::GitHub);
+
+    assert_eq!(parse_remote("/srv/git/app.git", None), None);
+    assert_eq!(parse_remote("https://github.com/alfred", None), None);
+  }
+
+  #[test]
+  fn issue_numbers_come_from_the_last_branch_segment() {
+    assert_eq!(
+      issue_from_branch("feature/123-login").as_deref(),
+      Some("123")
+    );
+    assert_eq!(issue_from_branch("fix/gh-42").as_deref(), Some("42"));
+    assert_eq!(issue_from_branch("issue_7").as_deref(), Some("7"));
+    assert_eq!(issue_from_branch("release/2024/notes"), None);
+    assert_eq!(issue_from_branch("main"), None);
+  }
+
+  #[test]
+  fn issue_urls_follow_each_api() {
+    let github = remote(ForgeKind::GitHub, "https://api.github.com", "wess/alfred");
+    assert_eq!(
+      issue_url(&github, "42"),
+      "https://api.github.com/repos/wess/alfred/issues/42"
+    );
+    let gitlab = remote(
+      ForgeKind::GitLab,
+      "https://gitlab.com/api/v4",
+      "group/sub/project",
+    );
+    assert_eq!(
+      issue_url(&gitlab, "7"),
+      "https://gitlab.com/api/v4/projects/group%2Fsub%2Fproject/issues/7"
+    );
+  }
+
+  #[test]
+  fn responses_parse_for_both_forges() {
+    let issue = parse_issue(ForgeKind::GitHub, "42", GITHUB_ISSUE, 20).unwrap();
+    assert_eq!(issue.title, "Login fails with SSO");
+    assert_eq!(issue.description, "Steps:\n1. Sign in wi");
+
+    let issue = parse_issue(ForgeKind::GitLab, "7", GITLAB_ISSUE, 500).unwrap();
+    assert_eq!(issue.title, "Export to CSV");
+    assert_eq!(issue.description, "");
+    assert_eq!(issue.prompt_context(), "#7 Export to CSV");
+
+    assert!(parse_issue(ForgeKind::GitHub, "1", "<html>rate limited</html>", 10).is_err());
+  }
+
+  #[test]
+  fn descriptions_are_cut_on_characters() {
+    let body = r#"{"title": "t", "body": "héllo wörld"}"#;
+    let issue = parse_issue(ForgeKind::GitHub, "1", body, 4).unwrap();
+    assert_eq!(issue.description, "héll");
+    assert_eq!(issue.prompt_context(), "#1 t\n\nhéll");
+  }
+
+  #[test]
+  fn each_forge_gets_its_own_auth_header() {
+    let (base, server) = serve_once(GITHUB_ISSUE);
+    let github = remote(ForgeKind::GitHub, &base, "wess/alfred");
+    let issue = fetch(&github, "42", "gh-secret", 100).unwrap();
+    assert_eq!(issue.title, "Login fails with SSO");
+    let request = server.join().unwrap();
+    assert!(
+      request.starts_with("get /repos/wess/alfred/issues/42 "),
+      "{}",
+      request
+    );
+    assert!(
+      request.contains("authorization: bearer gh-secret"),
+      "{}",
+      request
+    );
+
+    let (base, server) = serve_once(GITLAB_ISSUE);
+    let gitlab = remote(ForgeKind::GitLab, &base, "group/project");
+    fetch(&gitlab, "7", "gl-secret", 100).unwrap();
+    let request = server.join().unwrap();
+    assert!(
+      request.starts_with("get /projects/group%2fproject/issues/7 "),
+      "{}",
+      request
+    );
+    assert!(request.contains("private-token: gl-secret"), "{}", request);
+  }
+
+  #[test]
+  fn lookups_are_cached_for_the_day() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("forge-cache.json");
+    let cfg = ForgeConfig {
+      token: Some("secret".to_string()),
+      ..Default::default()
+    };
+
+    // The server answers once; the second lookup must come from the cache
+    let (base, server) = serve_once(GITHUB_ISSUE);
+    let github = remote(ForgeKind::GitHub, &base, "wess/alfred");
+    let first = lookup(&cache, &github, "42", &cfg).unwrap().unwrap();
+    server.join().unwrap();
+    let second = lookup(&cache, &github, "42", &cfg).unwrap().unwrap();
+    assert_eq!(first.title, second.title);
+
+    // Yesterday's copy is fetched again
+    let mut entries = load_cache(&cache);
+    entries.get_mut(&cache_key(&github, "42")).unwrap().day -= 1;
+    save_cache(&cache, &entries).unwrap();
+    assert!(
+      lookup(&cache, &github, "42", &cfg).is_err(),
+      "nothing is serving"
+    );
+  }
+}
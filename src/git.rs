@@ -1,6 +1,25 @@
 use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+use crate::config::CommitRules;
+use crate::conflict::{self, ConflictSegment};
+use crate::diff_filter;
+
+#[cfg(any(feature = "git-cli", test))]
+mod cli;
+#[cfg(any(not(feature = "git-cli"), test))]
+mod libgit;
+
+// Status, diffs, logs, branch lists and conflict stages are read through libgit2, or through
+// the git binary in builds with the `git-cli` feature. Everything else runs git
+#[cfg(feature = "git-cli")]
+use cli as backend;
+#[cfg(not(feature = "git-cli"))]
+use libgit as backend;
+
 #[derive(Debug, Default)]
 pub struct GitStatus {
   pub branch: String,
@@ -12,6 +31,45 @@ pub struct GitStatus {
   pub conflicts: Vec<String>,
 }
 
+/// Per-file line counts and structural flags from a diff
+#[derive(Debug, Default, Clone)]
+pub struct FileChange {
+  pub path: String,
+  pub added: usize,
+  pub deleted: usize,
+  pub binary: bool,
+  pub mode_changed: bool,
+  pub removed: bool,
+}
+
+/// One commit from the history, as used for changelogs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitRecord {
+  pub hash: String,
+  pub author: String,
+  /// Author date, `YYYY-MM-DD`
+  pub date: String,
+  pub message: String,
+}
+
+impl CommitRecord {
+  pub fn subject(&self) -> &str {
+    self.message.lines().next().unwrap_or("")
+  }
+
+  /// The message after the subject line
+  pub fn body(&self) -> &str {
+    self
+      .message
+      .split_once('\n')
+      .map_or("", |(_, body)| body.trim())
+  }
+
+  pub fn short_hash(&self) -> &str {
+    &self.hash[..self.hash.len().min(7)]
+  }
+}
+
 #[derive(Debug, Default)]
 pub struct ConflictInfo {
   pub file: String,
@@ -20,9 +78,86 @@ pub struct ConflictInfo {
   pub theirs: String,
 }
 
+/// The in-progress operation that produced conflicts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+  Merge,
+  Rebase,
+  CherryPick,
+  Revert,
+  /// No operation state on disk; conflicts left by `git stash pop/apply`
+  Stash,
+}
+
+impl Operation {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Operation::Merge => "merge",
+      Operation::Rebase => "rebase",
+      Operation::CherryPick => "cherry-pick",
+      Operation::Revert => "revert",
+      Operation::Stash => "stash",
+    }
+  }
+}
+
+/// Human-meaningful names for the two sides of a conflict
+#[derive(Debug, Clone)]
+pub struct ConflictSides {
+  pub operation: Operation,
+  /// What git calls "ours" (stage 2)
+  pub ours: String,
+  /// What git calls "theirs" (stage 3)
+  pub theirs: String,
+}
+
+impl ConflictSides {
+  pub fn ours_label(&self) -> String {
+    match self.operation {
+      Operation::Rebase => format!("{} (rebase target)", self.ours),
+      _ => format!("{} (current branch)", self.ours),
+    }
+  }
+
+  pub fn theirs_label(&self) -> String {
+    match self.operation {
+      Operation::Merge => format!("{} (being merged)", self.theirs),
+      Operation::Rebase => format!("{} (being rebased)", self.theirs),
+      Operation::CherryPick => format!("{} (cherry-picked)", self.theirs),
+      Operation::Revert => format!("{} (reverted)", self.theirs),
+      Operation::Stash => self.theirs.clone(),
+    }
+  }
+}
+
 fn run(args: &[&str]) -> Result<String> {
-  let output = Command::new("git")
-    .args(args)
+  exec(Command::new("git").args(args), args)
+}
+
+/// Like `run`, in the repository containing `dir` instead of the current directory
+fn run_in(dir: &Path, args: &[&str]) -> Result<String> {
+  exec(Command::new("git").current_dir(dir).args(args), args)
+}
+
+/// Like `run`, but git keeps its prepared commit message instead of opening an editor
+fn run_no_edit(args: &[&str]) -> Result<String> {
+  exec(
+    Command::new("git").env("GIT_EDITOR", "true").args(args),
+    args,
+  )
+}
+
+/// Like `run`, keeping the output byte for byte; porcelain formats start lines with spaces
+fn run_raw(args: &[&str]) -> Result<String> {
+  exec_raw(Command::new("git").args(args), args)
+}
+
+fn exec(cmd: &mut Command, args: &[&str]) -> Result<String> {
+  Ok(exec_raw(cmd, args)?.trim().to_string())
+}
+
+fn exec_raw(cmd: &mut Command, args: &[&str]) -> Result<String> {
+  let output = cmd
     .stdout(Stdio::piped())
     .stderr(Stdio::piped())
     .output()
@@ -39,7 +174,7 @@ fn run(args: &[&str]) -> Result<String> {
     }
   }
 
-  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
 pub fn passthrough(args: &[String]) -> Result<i32> {
@@ -59,91 +194,426 @@ pub fn is_git_repo() -> bool {
 }
 
 pub fn status() -> Result<GitStatus> {
-  let branch = run(&["branch", "--show-current"]).unwrap_or_default();
+  backend::status(Path::new("."))
+}
+
+pub fn diff(staged: bool) -> Result<String> {
+  backend::diff(Path::new("."), staged)
+}
 
-  let (ahead, behind) = run(&["rev-list", "--left-right", "--count", "@{u}...HEAD"])
+/// Like `diff`, without the files matched by `.alfredignore` patterns
+pub fn diff_filtered(staged: bool, ignore: &[glob::Pattern]) -> Result<String> {
+  Ok(diff_filter::filter_diff(&diff(staged)?, ignore))
+}
+
+/// Top of the working tree
+pub fn repo_root() -> Option<PathBuf> {
+  run(&["rev-parse", "--show-toplevel"])
     .ok()
-    .and_then(|s| {
-      let parts: Vec<&str> = s.split('\t').collect();
-      if parts.len() == 2 {
-        let b = parts[0].parse().unwrap_or(0);
-        let a = parts[1].parse().unwrap_or(0);
-        Some((a, b))
+    .filter(|r| !r.is_empty())
+    .map(PathBuf::from)
+}
+
+/// Staged and unstaged changes against HEAD, i.e. what `git stash push` saves
+pub fn diff_worktree() -> Result<String> {
+  run(&["diff", "HEAD"])
+}
+
+/// One entry from `git stash list`
+#[derive(Debug, Clone)]
+pub struct StashEntry {
+  /// `stash@{0}`
+  pub name: String,
+  /// "On main: message" or "WIP on main: ..."
+  pub description: String,
+  /// When it was stashed, e.g. "3 days ago"
+  pub age: String,
+}
+
+pub fn stash_list() -> Result<Vec<StashEntry>> {
+  stash_list_in(Path::new("."))
+}
+
+fn stash_list_in(dir: &Path) -> Result<Vec<StashEntry>> {
+  let output = run_in(dir, &["stash", "list", "--format=%gd%x1f%gs%x1f%cr"])?;
+  Ok(
+    output
+      .lines()
+      .filter_map(|line| {
+        let mut fields = line.splitn(3, '\x1f');
+        Some(StashEntry {
+          name: fields.next()?.to_string(),
+          description: fields.next()?.to_string(),
+          age: fields.next().unwrap_or_default().to_string(),
+        })
+      })
+      .collect(),
+  )
+}
+
+/// Patch a stash entry would restore
+pub fn stash_diff(name: &str) -> Result<String> {
+  stash_diff_in(Path::new("."), name)
+}
+
+fn stash_diff_in(dir: &Path, name: &str) -> Result<String> {
+  run_in(dir, &["stash", "show", "-p", name])
+}
+
+/// `git stash show --stat` for display
+pub fn stash_stat(name: &str) -> Result<String> {
+  run(&["stash", "show", "--stat", name])
+}
+
+pub fn stash_push(message: &str) -> Result<()> {
+  stash_push_in(Path::new("."), message)
+}
+
+fn stash_push_in(dir: &Path, message: &str) -> Result<()> {
+  run_in(dir, &["stash", "push", "-m", message])?;
+  Ok(())
+}
+
+pub fn stash_pop(name: &str) -> Result<()> {
+  run(&["stash", "pop", name])?;
+  Ok(())
+}
+
+/// Diff introduced by the last commit (`git show HEAD` without the message)
+pub fn diff_head() -> Result<String> {
+  run(&["show", "--format=", "HEAD"])
+}
+
+/// Paths touched by a commit
+pub fn commit_files(rev: &str) -> Result<Vec<String>> {
+  let output = run(&["show", "--name-only", "--format=", rev])?;
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+/// Whether any remote-tracking branch already contains `rev`
+pub fn is_pushed(rev: &str) -> bool {
+  run(&["branch", "-r", "--contains", rev])
+    .map(|out| !out.is_empty())
+    .unwrap_or(false)
+}
+
+/// Paths of all staged files
+pub fn staged_files() -> Result<Vec<String>> {
+  let output = run(&["diff", "--cached", "--name-only"])?;
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+/// Whether the index differs from HEAD, so committing now would record something
+pub fn has_staged_changes() -> bool {
+  // Exits with 1 when there are differences
+  run(&["diff", "--cached", "--quiet"]).is_err()
+}
+
+/// Paths of files the staged changes (or all uncommitted changes) add
+pub fn added_files(staged: bool) -> Result<Vec<String>> {
+  let output = if staged {
+    run(&["diff", "--cached", "--name-only", "--diff-filter=A"])?
+  } else {
+    run(&["diff", "HEAD", "--name-only", "--diff-filter=A"])?
+  };
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+/// Paths of all files changed since HEAD, staged or not
+pub fn uncommitted_files() -> Result<Vec<String>> {
+  let output = run(&["diff", "HEAD", "--name-only"])?;
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+/// A file's content at a revision, `None` when it doesn't exist there
+pub fn file_at(rev: &str, path: &str) -> Option<String> {
+  run_raw(&["show", &format!("{}:{}", rev, path)]).ok()
+}
+
+/// A file's content as staged in the index
+pub fn staged_content(path: &str) -> Option<String> {
+  run(&["show", &format!(":{}", path)]).ok()
+}
+
+/// `git diff --stat` output for display
+pub fn diff_stat(staged: bool) -> Result<String> {
+  if staged {
+    run(&["diff", "--cached", "--stat"])
+  } else {
+    run(&["diff", "--stat"])
+  }
+}
+
+/// Per-file statistics from `--numstat` plus mode/delete info from `--summary`
+pub fn diff_stats(staged: bool) -> Result<Vec<FileChange>> {
+  let output = if staged {
+    run(&["diff", "--cached", "--numstat", "--summary"])?
+  } else {
+    run(&["diff", "--numstat", "--summary"])?
+  };
+
+  let mut changes: Vec<FileChange> = Vec::new();
+
+  for line in output.lines() {
+    let parts: Vec<&str> = line.splitn(3, '\t').collect();
+    if parts.len() == 3 {
+      let binary = parts[0] == "-" && parts[1] == "-";
+      changes.push(FileChange {
+        path: parts[2].to_string(),
+        added: parts[0].parse().unwrap_or(0),
+        deleted: parts[1].parse().unwrap_or(0),
+        binary,
+        ..Default::default()
+      });
+      continue;
+    }
+
+    let line = line.trim();
+    let (path, is_mode) = if let Some(rest) = line.strip_prefix("mode change ") {
+      // "mode change 100644 => 100755 path"
+      (rest.splitn(4, ' ').nth(3), true)
+    } else if let Some(rest) = line.strip_prefix("delete mode ") {
+      // "delete mode 100644 path"
+      (rest.split_once(' ').map(|(_, p)| p), false)
+    } else {
+      continue;
+    };
+
+    if let Some(change) = path.and_then(|p| changes.iter_mut().find(|c| c.path == p)) {
+      if is_mode {
+        change.mode_changed = true;
       } else {
-        None
+        change.removed = true;
       }
-    })
-    .unwrap_or((0, 0));
+    }
+  }
+
+  Ok(changes)
+}
+
+/// A change git describes in diff headers rather than content hunks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StructuralChange {
+  Rename {
+    from: String,
+    to: String,
+    /// Similarity index, 0-100
+    similarity: u32,
+  },
+  /// A regular file removed outright; `lines` is `None` for binary files
+  Delete { path: String, lines: Option<usize> },
+  /// Permission change, e.g. `100644` to `100755`
+  Mode {
+    path: String,
+    old: String,
+    new: String,
+  },
+  /// `change` is `added`, `removed`, `retargeted` or `type changed`
+  Symlink { path: String, change: String },
+}
+
+const SYMLINK_MODE: &str = "120000";
+
+/// Structural changes in the staged diff
+pub fn staged_structure() -> Result<Vec<StructuralChange>> {
+  staged_structure_in(Path::new("."))
+}
+
+fn staged_structure_in(repo: &Path) -> Result<Vec<StructuralChange>> {
+  let raw = run_in(repo, &["diff", "--cached", "-M", "--raw", "-z"])?;
+  let numstat = run_in(repo, &["diff", "--cached", "-M", "--numstat", "-z"])?;
+  Ok(parse_structure(&raw, &numstat))
+}
 
-  let porcelain = run(&["status", "--porcelain=v1"]).unwrap_or_default();
+/// Structural changes made by a single commit
+pub fn commit_structure(rev: &str) -> Result<Vec<StructuralChange>> {
+  let args = ["diff-tree", "-r", "--root", "-M", "--no-commit-id", "-z"];
+  let raw = run(&[&args[..], &["--raw", rev]].concat())?;
+  let numstat = run(&[&args[..], &["--numstat", rev]].concat())?;
+  Ok(parse_structure(&raw, &numstat))
+}
 
-  let mut staged = Vec::new();
-  let mut unstaged = Vec::new();
-  let mut untracked = Vec::new();
-  let mut conflicts = Vec::new();
+/// Combine `--raw -z` and `--numstat -z` output into structural changes.
+///
+/// Raw records are `:<old mode> <new mode> <old sha> <new sha> <status>\0<path>\0`,
+/// with a second path for renames and copies. Numstat supplies deleted line counts.
+pub fn parse_structure(raw: &str, numstat: &str) -> Vec<StructuralChange> {
+  let deleted_lines = parse_numstat_deletions(numstat);
+  let mut changes = Vec::new();
+  let mut fields = raw.split('\0');
 
-  for line in porcelain.lines() {
-    if line.len() < 3 {
+  while let Some(meta) = fields.next() {
+    let Some(meta) = meta.trim_start_matches('\n').strip_prefix(':') else {
+      continue;
+    };
+    let parts: Vec<&str> = meta.split_whitespace().collect();
+    if parts.len() < 5 {
       continue;
     }
+    let (old_mode, new_mode, status) = (parts[0], parts[1], parts[4]);
+    let Some(path) = fields.next() else {
+      break;
+    };
 
-    let index = line.chars().next().unwrap_or(' ');
-    let worktree = line.chars().nth(1).unwrap_or(' ');
-    let file = line[3..].to_string();
-
-    // Check for conflicts
-    if index == 'U'
-      || worktree == 'U'
-      || (index == 'A' && worktree == 'A')
-      || (index == 'D' && worktree == 'D')
-    {
-      conflicts.push(file);
-    } else if index == '?' {
-      untracked.push(file);
-    } else {
-      if index != ' ' && index != '?' {
-        staged.push(file.clone());
+    let symlink = old_mode == SYMLINK_MODE || new_mode == SYMLINK_MODE;
+    match status.chars().next() {
+      Some('R') => {
+        let Some(to) = fields.next() else {
+          break;
+        };
+        changes.push(StructuralChange::Rename {
+          from: path.to_string(),
+          to: to.to_string(),
+          similarity: status[1..].parse().unwrap_or(0),
+        });
+        if old_mode != new_mode && !symlink {
+          changes.push(StructuralChange::Mode {
+            path: to.to_string(),
+            old: old_mode.to_string(),
+            new: new_mode.to_string(),
+          });
+        }
+      }
+      Some('C') => {
+        // Copies add a file; nothing to foreground
+        fields.next();
       }
-      if worktree != ' ' && worktree != '?' {
-        unstaged.push(file);
+      Some('T') => changes.push(StructuralChange::Symlink {
+        path: path.to_string(),
+        change: "type changed".to_string(),
+      }),
+      Some(c) if symlink => {
+        let change = match c {
+          'A' => "added",
+          'D' => "removed",
+          _ => "retargeted",
+        };
+        changes.push(StructuralChange::Symlink {
+          path: path.to_string(),
+          change: change.to_string(),
+        });
       }
+      Some('D') => changes.push(StructuralChange::Delete {
+        path: path.to_string(),
+        lines: deleted_lines.get(path).copied().flatten(),
+      }),
+      Some('M') if old_mode != new_mode => changes.push(StructuralChange::Mode {
+        path: path.to_string(),
+        old: old_mode.to_string(),
+        new: new_mode.to_string(),
+      }),
+      _ => {}
     }
   }
 
-  Ok(GitStatus {
-    branch,
-    ahead,
-    behind,
-    staged,
-    unstaged,
-    untracked,
-    conflicts,
-  })
+  changes
 }
 
-pub fn diff(staged: bool) -> Result<String> {
-  if staged {
-    run(&["diff", "--cached"])
-  } else {
-    run(&["diff"])
+/// Deleted line counts by path from `--numstat -z`; `None` marks binary files
+fn parse_numstat_deletions(numstat: &str) -> std::collections::HashMap<String, Option<usize>> {
+  let mut counts = std::collections::HashMap::new();
+  let mut fields = numstat.split('\0');
+
+  while let Some(record) = fields.next() {
+    let parts: Vec<&str> = record.trim_start_matches('\n').splitn(3, '\t').collect();
+    if parts.len() != 3 {
+      continue;
+    }
+    if parts[2].is_empty() {
+      // Rename: the old and new paths follow as separate fields
+      fields.next();
+      fields.next();
+      continue;
+    }
+    counts.insert(parts[2].to_string(), parts[1].parse().ok());
   }
+
+  counts
+}
+
+/// Statistics for untracked files as if they were added
+pub fn untracked_stats(files: &[String]) -> Vec<FileChange> {
+  files
+    .iter()
+    .map(|path| {
+      let content = fs::read(path).unwrap_or_default();
+      let binary = content.contains(&0);
+      FileChange {
+        path: path.clone(),
+        added: if binary {
+          0
+        } else {
+          content.iter().filter(|b| **b == b'\n').count()
+        },
+        binary,
+        ..Default::default()
+      }
+    })
+    .collect()
 }
 
 pub fn log(count: usize) -> Result<String> {
-  run(&["log", "--oneline", &format!("-{}", count)])
+  backend::log(Path::new("."), count)
 }
 
-pub fn get_branches() -> Result<Vec<String>> {
-  let output = run(&["branch", "--format=%(refname:short)"])?;
+/// Full messages of the last `n` commits on HEAD that aren't merges, newest first
+pub fn recent_commit_messages(n: usize) -> Result<Vec<String>> {
+  if run(&["rev-parse", "--verify", "-q", "HEAD"]).is_err() {
+    return Ok(Vec::new());
+  }
+  let output = run(&["log", "--no-merges", "--format=%B%x1e", &format!("-{}", n)])?;
   Ok(
     output
-      .lines()
-      .filter(|s| !s.is_empty())
+      .split('\x1e')
+      .map(str::trim)
+      .filter(|m| !m.is_empty())
       .map(String::from)
       .collect(),
   )
 }
 
+/// Subjects of the last `n` commits on HEAD, newest first. Empty before the first commit
+pub fn recent_commit_subjects(n: usize) -> Result<Vec<String>> {
+  recent_commit_subjects_in(Path::new("."), n)
+}
+
+fn recent_commit_subjects_in(dir: &Path, n: usize) -> Result<Vec<String>> {
+  if run_in(dir, &["rev-parse", "--verify", "-q", "HEAD"]).is_err() {
+    return Ok(Vec::new());
+  }
+  let output = run_in(dir, &["log", "--format=%s", &format!("-{}", n)])?;
+  Ok(output.lines().map(String::from).collect())
+}
+
+pub fn get_branches() -> Result<Vec<String>> {
+  backend::get_branches(Path::new("."))
+}
+
 pub fn get_remote_branches() -> Result<Vec<String>> {
   let output = run(&["branch", "-r", "--format=%(refname:short)"])?;
   Ok(
@@ -155,102 +625,1569 @@ pub fn get_remote_branches() -> Result<Vec<String>> {
   )
 }
 
-pub fn get_conflict_info(file: &str) -> Result<ConflictInfo> {
-  let base = run(&["show", &format!(":1:{}", file)]).unwrap_or_default();
-  let ours = run(&["show", &format!(":2:{}", file)]).unwrap_or_default();
-  let theirs = run(&["show", &format!(":3:{}", file)]).unwrap_or_default();
-
-  Ok(ConflictInfo {
-    file: file.to_string(),
-    base,
-    ours,
-    theirs,
-  })
+/// A branch with its upstream and tip, for `branch list`
+#[derive(Debug, Clone)]
+pub struct BranchInfo {
+  /// Short name, e.g. `feature/x` or `origin/main`
+  pub name: String,
+  pub remote: bool,
+  /// Checked out in this worktree
+  pub current: bool,
+  pub upstream: Option<String>,
+  /// Commits the branch has that its upstream doesn't
+  pub ahead: u32,
+  /// Commits the upstream has that the branch doesn't
+  pub behind: u32,
+  /// The upstream was deleted on the remote
+  pub gone: bool,
+  /// Committer date of the tip as a Unix timestamp, for sorting
+  pub timestamp: i64,
+  /// Committer date of the tip, relative ("2 days ago")
+  pub date: String,
+  pub subject: String,
 }
 
-pub fn get_rebase_commits(onto: &str) -> Result<Vec<String>> {
-  let output = run(&["log", "--oneline", &format!("{}..HEAD", onto)])?;
+/// Local branches, and remote ones with `remotes`, with their upstream tracking state
+pub fn for_each_branch_info(remotes: bool) -> Result<Vec<BranchInfo>> {
+  let mut args = vec![
+    "for-each-ref",
+    "--format=%(HEAD)%00%(refname)%00%(refname:short)%00%(upstream:short)%00%(upstream:track)%00%(committerdate:unix)%00%(committerdate:relative)%00%(contents:subject)",
+    "refs/heads",
+  ];
+  if remotes {
+    args.push("refs/remotes");
+  }
+  let output = run(&args)?;
+
   Ok(
     output
       .lines()
-      .filter(|s| !s.is_empty())
-      .map(String::from)
+      .filter_map(|line| {
+        let fields: Vec<&str> = line.splitn(8, '\0').collect();
+        let [head, refname, name, upstream, track, timestamp, date, subject] = fields[..] else {
+          return None;
+        };
+        // `origin/HEAD` only points at another remote branch
+        if refname.starts_with("refs/remotes/") && refname.ends_with("/HEAD") {
+          return None;
+        }
+        let (ahead, behind, gone) = parse_track(track);
+        Some(BranchInfo {
+          name: name.to_string(),
+          remote: refname.starts_with("refs/remotes/"),
+          current: head == "*",
+          upstream: (!upstream.is_empty()).then(|| upstream.to_string()),
+          ahead,
+          behind,
+          gone,
+          timestamp: timestamp.parse().unwrap_or(0),
+          date: date.to_string(),
+          subject: subject.to_string(),
+        })
+      })
       .collect(),
   )
 }
 
-pub fn commit(message: &str) -> Result<()> {
-  run(&["commit", "-m", message])?;
-  Ok(())
+/// `%(upstream:track)`: "[ahead 2, behind 1]", "[behind 3]", "[gone]" or nothing
+fn parse_track(track: &str) -> (u32, u32, bool) {
+  let inner = track.trim().trim_start_matches('[').trim_end_matches(']');
+  if inner == "gone" {
+    return (0, 0, true);
+  }
+  let mut ahead = 0;
+  let mut behind = 0;
+  for part in inner.split(", ") {
+    if let Some(n) = part.strip_prefix("ahead ") {
+      ahead = n.parse().unwrap_or(0);
+    } else if let Some(n) = part.strip_prefix("behind ") {
+      behind = n.parse().unwrap_or(0);
+    }
+  }
+  (ahead, behind, false)
 }
 
-pub fn add(files: &[String]) -> Result<()> {
-  let mut args = vec!["add"];
-  for f in files {
-    args.push(f);
+/// The three versions of a conflicted file. A version missing from the index, like the base
+/// of a file both sides added, is empty; failing to read one that exists is an error
+pub fn get_conflict_info(file: &str) -> Result<ConflictInfo> {
+  backend::get_conflict_info(Path::new("."), file)
+}
+
+/// Rebuild the three versions of a conflicted file from the markers in the working tree, for
+/// conflicts whose index stages are missing. Text outside conflict regions goes into every
+/// version. The base is only known with `merge.conflictStyle=diff3` (or `zdiff3`) markers; a
+/// region without a `|||||||` section leaves it empty
+pub fn parse_conflict_markers(path: &str) -> Result<ConflictInfo> {
+  let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+  let segments =
+    conflict::split_conflicts(&content).with_context(|| format!("{} is malformed", path))?;
+
+  let mut info = ConflictInfo {
+    file: path.to_string(),
+    ..Default::default()
+  };
+  let mut regions = 0;
+  let mut every_region_has_base = true;
+
+  for segment in &segments {
+    match segment {
+      ConflictSegment::Text(text) => {
+        info.base.push_str(text);
+        info.ours.push_str(text);
+        info.theirs.push_str(text);
+      }
+      ConflictSegment::Conflict(hunk) => {
+        regions += 1;
+        info.ours.push_str(&hunk.ours);
+        info.theirs.push_str(&hunk.theirs);
+        match &hunk.base {
+          Some(base) => info.base.push_str(base),
+          None => every_region_has_base = false,
+        }
+      }
+    }
   }
-  run(&args)?;
-  Ok(())
+
+  if regions == 0 {
+    return Err(anyhow!("{} has no conflict markers", path));
+  }
+  if !every_region_has_base {
+    info.base.clear();
+  }
+  Ok(info)
 }
 
-pub fn checkout(branch: &str) -> Result<()> {
-  run(&["checkout", branch])?;
-  Ok(())
+/// Number of conflict regions left in a working-tree file
+pub fn conflict_hunks(file: &str) -> usize {
+  fs::read_to_string(file)
+    .map(|content| conflict::count(&content))
+    .unwrap_or(0)
 }
 
-pub fn create_branch(name: &str) -> Result<()> {
-  run(&["checkout", "-b", name])?;
-  Ok(())
+pub fn get_rebase_commits(onto: &str) -> Result<Vec<String>> {
+  backend::get_rebase_commits(Path::new("."), onto)
 }
 
-pub fn delete_branch(name: &str, force: bool) -> Result<()> {
-  let flag = if force { "-D" } else { "-d" };
-  run(&["branch", flag, name])?;
-  Ok(())
+/// The integration branch: `base_branch` from the config, else `main`, then `master`
+pub fn default_base() -> Option<String> {
+  if let Some(base) = crate::config::current().base_branch.clone() {
+    return Some(base);
+  }
+  let branches = get_branches().ok()?;
+  ["main", "master"]
+    .iter()
+    .find(|b| branches.iter().any(|x| x == *b))
+    .map(|b| b.to_string())
 }
 
-pub fn rebase(onto: &str, interactive: bool) -> Result<()> {
-  if interactive {
-    // For interactive rebase, we need to use inherit for stdin/stdout
-    let status = Command::new("git")
-      .args(["rebase", "-i", onto])
-      .stdin(Stdio::inherit())
-      .stdout(Stdio::inherit())
-      .stderr(Stdio::inherit())
-      .status()
-      .with_context(|| "Failed to execute git rebase")?;
+/// Combined diff of everything `head` adds since its merge base with `base`
+pub fn diff_range(base: &str, head: &str) -> Result<String> {
+  run(&["diff", &format!("{}...{}", base, head)])
+}
 
-    if !status.success() {
-      return Err(anyhow!("Rebase failed or has conflicts"));
-    }
-  } else {
-    run(&["rebase", onto])?;
-  }
-  Ok(())
+/// `git diff --stat` for the same range as `diff_range`
+pub fn diff_range_stat(base: &str, head: &str) -> Result<String> {
+  run(&["diff", "--stat", &format!("{}...{}", base, head)])
 }
 
-pub fn abort_rebase() -> Result<()> {
-  run(&["rebase", "--abort"])?;
-  Ok(())
+/// `git diff --shortstat` for the same range as `diff_range`, e.g. "3 files changed, 10 insertions(+)"
+pub fn diff_range_shortstat(base: &str, head: &str) -> String {
+  run(&["diff", "--shortstat", &format!("{}...{}", base, head)]).unwrap_or_default()
 }
 
-pub fn continue_rebase() -> Result<()> {
-  run(&["rebase", "--continue"])?;
-  Ok(())
+/// Subject and diff of each commit since the merge base with `base`, oldest first
+pub fn get_branch_commits_with_diffs(base: &str) -> Result<Vec<(String, String)>> {
+  let merge_base = run(&["merge-base", base, "HEAD"])?;
+  let hashes = run(&[
+    "rev-list",
+    "--reverse",
+    "--no-merges",
+    &format!("{}..HEAD", merge_base),
+  ])?;
+
+  hashes
+    .lines()
+    .filter(|s| !s.is_empty())
+    .map(|hash| {
+      let title = run(&["log", "-1", "--format=%s", hash])?;
+      let diff = run(&["show", "--format=", hash])?;
+      Ok((title, diff))
+    })
+    .collect()
 }
 
-pub fn stage_file(file: &str) -> Result<()> {
-  run(&["add", file])?;
+/// Most recent tag reachable from `rev`
+pub fn latest_tag(rev: &str) -> Option<String> {
+  run(&["describe", "--tags", "--abbrev=0", rev])
+    .ok()
+    .filter(|t| !t.is_empty())
+}
+
+/// Most recent tag reachable from HEAD
+pub fn get_latest_tag() -> Result<String> {
+  latest_tag("HEAD").ok_or_else(|| anyhow!("No tags found"))
+}
+
+/// Create an annotated tag on HEAD, GPG-signed when `sign` is set
+pub fn create_tag(name: &str, message: &str, sign: bool) -> Result<()> {
+  let flag = if sign { "-s" } else { "-a" };
+  run(&["tag", flag, name, "-m", message])?;
   Ok(())
 }
 
-pub fn get_merged_branches(into: &str) -> Result<Vec<String>> {
-  let output = run(&["branch", "--merged", into, "--format=%(refname:short)"])?;
+pub fn is_tag(name: &str) -> bool {
+  run(&[
+    "rev-parse",
+    "--verify",
+    "--quiet",
+    &format!("refs/tags/{}", name),
+  ])
+  .is_ok()
+}
+
+/// Non-merge commits in `from..to`, newest first. An empty `from` means the whole history
+pub fn get_commits_between(from: &str, to: &str) -> Result<Vec<CommitRecord>> {
+  if from.is_empty() {
+    get_commits_with_messages(to)
+  } else {
+    get_commits_with_messages(&format!("{}..{}", from, to))
+  }
+}
+
+/// Non-merge commits in a revision range such as `v1.0.0..HEAD`, newest first, with their
+/// full messages and authors
+pub fn get_commits_with_messages(range: &str) -> Result<Vec<CommitRecord>> {
+  const FIELD: char = '\x1f';
+  const RECORD: char = '\x1e';
+
+  let output = run(&[
+    "log",
+    "--no-merges",
+    "--date=short",
+    "--format=%H%x1f%an%x1f%ad%x1f%B%x1e",
+    range,
+  ])?;
+
   Ok(
     output
-      .lines()
-      .filter(|s| !s.is_empty() && *s != into && *s != "master" && *s != "main")
+      .split(RECORD)
+      .filter_map(|record| {
+        let mut fields = record.trim().splitn(4, FIELD);
+        Some(CommitRecord {
+          hash: fields.next()?.to_string(),
+          author: fields.next()?.to_string(),
+          date: fields.next()?.to_string(),
+          message: fields.next()?.trim().to_string(),
+        })
+      })
+      .collect(),
+  )
+}
+
+pub fn remote_url(remote: &str) -> Option<String> {
+  run(&["remote", "get-url", remote])
+    .ok()
+    .filter(|u| !u.is_empty())
+}
+
+/// A git config value, e.g. `user.name`
+pub fn config_value(key: &str) -> Option<String> {
+  run(&["config", "--get", key])
+    .ok()
+    .filter(|v| !v.is_empty())
+}
+
+/// `Name <email>` from `user.name` and `user.email`
+pub fn user_identity() -> Result<String> {
+  match (config_value("user.name"), config_value("user.email")) {
+    (Some(name), Some(email)) => Ok(format!("{} <{}>", name, email)),
+    _ => Err(anyhow!(
+      "user.name and user.email must be set to sign off commits"
+    )),
+  }
+}
+
+pub fn commit(message: &str) -> Result<()> {
+  run(&["commit", "-m", message])?;
+  Ok(())
+}
+
+/// Commit the staged changes as `fixup! <subject>` of `target`, for `rebase --autosquash`
+pub fn commit_fixup(target: &str) -> Result<()> {
+  run(&["commit", &format!("--fixup={}", target)])?;
+  Ok(())
+}
+
+pub fn merge_base(a: &str, b: &str) -> Result<String> {
+  run(&["merge-base", a, b])
+}
+
+/// Replace the last commit's message, folding in anything staged. The author date is kept
+pub fn amend(message: &str) -> Result<()> {
+  run(&["commit", "--amend", "-m", message])?;
+  Ok(())
+}
+
+/// A commit message rule violation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintError {
+  /// 1-based line in the message, after comments are removed
+  pub line: usize,
+  pub message: String,
+}
+
+/// Messages git writes itself, exempt from the conventional format
+const GENERATED_PREFIXES: &[&str] = &["fixup! ", "squash! ", "amend! ", "Merge ", "Revert \""];
+
+/// Check a commit message, as git passes it to the commit-msg hook, against `rules`
+pub fn validate_commit_message(msg: &str, rules: &CommitRules) -> Vec<LintError> {
+  // Drop comments and anything below the `commit -v` scissors line
+  let lines: Vec<&str> = msg
+    .lines()
+    .take_while(|l| !l.starts_with("# ------------------------ >8"))
+    .filter(|l| !l.starts_with('#'))
+    .collect();
+  let end = lines
+    .iter()
+    .rposition(|l| !l.trim().is_empty())
+    .map_or(0, |i| i + 1);
+  let lines = &lines[..end];
+
+  let mut errors = Vec::new();
+  let error = |line: usize, message: String| LintError { line, message };
+
+  let Some(subject) = lines.first() else {
+    errors.push(error(1, "Message is empty".to_string()));
+    return errors;
+  };
+
+  let length = subject.chars().count();
+  if length > rules.max_subject_length {
+    errors.push(error(
+      1,
+      format!(
+        "Subject is {} characters (max {})",
+        length, rules.max_subject_length
+      ),
+    ));
+  }
+
+  if rules.require_conventional && !GENERATED_PREFIXES.iter().any(|p| subject.starts_with(p)) {
+    if let Some(message) = conventional_error(subject, &rules.types) {
+      errors.push(error(1, message));
+    }
+  }
+
+  if lines.get(1).is_some_and(|l| !l.trim().is_empty()) {
+    errors.push(error(
+      2,
+      "Leave a blank line between the subject and the body".to_string(),
+    ));
+  }
+
+  if rules.max_body_line_length > 0 {
+    for (i, line) in lines.iter().enumerate().skip(1) {
+      let length = line.chars().count();
+      // Long URLs can't be wrapped
+      if length > rules.max_body_line_length && !line.contains("://") {
+        errors.push(error(
+          i + 1,
+          format!(
+            "Body line is {} characters (max {})",
+            length, rules.max_body_line_length
+          ),
+        ));
+      }
+    }
+  }
+
+  errors
+}
+
+/// Why a subject isn't `type(scope)!: description`, if it isn't
+fn conventional_error(subject: &str, types: &[String]) -> Option<String> {
+  // Gitmoji subjects lead with the emoji
+  let subject = match subject.split_once(' ') {
+    Some((first, rest)) if !first.chars().any(|c| c.is_ascii_alphanumeric()) => rest,
+    _ => subject,
+  };
+
+  let Some((prefix, description)) = subject.split_once(": ") else {
+    return Some("Subject should look like 'type(scope): description'".to_string());
+  };
+  let kind = prefix
+    .trim_end_matches('!')
+    .split('(')
+    .next()
+    .unwrap_or_default();
+
+  if !types.iter().any(|t| t == kind) {
+    return Some(format!(
+      "Unknown type '{}' (expected one of: {})",
+      kind,
+      types.join(", ")
+    ));
+  }
+  if description.trim().is_empty() {
+    return Some("Subject has no description after the type".to_string());
+  }
+  None
+}
+
+/// Where git looks for hooks, honoring `core.hooksPath`
+pub fn hooks_dir() -> Option<PathBuf> {
+  git_path("hooks")
+}
+
+pub fn add(files: &[String]) -> Result<()> {
+  let mut args = vec!["add"];
+  for f in files {
+    args.push(f);
+  }
+  run(&args)?;
+  Ok(())
+}
+
+pub fn checkout(branch: &str) -> Result<()> {
+  run(&["checkout", branch])?;
+  Ok(())
+}
+
+pub fn create_branch(name: &str) -> Result<()> {
+  run(&["checkout", "-b", name])?;
+  Ok(())
+}
+
+/// Delete a local branch, refusing the checked-out branch and protected ones up front
+pub fn delete_branch(name: &str, force: bool) -> Result<()> {
+  if current_branch().as_deref() == Some(name) {
+    return Err(anyhow!(
+      "'{}' is checked out; switch to another branch before deleting it",
+      name
+    ));
+  }
+  if crate::config::current().is_protected_branch(name) {
+    return Err(anyhow!(
+      "'{}' is protected; remove it from protected_branches to delete it",
+      name
+    ));
+  }
+
+  let flag = if force { "-D" } else { "-d" };
+  run(&["branch", flag, name])?;
+  Ok(())
+}
+
+/// Delete a branch on its remote, given as `origin/feature`. Protected branches are refused
+pub fn delete_remote_branch(name: &str) -> Result<()> {
+  let (remote, branch) = name
+    .split_once('/')
+    .ok_or_else(|| anyhow!("'{}' is not a remote branch", name))?;
+  if crate::config::current().is_protected_branch(branch) {
+    return Err(anyhow!(
+      "'{}' is protected; remove it from protected_branches to delete it",
+      branch
+    ));
+  }
+
+  run(&["push", remote, "--delete", branch])?;
+  Ok(())
+}
+
+pub fn rebase(onto: &str, interactive: bool) -> Result<()> {
+  if interactive {
+    // For interactive rebase, we need to use inherit for stdin/stdout
+    let status = Command::new("git")
+      .args(["rebase", "-i", onto])
+      .stdin(Stdio::inherit())
+      .stdout(Stdio::inherit())
+      .stderr(Stdio::inherit())
+      .status()
+      .with_context(|| "Failed to execute git rebase")?;
+
+    if !status.success() {
+      return Err(anyhow!("Rebase failed or has conflicts"));
+    }
+  } else {
+    run(&["rebase", onto])?;
+  }
+  Ok(())
+}
+
+pub fn abort_rebase() -> Result<()> {
+  run(&["rebase", "--abort"])?;
+  Ok(())
+}
+
+pub fn continue_rebase() -> Result<()> {
+  continue_operation(Operation::Rebase)
+}
+
+/// Finish the in-progress operation once its conflicts are staged
+pub fn continue_operation(operation: Operation) -> Result<()> {
+  match operation {
+    Operation::Merge => run_no_edit(&["commit", "--no-edit"])?,
+    Operation::Rebase => run_no_edit(&["rebase", "--continue"])?,
+    Operation::CherryPick => run_no_edit(&["cherry-pick", "--continue"])?,
+    Operation::Revert => run_no_edit(&["revert", "--continue"])?,
+    // Nothing to finish; the resolved changes stay in the working tree
+    Operation::Stash => String::new(),
+  };
+  Ok(())
+}
+
+pub fn head() -> Result<String> {
+  run(&["rev-parse", "HEAD"])
+}
+
+pub fn current_branch() -> Option<String> {
+  run(&["branch", "--show-current"])
+    .ok()
+    .filter(|b| !b.is_empty())
+}
+
+pub fn reset_hard(rev: &str) -> Result<()> {
+  run(&["reset", "--hard", rev])?;
+  Ok(())
+}
+
+/// Expand a ref or `a..b` range into commit hashes, oldest first
+pub fn resolve_commits(spec: &str) -> Result<Vec<String>> {
+  if spec.contains("..") {
+    let output = run(&["rev-list", "--reverse", "--no-merges", spec])
+      .map_err(|_| anyhow!("Invalid range: {}", spec))?;
+    return Ok(
+      output
+        .lines()
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect(),
+    );
+  }
+
+  let hash = run(&[
+    "rev-parse",
+    "--verify",
+    "--quiet",
+    &format!("{}^{{commit}}", spec),
+  ])
+  .map_err(|_| anyhow!("Unknown commit: {}", spec))?;
+  Ok(vec![hash])
+}
+
+/// Short hash and subject, e.g. "1a2b3c4 Fix parser"
+pub fn commit_oneline(hash: &str) -> String {
+  run(&["log", "-1", "--format=%h %s", hash]).unwrap_or_else(|_| short_hash(hash))
+}
+
+/// One-line diffstat for a commit, e.g. "2 files changed, 10 insertions(+)"
+pub fn commit_shortstat(hash: &str) -> String {
+  run(&["show", "--shortstat", "--format=", hash]).unwrap_or_default()
+}
+
+/// Parents of a commit, first parent first; more than one means a merge
+pub fn commit_parents(hash: &str) -> Result<Vec<String>> {
+  let output = run(&["rev-list", "--parents", "-n", "1", hash])?;
+  Ok(
+    output
+      .split_whitespace()
+      .skip(1)
       .map(String::from)
       .collect(),
   )
 }
+
+/// Full message and diff of a single commit
+pub fn show_commit(commit: &str) -> Result<(String, String)> {
+  let spec = format!("{}^{{commit}}", commit);
+  let hash = run(&["rev-parse", "--verify", "--quiet", &spec])
+    .map_err(|_| anyhow!("Unknown commit: {}", commit))?;
+  let message = run(&["log", "-1", "--format=%B", &hash])?;
+  let diff = run(&["show", "--format=", &hash])?;
+  Ok((message, diff))
+}
+
+pub fn cherry_pick(commit: &str, record_origin: bool) -> Result<()> {
+  let mut args = vec!["cherry-pick"];
+  if record_origin {
+    args.push("-x");
+  }
+  args.push(commit);
+  run(&args)?;
+  Ok(())
+}
+
+pub fn skip_cherry_pick() -> Result<()> {
+  run(&["cherry-pick", "--skip"])?;
+  Ok(())
+}
+
+pub fn abort_cherry_pick() -> Result<()> {
+  run(&["cherry-pick", "--abort"])?;
+  Ok(())
+}
+
+/// Cherry-pick progress recorded by git
+#[derive(Debug, Default)]
+pub struct CherryPickState {
+  /// Commit git stopped on (CHERRY_PICK_HEAD)
+  pub current: Option<String>,
+  /// `pick` lines left in the sequencer todo of a multi-commit pick, the current one first
+  pub todo: Vec<String>,
+}
+
+/// The cherry-pick in progress, if any
+pub fn cherry_pick_state() -> Option<CherryPickState> {
+  cherry_pick_state_in(Path::new("."))
+}
+
+fn cherry_pick_state_in(repo: &Path) -> Option<CherryPickState> {
+  let current = read_git_file_in(repo, "CHERRY_PICK_HEAD");
+  let todo: Vec<String> = read_git_file_in(repo, "sequencer/todo")
+    .map(|todo| {
+      todo
+        .lines()
+        .filter(|l| l.starts_with("pick "))
+        .map(String::from)
+        .collect()
+    })
+    .unwrap_or_default();
+
+  if current.is_none() && todo.is_empty() {
+    None
+  } else {
+    Some(CherryPickState { current, todo })
+  }
+}
+
+const SEQUENCE_START_FILE: &str = "ALFRED_CHERRY_PICK_START";
+
+/// Where an `alfred cherry-pick` sequence started, kept so it can be undone
+#[derive(Debug, Clone)]
+pub struct SequenceStart {
+  pub tip: String,
+  pub branch: Option<String>,
+}
+
+pub fn record_sequence_start(start: &SequenceStart) -> Result<()> {
+  record_sequence_start_in(Path::new("."), start)
+}
+
+fn record_sequence_start_in(repo: &Path, start: &SequenceStart) -> Result<()> {
+  let path =
+    git_path_in(repo, SEQUENCE_START_FILE).ok_or_else(|| anyhow!("Cannot locate git directory"))?;
+  let branch = start.branch.as_deref().unwrap_or("");
+  fs::write(path, format!("{}\n{}\n", start.tip, branch))?;
+  Ok(())
+}
+
+pub fn sequence_start() -> Option<SequenceStart> {
+  sequence_start_in(Path::new("."))
+}
+
+fn sequence_start_in(repo: &Path) -> Option<SequenceStart> {
+  let content = read_git_file_in(repo, SEQUENCE_START_FILE)?;
+  let mut lines = content.lines();
+  let tip = lines.next()?.trim().to_string();
+  let branch = lines
+    .next()
+    .map(|b| b.trim().to_string())
+    .filter(|b| !b.is_empty());
+  Some(SequenceStart { tip, branch })
+}
+
+pub fn clear_sequence_start() {
+  if let Some(path) = git_path(SEQUENCE_START_FILE) {
+    let _ = fs::remove_file(path);
+  }
+}
+
+/// Where `git bisect` is after starting or marking a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BisectStep {
+  /// A commit is checked out to test, with roughly `remaining` revisions left after it
+  Commit {
+    hash: String,
+    remaining: Option<usize>,
+  },
+  /// The first bad commit
+  Done(String),
+}
+
+/// Run a bisect command, keeping its output when it fails; git explains on stdout why it
+/// can't go on, e.g. when only skipped commits are left
+fn run_bisect(args: &[&str]) -> Result<String> {
+  let output = Command::new("git")
+    .arg("bisect")
+    .args(args)
+    .output()
+    .with_context(|| "Failed to execute git bisect")?;
+  let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = [stdout.as_str(), stderr.trim()]
+      .iter()
+      .filter(|s| !s.is_empty())
+      .copied()
+      .collect::<Vec<_>>()
+      .join("\n");
+    return Err(anyhow!("git bisect {} failed: {}", args.join(" "), reason));
+  }
+  Ok(stdout)
+}
+
+/// The step git moved to, from what the last bisect command printed
+pub fn bisect_next(output: &str) -> Result<BisectStep> {
+  if let Some(line) = output
+    .lines()
+    .find(|l| l.ends_with("is the first bad commit"))
+  {
+    let hash = line.split_whitespace().next().unwrap_or_default();
+    return Ok(BisectStep::Done(hash.to_string()));
+  }
+
+  // "Bisecting: 3 revisions left to test after this (roughly 2 steps)"
+  let remaining = output
+    .lines()
+    .find_map(|l| l.strip_prefix("Bisecting: "))
+    .and_then(|rest| rest.split_whitespace().next())
+    .and_then(|n| n.parse().ok());
+  Ok(BisectStep::Commit {
+    hash: run(&["rev-parse", "HEAD"])?,
+    remaining,
+  })
+}
+
+/// Start bisecting between a bad and a good revision
+pub fn bisect_start(bad: &str, good: &str) -> Result<BisectStep> {
+  bisect_next(&run_bisect(&["start", bad, good])?)
+}
+
+/// Mark the checked-out commit with `good`, `bad` or `skip`
+pub fn bisect_mark(term: &str) -> Result<BisectStep> {
+  bisect_next(&run_bisect(&[term])?)
+}
+
+/// Leave bisect mode, checking out the branch it started from
+pub fn bisect_reset() -> Result<()> {
+  run_bisect(&["reset"])?;
+  Ok(())
+}
+
+pub fn bisect_in_progress() -> bool {
+  git_path("BISECT_LOG").is_some_and(|p| p.exists())
+}
+
+pub fn merge_in_progress() -> bool {
+  git_path("MERGE_HEAD").is_some_and(|p| p.exists())
+}
+
+pub fn stage_file(file: &str) -> Result<()> {
+  run(&["add", file])?;
+  Ok(())
+}
+
+/// A branch whose tip is already in the base branch
+#[derive(Debug, Clone)]
+pub struct MergedBranch {
+  pub name: String,
+  /// Committer date of the tip, relative ("3 weeks ago")
+  pub date: String,
+  /// Subject of the tip commit
+  pub subject: String,
+}
+
+/// Branches merged into `into`, oldest tip commit first
+pub fn get_merged_branches(into: &str) -> Result<Vec<MergedBranch>> {
+  backend::merged_branches(Path::new("."), false, into)
+}
+
+/// Remote-tracking branches merged into `into`, named like `origin/feature`, oldest tip
+/// commit first. Remote HEADs and the remote copies of `into` are left out
+pub fn get_merged_remote_branches(into: &str) -> Result<Vec<MergedBranch>> {
+  let mut branches = backend::merged_branches(Path::new("."), true, into)?;
+  branches.retain(|b| b.name.split_once('/').is_none_or(|(_, name)| name != into));
+  Ok(branches)
+}
+
+/// Local branches with the committer date of their tip as a Unix timestamp, oldest first
+pub fn branch_last_commit_dates() -> Result<Vec<(String, i64)>> {
+  let output = run(&[
+    "for-each-ref",
+    "--sort=committerdate",
+    "--format=%(refname:short)%00%(committerdate:unix)",
+    "refs/heads",
+  ])?;
+  Ok(
+    output
+      .lines()
+      .filter_map(|line| {
+        let (name, timestamp) = line.split_once('\0')?;
+        Some((name.to_string(), timestamp.parse().ok()?))
+      })
+      .collect(),
+  )
+}
+
+/// Path of a file inside the git directory (worktree-aware)
+pub fn git_path(name: &str) -> Option<PathBuf> {
+  git_path_in(Path::new("."), name)
+}
+
+fn git_path_in(dir: &Path, name: &str) -> Option<PathBuf> {
+  run_in(dir, &["rev-parse", "--git-path", name])
+    .ok()
+    .map(|path| dir.join(path))
+}
+
+fn read_git_file_in(dir: &Path, name: &str) -> Option<String> {
+  let content = fs::read_to_string(git_path_in(dir, name)?).ok()?;
+  let content = content.trim();
+  if content.is_empty() {
+    None
+  } else {
+    Some(content.to_string())
+  }
+}
+
+fn short_hash(hash: &str) -> String {
+  hash.chars().take(7).collect()
+}
+
+/// Name a commit by a branch pointing at it, falling back to its short hash
+fn name_commit(dir: &Path, hash: &str) -> String {
+  run_in(
+    dir,
+    &[
+      "for-each-ref",
+      "--points-at",
+      hash,
+      "--format=%(refname:short)",
+      "refs/heads",
+      "refs/remotes",
+    ],
+  )
+  .ok()
+  .and_then(|out| out.lines().next().map(String::from))
+  .unwrap_or_else(|| short_hash(hash))
+}
+
+fn commit_subject(dir: &Path, hash: &str) -> String {
+  let subject = run_in(dir, &["log", "-1", "--format=%s", hash]).unwrap_or_default();
+  if subject.is_empty() {
+    format!("commit {}", short_hash(hash))
+  } else {
+    format!("commit {} \"{}\"", short_hash(hash), subject)
+  }
+}
+
+/// Extract the merged ref from a MERGE_MSG such as "Merge branch 'feature/x' into main"
+fn parse_merge_msg(msg: &str) -> Option<String> {
+  let first = msg.lines().next()?;
+  let start = first.find('\'')? + 1;
+  let end = start + first[start..].find('\'')?;
+  Some(first[start..end].to_string())
+}
+
+fn strip_head_name(head_name: &str) -> String {
+  head_name
+    .strip_prefix("refs/heads/")
+    .unwrap_or(head_name)
+    .to_string()
+}
+
+/// Detect the operation in progress and name each side of its conflicts
+pub fn conflict_sides() -> ConflictSides {
+  conflict_sides_in(Path::new("."))
+}
+
+fn conflict_sides_in(repo: &Path) -> ConflictSides {
+  let branch = run_in(repo, &["branch", "--show-current"])
+    .ok()
+    .filter(|b| !b.is_empty())
+    .unwrap_or_else(|| "HEAD".to_string());
+
+  let exists = |name: &str| git_path_in(repo, name).is_some_and(|p| p.exists());
+  let read = |name: &str| read_git_file_in(repo, name);
+  let rebase_dir = if exists("rebase-merge") {
+    Some("rebase-merge")
+  } else if exists("rebase-apply/rebasing") {
+    Some("rebase-apply")
+  } else {
+    None
+  };
+
+  if let Some(dir) = rebase_dir {
+    let ours = read(&format!("{}/onto", dir))
+      .map(|hash| name_commit(repo, &hash))
+      .unwrap_or_else(|| "upstream".to_string());
+    let theirs = read(&format!("{}/head-name", dir))
+      .filter(|h| h != "detached HEAD")
+      .map(|h| strip_head_name(&h))
+      .or_else(|| read(&format!("{}/orig-head", dir)).map(|h| short_hash(&h)))
+      .unwrap_or_else(|| "your branch".to_string());
+
+    return ConflictSides {
+      operation: Operation::Rebase,
+      ours,
+      theirs,
+    };
+  }
+
+  if let Some(hash) = read("MERGE_HEAD") {
+    let theirs = read("MERGE_MSG")
+      .and_then(|msg| parse_merge_msg(&msg))
+      .unwrap_or_else(|| name_commit(repo, hash.lines().next().unwrap_or(&hash)));
+    return ConflictSides {
+      operation: Operation::Merge,
+      ours: branch,
+      theirs,
+    };
+  }
+
+  if let Some(hash) = read("CHERRY_PICK_HEAD") {
+    return ConflictSides {
+      operation: Operation::CherryPick,
+      ours: branch,
+      theirs: commit_subject(repo, &hash),
+    };
+  }
+
+  if let Some(hash) = read("REVERT_HEAD") {
+    return ConflictSides {
+      operation: Operation::Revert,
+      ours: branch,
+      theirs: commit_subject(repo, &hash),
+    };
+  }
+
+  ConflictSides {
+    operation: Operation::Stash,
+    ours: branch,
+    theirs: "stashed changes".to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    cherry_pick_state_in, cli, conflict_sides_in, libgit, parse_merge_msg, parse_structure,
+    recent_commit_subjects_in, record_sequence_start_in, sequence_start_in, staged_structure_in,
+    stash_diff_in, stash_list_in, stash_push_in, strip_head_name, ConflictInfo, GitStatus,
+    MergedBranch, Operation, SequenceStart, StructuralChange,
+  };
+  use std::fs;
+  use std::path::Path;
+  use std::process::Command;
+  use tempfile::TempDir;
+
+  const DATE: &str = "2020-01-01T12:00:00Z";
+
+  fn git(dir: &Path, args: &[&str]) {
+    git_at(dir, DATE, args);
+  }
+
+  /// Run git in the fixture with a fixed identity and date, so both backends see the same
+  /// history and relative dates
+  fn git_at(dir: &Path, date: &str, args: &[&str]) {
+    let output = command(dir, date, args).output().expect("git runs");
+    assert!(
+      output.status.success(),
+      "git {:?}: {}",
+      args,
+      String::from_utf8_lossy(&output.stderr)
+    );
+  }
+
+  fn command(dir: &Path, date: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::new("git");
+    cmd
+      .current_dir(dir)
+      .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+      .args([
+        "-c",
+        "commit.gpgsign=false",
+        "-c",
+        "init.defaultBranch=main",
+      ])
+      .args(args)
+      .env("GIT_AUTHOR_DATE", date)
+      .env("GIT_COMMITTER_DATE", date);
+    cmd
+  }
+
+  fn rev_parse(dir: &Path, rev: &str) -> String {
+    let output = command(dir, DATE, &["rev-parse", rev]).output().unwrap();
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+  }
+
+  fn write(dir: &Path, file: &str, content: &str) {
+    let path = dir.join(file);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, content).unwrap();
+  }
+
+  /// A repo with merged and unmerged branches, an upstream, a remote HEAD, and staged,
+  /// unstaged and untracked changes including a staged rename
+  fn fixture() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    write(repo, "a.txt", "one\ntwo\nthree\n");
+    write(
+      repo,
+      "moved.txt",
+      "a file long enough\nto be seen as renamed\nonce moved\n",
+    );
+    git(repo, &["add", "."]);
+    git_at(
+      repo,
+      "2019-03-01T12:00:00Z",
+      &["commit", "-qm", "Add files"],
+    );
+    git(repo, &["branch", "old"]);
+
+    write(repo, "a.txt", "one\n2\nthree\n");
+    git_at(
+      repo,
+      "2019-06-01T12:00:00Z",
+      &["commit", "-qam", "Change a\nover two lines\n\nBody"],
+    );
+    git(repo, &["branch", "done"]);
+    git(repo, &["branch", "--set-upstream-to=old"]);
+
+    git(repo, &["checkout", "-qb", "feature"]);
+    write(repo, "b.txt", "b\n");
+    git(repo, &["add", "b.txt"]);
+    git_at(repo, "2019-09-01T12:00:00Z", &["commit", "-qm", "Add b"]);
+    git(repo, &["checkout", "-q", "main"]);
+
+    git(repo, &["update-ref", "refs/remotes/origin/old", "old"]);
+    git(
+      repo,
+      &["update-ref", "refs/remotes/origin/feature", "feature"],
+    );
+    git(
+      repo,
+      &[
+        "symbolic-ref",
+        "refs/remotes/origin/HEAD",
+        "refs/remotes/origin/old",
+      ],
+    );
+
+    write(repo, "a.txt", "one\n2\n3\n");
+    write(repo, "c.txt", "new\n");
+    git(repo, &["add", "c.txt"]);
+    git(repo, &["mv", "moved.txt", "renamed.txt"]);
+    write(repo, "untracked.txt", "?\n");
+    write(repo, "scratch/notes.txt", "?\n");
+    dir
+  }
+
+  /// A repo stopped in a merge with `a.txt` changed on both sides and `b.txt` added on both
+  fn conflicted() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    write(repo, "a.txt", "base\n");
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-qm", "Base"]);
+    git(repo, &["checkout", "-qb", "other"]);
+    write(repo, "a.txt", "theirs\n");
+    write(repo, "b.txt", "their b\n");
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-qm", "Theirs"]);
+    git(repo, &["checkout", "-q", "main"]);
+    write(repo, "a.txt", "ours\n");
+    write(repo, "b.txt", "our b\n");
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-qm", "Ours"]);
+    let merge = command(repo, DATE, &["merge", "-q", "other"])
+      .output()
+      .unwrap();
+    assert!(!merge.status.success(), "the merge conflicts");
+    dir
+  }
+
+  fn summary(status: &GitStatus) -> String {
+    format!(
+      "{} +{} -{} staged={:?} unstaged={:?} untracked={:?} conflicts={:?}",
+      status.branch,
+      status.ahead,
+      status.behind,
+      status.staged,
+      status.unstaged,
+      status.untracked,
+      status.conflicts
+    )
+  }
+
+  fn names(branches: &[MergedBranch]) -> Vec<String> {
+    branches
+      .iter()
+      .map(|b| format!("{} | {} | {}", b.name, b.date, b.subject))
+      .collect()
+  }
+
+  fn stages(info: &ConflictInfo) -> [&str; 4] {
+    [&info.file, &info.base, &info.ours, &info.theirs]
+  }
+
+  #[test]
+  fn backends_agree_on_status() {
+    let repo = fixture();
+    let status = libgit::status(repo.path()).unwrap();
+    assert_eq!(
+      summary(&status),
+      summary(&cli::status(repo.path()).unwrap())
+    );
+    assert_eq!(
+      summary(&status),
+      "main +1 -0 staged=[\"c.txt\", \"renamed.txt\"] unstaged=[\"a.txt\"] \
+       untracked=[\"scratch/\", \"untracked.txt\"] conflicts=[]"
+    );
+
+    let repo = conflicted();
+    let status = libgit::status(repo.path()).unwrap();
+    assert_eq!(
+      summary(&status),
+      summary(&cli::status(repo.path()).unwrap())
+    );
+    assert_eq!(status.conflicts, ["a.txt", "b.txt"]);
+  }
+
+  #[test]
+  fn backends_agree_on_diffs() {
+    let repo = fixture();
+    for staged in [true, false] {
+      let diff = libgit::diff(repo.path(), staged).unwrap();
+      assert_eq!(diff, cli::diff(repo.path(), staged).unwrap());
+      assert!(diff.starts_with("diff --git"), "{}", diff);
+    }
+  }
+
+  #[test]
+  fn backends_agree_on_history_and_branches() {
+    let repo = fixture();
+    let dir = repo.path();
+    assert_eq!(libgit::log(dir, 10).unwrap(), cli::log(dir, 10).unwrap());
+    assert_eq!(libgit::log(dir, 1).unwrap().lines().count(), 1);
+
+    let branches = libgit::get_branches(dir).unwrap();
+    assert_eq!(branches, cli::get_branches(dir).unwrap());
+    assert_eq!(branches, ["done", "feature", "main", "old"]);
+
+    for remote in [false, true] {
+      let merged = libgit::merged_branches(dir, remote, "main").unwrap();
+      assert_eq!(
+        names(&merged),
+        names(&cli::merged_branches(dir, remote, "main").unwrap())
+      );
+    }
+    let merged = libgit::merged_branches(dir, false, "main").unwrap();
+    assert_eq!(
+      merged.iter().map(|b| b.name.as_str()).collect::<Vec<_>>(),
+      ["old", "done"]
+    );
+
+    let rebased = libgit::get_rebase_commits(dir, "old").unwrap();
+    assert_eq!(rebased, cli::get_rebase_commits(dir, "old").unwrap());
+    assert_eq!(rebased.len(), 1);
+  }
+
+  #[test]
+  fn backends_agree_on_conflict_stages() {
+    let repo = conflicted();
+    let dir = repo.path();
+    for file in ["a.txt", "b.txt"] {
+      let info = libgit::get_conflict_info(dir, file).unwrap();
+      assert_eq!(
+        stages(&info),
+        stages(&cli::get_conflict_info(dir, file).unwrap())
+      );
+    }
+    let info = libgit::get_conflict_info(dir, "a.txt").unwrap();
+    assert_eq!(stages(&info), ["a.txt", "base\n", "ours\n", "theirs\n"]);
+    // Added on both sides: there is no base
+    assert_eq!(libgit::get_conflict_info(dir, "b.txt").unwrap().base, "");
+
+    assert!(libgit::get_conflict_info(dir, "missing.txt").is_err());
+    assert!(cli::get_conflict_info(dir, "missing.txt").is_err());
+  }
+
+  fn sides(repo: &Path) -> (Operation, String, String) {
+    let sides = conflict_sides_in(repo);
+    (sides.operation, sides.ours_label(), sides.theirs_label())
+  }
+
+  fn expected(operation: Operation, ours: &str, theirs: &str) -> (Operation, String, String) {
+    (operation, ours.to_string(), theirs.to_string())
+  }
+
+  #[test]
+  fn merge_messages_name_the_merged_ref() {
+    let name = |msg: &str| parse_merge_msg(msg);
+    assert_eq!(
+      name("Merge branch 'feature/x' into main\n\n# Conflicts:").as_deref(),
+      Some("feature/x")
+    );
+    assert_eq!(
+      name("Merge remote-tracking branch 'origin/main'").as_deref(),
+      Some("origin/main")
+    );
+    assert_eq!(name("Merge 'unterminated"), None);
+    assert_eq!(name(""), None);
+  }
+
+  #[test]
+  fn head_names_lose_the_heads_prefix() {
+    assert_eq!(strip_head_name("refs/heads/feature/x"), "feature/x");
+    assert_eq!(strip_head_name("refs/tags/v1"), "refs/tags/v1");
+  }
+
+  #[test]
+  fn rebase_state_names_the_target_and_the_replayed_branch() {
+    let repo = fixture();
+    let dir = repo.path();
+    let state = dir.join(".git/rebase-merge");
+    fs::create_dir_all(&state).unwrap();
+    fs::write(state.join("onto"), rev_parse(dir, "old") + "\n").unwrap();
+    fs::write(state.join("head-name"), "refs/heads/feature\n").unwrap();
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::Rebase,
+        "old (rebase target)",
+        "feature (being rebased)"
+      )
+    );
+
+    // A detached rebase is named by the commit it started from
+    let feature = rev_parse(dir, "feature");
+    fs::write(state.join("head-name"), "detached HEAD\n").unwrap();
+    fs::write(state.join("orig-head"), &feature).unwrap();
+    assert_eq!(sides(dir).2, format!("{} (being rebased)", &feature[..7]));
+
+    // `git am` also uses rebase-apply; only `rebasing` marks a rebase
+    fs::remove_dir_all(&state).unwrap();
+    let apply = dir.join(".git/rebase-apply");
+    fs::create_dir_all(&apply).unwrap();
+    fs::write(apply.join("head-name"), "refs/heads/feature\n").unwrap();
+    assert_eq!(sides(dir).0, Operation::Stash);
+    fs::write(apply.join("rebasing"), "").unwrap();
+    assert_eq!(sides(dir).0, Operation::Rebase);
+  }
+
+  #[test]
+  fn merge_state_names_the_merged_ref() {
+    let repo = fixture();
+    let dir = repo.path();
+    fs::write(
+      dir.join(".git/MERGE_HEAD"),
+      rev_parse(dir, "feature") + "\n",
+    )
+    .unwrap();
+    fs::write(dir.join(".git/MERGE_MSG"), "Merge branch 'feature/x'\n").unwrap();
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::Merge,
+        "main (current branch)",
+        "feature/x (being merged)"
+      )
+    );
+
+    // Without a message, a branch pointing at MERGE_HEAD names it
+    fs::remove_file(dir.join(".git/MERGE_MSG")).unwrap();
+    assert_eq!(sides(dir).2, "feature (being merged)");
+  }
+
+  #[test]
+  fn cherry_pick_and_revert_state_name_the_commit() {
+    let repo = fixture();
+    let dir = repo.path();
+    let feature = rev_parse(dir, "feature");
+    let picked = format!("commit {} \"Add b\"", &feature[..7]);
+
+    fs::write(dir.join(".git/REVERT_HEAD"), &feature).unwrap();
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::Revert,
+        "main (current branch)",
+        &format!("{} (reverted)", picked)
+      )
+    );
+    fs::write(dir.join(".git/CHERRY_PICK_HEAD"), &feature).unwrap();
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::CherryPick,
+        "main (current branch)",
+        &format!("{} (cherry-picked)", picked)
+      )
+    );
+  }
+
+  #[test]
+  fn conflicts_without_operation_state_come_from_a_stash() {
+    let repo = fixture();
+    assert_eq!(
+      sides(repo.path()),
+      expected(Operation::Stash, "main (current branch)", "stashed changes")
+    );
+  }
+
+  #[test]
+  fn sides_swap_between_a_conflicted_merge_and_rebase() {
+    let repo = conflicted();
+    let dir = repo.path();
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::Merge,
+        "main (current branch)",
+        "other (being merged)"
+      )
+    );
+
+    git(dir, &["merge", "--abort"]);
+    git(dir, &["checkout", "-q", "other"]);
+    let rebase = command(dir, DATE, &["rebase", "main"]).output().unwrap();
+    assert!(!rebase.status.success(), "the rebase conflicts");
+    assert_eq!(
+      sides(dir),
+      expected(
+        Operation::Rebase,
+        "main (rebase target)",
+        "other (being rebased)"
+      )
+    );
+  }
+
+  /// `main` changed `a.txt` after `fixes` branched off with a fix to it and a new file
+  fn picks() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    write(repo, "a.txt", "base\n");
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-qm", "Base"]);
+    git(repo, &["checkout", "-qb", "fixes"]);
+    write(repo, "a.txt", "fixed\n");
+    git(repo, &["commit", "-qam", "Fix a"]);
+    write(repo, "c.txt", "c\n");
+    git(repo, &["add", "c.txt"]);
+    git(repo, &["commit", "-qm", "Add c"]);
+    git(repo, &["checkout", "-q", "main"]);
+    write(repo, "a.txt", "changed on main\n");
+    git(repo, &["commit", "-qam", "Change a"]);
+    dir
+  }
+
+  #[test]
+  fn a_clean_pick_leaves_no_state() {
+    let repo = picks();
+    let dir = repo.path();
+    git(dir, &["cherry-pick", "fixes"]);
+    assert!(cherry_pick_state_in(dir).is_none());
+  }
+
+  #[test]
+  fn a_stopped_range_reports_the_current_pick_and_the_rest() {
+    let repo = picks();
+    let dir = repo.path();
+    let tip = rev_parse(dir, "HEAD");
+    let pick = command(dir, DATE, &["cherry-pick", "fixes~1", "fixes"])
+      .output()
+      .unwrap();
+    assert!(!pick.status.success(), "Fix a conflicts");
+
+    let state = cherry_pick_state_in(dir).expect("a pick in progress");
+    assert_eq!(state.current, Some(rev_parse(dir, "fixes~1")));
+    assert_eq!(state.todo.len(), 2, "{:?}", state.todo);
+    assert!(state.todo[0].ends_with("Fix a"), "{:?}", state.todo);
+    assert!(state.todo[1].ends_with("Add c"), "{:?}", state.todo);
+
+    git(dir, &["cherry-pick", "--abort"]);
+    assert!(cherry_pick_state_in(dir).is_none());
+    assert_eq!(rev_parse(dir, "HEAD"), tip);
+  }
+
+  #[test]
+  fn sequence_start_round_trips() {
+    let repo = picks();
+    let dir = repo.path();
+    assert!(sequence_start_in(dir).is_none());
+
+    let tip = rev_parse(dir, "HEAD");
+    let start = SequenceStart {
+      tip: tip.clone(),
+      branch: Some("main".to_string()),
+    };
+    record_sequence_start_in(dir, &start).unwrap();
+    let read = sequence_start_in(dir).unwrap();
+    assert_eq!(
+      (read.tip.as_str(), read.branch.as_deref()),
+      (tip.as_str(), Some("main"))
+    );
+
+    // Started on a detached HEAD: nothing to check out again
+    let start = SequenceStart { tip, branch: None };
+    record_sequence_start_in(dir, &start).unwrap();
+    assert_eq!(sequence_start_in(dir).unwrap().branch, None);
+  }
+
+  #[test]
+  fn staged_structure_lists_moves_deletions_and_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    let long = "line one\nline two\nline three\nline four\nline five\n";
+    write(repo, "old dir/my notes.txt", long);
+    write(repo, "gone.txt", "a\nb\nc\n");
+    fs::write(repo.join("logo.bin"), [0u8, 159, 146, 150, 0, 1]).unwrap();
+    write(repo, "run.sh", "echo hi\n");
+    git(repo, &["add", "."]);
+    git(repo, &["commit", "-qm", "Base"]);
+
+    fs::create_dir(repo.join("new dir")).unwrap();
+    git(
+      repo,
+      &["mv", "old dir/my notes.txt", "new dir/my notes.txt"],
+    );
+    git(repo, &["rm", "-q", "gone.txt", "logo.bin"]);
+    git(repo, &["update-index", "--chmod=+x", "run.sh"]);
+
+    let mut changes = staged_structure_in(repo).unwrap();
+    changes.sort_by_key(|c| format!("{:?}", c));
+    assert_eq!(
+      changes,
+      [
+        StructuralChange::Delete {
+          path: "gone.txt".to_string(),
+          lines: Some(3)
+        },
+        StructuralChange::Delete {
+          path: "logo.bin".to_string(),
+          lines: None
+        },
+        StructuralChange::Mode {
+          path: "run.sh".to_string(),
+          old: "100644".to_string(),
+          new: "100755".to_string()
+        },
+        StructuralChange::Rename {
+          from: "old dir/my notes.txt".to_string(),
+          to: "new dir/my notes.txt".to_string(),
+          similarity: 100
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn content_edits_are_not_structural() {
+    let raw = ":100644 100644 1111111 2222222 M\0src/lib.rs\0";
+    assert!(parse_structure(raw, "3\t1\tsrc/lib.rs\0").is_empty());
+  }
+
+  #[test]
+  fn renames_that_change_mode_report_both() {
+    let raw = ":100644 100755 1111111 2222222 R087\0bin/run\0scripts/run.sh\0";
+    let numstat = "1\t1\t\0bin/run\0scripts/run.sh\0";
+    assert_eq!(
+      parse_structure(raw, numstat),
+      [
+        StructuralChange::Rename {
+          from: "bin/run".to_string(),
+          to: "scripts/run.sh".to_string(),
+          similarity: 87
+        },
+        StructuralChange::Mode {
+          path: "scripts/run.sh".to_string(),
+          old: "100644".to_string(),
+          new: "100755".to_string()
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn symlinks_are_reported_as_such() {
+    let raw = ":000000 120000 0000000 1111111 A\0current\0\
+               :120000 120000 1111111 2222222 M\0latest\0\
+               :100644 120000 1111111 2222222 T\0config\0";
+    let changes: Vec<String> = parse_structure(raw, "")
+      .into_iter()
+      .map(|c| match c {
+        StructuralChange::Symlink { path, change } => format!("{} {}", path, change),
+        other => panic!("{:?}", other),
+      })
+      .collect();
+    assert_eq!(
+      changes,
+      ["current added", "latest retargeted", "config type changed"]
+    );
+  }
+
+  #[test]
+  fn recent_subjects_come_newest_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    assert!(recent_commit_subjects_in(repo, 5).unwrap().is_empty());
+
+    for subject in ["First", "Second", "Third"] {
+      write(repo, "a.txt", subject);
+      git(repo, &["add", "a.txt"]);
+      git(repo, &["commit", "-qm", subject]);
+    }
+    assert_eq!(
+      recent_commit_subjects_in(repo, 2).unwrap(),
+      ["Third", "Second"]
+    );
+    assert_eq!(recent_commit_subjects_in(repo, 10).unwrap().len(), 3);
+  }
+
+  #[test]
+  fn stashes_keep_their_message_and_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path();
+    git(repo, &["init", "-q"]);
+    write(repo, "a.txt", "one\n");
+    git(repo, &["add", "a.txt"]);
+    git(repo, &["commit", "-qm", "Add a"]);
+    // Stashing commits through plain git, which needs an identity of its own
+    git(repo, &["config", "user.name", "Test"]);
+    git(repo, &["config", "user.email", "test@example.com"]);
+    assert!(stash_list_in(repo).unwrap().is_empty());
+
+    write(repo, "a.txt", "one\nlogin form\n");
+    git(repo, &["add", "a.txt"]);
+    stash_push_in(repo, "Half-done login form").unwrap();
+    write(repo, "a.txt", "two\n");
+    stash_push_in(repo, "Try a new greeting").unwrap();
+
+    let entries = stash_list_in(repo).unwrap();
+    let listed: Vec<(&str, &str)> = entries
+      .iter()
+      .map(|e| (e.name.as_str(), e.description.as_str()))
+      .collect();
+    assert_eq!(
+      listed,
+      [
+        ("stash@{0}", "On main: Try a new greeting"),
+        ("stash@{1}", "On main: Half-done login form"),
+      ]
+    );
+    assert!(entries.iter().all(|e| e.age.ends_with(" ago")));
+
+    let diff = stash_diff_in(repo, "stash@{1}").unwrap();
+    assert!(diff.contains("+login form"), "{}", diff);
+    assert!(stash_diff_in(repo, "stash@{5}").is_err());
+  }
+}
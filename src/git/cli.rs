@@ -0,0 +1,184 @@
+//! The read-only operations run through the git binary, for builds with the `git-cli` feature
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+use super::{exec_raw, run_in as run, ConflictInfo, GitStatus, MergedBranch};
+
+fn run_raw(dir: &Path, args: &[&str]) -> Result<String> {
+  exec_raw(Command::new("git").current_dir(dir).args(args), args)
+}
+
+pub(super) fn status(dir: &Path) -> Result<GitStatus> {
+  let branch = run(dir, &["branch", "--show-current"]).unwrap_or_default();
+
+  let (ahead, behind) = run(dir, &["rev-list", "--left-right", "--count", "@{u}...HEAD"])
+    .ok()
+    .and_then(|s| {
+      let parts: Vec<&str> = s.split('\t').collect();
+      if parts.len() == 2 {
+        let b = parts[0].parse().unwrap_or(0);
+        let a = parts[1].parse().unwrap_or(0);
+        Some((a, b))
+      } else {
+        None
+      }
+    })
+    .unwrap_or((0, 0));
+
+  // -z keeps paths unquoted and gives a rename's new path as its own entry
+  let porcelain = run_raw(dir, &["status", "--porcelain=v1", "-z"])?;
+
+  let mut staged = Vec::new();
+  let mut unstaged = Vec::new();
+  let mut untracked = Vec::new();
+  let mut conflicts = Vec::new();
+
+  let mut entries = porcelain.split('\0').filter(|e| !e.is_empty());
+  while let Some(entry) = entries.next() {
+    let (Some(code), Some(file)) = (entry.get(..2), entry.get(3..)) else {
+      return Err(anyhow!("Unexpected git status entry: {:?}", entry));
+    };
+    let mut code = code.chars();
+    let index = code.next().unwrap_or(' ');
+    let worktree = code.next().unwrap_or(' ');
+    let file = file.to_string();
+
+    // Renames and copies are followed by the path they came from
+    if matches!(index, 'R' | 'C') {
+      entries.next();
+    }
+
+    // Check for conflicts
+    if index == 'U'
+      || worktree == 'U'
+      || (index == 'A' && worktree == 'A')
+      || (index == 'D' && worktree == 'D')
+    {
+      conflicts.push(file);
+    } else if index == '?' {
+      untracked.push(file);
+    } else if index != '!' {
+      if index != ' ' {
+        staged.push(file.clone());
+      }
+      if worktree != ' ' {
+        unstaged.push(file);
+      }
+    }
+  }
+
+  Ok(GitStatus {
+    branch,
+    ahead,
+    behind,
+    staged,
+    unstaged,
+    untracked,
+    conflicts,
+  })
+}
+
+pub(super) fn diff(dir: &Path, staged: bool) -> Result<String> {
+  if staged {
+    run(dir, &["diff", "--cached"])
+  } else {
+    run(dir, &["diff"])
+  }
+}
+
+pub(super) fn log(dir: &Path, count: usize) -> Result<String> {
+  run(dir, &["log", "--oneline", &format!("-{}", count)])
+}
+
+pub(super) fn get_branches(dir: &Path) -> Result<Vec<String>> {
+  let output = run(dir, &["branch", "--format=%(refname:short)"])?;
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+pub(super) fn merged_branches(dir: &Path, remote: bool, into: &str) -> Result<Vec<MergedBranch>> {
+  let mut args = vec!["branch"];
+  if remote {
+    args.push("-r");
+  }
+  args.extend([
+    "--merged",
+    into,
+    "--sort=committerdate",
+    "--format=%(refname:short)%00%(symref)%00%(committerdate:relative)%00%(contents:subject)",
+  ]);
+  let output = run(dir, &args)?;
+  Ok(
+    output
+      .lines()
+      .filter_map(|line| {
+        let mut fields = line.splitn(4, '\0');
+        let name = fields.next()?;
+        // Symbolic refs such as origin/HEAD only point at another branch
+        let symref = fields.next().unwrap_or_default();
+        if name.is_empty() || name == into || !symref.is_empty() {
+          return None;
+        }
+        Some(MergedBranch {
+          name: name.to_string(),
+          date: fields.next().unwrap_or_default().to_string(),
+          subject: fields.next().unwrap_or_default().to_string(),
+        })
+      })
+      .collect(),
+  )
+}
+
+pub(super) fn get_conflict_info(dir: &Path, file: &str) -> Result<ConflictInfo> {
+  let mut info = ConflictInfo {
+    file: file.to_string(),
+    ..Default::default()
+  };
+
+  // Each line: "<mode> <blob> <stage>\t<path>"
+  let stages = run_raw(dir, &["ls-files", "--unmerged", "-z", "--", file])?;
+  let mut found = false;
+  for entry in stages.split('\0').filter(|e| !e.is_empty()) {
+    found = true;
+    let fields: Vec<&str> = entry
+      .split('\t')
+      .next()
+      .unwrap_or_default()
+      .split(' ')
+      .collect();
+    let [_, blob, stage] = fields[..] else {
+      return Err(anyhow!("Unexpected git ls-files entry: {:?}", entry));
+    };
+    let content = run_raw(dir, &["cat-file", "blob", blob])
+      .with_context(|| format!("Failed to read stage {} of {}", stage, file))?;
+    match stage {
+      "1" => info.base = content,
+      "2" => info.ours = content,
+      "3" => info.theirs = content,
+      _ => {}
+    }
+  }
+
+  if !found {
+    return Err(anyhow!("{} has no conflict stages in the index", file));
+  }
+  Ok(info)
+}
+
+pub(super) fn get_rebase_commits(dir: &Path, onto: &str) -> Result<Vec<String>> {
+  let output = run(dir, &["log", "--oneline", &format!("{}..HEAD", onto)])?;
+  Ok(
+    output
+      .lines()
+      .filter(|s| !s.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
@@ -0,0 +1,289 @@
+//! The read-only operations through libgit2, without starting a git process for each
+
+use anyhow::{anyhow, Context, Result};
+use git2::{
+  BranchType, Commit, DiffFormat, Oid, ReferenceType, Repository, RepositoryOpenFlags, Status,
+  StatusEntry, StatusOptions,
+};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{ConflictInfo, GitStatus, MergedBranch};
+
+const STAGED: Status = Status::INDEX_NEW
+  .union(Status::INDEX_MODIFIED)
+  .union(Status::INDEX_DELETED)
+  .union(Status::INDEX_RENAMED)
+  .union(Status::INDEX_TYPECHANGE);
+
+const UNSTAGED: Status = Status::WT_MODIFIED
+  .union(Status::WT_DELETED)
+  .union(Status::WT_RENAMED)
+  .union(Status::WT_TYPECHANGE);
+
+/// The repository containing `dir`, honoring `GIT_DIR`, `GIT_INDEX_FILE` and friends the way
+/// git does, since hooks run with them set
+fn open(dir: &Path) -> Result<Repository> {
+  Repository::open_ext(dir, RepositoryOpenFlags::FROM_ENV, &[] as &[&OsStr])
+    .map_err(|e| anyhow!("Not a git repository: {}", e.message()))
+}
+
+fn lossy(bytes: &[u8]) -> String {
+  String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// `git log --oneline`: abbreviated hash and subject
+fn oneline(commit: &Commit) -> Result<String> {
+  let id = commit.as_object().short_id()?;
+  Ok(format!(
+    "{} {}",
+    lossy(&id),
+    lossy(commit.summary_bytes().unwrap_or_default())
+  ))
+}
+
+pub(super) fn status(dir: &Path) -> Result<GitStatus> {
+  let repo = open(dir)?;
+
+  // Like `git branch --show-current`: the branch HEAD names, even before its first commit
+  let branch = repo
+    .find_reference("HEAD")
+    .ok()
+    .and_then(|head| {
+      head
+        .symbolic_target()?
+        .strip_prefix("refs/heads/")
+        .map(String::from)
+    })
+    .unwrap_or_default();
+
+  let (ahead, behind) = ahead_behind(&repo).unwrap_or((0, 0));
+
+  let mut options = StatusOptions::new();
+  options
+    .include_untracked(true)
+    .recurse_untracked_dirs(false)
+    .include_ignored(false)
+    .renames_head_to_index(true);
+  let statuses = repo.statuses(Some(&mut options))?;
+
+  let mut staged = Vec::new();
+  let mut unstaged = Vec::new();
+  let mut untracked = Vec::new();
+  let mut conflicts = Vec::new();
+
+  for entry in statuses.iter() {
+    let state = entry.status();
+    let file = entry_path(&entry);
+    if state.is_conflicted() {
+      conflicts.push(file);
+    } else if state.is_wt_new() && !state.intersects(STAGED) {
+      untracked.push(file);
+    } else {
+      if state.intersects(STAGED) {
+        staged.push(file.clone());
+      }
+      if state.intersects(UNSTAGED) {
+        unstaged.push(file);
+      }
+    }
+  }
+
+  Ok(GitStatus {
+    branch,
+    ahead,
+    behind,
+    staged,
+    unstaged,
+    untracked,
+    conflicts,
+  })
+}
+
+/// A staged rename is reported under its new path, as `git status` does
+fn entry_path(entry: &StatusEntry) -> String {
+  entry
+    .head_to_index()
+    .filter(|delta| delta.status() == git2::Delta::Renamed)
+    .and_then(|delta| delta.new_file().path_bytes().map(lossy))
+    .unwrap_or_else(|| lossy(entry.path_bytes()))
+}
+
+/// Commits the current branch has that its upstream doesn't, and the other way around
+fn ahead_behind(repo: &Repository) -> Option<(usize, usize)> {
+  let head = repo.head().ok()?;
+  let local = head.target()?;
+  let upstream = repo.branch_upstream_name(head.name()?).ok()?;
+  let upstream = repo.refname_to_id(upstream.as_str()?).ok()?;
+  repo.graph_ahead_behind(local, upstream).ok()
+}
+
+pub(super) fn diff(dir: &Path, staged: bool) -> Result<String> {
+  let repo = open(dir)?;
+  let mut diff = if staged {
+    // Before the first commit everything in the index is new
+    let head = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+    repo.diff_tree_to_index(head.as_ref(), None, None)?
+  } else {
+    repo.diff_index_to_workdir(None, None)?
+  };
+  // Rename detection follows `diff.renames`, on unless configured off, like git diff
+  diff.find_similar(None)?;
+
+  let mut patch = Vec::new();
+  diff.print(DiffFormat::Patch, |_, _, line| {
+    if matches!(line.origin(), '+' | '-' | ' ') {
+      patch.push(line.origin() as u8);
+    }
+    patch.extend_from_slice(line.content());
+    true
+  })?;
+  Ok(String::from_utf8_lossy(&patch).trim().to_string())
+}
+
+pub(super) fn log(dir: &Path, count: usize) -> Result<String> {
+  let repo = open(dir)?;
+  let mut walk = repo.revwalk()?;
+  walk.push_head()?;
+  let lines = walk
+    .take(count)
+    .map(|id| oneline(&repo.find_commit(id?)?))
+    .collect::<Result<Vec<_>>>()?;
+  Ok(lines.join("\n"))
+}
+
+pub(super) fn get_branches(dir: &Path) -> Result<Vec<String>> {
+  let repo = open(dir)?;
+  let mut names = repo
+    .branches(Some(BranchType::Local))?
+    .map(|branch| Ok(lossy(branch?.0.name_bytes()?)))
+    .collect::<Result<Vec<_>>>()?;
+  names.sort();
+  Ok(names)
+}
+
+pub(super) fn merged_branches(dir: &Path, remote: bool, into: &str) -> Result<Vec<MergedBranch>> {
+  let repo = open(dir)?;
+  let target = repo
+    .revparse_single(into)
+    .and_then(|object| object.peel_to_commit())
+    .with_context(|| format!("Unknown branch {}", into))?
+    .id();
+  let kind = if remote {
+    BranchType::Remote
+  } else {
+    BranchType::Local
+  };
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs() as i64);
+
+  let mut merged = Vec::new();
+  for branch in repo.branches(Some(kind))? {
+    let (branch, _) = branch?;
+    // Symbolic refs such as origin/HEAD only point at another branch
+    if branch.get().kind() == Some(ReferenceType::Symbolic) {
+      continue;
+    }
+    let name = lossy(branch.name_bytes()?);
+    if name == into {
+      continue;
+    }
+    let tip = branch.get().peel_to_commit()?;
+    if !is_merged(&repo, tip.id(), target)? {
+      continue;
+    }
+    let time = tip.committer().when().seconds();
+    merged.push((
+      time,
+      MergedBranch {
+        name,
+        date: relative_date(now - time),
+        subject: lossy(tip.summary_bytes().unwrap_or_default()),
+      },
+    ));
+  }
+
+  // Oldest tip first; git breaks ties by name
+  merged.sort_by(|(a_time, a), (b_time, b)| a_time.cmp(b_time).then_with(|| a.name.cmp(&b.name)));
+  Ok(merged.into_iter().map(|(_, branch)| branch).collect())
+}
+
+fn is_merged(repo: &Repository, tip: Oid, into: Oid) -> Result<bool> {
+  Ok(tip == into || repo.graph_descendant_of(into, tip)?)
+}
+
+/// `%(committerdate:relative)`, rounded the way git rounds it
+fn relative_date(seconds: i64) -> String {
+  fn ago(n: i64, unit: &str) -> String {
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+  }
+
+  if seconds < 0 {
+    return "in the future".to_string();
+  }
+  if seconds < 90 {
+    return ago(seconds, "second");
+  }
+  let minutes = (seconds + 30) / 60;
+  if minutes < 90 {
+    return ago(minutes, "minute");
+  }
+  let hours = (minutes + 30) / 60;
+  if hours < 36 {
+    return ago(hours, "hour");
+  }
+  let days = (hours + 12) / 24;
+  if days < 14 {
+    return ago(days, "day");
+  }
+  if days < 70 {
+    return ago((days + 3) / 7, "week");
+  }
+  if days < 365 {
+    return ago((days + 15) / 30, "month");
+  }
+  if days < 1825 {
+    let total_months = (days * 12 * 2 + 365) / (365 * 2);
+    let (years, months) = (total_months / 12, total_months % 12);
+    if months == 0 {
+      return ago(years, "year");
+    }
+    let years = format!("{} year{}", years, if years == 1 { "" } else { "s" });
+    return format!("{}, {}", years, ago(months, "month"));
+  }
+  ago((days + 183) / 365, "year")
+}
+
+pub(super) fn get_conflict_info(dir: &Path, file: &str) -> Result<ConflictInfo> {
+  let repo = open(dir)?;
+  let conflict = repo
+    .index()?
+    .conflict_get(Path::new(file))
+    .map_err(|_| anyhow!("{} has no conflict stages in the index", file))?;
+
+  let read = |entry: Option<git2::IndexEntry>, stage: u8| -> Result<String> {
+    let Some(entry) = entry else {
+      return Ok(String::new());
+    };
+    let blob = repo
+      .find_blob(entry.id)
+      .with_context(|| format!("Failed to read stage {} of {}", stage, file))?;
+    Ok(lossy(blob.content()))
+  };
+
+  Ok(ConflictInfo {
+    file: file.to_string(),
+    base: read(conflict.ancestor, 1)?,
+    ours: read(conflict.our, 2)?,
+    theirs: read(conflict.their, 3)?,
+  })
+}
+
+pub(super) fn get_rebase_commits(dir: &Path, onto: &str) -> Result<Vec<String>> {
+  let repo = open(dir)?;
+  let mut walk = repo.revwalk()?;
+  walk.push_range(&format!("{}..HEAD", onto))?;
+  walk.map(|id| oneline(&repo.find_commit(id?)?)).collect()
+}
@@ -3,5 +3,16 @@
 //! This library provides the core functionality for the alfred CLI tool.
 
 pub mod config;
+pub mod conflict;
 pub mod daemon_client;
+pub mod daemon_log;
+pub mod daemon_tls;
+pub mod diff_filter;
+pub mod diff_truncator;
+pub mod git;
 pub mod llm;
+pub mod ollama;
+pub mod prompt_template;
+pub mod remote;
+pub mod response_cache;
+pub mod risk;
@@ -1,29 +1,144 @@
 use anyhow::{anyhow, Context, Result};
 use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
 use llama_cpp_2::llama_backend::LlamaBackend;
 use llama_cpp_2::llama_batch::LlamaBatch;
 use llama_cpp_2::model::params::LlamaModelParams;
 use llama_cpp_2::model::LlamaModel;
 use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::LlamaToken;
 use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use crate::config;
+use crate::config::{self, Backend, CommitStyle, SamplingConfig};
 use crate::daemon_client;
+use crate::git::{CommitRecord, StructuralChange};
+use crate::risk::RiskLevel;
+use crate::{diff_truncator, ollama, prompt_template, remote, response_cache};
 
 static BACKEND: OnceCell<LlamaBackend> = OnceCell::new();
-static MODEL: OnceCell<LlamaModel> = OnceCell::new();
+/// Local models by file, loaded as tasks ask for them; the daemon can swap them out with
+/// `reload_model`
+static MODELS: RwLock<BTreeMap<PathBuf, LoadedModel>> = RwLock::new(BTreeMap::new());
+/// File the daemon was started with, in place of the `default` model's
+static DEFAULT_MODEL_FILE: RwLock<Option<PathBuf>> = RwLock::new(None);
+/// Models the cached context borrows from. Only used outside the daemon, which never reloads
+static PINNED_MODELS: Mutex<Vec<&'static LlamaModel>> = Mutex::new(Vec::new());
+static DAEMON_MODE: AtomicBool = AtomicBool::new(false);
+/// When the local generation in progress started, and its token budget
+static GENERATION: Mutex<Option<(Instant, u32)>> = Mutex::new(None);
+/// Smoothed local generation speed, prompt evaluation included
+static TOKENS_PER_SEC: Mutex<Option<f64>> = Mutex::new(None);
+
+/// Called with each piece of generated text
+type TokenCallback = Box<dyn FnMut(&str)>;
+
+thread_local! {
+  /// Sampling sent along with a daemon request, used instead of the daemon's own config
+  static SAMPLING: RefCell<Option<SamplingConfig>> = const { RefCell::new(None) };
+  /// Model alias for this generation: a daemon request's, or the running task's
+  static MODEL_ALIAS: RefCell<Option<String>> = const { RefCell::new(None) };
+  /// What the model did on this thread since `measuring_usage` began
+  static USAGE: Cell<Usage> = const {
+    Cell::new(Usage {
+      prompt_tokens: 0,
+      completion_tokens: 0,
+      duration_ms: 0,
+    })
+  };
+  /// Context kept between generations outside the daemon, with the model and size it was
+  /// built for. Commands like `resolve` generate many times in one run; rebuilding it each
+  /// time is slow
+  static CONTEXT: RefCell<Option<(&'static LlamaModel, u32, LlamaContext<'static>)>> =
+    const { RefCell::new(None) };
+  /// Where generated text goes as it's produced, for streaming it to a terminal or a client
+  static ON_TOKEN: RefCell<Option<TokenCallback>> = const { RefCell::new(None) };
+}
+
+/// Suggests conflict resolutions in place of the model in tests
+#[cfg(test)]
+type ConflictStub = Box<dyn Fn(&Conflict) -> Result<String>>;
+
+#[cfg(test)]
+thread_local! {
+  static CONFLICT_STUB: RefCell<Option<ConflictStub>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with conflict suggestions from `suggest`, and without loading a model, so the
+/// flows around resolving can be tested
+#[cfg(test)]
+pub fn with_conflict_suggestions<T>(
+  suggest: impl Fn(&Conflict) -> Result<String> + 'static,
+  f: impl FnOnce() -> T,
+) -> T {
+  let previous = CONFLICT_STUB.with(|cell| cell.replace(Some(Box::new(suggest))));
+  let result = f();
+  CONFLICT_STUB.with(|cell| *cell.borrow_mut() = previous);
+  result
+}
+
+/// A loaded model file and what it was loaded with
+struct LoadedModel {
+  model: Arc<LlamaModel>,
+  /// Layers actually offloaded to the GPU
+  gpu_layers: u32,
+}
+
+/// Tokens the repeat penalty looks back over
+const PENALTY_LAST_N: i32 = 64;
 
 fn get_backend() -> &'static LlamaBackend {
   BACKEND.get_or_init(|| LlamaBackend::init().expect("Failed to initialize llama backend"))
 }
 
-pub fn load_model() -> Result<()> {
-  if MODEL.get().is_some() {
+/// Load the model behind an alias from `models`; `default` is the configured model
+pub fn load_model(alias: &str) -> Result<()> {
+  #[cfg(test)]
+  if CONFLICT_STUB.with(|cell| cell.borrow().is_some()) {
+    return Ok(());
+  }
+  if !config::get_backend().is_local() {
+    return Ok(());
+  }
+  load_file(&model_spec(alias)?.path)
+}
+
+/// Load a specific model file as the `default` model instead of the configured one
+pub fn load_model_from(model_path: &Path) -> Result<()> {
+  *DEFAULT_MODEL_FILE
+    .write()
+    .unwrap_or_else(|e| e.into_inner()) = Some(model_path.to_path_buf());
+  load_model(config::DEFAULT_MODEL)
+}
+
+/// Load a model file unless it already is
+fn load_file(model_path: &Path) -> Result<()> {
+  // Ollama and remote APIs keep their own models loaded
+  if loaded(model_path, |_| ()).is_some() || !config::get_backend().is_local() {
     return Ok(());
   }
 
-  let model_path = config::get_model_path();
+  // Never load a half-finished download, even if the config points at it
+  if model_path.extension().is_some_and(|ext| ext == "part") {
+    return Err(anyhow!(
+      "{} is an incomplete download\nRun 'alfred setup --resume' to finish it.",
+      model_path.display()
+    ));
+  }
+  if !model_path.exists() && config::part_path(model_path).exists() {
+    return Err(anyhow!(
+      "The download of {} didn't finish\nRun 'alfred setup --resume' to finish it.",
+      model_path.display()
+    ));
+  }
+
   if !model_path.exists() {
     return Err(anyhow!(
       "Model not found at {}\nRun 'alfred setup' to download it.",
@@ -32,43 +147,619 @@ pub fn load_model() -> Result<()> {
   }
 
   let backend = get_backend();
-  let model_params = LlamaModelParams::default();
 
-  let model = LlamaModel::load_from_file(backend, &model_path, &model_params)
+  // Unset means offload everything when a GPU backend (CUDA/Metal) is available
+  let requested = match config::get_gpu_layers() {
+    Some(n) => n,
+    None if backend.supports_gpu_offload() => config::ALL_GPU_LAYERS,
+    None => 0,
+  };
+  let model_params = LlamaModelParams::default().with_n_gpu_layers(requested);
+
+  let model = LlamaModel::load_from_file(backend, model_path, &model_params)
     .with_context(|| format!("Failed to load model from {}", model_path.display()))?;
 
-  MODEL
-    .set(model)
-    .map_err(|_| anyhow!("Model already loaded"))?;
+  let offloaded = if backend.supports_gpu_offload() {
+    requested.min(model.n_layer())
+  } else {
+    0
+  };
+  MODELS.write().unwrap_or_else(|e| e.into_inner()).insert(
+    model_path.to_path_buf(),
+    LoadedModel {
+      model: Arc::new(model),
+      gpu_layers: offloaded,
+    },
+  );
 
   Ok(())
 }
 
+/// Drop every loaded model and load `model_path`, or the configured model, as `default`.
+/// The old models are freed first, so old and new are never in memory together. Other
+/// aliases load again on their next request
+pub fn reload_model(model_path: Option<&Path>) -> Result<()> {
+  if !config::get_backend().is_local() {
+    return Ok(());
+  }
+  if !PINNED_MODELS
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .is_empty()
+  {
+    return Err(anyhow!("The model can only be reloaded in the daemon"));
+  }
+
+  MODELS.write().unwrap_or_else(|e| e.into_inner()).clear();
+  match model_path {
+    Some(path) => load_model_from(path),
+    None => {
+      DEFAULT_MODEL_FILE
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+      load_model(config::DEFAULT_MODEL)
+    }
+  }
+}
+
+/// Run `f` with the model behind `alias` on this thread; `None` leaves the choice to the task.
+/// The daemon uses it so each request runs on the model its client asked for
+pub fn with_model<T>(alias: Option<String>, f: impl FnOnce() -> T) -> T {
+  let previous = MODEL_ALIAS.with(|cell| cell.replace(alias));
+  let result = f();
+  MODEL_ALIAS.with(|cell| *cell.borrow_mut() = previous);
+  result
+}
+
+/// Puts back the model alias that was in use before `task_model`
+struct ModelAliasGuard(Option<String>);
+
+impl Drop for ModelAliasGuard {
+  fn drop(&mut self) {
+    MODEL_ALIAS.with(|cell| *cell.borrow_mut() = self.0.take());
+  }
+}
+
+/// Generate with the model `tasks` picks for `task` until the guard drops, unless a daemon
+/// request already chose one
+fn task_model(task: config::Task) -> ModelAliasGuard {
+  MODEL_ALIAS.with(|cell| {
+    let previous = cell.borrow().clone();
+    if previous.is_none() {
+      *cell.borrow_mut() = Some(config::get_task_model(task));
+    }
+    ModelAliasGuard(previous)
+  })
+}
+
+/// Alias of the model this generation uses
+pub fn model_alias() -> String {
+  MODEL_ALIAS
+    .with(|cell| cell.borrow().clone())
+    .unwrap_or_else(|| config::DEFAULT_MODEL.to_string())
+}
+
+/// File and context size behind `alias`, with the daemon's `--model` standing in for `default`
+fn model_spec(alias: &str) -> Result<config::ModelSpec> {
+  let mut spec = config::get_model_spec(alias)?;
+  if alias == config::DEFAULT_MODEL {
+    if let Some(path) = DEFAULT_MODEL_FILE
+      .read()
+      .unwrap_or_else(|e| e.into_inner())
+      .clone()
+    {
+      spec.path = path;
+    }
+  }
+  Ok(spec)
+}
+
+/// Context window of the model this generation uses
+pub fn context_size() -> u32 {
+  model_spec(&model_alias())
+    .map(|spec| spec.context_size)
+    .unwrap_or_else(|_| config::get_context_size())
+}
+
+/// Whether the model this generation uses is loaded
 pub fn is_loaded() -> bool {
-  MODEL.get().is_some()
+  model_spec(&model_alias()).is_ok_and(|spec| loaded(&spec.path, |_| ()).is_some())
+}
+
+fn loaded<T>(path: &Path, f: impl FnOnce(&LoadedModel) -> T) -> Option<T> {
+  MODELS
+    .read()
+    .unwrap_or_else(|e| e.into_inner())
+    .get(path)
+    .map(f)
+}
+
+/// The model this generation uses, loaded on first use and shared with whoever else is
+/// generating with it
+fn model() -> Result<Arc<LlamaModel>> {
+  let path = model_spec(&model_alias())?.path;
+  load_file(&path)?;
+  loaded(&path, |l| l.model.clone()).ok_or_else(|| anyhow!("Model not loaded"))
+}
+
+/// File names of the loaded models
+pub fn loaded_models() -> Vec<String> {
+  MODELS
+    .read()
+    .unwrap_or_else(|e| e.into_inner())
+    .keys()
+    .map(|path| file_name(path))
+    .collect()
+}
+
+fn file_name(path: &Path) -> String {
+  path
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Number of layers offloaded to the GPU, once the model is loaded
+pub fn gpu_layers() -> Option<u32> {
+  let path = model_spec(&model_alias()).ok()?.path;
+  loaded(&path, |l| l.gpu_layers)
+}
+
+/// Name of the model generation uses: the file behind the alias, or the configured backend's
+/// model
+pub fn model_name() -> String {
+  let cfg = config::current();
+  match cfg.backend {
+    config::Backend::Local => model_spec(&model_alias())
+      .map(|spec| file_name(&spec.path))
+      .unwrap_or_default(),
+    config::Backend::Ollama => cfg.ollama_model.clone().unwrap_or_default(),
+    config::Backend::Remote => cfg.remote.model.clone().unwrap_or_default(),
+  }
+}
+
+/// Elapsed time and token budget of the local generation in progress
+pub fn generation_progress() -> Option<(Duration, u32)> {
+  GENERATION
+    .lock()
+    .ok()?
+    .map(|(started, budget)| (started.elapsed(), budget))
+}
+
+/// Observed local generation speed in tokens per second, once something has been generated
+pub fn tokens_per_sec() -> Option<f64> {
+  *TOKENS_PER_SEC.lock().ok()?
+}
+
+fn record_throughput(tokens: u32, elapsed: Duration) {
+  if tokens == 0 || elapsed.is_zero() {
+    return;
+  }
+  let observed = tokens as f64 / elapsed.as_secs_f64();
+  if let Ok(mut rate) = TOKENS_PER_SEC.lock() {
+    *rate = Some(match *rate {
+      Some(previous) => previous * 0.7 + observed * 0.3,
+      None => observed,
+    });
+  }
+}
+
+/// Clears `GENERATION` however the generation ends
+struct GenerationGuard;
+
+impl Drop for GenerationGuard {
+  fn drop(&mut self) {
+    if let Ok(mut generation) = GENERATION.lock() {
+      *generation = None;
+    }
+  }
+}
+
+/// Mark this process as the daemon so helpers never try to connect back to it
+pub fn set_daemon_mode() {
+  DAEMON_MODE.store(true, Ordering::Relaxed);
+}
+
+/// Connect to the daemon unless we are the daemon, or generation happens elsewhere.
+///
+/// A busy daemon is waited for while it should be free within `llm.busy_wait_secs`.
+/// `None` means generate locally, which `llm.prefer_daemon: strict` turns into an error.
+fn daemon() -> Result<Option<daemon_client::DaemonClient>> {
+  if DAEMON_MODE.load(Ordering::Relaxed) || !config::get_backend().is_local() {
+    return Ok(None);
+  }
+  let cfg = config::get_llm_config();
+  let strict = cfg.prefer_daemon == config::PreferDaemon::Strict;
+  let busy_wait = Duration::from_secs(cfg.busy_wait_secs);
+
+  match daemon_client::connect_when_ready(busy_wait, strict) {
+    Ok(client) => Ok(client),
+    Err(e) if strict => Err(e.context(
+      "llm.prefer_daemon is strict, so the model isn't loaded locally.\n\
+       Start the daemon with 'alfred daemon start'",
+    )),
+    Err(_) => Ok(None),
+  }
+}
+
+/// A prompt that leaves too little of the context window for a reply. Callers can
+/// `downcast_ref` it from the returned error and retry with less input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptTooLarge {
+  pub prompt_tokens: u32,
+  pub context_size: u32,
+}
+
+impl std::fmt::Display for PromptTooLarge {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Prompt of {} tokens leaves no room for a reply in the context window of {} tokens. \
+       Raise context_size, or send less",
+      self.prompt_tokens, self.context_size
+    )
+  }
+}
+
+impl std::error::Error for PromptTooLarge {}
+
+/// Prompts may fill at most 90% of the context window; the rest is for the reply
+fn check_prompt_size(prompt_tokens: u32, context_size: u32) -> Result<(), PromptTooLarge> {
+  if prompt_tokens as u64 * 10 > context_size as u64 * 9 {
+    return Err(PromptTooLarge {
+      prompt_tokens,
+      context_size,
+    });
+  }
+  Ok(())
+}
+
+/// Tokens in `text` as the local model reads it, beginning-of-sequence token included,
+/// without running inference. Other backends tokenize on their side, so they get an estimate
+pub fn count_tokens(text: &str) -> Result<u32> {
+  if !config::get_backend().is_local() {
+    return Ok(text.len().div_ceil(CHARS_PER_TOKEN) as u32);
+  }
+  let model = model()?;
+  let tokens = model
+    .str_to_token(text, llama_cpp_2::model::AddBos::Always)
+    .with_context(|| "Failed to tokenize prompt")?;
+  Ok(tokens.len() as u32)
+}
+
+/// Build a prompt from `build(budget)`, halving the budget until the prompt and a
+/// `max_tokens` reply fit the context window. Fails with `PromptTooLarge` when even an
+/// empty budget leaves no room
+fn fit_prompt(budget: usize, max_tokens: u32, build: impl Fn(usize) -> String) -> Result<String> {
+  fit_prompt_with(context_size(), count_tokens, budget, max_tokens, build)
+}
+
+/// `fit_prompt` for a context of `n_ctx` tokens, counting with `count`
+fn fit_prompt_with(
+  n_ctx: u32,
+  count: impl Fn(&str) -> Result<u32>,
+  mut budget: usize,
+  max_tokens: u32,
+  build: impl Fn(usize) -> String,
+) -> Result<String> {
+  loop {
+    let prompt = build(budget);
+    let prompt_tokens = count(&prompt)?;
+    if prompt_tokens + max_tokens <= n_ctx || budget == 0 {
+      check_prompt_size(prompt_tokens, n_ctx)?;
+      return Ok(prompt);
+    }
+    budget /= 2;
+  }
+}
+
+/// Tokens in and out of the model and the time spent generating them. Counts are estimated
+/// from text length on the Ollama and remote backends, which tokenize on their side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+  pub prompt_tokens: u32,
+  pub completion_tokens: u32,
+  pub duration_ms: u64,
+}
+
+impl Usage {
+  fn add(self, other: Usage) -> Usage {
+    Usage {
+      prompt_tokens: self.prompt_tokens.saturating_add(other.prompt_tokens),
+      completion_tokens: self
+        .completion_tokens
+        .saturating_add(other.completion_tokens),
+      duration_ms: self.duration_ms.saturating_add(other.duration_ms),
+    }
+  }
+}
+
+impl std::fmt::Display for Usage {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if *self == Usage::default() {
+      return write!(f, "no generation (cached reply)");
+    }
+    let secs = self.duration_ms as f64 / 1000.0;
+    write!(
+      f,
+      "{} prompt tokens, {} completion tokens in {:.2}s",
+      self.prompt_tokens, self.completion_tokens, secs
+    )?;
+    if secs > 0.0 {
+      write!(f, " ({:.1} tokens/s)", self.completion_tokens as f64 / secs)?;
+    }
+    Ok(())
+  }
+}
+
+/// Run `f`, returning with it what the model did for it on this thread, daemon requests
+/// included. Cached replies count nothing
+pub fn measuring_usage<T>(f: impl FnOnce() -> T) -> (T, Usage) {
+  let previous = USAGE.with(|cell| cell.replace(Usage::default()));
+  let result = f();
+  let measured = USAGE.with(|cell| cell.replace(previous));
+  (result, measured)
+}
+
+/// Count `usage` towards `measuring_usage`; the daemon client adds what each response reports
+pub fn add_usage(usage: Usage) {
+  USAGE.with(|cell| cell.set(cell.get().add(usage)));
+}
+
+/// Run `f` with `sampling` in place of the configured sampling on this thread.
+/// The daemon uses it so each request samples the way its client is configured
+pub fn with_sampling<T>(sampling: Option<SamplingConfig>, f: impl FnOnce() -> T) -> T {
+  let previous = SAMPLING.with(|cell| cell.replace(sampling));
+  let result = f();
+  SAMPLING.with(|cell| *cell.borrow_mut() = previous);
+  result
+}
+
+/// Run `f` with each piece of generated text also passed to `on_token` as it's produced.
+/// The Ollama and remote backends and cached replies pass the whole reply as one piece
+pub fn with_token_callback<T>(on_token: impl FnMut(&str) + 'static, f: impl FnOnce() -> T) -> T {
+  let previous = ON_TOKEN.with(|cell| cell.replace(Some(Box::new(on_token))));
+  let result = f();
+  ON_TOKEN.with(|cell| *cell.borrow_mut() = previous);
+  result
+}
+
+/// Whether generated text is being streamed somewhere
+pub fn streaming() -> bool {
+  ON_TOKEN.with(|cell| cell.borrow().is_some())
+}
+
+/// Pass a piece of generated text to the callback, if there is one
+pub fn emit_token(piece: &str) {
+  ON_TOKEN.with(|cell| {
+    if let Some(on_token) = cell.borrow_mut().as_mut() {
+      on_token(piece);
+    }
+  });
+}
+
+/// Sampling for this generation: the daemon request's, or the config's
+fn sampling() -> SamplingConfig {
+  SAMPLING
+    .with(|cell| *cell.borrow())
+    .unwrap_or_else(config::get_sampling)
 }
 
 /// Generate text locally (used by daemon)
 pub fn generate_local(prompt: &str, max_tokens: u32) -> Result<String> {
-  if !is_loaded() {
-    load_model()?;
+  generate_local_seeded(prompt, max_tokens, sampling().seed)
+}
+
+/// Like `generate_local`, with an explicit sampling seed so repeated runs can differ
+pub fn generate_local_seeded(prompt: &str, max_tokens: u32, seed: u32) -> Result<String> {
+  generate_cached(prompt, max_tokens, seed, None)
+}
+
+/// Like `generate_local`, with the reply constrained to a GBNF grammar whose start rule is
+/// `root`. Only the local backend can enforce it; Ollama and remote APIs ignore it
+pub fn generate_local_with_grammar(prompt: &str, max_tokens: u32, grammar: &str) -> Result<String> {
+  generate_cached(prompt, max_tokens, sampling().seed, Some(grammar))
+}
+
+fn generate_cached(
+  prompt: &str,
+  max_tokens: u32,
+  seed: u32,
+  grammar: Option<&str>,
+) -> Result<String> {
+  let cfg = config::current();
+  let sampling = SamplingConfig { seed, ..sampling() };
+
+  // Every generation ends up here, daemon requests included, so this one check covers them all
+  let key = response_cache::key(&model_name(), prompt, max_tokens, grammar, &sampling);
+  if let Some(reply) = response_cache::get(key) {
+    emit_token(&reply);
+    return Ok(reply);
   }
 
-  let model = MODEL.get().ok_or_else(|| anyhow!("Model not loaded"))?;
-  let backend = get_backend();
+  let reply = infer(&cfg, &sampling, prompt, max_tokens, grammar)?;
+  // An empty reply gets retried with another seed; don't make it stick
+  if !reply.trim().is_empty() {
+    response_cache::put(key, &reply);
+  }
+  Ok(reply)
+}
+
+/// Run the prompt through the configured backend
+fn infer(
+  cfg: &config::Config,
+  sampling: &SamplingConfig,
+  prompt: &str,
+  max_tokens: u32,
+  grammar: Option<&str>,
+) -> Result<String> {
+  let started = Instant::now();
+  // These answer all at once
+  let reply = match cfg.backend {
+    Backend::Ollama => ollama::generate(cfg, sampling, prompt, max_tokens),
+    Backend::Remote => remote::generate(&cfg.remote, sampling, prompt, max_tokens),
+    Backend::Local => infer_local(sampling, prompt, max_tokens, grammar),
+  };
+
+  let mut usage = Usage {
+    duration_ms: started.elapsed().as_millis() as u64,
+    ..Usage::default()
+  };
+  if cfg.backend != Backend::Local {
+    // Tokenized on the other side, so only an estimate
+    usage.prompt_tokens = prompt.len().div_ceil(CHARS_PER_TOKEN) as u32;
+    if let Ok(reply) = &reply {
+      usage.completion_tokens = reply.len().div_ceil(CHARS_PER_TOKEN) as u32;
+      emit_token(reply);
+    }
+  }
+  add_usage(usage);
+  reply
+}
+
+/// Run the prompt through the local model, streaming each piece as it's sampled
+fn infer_local(
+  sampling: &SamplingConfig,
+  prompt: &str,
+  max_tokens: u32,
+  grammar: Option<&str>,
+) -> Result<String> {
+  let model = model()?;
 
-  // Create context
-  let ctx_params = LlamaContextParams::default().with_n_ctx(NonZeroU32::new(2048));
-  let mut ctx = model
-    .new_context(backend, ctx_params)
-    .with_context(|| "Failed to create context")?;
+  let started = Instant::now();
+  if let Ok(mut generation) = GENERATION.lock() {
+    *generation = Some((started, max_tokens));
+  }
+  let _generation = GenerationGuard;
 
   // Tokenize prompt
   let tokens = model
     .str_to_token(prompt, llama_cpp_2::model::AddBos::Always)
     .with_context(|| "Failed to tokenize prompt")?;
 
+  add_usage(Usage {
+    prompt_tokens: tokens.len() as u32,
+    ..Usage::default()
+  });
+
+  let n_ctx = context_size();
+  check_prompt_size(tokens.len() as u32, n_ctx)?;
+
+  // Daemon requests run on whichever thread picks them up, so they don't share a context
+  let (output, n_generated) = if DAEMON_MODE.load(Ordering::Relaxed) {
+    let mut ctx = new_context(&model, n_ctx)?;
+    decode_and_sample(&mut ctx, &model, &tokens, max_tokens, sampling, grammar)?
+  } else {
+    let model = pin(model);
+    CONTEXT.with(|cell| -> Result<(String, u32)> {
+      let mut cached = cell.borrow_mut();
+      // Free a context built for another model or size before allocating the new one
+      if cached
+        .as_ref()
+        .is_some_and(|(m, size, _)| !std::ptr::eq(*m, model) || *size != n_ctx)
+      {
+        *cached = None;
+      }
+      let mut ctx = match cached.take() {
+        Some((_, _, mut ctx)) => {
+          // Positions restart at 0, so nothing from the last prompt may stay in the cache
+          ctx.clear_kv_cache();
+          ctx
+        }
+        None => new_context(model, n_ctx)?,
+      };
+      let result = decode_and_sample(&mut ctx, model, &tokens, max_tokens, sampling, grammar);
+      *cached = Some((model, n_ctx, ctx));
+      result
+    })?
+  };
+
+  record_throughput(n_generated, started.elapsed());
+  add_usage(Usage {
+    completion_tokens: n_generated,
+    ..Usage::default()
+  });
+  Ok(output.trim().to_string())
+}
+
+/// The cached context outlives each generation, so it borrows a model that is never dropped
+fn pin(model: Arc<LlamaModel>) -> &'static LlamaModel {
+  let mut pinned = PINNED_MODELS.lock().unwrap_or_else(|e| e.into_inner());
+  if let Some(&found) = pinned.iter().find(|&&m| std::ptr::eq(m, &*model)) {
+    return found;
+  }
+  let leaked: &'static Arc<LlamaModel> = Box::leak(Box::new(model));
+  pinned.push(leaked);
+  leaked
+}
+
+/// Threads local inference runs on: the configured count, else llama.cpp's default
+pub fn threads() -> u32 {
+  config::get_threads().unwrap_or_else(|| LlamaContextParams::default().n_threads().max(1) as u32)
+}
+
+fn new_context(model: &LlamaModel, n_ctx: u32) -> Result<LlamaContext<'_>> {
+  let mut ctx_params = LlamaContextParams::default().with_n_ctx(NonZeroU32::new(n_ctx));
+  if let Some(n) = config::get_threads() {
+    let n = n.min(i32::MAX as u32) as i32;
+    ctx_params = ctx_params.with_n_threads(n).with_n_threads_batch(n);
+  }
+  model
+    .new_context(get_backend(), ctx_params)
+    .with_context(|| "Failed to create context")
+}
+
+/// KV cache bytes per token of context for a Phi-3 Mini sized model: keys and values for
+/// 32 layers of 3072 f16 values
+const KV_BYTES_PER_TOKEN: u64 = 2 * 32 * 3072 * 2;
+
+/// Context sizes `detect_safe_context_size` chooses between, smallest first
+const CONTEXT_SIZES: [u32; 4] = [2048, 4096, 8192, 16384];
+
+/// The largest context whose KV cache fits in half the memory left once the model is
+/// loaded. Falls back to the smallest size when free memory can't be read
+pub fn detect_safe_context_size() -> u32 {
+  let Some(available) = available_memory() else {
+    return CONTEXT_SIZES[0];
+  };
+  let model_size = if config::get_backend().is_local() {
+    std::fs::metadata(config::get_model_path())
+      .map(|m| m.len())
+      .unwrap_or(0)
+  } else {
+    0
+  };
+  let budget = available.saturating_sub(model_size) / 2;
+
+  CONTEXT_SIZES
+    .iter()
+    .rev()
+    .copied()
+    .find(|&n| n as u64 * KV_BYTES_PER_TOKEN <= budget)
+    .unwrap_or(CONTEXT_SIZES[0])
+}
+
+/// Memory available to new allocations, in bytes, or `None` where the platform doesn't
+/// report it. Windows only reports free memory, which is counted as available
+fn available_memory() -> Option<u64> {
+  let mut system = sysinfo::System::new();
+  system.refresh_memory();
+  Some(system.available_memory()).filter(|&bytes| bytes > 0)
+}
+
+/// Evaluate the prompt from position 0 of an empty context and sample up to `max_tokens`.
+/// Returns the text and how many tokens were generated
+fn decode_and_sample(
+  ctx: &mut LlamaContext<'_>,
+  model: &LlamaModel,
+  tokens: &[LlamaToken],
+  max_tokens: u32,
+  sampling: &SamplingConfig,
+  grammar: Option<&str>,
+) -> Result<(String, u32)> {
   let n_tokens = tokens.len();
+  let n_ctx = ctx.n_ctx() as usize;
 
   // Create batch and add prompt tokens
   let mut batch = LlamaBatch::new(n_tokens.max(1), 1);
@@ -83,13 +774,29 @@ pub fn generate_local(prompt: &str, max_tokens: u32) -> Result<String> {
     .decode(&mut batch)
     .with_context(|| "Failed to decode prompt")?;
 
-  // Setup sampler - chain temperature, top-k, top-p, and distribution samplers
-  let mut sampler = LlamaSampler::chain_simple([
-    LlamaSampler::temp(0.7),
-    LlamaSampler::top_k(40),
-    LlamaSampler::top_p(0.9, 1),
-    LlamaSampler::dist(42),
+  // Setup sampler - chain grammar, repeat penalty, temperature, top-k, top-p, and distribution samplers
+  let mut samplers = Vec::new();
+  if let Some(grammar) = grammar {
+    samplers.push(
+      LlamaSampler::grammar(model, grammar, "root")
+        .map_err(|e| anyhow!("Invalid grammar: {}", e))?,
+    );
+  }
+  if sampling.repeat_penalty != 1.0 {
+    samplers.push(LlamaSampler::penalties(
+      PENALTY_LAST_N,
+      sampling.repeat_penalty as f32,
+      0.0,
+      0.0,
+    ));
+  }
+  samplers.extend([
+    LlamaSampler::temp(sampling.temperature as f32),
+    LlamaSampler::top_k(sampling.top_k as i32),
+    LlamaSampler::top_p(sampling.top_p as f32, 1),
+    LlamaSampler::dist(sampling.seed),
   ]);
+  let mut sampler = LlamaSampler::chain_simple(samplers);
 
   // Generate tokens
   let mut output = String::new();
@@ -97,9 +804,9 @@ pub fn generate_local(prompt: &str, max_tokens: u32) -> Result<String> {
   let mut n_cur = n_tokens;
   let eos_token = model.token_eos();
 
-  while n_generated < max_tokens {
+  while n_generated < max_tokens && n_cur < n_ctx {
     // Sample next token
-    let token = sampler.sample(&ctx, -1);
+    let token = sampler.sample(ctx, -1);
     sampler.accept(token);
 
     // Check for EOS
@@ -109,6 +816,7 @@ pub fn generate_local(prompt: &str, max_tokens: u32) -> Result<String> {
 
     // Convert token to string
     if let Ok(piece) = model.token_to_str(token, llama_cpp_2::model::Special::Tokenize) {
+      emit_token(&piece);
       output.push_str(&piece);
     }
 
@@ -125,13 +833,75 @@ pub fn generate_local(prompt: &str, max_tokens: u32) -> Result<String> {
     n_generated += 1;
   }
 
-  Ok(output.trim().to_string())
+  Ok((output, n_generated))
+}
+
+/// Generate and clean up a reply, retrying with a new seed while the cleaned reply is empty.
+///
+/// Small models occasionally emit nothing but EOS; `generation_retries` bounds the extra attempts.
+fn generate_nonempty(
+  prompt: &str,
+  max_tokens: u32,
+  grammar: Option<&str>,
+  clean: impl Fn(&str) -> String,
+) -> Result<String> {
+  retry_nonempty(
+    config::get_generation_retries(),
+    sampling().seed,
+    |seed| generate_cached(prompt, max_tokens, seed, grammar),
+    clean,
+  )
+}
+
+/// Run `generate` with `seed`, then the following seeds, until `clean` leaves something
+fn retry_nonempty(
+  retries: u32,
+  seed: u32,
+  mut generate: impl FnMut(u32) -> Result<String>,
+  clean: impl Fn(&str) -> String,
+) -> Result<String> {
+  for attempt in 0..=retries {
+    let reply = clean(&generate(seed.wrapping_add(attempt))?);
+    if !reply.trim().is_empty() {
+      return Ok(reply);
+    }
+  }
+  Err(anyhow!(
+    "The model returned an empty response {} time(s); try again or use a larger model",
+    retries + 1
+  ))
+}
+
+/// Split a Phi-3 style prompt into its system and user parts, for backends
+/// that apply their own chat template (Ollama, OpenAI-compatible APIs)
+pub(crate) fn split_chat(prompt: &str) -> (Option<String>, String) {
+  let section = |tag: &str| {
+    let start = prompt.find(tag)? + tag.len();
+    let end = prompt[start..]
+      .find("<|end|>")
+      .map_or(prompt.len(), |e| start + e);
+    Some(prompt[start..end].trim().to_string())
+  };
+
+  match section("<|user|>") {
+    Some(user) => (section("<|system|>"), user),
+    None => (None, prompt.to_string()),
+  }
+}
+
+/// reqwest's blocking client must not run on a tokio worker, so requests go on their own thread
+pub(crate) fn blocking<T: Send + 'static>(
+  f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+  std::thread::spawn(f)
+    .join()
+    .map_err(|_| anyhow!("Ollama request thread panicked"))?
 }
 
 /// Generate text - tries daemon first, falls back to local
 pub fn generate(prompt: &str, max_tokens: u32) -> Result<String> {
   // Try daemon first
-  if let Ok(mut client) = daemon_client::connect() {
+  if let Some(mut client) = daemon()? {
     return client.generate(prompt, max_tokens);
   }
 
@@ -139,137 +909,2573 @@ pub fn generate(prompt: &str, max_tokens: u32) -> Result<String> {
   generate_local(prompt, max_tokens)
 }
 
-pub fn generate_commit_message(diff: &str) -> Result<String> {
+/// Tokens held back from the context window for instructions and the reply
+const PR_RESERVED_TOKENS: u32 = 700;
+
+/// Rough characters per token, used to size prompts before tokenizing
+const CHARS_PER_TOKEN: usize = 3;
+
+/// Characters of diff a prompt can hold: what the context leaves room for, capped by
+/// `max_diff_chars`
+fn prompt_budget() -> usize {
+  (context_size().saturating_sub(PR_RESERVED_TOKENS) as usize * CHARS_PER_TOKEN)
+    .min(config::get_max_diff_chars())
+}
+
+/// Fit commit titles and diffs into `budget` characters.
+///
+/// Every title is kept; the remaining space is shared evenly between diffs,
+/// so one huge commit can't crowd out the rest.
+fn compress_commits(commits: &[(String, String)], budget: usize) -> String {
+  let titles: usize = commits.iter().map(|(t, _)| t.len() + 4).sum();
+  let per_diff = budget.saturating_sub(titles) / commits.len().max(1);
+
+  let mut out = String::new();
+  for (title, diff) in commits {
+    out.push_str(&format!("## {}\n", title));
+    if per_diff > 0 {
+      let mut end = diff.len().min(per_diff);
+      while !diff.is_char_boundary(end) {
+        end -= 1;
+      }
+      out.push_str(&diff[..end]);
+      if end < diff.len() {
+        out.push_str("\n[diff truncated]");
+      }
+      out.push('\n');
+    }
+  }
+  out
+}
+
+/// Generate a Markdown pull request description: a `# Title` line, then the body
+pub fn generate_pr_description(
+  commits: &[(String, String)],
+  issue: Option<&str>,
+) -> Result<String> {
   // Try daemon first
-  if let Ok(mut client) = daemon_client::connect() {
-    return client.generate_commit_message(diff);
+  if let Some(mut client) = daemon()? {
+    return client.generate_pr_description(commits, issue);
   }
 
+  let budget = prompt_budget();
+
   // Fallback to local
   let prompt = format!(
     r#"<|system|>
-You are a helpful assistant that generates concise, conventional git commit messages.
-Follow the conventional commits format: type(scope): description
-Types: feat, fix, docs, style, refactor, test, chore
-Keep the first line under 72 characters.
-Only output the commit message, nothing else.<|end|>
+You are a helpful assistant that writes pull request descriptions.
+Start with a level-one heading holding a concise title, then a Summary section (level-two heading)
+with 1-3 sentences, then a Changes section (level-two heading) with bullet points. Use Markdown.
+Only output the description, nothing else.<|end|>
 <|user|>
-Generate a commit message for this diff:
+{}Write a pull request description for these commits:
 
 {}<|end|>
 <|assistant|>"#,
-    &diff[..diff.len().min(4000)]
+    issue_hint(issue),
+    compress_commits(commits, budget)
   );
 
-  let response = generate_local(&prompt, 100)?;
-  Ok(
-    response
-      .lines()
-      .next()
-      .unwrap_or(&response)
-      .trim()
-      .to_string(),
-  )
+  generate_local(&prompt, 400)
 }
 
-pub fn suggest_conflict_resolution(
-  file: &str,
-  ours: &str,
-  theirs: &str,
-  base: &str,
-) -> Result<String> {
+/// What the model makes of a commit while bisecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BisectClassification {
+  Good,
+  Bad,
+  Skip,
+  /// The commit's changes don't say either way
+  Unknown,
+}
+
+impl BisectClassification {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Good => "good",
+      Self::Bad => "bad",
+      Self::Skip => "skip",
+      Self::Unknown => "unknown",
+    }
+  }
+
+  /// The first word of a reply; anything unrecognized is `Unknown`
+  pub fn parse(reply: &str) -> Self {
+    let word = reply
+      .split(|c: char| !c.is_ascii_alphabetic())
+      .find(|w| !w.is_empty())
+      .unwrap_or_default()
+      .to_lowercase();
+    [Self::Good, Self::Bad, Self::Skip]
+      .into_iter()
+      .find(|c| c.name() == word)
+      .unwrap_or(Self::Unknown)
+  }
+}
+
+/// Judge from a commit's changes whether the regression `symptom` describes is present
+pub fn classify_bisect_step(diff: &str, symptom: &str) -> Result<BisectClassification> {
   // Try daemon first
-  if let Ok(mut client) = daemon_client::connect() {
-    return client.suggest_conflict_resolution(file, ours, theirs, base);
+  if let Some(mut client) = daemon()? {
+    return client
+      .classify_bisect_step(diff, symptom)
+      .map(|reply| BisectClassification::parse(&reply));
   }
 
   // Fallback to local
   let prompt = format!(
     r#"<|system|>
-You are a helpful assistant that resolves git merge conflicts.
-Analyze the conflict and provide a merged result that preserves the intent of both changes.
-Only output the resolved code, no explanations.<|end|>
+You are a helpful assistant that helps find the commit that introduced a regression with git bisect.
+Given a description of the regression and one commit's changes, answer with one word:
+bad - the regression is present at this commit, e.g. these changes introduce it
+good - the regression is clearly not present at this commit
+skip - this commit can't be judged, e.g. it leaves the code broken in an unrelated way
+unknown - the changes don't say either way
+Only output the word, nothing else.<|end|>
 <|user|>
-Resolve this merge conflict in {}:
-
-BASE (original):
-{}
-
-OURS (current branch):
-{}
-
-THEIRS (incoming branch):
-{}
+Regression: {}
 
-Provide the merged result:<|end|>
+Changes:
+{}<|end|>
 <|assistant|>"#,
-    file,
-    &base[..base.len().min(2000)],
-    &ours[..ours.len().min(2000)],
-    &theirs[..theirs.len().min(2000)]
+    symptom,
+    fit_diff(diff, commit_diff_tokens())
   );
 
-  generate_local(&prompt, 500)
+  let grammar = config::get_grammar().then_some(r#"root ::= "good" | "bad" | "skip" | "unknown""#);
+  generate_nonempty(&prompt, 5, grammar, |reply| reply.trim().to_lowercase())
+    .map(|reply| BisectClassification::parse(&reply))
 }
 
-pub fn suggest_rebase_strategy(commits: &[String], onto: &str) -> Result<String> {
+/// A file a cherry-pick may conflict in, as the model sees it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictPrediction {
+  pub file: String,
+  /// From 0 to 1
+  pub likelihood: f32,
+  pub reason: String,
+}
+
+/// Parse `[NN%] path - reason` lines, skipping anything else and files given no chance
+fn parse_conflict_predictions(response: &str) -> Vec<ConflictPrediction> {
+  response
+    .lines()
+    .filter_map(|line| {
+      let line = line.trim().trim_start_matches(['-', '*', ' ']);
+      let (percent, rest) = line.strip_prefix('[')?.split_once(']')?;
+      let percent: f32 = percent.trim().trim_end_matches('%').trim().parse().ok()?;
+      let (file, reason) = rest.trim().split_once(" - ")?;
+      Some(ConflictPrediction {
+        file: file.trim().trim_matches('`').to_string(),
+        likelihood: percent.clamp(0.0, 100.0) / 100.0,
+        reason: reason.trim().to_string(),
+      })
+    })
+    .filter(|p| p.likelihood > 0.0)
+    .collect()
+}
+
+/// Guess which files applying `patch` would conflict in, given those files as they are on
+/// the target branch (`current_files` as path and content)
+pub fn predict_cherry_pick_conflicts(
+  patch: &str,
+  current_files: &[(String, String)],
+) -> Result<Vec<ConflictPrediction>> {
   // Try daemon first
-  if let Ok(mut client) = daemon_client::connect() {
-    return client.suggest_rebase_strategy(commits, onto);
+  if let Some(mut client) = daemon()? {
+    return client.predict_cherry_pick_conflicts(patch, current_files);
   }
 
-  // Fallback to local
+  // The patch and the files it touches share the room evenly
+  let budget = prompt_budget() / 2;
   let prompt = format!(
     r#"<|system|>
-You are a helpful assistant that suggests git rebase strategies.
-Analyze the commits and suggest which ones to squash, reorder, or reword.
-Be concise and provide actionable suggestions.<|end|>
+You are a helpful assistant that predicts merge conflicts before a git cherry-pick.
+Given a commit's patch and the files it touches as they are on the target branch, decide
+for each file whether the patch's hunks still apply: a conflict is likely when the lines a
+hunk changes or its context lines are different or gone on the target branch.
+Write one line per file that might conflict, in exactly this form:
+[likelihood%] path/to/file - short reason
+If no file is likely to conflict, output nothing.<|end|>
 <|user|>
-I'm rebasing these commits onto {}:
-
+Patch:
 {}
 
-Suggest a rebase strategy (squash, reorder, reword):<|end|>
+Files on the target branch:
+{}<|end|>
 <|assistant|>"#,
-    onto,
-    commits.join("\n")
+    diff_truncator::truncate_diff(patch, budget),
+    compress_commits(current_files, budget)
   );
 
-  generate_local(&prompt, 200)
+  let response = generate_local(&prompt, 400)?;
+  let mut predictions = parse_conflict_predictions(&response);
+  // Only the files the patch touches can conflict
+  predictions.retain(|p| current_files.iter().any(|(path, _)| *path == p.file));
+  Ok(predictions)
 }
 
-pub fn suggest_branch_name(description: &str) -> Result<String> {
+/// A paragraph briefing someone on the work in recent commit messages, newest first
+pub fn summarize_log(messages: &[String]) -> Result<String> {
   // Try daemon first
-  if let Ok(mut client) = daemon_client::connect() {
-    return client.suggest_branch_name(description);
+  if let Some(mut client) = daemon()? {
+    return client.summarize_log(messages);
   }
 
+  // Subjects are always kept; bodies share what room is left
+  let commits: Vec<(String, String)> = messages
+    .iter()
+    .map(|m| {
+      let (subject, body) = m.split_once('\n').unwrap_or((m, ""));
+      (subject.trim().to_string(), body.trim().to_string())
+    })
+    .collect();
+
   // Fallback to local
   let prompt = format!(
     r#"<|system|>
-You are a helpful assistant that suggests git branch names.
-Follow conventions: feature/, bugfix/, hotfix/, chore/
-Use kebab-case, keep it short but descriptive.
-Only output the branch name, nothing else.<|end|>
+You are a helpful assistant that briefs developers on recent work in a repository.
+Write one paragraph of 3-5 sentences on what these commits did, grouping related work
+and leading with the most significant changes. Don't list commits one by one.
+Only output the paragraph, nothing else.<|end|>
 <|user|>
-Suggest a branch name for: {}<|end|>
+Summarize the recent work in these commits, newest first:
+
+{}<|end|>
 <|assistant|>"#,
-    description
+    compress_commits(&commits, prompt_budget())
   );
 
-  let response = generate_local(&prompt, 30)?;
-  let name = response
-    .trim()
-    .trim_matches(|c| c == '"' || c == '\'' || c == '`')
-    .lines()
-    .next()
-    .unwrap_or("")
+  generate_local(&prompt, 300)
+}
+
+/// One-line description of work in progress, for `git stash push -m`
+pub fn generate_stash_message(diff: &str) -> Result<String> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.generate_stash_message(diff);
+  }
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that labels stashed work in progress.
+Describe what the changes are doing in one short line (under 60 characters),
+so the stash is easy to recognize later. No type prefix, no trailing period.
+Only output the description, nothing else.<|end|>
+<|user|>
+Describe these uncommitted changes:
+
+{}<|end|>
+<|assistant|>"#,
+    fit_diff(diff, commit_diff_tokens())
+  );
+
+  let response = generate_local(&prompt, 40)?;
+  Ok(
+    response
+      .lines()
+      .find(|l| !l.trim().is_empty())
+      .unwrap_or("")
+      .trim()
+      .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+      .to_string(),
+  )
+}
+
+/// What merging a branch would bring in, as the model sees it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeAnalysis {
+  pub risk: RiskLevel,
+  /// Files the model expects to conflict
+  pub conflict_files: Vec<String>,
+  pub summary: String,
+}
+
+/// Parse the `Risk:`, `Conflicts:` and `Summary:` lines of a merge analysis. A reply
+/// without a risk line counts as medium, and without a summary line is taken whole
+fn parse_merge_analysis(response: &str) -> MergeAnalysis {
+  let mut risk = None;
+  let mut conflict_files = Vec::new();
+  let mut summary = None;
+
+  for line in response.lines() {
+    let Some((key, value)) = line.trim().split_once(':') else {
+      continue;
+    };
+    match key.trim().to_lowercase().as_str() {
+      "risk" => risk = risk.or(RiskLevel::parse(value)),
+      "conflicts" => {
+        conflict_files = value
+          .split(',')
+          .map(|file| file.trim().trim_matches('`'))
+          .filter(|file| !file.is_empty() && !file.eq_ignore_ascii_case("none"))
+          .map(str::to_string)
+          .collect();
+      }
+      "summary" => summary = Some(value.trim().to_string()),
+      _ => {}
+    }
+  }
+
+  MergeAnalysis {
+    risk: risk.unwrap_or(RiskLevel::Medium),
+    conflict_files,
+    summary: summary
+      .filter(|s| !s.is_empty())
+      .unwrap_or_else(|| response.trim().to_string()),
+  }
+}
+
+/// Assess merging `branch`, given the diff of everything it adds since the merge base
+pub fn analyze_merge(branch: &str, diff: &str) -> Result<MergeAnalysis> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.analyze_merge(branch, diff);
+  }
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that reviews a branch before it is merged.
+Given the changes the branch brings in, judge how risky the merge is: low for small,
+self-contained changes, medium for changes to shared code or behavior, high for sweeping
+changes, migrations, or changes to build, CI or security-sensitive code.
+List the files most likely to conflict with other work, such as heavily edited shared files,
+lockfiles and generated files.
+Answer in exactly this form:
+Risk: low|medium|high
+Conflicts: path/one, path/two (or none)
+Summary: one or two sentences on what the merge brings in and what to check<|end|>
+<|user|>
+Branch: {}
+
+Changes:
+{}<|end|>
+<|assistant|>"#,
+    branch,
+    diff_truncator::truncate_diff(diff, prompt_budget())
+  );
+
+  let response = generate_local(&prompt, 300)?;
+  Ok(parse_merge_analysis(&response))
+}
+
+/// Explain what a commit or range changed, keeping the stated intent apart from what the
+/// diff does. `messages` are the commit messages, oldest first; `deleted_tests` are test
+/// files the diff removes, so the model can't miss them in a truncated diff
+pub fn explain_changes(
+  messages: &[String],
+  diff: &str,
+  deleted_tests: &[String],
+  short: bool,
+) -> Result<String> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.explain_changes(messages, diff, deleted_tests, short);
+  }
+
+  // Messages get at most a third of the room; the diff is what's being explained
+  let budget = prompt_budget();
+  let mut listing = String::new();
+  for (i, message) in messages.iter().enumerate() {
+    let entry = format!("- {}\n", message.trim().replace('\n', "\n  "));
+    if i > 0 && listing.len() + entry.len() > budget / 3 {
+      listing.push_str(&format!("- ... and {} more\n", messages.len() - i));
+      break;
+    }
+    listing.push_str(&entry);
+  }
+
+  let shape = if short {
+    "Write one paragraph of at most four sentences covering what the change is meant to do,\n\
+     what it actually does, and anything surprising."
+  } else {
+    "Write four short paragraphs, each starting with its label:\n\
+     Intent: what the commit messages say the change is for.\n\
+     What it does: the concrete changes in the diff.\n\
+     Impact: behavior, callers or users that could be affected.\n\
+     Surprises: anything unexpected, or \"None\"."
+  };
+  let deleted = if deleted_tests.is_empty() {
+    String::new()
+  } else {
+    format!("Test files deleted: {}\n\n", deleted_tests.join(", "))
+  };
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that explains git history to developers new to a codebase.
+{}
+Keep the stated intent (the commit messages) apart from what the diff actually does, and
+say where they disagree. Flag anything surprising, such as deleted or weakened tests, removed
+error handling, or changes the messages don't mention. Be concise and don't repeat the diff back.<|end|>
+<|user|>
+Explain these changes.
+
+Commit messages:
+{}
+{}Diff:
+{}<|end|>
+<|assistant|>"#,
+    shape,
+    listing,
+    deleted,
+    diff_truncator::truncate_diff(diff, budget.saturating_sub(listing.len()))
+  );
+
+  generate_local(&prompt, if short { 150 } else { 400 })
+}
+
+/// How serious a review comment is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Info,
+  Warn,
+  Error,
+}
+
+impl Severity {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name.trim().to_lowercase().as_str() {
+      "info" | "note" => Some(Self::Info),
+      "warn" | "warning" => Some(Self::Warn),
+      "error" | "err" => Some(Self::Error),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Info => "info",
+      Self::Warn => "warn",
+      Self::Error => "error",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewComment {
+  pub file: String,
+  pub line: Option<u32>,
+  pub severity: Severity,
+  pub message: String,
+}
+
+/// Parse `[severity] path:line - message` lines, skipping anything else the model wrote
+fn parse_review(response: &str) -> Vec<ReviewComment> {
+  response
+    .lines()
+    .filter_map(|line| {
+      let line = line.trim().trim_start_matches(['-', '*', ' ']);
+      let (severity, rest) = line.strip_prefix('[')?.split_once(']')?;
+      let severity = Severity::parse(severity)?;
+      let (location, message) = rest.trim().split_once(" - ")?;
+      let (file, line) = match location.trim().rsplit_once(':') {
+        Some((file, n)) if n.parse::<u32>().is_ok() => (file, n.parse().ok()),
+        _ => (location.trim(), None),
+      };
+      Some(ReviewComment {
+        file: file.trim_matches('`').to_string(),
+        line,
+        severity,
+        message: message.trim().to_string(),
+      })
+    })
+    .collect()
+}
+
+/// `--severity` as a minimum, if one was given
+fn minimum_severity(severity_filter: Option<&str>) -> Result<Option<Severity>> {
+  severity_filter
+    .map(|name| {
+      Severity::parse(name)
+        .ok_or_else(|| anyhow!("Unknown severity '{}'. Use error, warn or info", name))
+    })
+    .transpose()
+}
+
+/// Review a diff for mistakes, keeping comments at or above `severity_filter` (e.g. "warn")
+pub fn review_changes(diff: &str, severity_filter: Option<&str>) -> Result<Vec<ReviewComment>> {
+  let minimum = minimum_severity(severity_filter)?;
+
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.review_changes(diff, severity_filter);
+  }
+
+  // Fallback to local
+  review_local(diff, minimum)
+}
+
+/// Review a diff split by `chunk_diff` one piece at a time, so a big diff is reviewed whole
+/// instead of truncated. Comments come back in diff order
+pub fn review_diff(
+  diff_chunks: &[&str],
+  severity_filter: Option<&str>,
+) -> Result<Vec<ReviewComment>> {
+  let minimum = minimum_severity(severity_filter)?;
+
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.review_diff(diff_chunks, severity_filter);
+  }
+
+  // Fallback to local
+  let mut comments = Vec::new();
+  for chunk in diff_chunks {
+    comments.extend(review_local(chunk, minimum)?);
+  }
+  Ok(comments)
+}
+
+fn review_local(diff: &str, minimum: Option<Severity>) -> Result<Vec<ReviewComment>> {
+  let budget = prompt_budget();
+  let focus = match minimum {
+    Some(Severity::Error) => "Only report errors.\n",
+    Some(Severity::Warn) => "Only report errors and warnings.\n",
+    _ => "",
+  };
+
+  let prompt = format!(
+    r#"<|system|>
+You are a careful code reviewer looking at changes before they are committed.
+Point out real problems in the added lines: bugs, crashes, security issues, leftover
+debugging code, typos in identifiers, and missing error handling.
+Severity: error for bugs that will break something, warn for likely mistakes or risky code,
+info for minor suggestions. Don't comment on formatting or praise the code.
+{}Write one comment per line in exactly this form, using the new file's line number:
+[severity] path/to/file:line - comment
+If there is nothing worth reporting, output nothing.<|end|>
+<|user|>
+Review these changes:
+
+{}<|end|>
+<|assistant|>"#,
+    focus,
+    diff_truncator::truncate_diff(diff, budget)
+  );
+
+  let response = generate_local(&prompt, 600)?;
+  let mut comments = parse_review(&response);
+  if let Some(minimum) = minimum {
+    comments.retain(|c| c.severity >= minimum);
+  }
+  Ok(comments)
+}
+
+/// Split a diff into pieces that each fit the context window, breaking between files where possible
+pub fn chunk_diff(diff: &str) -> Vec<String> {
+  let budget = prompt_budget().max(1);
+  let mut chunks = Vec::new();
+  let mut current = String::new();
+
+  for file in diff_sections(diff) {
+    if !current.is_empty() && current.len() + file.len() > budget {
+      chunks.push(std::mem::take(&mut current));
+    }
+    if file.len() <= budget {
+      current.push_str(file);
+      continue;
+    }
+
+    // A single file larger than the budget gets split on line boundaries
+    for line in file.split_inclusive('\n') {
+      if !current.is_empty() && current.len() + line.len() > budget {
+        chunks.push(std::mem::take(&mut current));
+      }
+      current.push_str(line);
+    }
+  }
+
+  if !current.trim().is_empty() {
+    chunks.push(current);
+  }
+  chunks
+}
+
+/// Most diff tokens included in commit and stash prompts, when the context has room
+const COMMIT_DIFF_TOKENS: usize = 1300;
+
+/// Room for the diff in commit and stash prompts
+fn commit_diff_tokens() -> usize {
+  (context_size().saturating_sub(PR_RESERVED_TOKENS) as usize)
+    .min(COMMIT_DIFF_TOKENS)
+    .min(config::get_max_diff_chars() / CHARS_PER_TOKEN)
+}
+
+/// Lockfiles: generated, long, and rarely what a change is about
+const NOISE_FILES: &[&str] = &[
+  "Cargo.lock",
+  "package-lock.json",
+  "npm-shrinkwrap.json",
+  "yarn.lock",
+  "pnpm-lock.yaml",
+  "bun.lockb",
+  "Gemfile.lock",
+  "poetry.lock",
+  "Pipfile.lock",
+  "composer.lock",
+  "go.sum",
+];
+
+/// Minified and generated assets
+const NOISE_SUFFIXES: &[&str] = &[".min.js", ".min.css", ".map"];
+
+fn is_noise_file(path: &str) -> bool {
+  let name = path.rsplit('/').next().unwrap_or(path);
+  NOISE_FILES.contains(&name) || NOISE_SUFFIXES.iter().any(|s| name.ends_with(s))
+}
+
+/// File a diff section changes, from its `diff --git a/... b/...` header
+fn section_path(section: &str) -> Option<&str> {
+  let header = section.lines().next()?.strip_prefix("diff --git ")?;
+  header.rsplit_once(" b/").map(|(_, path)| path)
+}
+
+/// Tokens in `text`, counted with the local model's tokenizer when there is one
+fn measure_tokens(model: Option<&LlamaModel>, text: &str) -> usize {
+  model
+    .and_then(|m| m.str_to_token(text, llama_cpp_2::model::AddBos::Never).ok())
+    .map_or_else(|| text.len().div_ceil(CHARS_PER_TOKEN), |t| t.len())
+}
+
+/// Fit a diff into `max_tokens` for a prompt.
+///
+/// Lockfiles and minified assets are dropped (unless nothing else changed), the budget is
+/// shared fairly between the remaining files, and a closing line counts
+/// the files that were left out.
+pub fn fit_diff(diff: &str, max_tokens: usize) -> String {
+  // The model is needed for the reply anyway; loading it first lets us measure in real tokens
+  let model = if DAEMON_MODE.load(Ordering::Relaxed) && !is_loaded() {
+    None
+  } else {
+    model().ok()
+  };
+  fit_diff_with(model.as_deref(), diff, max_tokens)
+}
+
+/// `fit_diff`, measuring with `model`'s tokenizer or, without one, estimating from length
+fn fit_diff_with(model: Option<&LlamaModel>, diff: &str, max_tokens: usize) -> String {
+  let sections = diff_sections(diff);
+  let (noise, code): (Vec<&str>, Vec<&str>) = sections
+    .iter()
+    .partition(|s| section_path(s).is_some_and(is_noise_file));
+  let (mut kept, mut omitted) = if code.is_empty() {
+    (noise, Vec::new())
+  } else {
+    (code, noise)
+  };
+
+  // Tokenizing is linear; sections far beyond the budget are only estimated
+  let limit = max_tokens * CHARS_PER_TOKEN * 4;
+  let sizes: Vec<usize> = kept
+    .iter()
+    .map(|s| {
+      if s.len() > limit {
+        s.len().div_ceil(CHARS_PER_TOKEN)
+      } else {
+        measure_tokens(model, s)
+      }
+    })
+    .collect();
+
+  // So many files that their headers alone would fill the budget: keep the first ones
+  let mut headers = 0;
+  let fits = kept
+    .iter()
+    .take_while(|s| {
+      headers += measure_tokens(model, s.lines().next().unwrap_or_default()) + 1;
+      headers <= max_tokens / 2
+    })
+    .count()
+    .max(1);
+  if fits < kept.len() {
+    omitted.extend(kept.split_off(fits));
+  }
+  let sizes = &sizes[..kept.len()];
+
+  let summary = match omitted.len() {
+    0 => String::new(),
+    n => {
+      let names: Vec<&str> = omitted.iter().filter_map(|s| section_path(s)).collect();
+      let listed = names.iter().take(5).copied().collect::<Vec<_>>().join(", ");
+      let more = names.len().saturating_sub(5);
+      format!(
+        "[{} other file(s) changed: {}{}]\n",
+        n,
+        listed,
+        if more > 0 {
+          format!(" and {} more", more)
+        } else {
+          String::new()
+        }
+      )
+    }
+  };
+
+  let budget = max_tokens.saturating_sub(measure_tokens(model, &summary));
+  let allowance = share_budget(sizes, budget);
+
+  let mut out = String::new();
+  for ((section, &size), &max) in kept.iter().zip(sizes).zip(&allowance) {
+    // Cut where the token allowance ends, assuming tokens are spread evenly over the bytes
+    let max_bytes = if max >= size {
+      section.len()
+    } else {
+      section.len() * max / size.max(1)
+    };
+    push_cut(&mut out, section, max_bytes);
+  }
+  if !summary.is_empty() && !out.is_empty() && !out.ends_with('\n') {
+    out.push('\n');
+  }
+  out.push_str(&summary);
+  out
+}
+
+/// Split `budget` between items of the given sizes.
+///
+/// Items smaller than an equal share get all they need and the leftover goes to the larger ones
+fn share_budget(sizes: &[usize], budget: usize) -> Vec<usize> {
+  let mut order: Vec<usize> = (0..sizes.len()).collect();
+  order.sort_by_key(|&i| sizes[i]);
+
+  let mut allowance = vec![0; sizes.len()];
+  let mut remaining = budget;
+  for (done, &i) in order.iter().enumerate() {
+    let share = remaining / (sizes.len() - done);
+    allowance[i] = sizes[i].min(share);
+    remaining -= allowance[i];
+  }
+  allowance
+}
+
+/// Append a diff section cut to about `max` bytes at a line boundary, keeping its header line
+fn push_cut(out: &mut String, section: &str, max: usize) {
+  if max >= section.len() {
+    out.push_str(section);
+    return;
+  }
+
+  // Always keep the `diff --git` header so every file is represented
+  let header = section.find('\n').map_or(section.len(), |i| i + 1);
+  let mut end = max;
+  while !section.is_char_boundary(end) {
+    end -= 1;
+  }
+  end = match section[..end].rfind('\n') {
+    Some(newline) if newline + 1 >= header => newline + 1,
+    _ => header,
+  };
+  out.push_str(&section[..end]);
+  if end == section.len() {
+    return;
+  }
+  if !out.ends_with('\n') {
+    out.push('\n');
+  }
+  out.push_str(&format!("[{} more bytes truncated]\n", section.len() - end));
+}
+
+/// Split a diff into per-file sections, each starting at its `diff --git` line
+fn diff_sections(diff: &str) -> Vec<&str> {
+  let mut starts: Vec<usize> = diff
+    .match_indices("\ndiff --git ")
+    .map(|(i, _)| i + 1)
+    .collect();
+  starts.insert(0, 0);
+  starts.push(diff.len());
+  starts.windows(2).map(|w| &diff[w[0]..w[1]]).collect()
+}
+
+/// Summarize a branch's changes as Markdown, reducing chunk summaries until one remains
+pub fn summarize_changes(diff_chunks: &[&str]) -> Result<String> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.summarize_changes(diff_chunks);
+  }
+
+  // Fallback to local
+  let final_prompt = |input: &str| {
+    format!(
+      r#"<|system|>
+You are a helpful assistant that summarizes code changes for reviewers.
+Start with a Summary section (level-two heading) of 1-3 sentences, then a Changes section
+(level-two heading) with bullet points grouped by area. Use Markdown.
+Only output the summary, nothing else.<|end|>
+<|user|>
+Summarize everything these changes do:
+
+{}<|end|>
+<|assistant|>"#,
+      input
+    )
+  };
+
+  if let [chunk] = diff_chunks {
+    return generate_local(&final_prompt(chunk), 500);
+  }
+
+  // Map: condense each chunk into notes
+  let mut notes = Vec::new();
+  for chunk in diff_chunks {
+    let prompt = format!(
+      r#"<|system|>
+You are a helpful assistant that takes notes on code changes.
+Write 3-6 short bullet points covering what this part of a larger diff changes.
+Only output the bullet points, nothing else.<|end|>
+<|user|>
+{}<|end|>
+<|assistant|>"#,
+      chunk
+    );
+    notes.push(generate_local(&prompt, 200)?);
+  }
+
+  // Reduce: merge notes in groups that fit the context until a single set remains
+  let budget = prompt_budget();
+  while notes.iter().map(|n| n.len() + 2).sum::<usize>() > budget && notes.len() > 1 {
+    let mut merged = Vec::new();
+    let mut group = String::new();
+    for note in notes {
+      if !group.is_empty() && group.len() + note.len() > budget {
+        merged.push(std::mem::take(&mut group));
+      }
+      group.push_str(&note);
+      group.push_str("\n\n");
+    }
+    merged.push(group);
+
+    notes = merged
+      .iter()
+      .map(|group| {
+        let prompt = format!(
+          r#"<|system|>
+You are a helpful assistant that condenses notes on code changes.
+Merge these notes into at most 8 bullet points, combining related items.
+Only output the bullet points, nothing else.<|end|>
+<|user|>
+{}<|end|>
+<|assistant|>"#,
+          group
+        );
+        generate_local(&prompt, 300)
+      })
+      .collect::<Result<Vec<_>>>()?;
+  }
+
+  generate_local(&final_prompt(&notes.join("\n\n")), 500)
+}
+
+/// Suggest the next semantic version after `latest_tag` from the commit messages since it
+pub fn suggest_next_version(latest_tag: &str, commits: &[String]) -> Result<String> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.suggest_next_version(latest_tag, commits);
+  }
+
+  let budget = context_size().saturating_sub(PR_RESERVED_TOKENS) as usize * CHARS_PER_TOKEN;
+  let mut listing = String::new();
+  for message in commits {
+    let entry = format!("- {}\n", message.trim().replace('\n', "\n  "));
+    if listing.len() + entry.len() > budget {
+      break;
+    }
+    listing.push_str(&entry);
+  }
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that picks semantic version numbers for releases.
+Use the conventional commit types: a "BREAKING CHANGE" footer or a "!" after the type means
+a major bump, any "feat" means a minor bump, otherwise a patch bump.
+Before 1.0.0, breaking changes bump the minor version instead.
+Keep the tag's prefix (such as "v"). Only output the new version, nothing else.<|end|>
+<|user|>
+The latest tag is {}. Commits since then:
+
+{}
+What is the next version?<|end|>
+<|assistant|>"#,
+    latest_tag, listing
+  );
+
+  let response = generate_local(&prompt, 20)?;
+  response
+    .split_whitespace()
+    .map(|w| w.trim_matches(|c: char| c == '`' || c == '"' || c == '.' || c == ','))
+    .find(|w| {
+      let numbers = w.trim_start_matches(|c: char| !c.is_ascii_digit());
+      numbers.split('.').count() == 3 && numbers.split('.').all(|n| n.parse::<u64>().is_ok())
+    })
+    .map(String::from)
+    .ok_or_else(|| anyhow!("The model didn't suggest a version: {}", response.trim()))
+}
+
+/// Pick the commit a diff most likely fixes; returns its full hash
+pub fn find_fixup_target(diff: &str, commits: &[CommitRecord]) -> Result<String> {
+  if commits.is_empty() {
+    return Err(anyhow!("No commits to choose from"));
+  }
+
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.find_fixup_target(diff, commits);
+  }
+
+  let listing: String = commits
+    .iter()
+    .map(|c| {
+      format!(
+        "{} {}\n",
+        &c.hash[..c.hash.len().min(7)],
+        c.message.lines().next().unwrap_or("")
+      )
+    })
+    .collect();
+  let budget = prompt_budget().saturating_sub(listing.len());
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that matches a fix to the commit it corrects.
+Given a diff and the commits on a branch, pick the one commit this diff fixes or completes:
+the commit that introduced the code being changed, or whose purpose the diff finishes.
+Only output that commit's hash, nothing else.<|end|>
+<|user|>
+Commits:
+{}
+Diff:
+{}<|end|>
+<|assistant|>"#,
+    listing,
+    diff_truncator::truncate_diff(diff, budget)
+  );
+
+  let response = generate_local(&prompt, 20)?;
+  response
+    .split(|c: char| !c.is_ascii_hexdigit())
+    .filter(|word| word.len() >= 7)
+    .find_map(|word| {
+      let word = word.to_lowercase();
+      commits.iter().find(|c| c.hash.starts_with(&word))
+    })
+    .map(|c| c.hash.clone())
+    .ok_or_else(|| {
+      anyhow!(
+        "The model didn't name one of the commits: {}",
+        response.trim()
+      )
+    })
+}
+
+/// Section layout for generated changelog entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangelogFormat {
+  /// keepachangelog.com: Added / Changed / Deprecated / Removed / Fixed / Security
+  KeepAChangelog,
+  /// conventional-changelog: Features / Bug Fixes / Performance / Breaking Changes
+  ConventionalChangelog,
+}
+
+impl ChangelogFormat {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "keep-a-changelog" | "keepachangelog" | "keep" => Some(Self::KeepAChangelog),
+      "conventional-changelog" | "conventional" => Some(Self::ConventionalChangelog),
+      _ => None,
+    }
+  }
+
+  /// Section headings, in the order they appear
+  pub fn headings(&self) -> &'static [&'static str] {
+    match self {
+      Self::KeepAChangelog => &[
+        "Added",
+        "Changed",
+        "Deprecated",
+        "Removed",
+        "Fixed",
+        "Security",
+      ],
+      Self::ConventionalChangelog => &[
+        "Features",
+        "Bug Fixes",
+        "Performance Improvements",
+        "BREAKING CHANGES",
+      ],
+    }
+  }
+
+  fn instructions(&self) -> &'static str {
+    match self {
+      Self::KeepAChangelog => {
+        "Group entries under these level-three headings, omitting empty ones: \
+         Added, Changed, Deprecated, Removed, Fixed, Security."
+      }
+      Self::ConventionalChangelog => {
+        "Group entries under these level-three headings, omitting empty ones: \
+         Features, Bug Fixes, Performance Improvements, BREAKING CHANGES. \
+         Use the conventional commit type to choose the group, and bold the scope if there is one."
+      }
+    }
+  }
+}
+
+/// Most commits put in one changelog prompt, so the reply has room for a bullet each
+const CHANGELOG_CHUNK_COMMITS: usize = 20;
+
+/// Generate changelog sections (no version heading) for the given commits. Long releases
+/// go to the model in chunks whose sections are merged here, so no prompt outgrows the context
+pub fn generate_changelog(commits: &[CommitRecord], format: &ChangelogFormat) -> Result<String> {
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.generate_changelog(commits, format);
+  }
+
+  // Fallback to local
+  let mut parts = Vec::new();
+  for listing in changelog_listings(commits, prompt_budget()) {
+    let prompt = format!(
+      r#"<|system|>
+You are a helpful assistant that writes changelogs for software releases.
+{}
+Each entry is one bullet point: the commit's subject exactly as written, followed by its
+short hash in parentheses. Put every commit under the one heading that fits it best; the
+indented line under a commit is context from its message, not an entry.
+Only output the Markdown sections, nothing else.<|end|>
+<|user|>
+Write changelog entries for these commits:
+
+{}<|end|>
+<|assistant|>"#,
+      format.instructions(),
+      listing
+    );
+    parts.push(generate_local(&prompt, 600)?);
+  }
+
+  Ok(merge_changelog_sections(&parts, format))
+}
+
+/// Commit listings of at most `CHANGELOG_CHUNK_COMMITS` commits and about `budget` characters
+/// each: subject and short hash, and the first line of the body for context
+fn changelog_listings(commits: &[CommitRecord], budget: usize) -> Vec<String> {
+  let mut listings = Vec::new();
+  let mut listing = String::new();
+  let mut count = 0;
+
+  for commit in commits {
+    let mut entry = format!("- {} ({})\n", commit.subject(), commit.short_hash());
+    // Trailers such as `Signed-off-by:` say nothing about the change
+    let context = commit.body().lines().map(str::trim).find(|line| {
+      !line.is_empty()
+        && !line.split_once(": ").is_some_and(|(key, _)| {
+          !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    });
+    if let Some(line) = context {
+      entry.push_str(&format!("  {}\n", head(line, 200)));
+    }
+
+    if count == CHANGELOG_CHUNK_COMMITS || (count > 0 && listing.len() + entry.len() > budget) {
+      listings.push(std::mem::take(&mut listing));
+      count = 0;
+    }
+    listing.push_str(&entry);
+    count += 1;
+  }
+
+  if !listing.is_empty() {
+    listings.push(listing);
+  }
+  listings
+}
+
+/// Combine the sections of several changelog replies: bullets under the same heading are
+/// gathered, in the format's heading order, with headings the format doesn't list last
+fn merge_changelog_sections(parts: &[String], format: &ChangelogFormat) -> String {
+  if let [part] = parts {
+    return part.trim().to_string();
+  }
+
+  let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+  for part in parts {
+    let mut heading = "Changed".to_string();
+    for line in part.lines().map(str::trim) {
+      if let Some(title) = line.strip_prefix('#') {
+        heading = title.trim_start_matches('#').trim().to_string();
+      } else if line.starts_with("- ") || line.starts_with("* ") {
+        let bullet = format!("- {}", &line[2..]);
+        match sections
+          .iter_mut()
+          .find(|(h, _)| h.eq_ignore_ascii_case(&heading))
+        {
+          Some((_, bullets)) if bullets.contains(&bullet) => {}
+          Some((_, bullets)) => bullets.push(bullet),
+          None => sections.push((heading.clone(), vec![bullet])),
+        }
+      }
+    }
+  }
+
+  let order = |heading: &str| {
+    format
+      .headings()
+      .iter()
+      .position(|h| h.eq_ignore_ascii_case(heading))
+      .unwrap_or(usize::MAX)
+  };
+  sections.sort_by_key(|(heading, _)| order(heading));
+  sections
+    .iter()
+    .map(|(heading, bullets)| format!("### {}\n\n{}", heading, bullets.join("\n")))
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+/// Top-level directories that hold code rather than name a component
+const SCOPE_CONTAINERS: &[&str] = &[
+  "src", "lib", "app", "apps", "pkg", "packages", "crates", "internal", "cmd",
+];
+
+/// Derive a conventional-commit scope from the changed paths.
+///
+/// All files under `src/daemon/` give `daemon`; all under `docs/` give `docs`.
+/// Files spread across top-level directories (or at the root) give no scope.
+pub fn infer_scope(files: &[String]) -> Option<String> {
+  let mut dirs = files.iter().map(|f| {
+    let parts: Vec<&str> = f.split('/').collect();
+    let dirs = &parts[..parts.len().saturating_sub(1)];
+    match dirs {
+      [] => None,
+      [top, sub, ..] if SCOPE_CONTAINERS.contains(top) => Some((*top, Some(*sub))),
+      [top, ..] => Some((*top, None)),
+    }
+  });
+
+  let first = dirs.next()??;
+  let mut shared_sub = first.1;
+  for dir in dirs {
+    let (top, sub) = dir?;
+    if top != first.0 {
+      return None;
+    }
+    if sub != shared_sub {
+      shared_sub = None;
+    }
+  }
+
+  match shared_sub {
+    Some(sub) => Some(sub.to_string()),
+    None if SCOPE_CONTAINERS.contains(&first.0) => None,
+    None => Some(first.0.to_string()),
+  }
+}
+
+/// Issue text from the forge, framed as the authoritative intent behind the change
+fn issue_hint(issue: Option<&str>) -> String {
+  issue
+    .map(|i| {
+      format!(
+        "The change implements this issue (treat it as the authoritative intent):\n{}\n\n",
+        i
+      )
+    })
+    .unwrap_or_default()
+}
+
+/// Most structural changes listed in a prompt before summarizing the rest
+const MAX_STRUCTURE_LINES: usize = 40;
+
+/// One line per rename, deletion, mode or symlink change
+fn structure_preamble(structure: &[StructuralChange]) -> String {
+  let mut out = String::new();
+  for change in structure.iter().take(MAX_STRUCTURE_LINES) {
+    let line = match change {
+      StructuralChange::Rename {
+        from,
+        to,
+        similarity,
+      } => format!("renamed {} -> {} ({}% similar)", from, to, similarity),
+      StructuralChange::Delete {
+        path,
+        lines: Some(lines),
+      } => format!("deleted {} ({} lines)", path, lines),
+      StructuralChange::Delete { path, lines: None } => format!("deleted {} (binary)", path),
+      StructuralChange::Mode { path, old, new } => {
+        format!("mode changed {}: {} -> {}", path, old, new)
+      }
+      StructuralChange::Symlink { path, change } => format!("symlink {} {}", path, change),
+    };
+    out.push_str(&format!("- {}\n", line));
+  }
+  if structure.len() > MAX_STRUCTURE_LINES {
+    out.push_str(&format!(
+      "- ... and {} more\n",
+      structure.len() - MAX_STRUCTURE_LINES
+    ));
+  }
+  out
+}
+
+/// Drop the hunks of deleted files; the preamble already lists them
+fn without_deleted_files(diff: &str) -> String {
+  let mut out = String::new();
+  let mut section = String::new();
+  for line in diff.lines() {
+    if line.starts_with("diff --git ") {
+      if !is_deletion(&section) {
+        out.push_str(&section);
+      }
+      section.clear();
+    }
+    section.push_str(line);
+    section.push('\n');
+  }
+  if !is_deletion(&section) {
+    out.push_str(&section);
+  }
+  out
+}
+
+fn is_deletion(section: &str) -> bool {
+  section
+    .lines()
+    .take_while(|l| !l.starts_with("@@"))
+    .any(|l| l.starts_with("deleted file mode"))
+}
+
+fn has_content_hunks(diff: &str) -> bool {
+  diff.lines().any(|l| {
+    l.starts_with("@@") || l.starts_with("Binary files") || l.starts_with("GIT binary patch")
+  })
+}
+
+/// Whether the diff only renames, deletes, or changes modes of files
+pub fn is_structural_only(diff: &str, structure: &[StructuralChange]) -> bool {
+  !structure.is_empty() && !has_content_hunks(&without_deleted_files(diff))
+}
+
+/// Gitmoji for each conventional commit type
+const GITMOJI: &[(&str, &str)] = &[
+  ("feat", "✨"),
+  ("fix", "🐛"),
+  ("docs", "📝"),
+  ("style", "🎨"),
+  ("refactor", "♻️"),
+  ("perf", "⚡️"),
+  ("test", "✅"),
+  ("build", "📦️"),
+  ("ci", "👷"),
+  ("chore", "🔧"),
+  ("revert", "⏪️"),
+];
+
+fn format_rules(style: CommitStyle, examples: &[String], body: bool, max_subject: usize) -> String {
+  let subject = match style {
+    CommitStyle::Conventional => "conventional commits format: type(scope): description\n\
+       Types: feat, fix, docs, style, refactor, test, chore"
+      .to_string(),
+    CommitStyle::Gitmoji => {
+      let emoji: Vec<String> = GITMOJI
+        .iter()
+        .map(|(t, e)| format!("{} {}", t, e))
+        .collect();
+      format!(
+        "gitmoji format: emoji type(scope): description\n\
+         Start with the emoji for the type: {}",
+        emoji.join(", ")
+      )
+    }
+    CommitStyle::Repo if !examples.is_empty() => {
+      let examples: Vec<String> = examples.iter().map(|e| format!("- {}", e)).collect();
+      format!(
+        "format of this repository's recent commit subjects, matching their prefixes, \
+         capitalization and tense:\n{}\n",
+        examples.join("\n")
+      )
+    }
+    // A repository without history to learn from gets plain subjects
+    CommitStyle::Plain | CommitStyle::Repo => {
+      "a plain imperative sentence, e.g. \"Add retry to uploads\", with no type prefix".to_string()
+    }
+  };
+
+  if body {
+    format!(
+      "Line 1 is the subject in {}\n\
+       Keep the subject under {} characters.\n\
+       Then write a blank line, then 2-5 bullet points starting with \"- \" that explain what changed and why.",
+      subject, max_subject
+    )
+  } else {
+    format!(
+      "Follow the {}\nKeep the first line under {} characters.",
+      subject, max_subject
+    )
+  }
+}
+
+/// The conventional type at the start of a subject, e.g. `feat` in `feat(api)!: ...`
+fn commit_type(subject: &str) -> Option<&str> {
+  let end = subject.find([':', '(', '!'])?;
+  let kind = &subject[..end];
+  GITMOJI.iter().any(|(t, _)| *t == kind).then_some(kind)
+}
+
+/// Enforce the commit style on a generated message, whatever the model did.
+///
+/// Gitmoji adds the emoji for the detected type when it's missing; plain drops a type prefix.
+pub fn apply_commit_style(message: &str, style: CommitStyle) -> String {
+  let (subject, rest) = message.split_once('\n').unwrap_or((message, ""));
+  let rest = if rest.is_empty() {
+    String::new()
+  } else {
+    format!("\n{}", rest)
+  };
+
+  match style {
+    CommitStyle::Conventional | CommitStyle::Repo => message.to_string(),
+    CommitStyle::Gitmoji => {
+      let starts_with_emoji = subject.chars().next().is_some_and(|c| !c.is_ascii());
+      let emoji = commit_type(subject).and_then(|t| GITMOJI.iter().find(|(k, _)| *k == t));
+      match emoji {
+        Some((_, emoji)) if !starts_with_emoji => format!("{} {}{}", emoji, subject, rest),
+        _ => message.to_string(),
+      }
+    }
+    CommitStyle::Plain => match (commit_type(subject), subject.split_once(": ")) {
+      (Some(_), Some((_, description))) => {
+        let mut chars = description.chars();
+        let capitalized: String = chars
+          .next()
+          .map(|c| c.to_uppercase().chain(chars).collect())
+          .unwrap_or_default();
+        format!("{}{}", capitalized, rest)
+      }
+      _ => message.to_string(),
+    },
+  }
+}
+
+/// Cut a subject longer than `max` characters at the last word that fits, dropping
+/// punctuation left dangling at the end. Returns the message and whether it was cut
+pub fn limit_subject(message: &str, max: usize) -> (String, bool) {
+  let (subject, rest) = match message.split_once('\n') {
+    Some((subject, rest)) => (subject, Some(rest)),
+    None => (message, None),
+  };
+  if max == 0 || subject.chars().count() <= max {
+    return (message.to_string(), false);
+  }
+
+  let mut cut = String::new();
+  for word in subject.split_whitespace() {
+    let len = cut.chars().count() + usize::from(!cut.is_empty()) + word.chars().count();
+    if len > max {
+      break;
+    }
+    if !cut.is_empty() {
+      cut.push(' ');
+    }
+    cut.push_str(word);
+  }
+  // A first word longer than the limit is cut mid-word rather than dropped
+  if cut.is_empty() {
+    cut = subject.chars().take(max).collect();
+  }
+  let cut = cut.trim_end_matches([',', ';', ':', '-', ' ', '(']);
+
+  let message = match rest {
+    Some(rest) => format!("{}\n{}", cut, rest),
+    None => cut.to_string(),
+  };
+  (message, true)
+}
+
+/// Context for commit message prompts besides the diff itself
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommitHints<'a> {
+  /// Preferred conventional-commit scope
+  pub scope: Option<&'a str>,
+  /// Issue text, treated as the authoritative intent
+  pub issue: Option<&'a str>,
+  /// Which tests changed; only used when the message has a body
+  pub tests: Option<&'a str>,
+  /// Recent subjects from the repository to imitate, which selects the `repo` style
+  pub examples: Option<&'a [String]>,
+}
+
+fn commit_prompt(
+  diff: &str,
+  structure: &[StructuralChange],
+  hints: CommitHints,
+  body: bool,
+  diff_tokens: usize,
+) -> String {
+  let scope_hint = hints
+    .scope
+    .map(|s| format!("Preferred scope: {}\n\n", s))
+    .unwrap_or_default();
+
+  // A subject line has no room for it, but a body can note the change is tested
+  let tests_hint = hints
+    .tests
+    .filter(|_| body)
+    .map(|t| format!("{}\nThe body may briefly mention the test coverage.\n\n", t))
+    .unwrap_or_default();
+
+  // Examples come from the client's repository, so they decide the style even in the daemon
+  let (style, examples) = match hints.examples {
+    Some(examples) => (CommitStyle::Repo, examples),
+    None => (config::get_commit_style(), &[][..]),
+  };
+  let format_rules = format_rules(style, examples, body, config::get_max_subject_length());
+
+  let changes = if structure.is_empty() {
+    format!(
+      "Generate a commit message for this diff:\n\n{}",
+      fit_diff(diff, diff_tokens)
+    )
+  } else if is_structural_only(diff, structure) {
+    format!(
+      "These files were only moved, deleted, or had their permissions changed; no content changed.\n\
+       Describe the restructuring: what was renamed, moved, or removed.\n\n\
+       Generate a commit message for these changes:\n\n{}",
+      structure_preamble(structure)
+    )
+  } else {
+    let diff = without_deleted_files(diff);
+    format!(
+      "Generate a commit message for these changes.\n\nStructural changes:\n{}\nDiff:\n{}",
+      structure_preamble(structure),
+      fit_diff(&diff, diff_tokens)
+    )
+  };
+
+  let context = format!("{}{}{}", issue_hint(hints.issue), scope_hint, tests_hint);
+  prompt_template::prompt(
+    "commit_message",
+    &[
+      ("rules", &format_rules),
+      ("context", &context),
+      ("diff", &changes),
+    ],
+  )
+}
+
+/// Longest shared directory of `paths`, without the file names
+fn common_dir<'a>(paths: impl Iterator<Item = &'a str>) -> String {
+  let mut common: Option<Vec<&str>> = None;
+  for path in paths {
+    let dirs: Vec<&str> = path
+      .rsplit_once('/')
+      .map_or(vec![], |(d, _)| d.split('/').collect());
+    common = Some(match common {
+      None => dirs,
+      Some(prev) => prev
+        .iter()
+        .zip(&dirs)
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| *a)
+        .collect(),
+    });
+  }
+  common.unwrap_or_default().join("/")
+}
+
+fn split_last(dir: &str) -> (&str, &str) {
+  dir.rsplit_once('/').unwrap_or(("", dir))
+}
+
+/// Commit subject derived from structural changes alone, for when the model can't help
+pub fn structural_message(structure: &[StructuralChange]) -> Option<String> {
+  let n = structure.len();
+  if n == 0 {
+    return None;
+  }
+  let renames: Vec<(&str, &str)> = structure
+    .iter()
+    .filter_map(|c| match c {
+      StructuralChange::Rename { from, to, .. } => Some((from.as_str(), to.as_str())),
+      _ => None,
+    })
+    .collect();
+  let deletes: Vec<&str> = structure
+    .iter()
+    .filter_map(|c| match c {
+      StructuralChange::Delete { path, .. } => Some(path.as_str()),
+      _ => None,
+    })
+    .collect();
+  let modes: Vec<(&str, &str)> = structure
+    .iter()
+    .filter_map(|c| match c {
+      StructuralChange::Mode { path, new, .. } => Some((path.as_str(), new.as_str())),
+      _ => None,
+    })
+    .collect();
+
+  let message = if renames.len() == n && n == 1 {
+    let (from, to) = renames[0];
+    if split_last(from).0 == split_last(to).0 {
+      format!(
+        "refactor: rename {} to {}",
+        split_last(from).1,
+        split_last(to).1
+      )
+    } else {
+      format!("refactor: move {} to {}", from, to)
+    }
+  } else if renames.len() == n {
+    let from = common_dir(renames.iter().map(|(f, _)| *f));
+    let to = common_dir(renames.iter().map(|(_, t)| *t));
+    let ((from_parent, from_name), (to_parent, to_name)) = (split_last(&from), split_last(&to));
+    if !from.is_empty() && !to.is_empty() && from_parent == to_parent && from_name != to_name {
+      format!(
+        "refactor: rename module {} to {} ({} files)",
+        from_name, to_name, n
+      )
+    } else if !to.is_empty() && from != to {
+      format!("refactor: move {} files to {}", n, to)
+    } else {
+      format!("refactor: rename {} files", n)
+    }
+  } else if deletes.len() == n && n == 1 {
+    format!("chore: remove {}", deletes[0])
+  } else if deletes.len() == n {
+    match common_dir(deletes.iter().copied()) {
+      dir if dir.is_empty() => format!("chore: remove {} files", n),
+      dir => format!("chore: remove {} ({} files)", dir, n),
+    }
+  } else if modes.len() == n && n == 1 && modes[0].1 == "100755" {
+    format!("chore: make {} executable", modes[0].0)
+  } else if modes.len() == n {
+    format!("chore: change permissions of {} file(s)", n)
+  } else {
+    format!("refactor: restructure {} files", n)
+  };
+
+  Some(message)
+}
+
+/// GBNF for a one-line conventional subject, `type(scope)!: description`, with the types
+/// `commit_rules` accepts. `None` when grammars are off or the style has no type prefix
+pub fn commit_subject_grammar() -> Option<String> {
+  let cfg = config::current();
+  if !cfg.grammar || cfg.commit_style != CommitStyle::Conventional {
+    return None;
+  }
+  let types: Vec<String> = cfg
+    .commit_rules
+    .types
+    .iter()
+    .map(|t| format!("{:?}", t))
+    .collect();
+  Some(format!(
+    r#"root ::= type scope? "!"? ": " description
+type ::= {}
+scope ::= "(" [a-z0-9._/-]+ ")"
+description ::= [^\n\r"'`] [^\n\r]{{0,{}}}
+"#,
+    types.join(" | "),
+    cfg.commit_rules.max_subject_length
+  ))
+}
+
+/// GBNF for a kebab-case branch name starting with one of `prefixes`
+pub fn branch_name_grammar(prefixes: &[String]) -> String {
+  let prefixes: Vec<String> = prefixes.iter().map(|p| format!("{:?}", p)).collect();
+  format!(
+    r#"root ::= prefix word ("-" word){{0,7}}
+prefix ::= {}
+word ::= [a-z0-9]+
+"#,
+    prefixes.join(" | ")
+  )
+}
+
+fn commit_reply(response: &str, body: bool) -> String {
+  if body {
+    format_commit_message(response)
+  } else {
+    response
+      .lines()
+      .next()
+      .unwrap_or(response)
+      .trim()
+      .to_string()
+  }
+}
+
+pub fn generate_commit_message(
+  diff: &str,
+  structure: &[StructuralChange],
+  hints: CommitHints,
+) -> Result<String> {
+  let _model = task_model(config::Task::Commit);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.generate_commit_message(diff, structure, hints);
+  }
+
+  // Fallback to local
+  let hints = CommitHints {
+    tests: None,
+    ..hints
+  };
+  let prompt = fit_prompt(commit_diff_tokens(), 100, |budget| {
+    commit_prompt(diff, structure, hints, false, budget)
+  })?;
+  let grammar = commit_subject_grammar();
+  generate_nonempty(&prompt, 100, grammar.as_deref(), |response| {
+    commit_reply(response, false)
+  })
+}
+
+/// Generate a commit message with a subject line and a bulleted body
+pub fn generate_commit_message_full(
+  diff: &str,
+  structure: &[StructuralChange],
+  hints: CommitHints,
+) -> Result<String> {
+  let _model = task_model(config::Task::Commit);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.generate_commit_message_full(diff, structure, hints);
+  }
+
+  // Fallback to local
+  let prompt = fit_prompt(commit_diff_tokens(), 300, |budget| {
+    commit_prompt(diff, structure, hints, true, budget)
+  })?;
+  generate_nonempty(&prompt, 300, None, |response| commit_reply(response, true))
+}
+
+/// Generate `n` commit messages with different sampling seeds, dropping duplicates
+pub fn generate_commit_candidates(
+  diff: &str,
+  structure: &[StructuralChange],
+  hints: CommitHints,
+  n: usize,
+  body: bool,
+) -> Result<Vec<String>> {
+  let _model = task_model(config::Task::Commit);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.generate_commit_candidates(diff, structure, hints, n, body);
+  }
+
+  // Fallback to local
+  let max_tokens = if body { 300 } else { 100 };
+  let prompt = fit_prompt(commit_diff_tokens(), max_tokens, |budget| {
+    commit_prompt(diff, structure, hints, body, budget)
+  })?;
+  let mut candidates: Vec<String> = Vec::new();
+  let seed = sampling().seed;
+
+  for i in 0..n {
+    let response = generate_local_seeded(&prompt, max_tokens, seed.wrapping_add(i as u32))?;
+    let message = commit_reply(&response, body);
+    if !message.is_empty() && !candidates.contains(&message) {
+      candidates.push(message);
+    }
+  }
+
+  Ok(candidates)
+}
+
+/// Normalize model output into "subject", blank line, body wrapped at 72 columns
+fn format_commit_message(response: &str) -> String {
+  let mut lines = response.lines().map(str::trim_end);
+  let subject = lines
+    .by_ref()
+    .map(str::trim)
+    .find(|l| !l.is_empty())
+    .unwrap_or("")
+    .trim_matches(|c| c == '`' || c == '"')
     .to_string();
 
-  Ok(name)
+  let mut body = Vec::new();
+  for line in lines {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with("```") {
+      continue;
+    }
+    let (marker, text) = match trimmed.strip_prefix("- ").or(trimmed.strip_prefix("* ")) {
+      Some(rest) => ("- ", rest),
+      None => ("", trimmed),
+    };
+    body.extend(wrap(text, marker, 72));
+  }
+
+  if body.is_empty() {
+    subject
+  } else {
+    format!("{}\n\n{}", subject, body.join("\n"))
+  }
+}
+
+/// Wrap `text` to `width`, prefixing the first line with `marker` and indenting the rest to match
+fn wrap(text: &str, marker: &str, width: usize) -> Vec<String> {
+  let indent = " ".repeat(marker.len());
+  let mut lines = Vec::new();
+  let mut current = marker.to_string();
+  let mut has_word = false;
+
+  for word in text.split_whitespace() {
+    if has_word && current.len() + 1 + word.len() > width {
+      lines.push(std::mem::replace(&mut current, indent.clone()));
+      has_word = false;
+    }
+    if has_word {
+      current.push(' ');
+    }
+    current.push_str(word);
+    has_word = true;
+  }
+
+  if has_word {
+    lines.push(current);
+  }
+  lines
+}
+
+/// Which side of a conflict each version came from, as shown to the user
+pub struct ConflictLabels<'a> {
+  pub ours: &'a str,
+  pub theirs: &'a str,
+}
+
+/// The versions of one conflict region, or of a whole file
+pub struct Conflict<'a> {
+  pub ours: &'a str,
+  pub theirs: &'a str,
+  /// Empty when unknown
+  pub base: &'a str,
+  /// Unconflicted lines around a region; empty for a whole file
+  pub before: &'a str,
+  pub after: &'a str,
+}
+
+/// Most characters of each version of a conflicting file put into the prompt
+const CONFLICT_SIDE_CHARS: usize = 2000;
+
+/// The lines around a conflict region, for the model to see where it sits
+fn conflict_context_hint(before: &str, after: &str) -> String {
+  if before.trim().is_empty() && after.trim().is_empty() {
+    return String::new();
+  }
+  format!(
+    "The conflict sits between these unchanged lines, which must not be repeated:\n{}[conflict]\n{}\n",
+    before,
+    after.trim_end()
+  )
+}
+
+/// The first `max` bytes of `text`, cut on a character boundary
+fn head(text: &str, max: usize) -> &str {
+  let mut end = text.len().min(max);
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+  &text[..end]
+}
+
+pub fn suggest_conflict_resolution(
+  file: &str,
+  conflict: &Conflict,
+  labels: &ConflictLabels,
+) -> Result<String> {
+  #[cfg(test)]
+  if let Some(suggestion) =
+    CONFLICT_STUB.with(|cell| cell.borrow().as_ref().map(|suggest| suggest(conflict)))
+  {
+    return suggestion;
+  }
+  let _model = task_model(config::Task::Resolve);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.suggest_conflict_resolution(file, conflict, labels);
+  }
+
+  // Fallback to local; each version gets up to `budget` characters
+  let context = conflict_context_hint(conflict.before, conflict.after);
+  let prompt = fit_prompt(CONFLICT_SIDE_CHARS, 500, |budget| {
+    prompt_template::prompt(
+      "conflict_resolution",
+      &[
+        ("file", file),
+        ("context", &context),
+        ("base", head(conflict.base, budget)),
+        ("ours_label", labels.ours),
+        ("ours", head(conflict.ours, budget)),
+        ("theirs_label", labels.theirs),
+        ("theirs", head(conflict.theirs, budget)),
+      ],
+    )
+  })?;
+
+  generate_local(&prompt, 500)
+}
+
+pub fn suggest_rebase_strategy(commits: &[String], onto: &str) -> Result<String> {
+  let _model = task_model(config::Task::Rebase);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.suggest_rebase_strategy(commits, onto);
+  }
+
+  // Fallback to local
+  let prompt = format!(
+    r#"<|system|>
+You are a helpful assistant that suggests git rebase strategies.
+Analyze the commits and suggest which ones to squash, reorder, or reword.
+Be concise and provide actionable suggestions.<|end|>
+<|user|>
+I'm rebasing these commits onto {}:
+
+{}
+
+Suggest a rebase strategy (squash, reorder, reword):<|end|>
+<|assistant|>"#,
+    onto,
+    commits.join("\n")
+  );
+
+  generate_local(&prompt, 200)
+}
+
+pub fn suggest_branch_name(description: &str, issue: Option<&str>) -> Result<String> {
+  let _model = task_model(config::Task::Branch);
+  // Try daemon first
+  if let Some(mut client) = daemon()? {
+    return client.suggest_branch_name(description, issue);
+  }
+
+  // Fallback to local
+  let prefixes = config::get_branch_prefixes();
+  let prompt = prompt_template::prompt(
+    "branch_name",
+    &[
+      ("prefixes", &prefixes.join(", ")),
+      ("context", &issue_hint(issue)),
+      ("description", description),
+    ],
+  );
+
+  let grammar = config::get_grammar().then(|| branch_name_grammar(&prefixes));
+  generate_nonempty(&prompt, 30, grammar.as_deref(), |response| {
+    response
+      .trim()
+      .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+      .lines()
+      .next()
+      .unwrap_or("")
+      .to_string()
+  })
 }
 
 pub fn unload() {
-  // OnceCell doesn't support removal, but the values will be dropped when the program exits
-  // In a more complex application, we might use a Mutex<Option<T>> instead
+  // The daemon frees the model with `reload_model`; other processes keep it until they exit
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    available_memory, branch_name_grammar, check_prompt_size, commit_prompt,
+    commit_subject_grammar, detect_safe_context_size, fit_diff_with, fit_prompt_with, format_rules,
+    is_noise_file, is_structural_only, limit_subject, push_cut, retry_nonempty, section_path,
+    share_budget, structural_message, structure_preamble, CommitHints, PromptTooLarge,
+    CONTEXT_SIZES,
+  };
+  use crate::config::{self, CommitStyle, Config};
+  use crate::git::StructuralChange;
+  use std::sync::Arc;
+
+  fn prompt(diff: &str, structure: &[StructuralChange]) -> String {
+    config::with_config(Arc::new(Config::default()), || {
+      commit_prompt(diff, structure, CommitHints::default(), false, 2000)
+    })
+  }
+
+  fn rename(from: &str, to: &str) -> StructuralChange {
+    StructuralChange::Rename {
+      from: from.to_string(),
+      to: to.to_string(),
+      similarity: 100,
+    }
+  }
+
+  fn delete(path: &str, lines: Option<usize>) -> StructuralChange {
+    StructuralChange::Delete {
+      path: path.to_string(),
+      lines,
+    }
+  }
+
+  fn mode(path: &str, new: &str) -> StructuralChange {
+    StructuralChange::Mode {
+      path: path.to_string(),
+      old: "100644".to_string(),
+      new: new.to_string(),
+    }
+  }
+
+  #[test]
+  fn preamble_lists_one_line_per_change() {
+    let structure = [
+      StructuralChange::Rename {
+        from: "old dir/my notes.txt".to_string(),
+        to: "new dir/my notes.txt".to_string(),
+        similarity: 92,
+      },
+      delete("gone.txt", Some(3)),
+      delete("logo.png", None),
+      mode("run.sh", "100755"),
+      StructuralChange::Symlink {
+        path: "current".to_string(),
+        change: "retargeted".to_string(),
+      },
+    ];
+    assert_eq!(
+      structure_preamble(&structure),
+      "- renamed old dir/my notes.txt -> new dir/my notes.txt (92% similar)\n\
+       - deleted gone.txt (3 lines)\n\
+       - deleted logo.png (binary)\n\
+       - mode changed run.sh: 100644 -> 100755\n\
+       - symlink current retargeted\n"
+    );
+  }
+
+  #[test]
+  fn long_preambles_are_summarized() {
+    let structure: Vec<_> = (0..45)
+      .map(|i| delete(&format!("f{}.txt", i), Some(1)))
+      .collect();
+    let preamble = structure_preamble(&structure);
+    assert_eq!(preamble.lines().count(), 41);
+    assert!(preamble.ends_with("- ... and 5 more\n"));
+  }
+
+  #[test]
+  fn pure_renames_and_deletions_are_structural_only() {
+    let diff = "diff --git a/a.rs b/src/a.rs\n\
+                similarity index 100%\n\
+                rename from a.rs\n\
+                rename to src/a.rs\n\
+                diff --git a/old.rs b/old.rs\n\
+                deleted file mode 100644\n\
+                index 1111111..0000000\n\
+                --- a/old.rs\n\
+                +++ /dev/null\n\
+                @@ -1 +0,0 @@\n\
+                -fn old() {}\n";
+    let structure = [rename("a.rs", "src/a.rs"), delete("old.rs", Some(1))];
+    assert!(is_structural_only(diff, &structure));
+  }
+
+  #[test]
+  fn edits_alongside_a_rename_are_not_structural_only() {
+    let diff = "diff --git a/a.rs b/src/a.rs\n\
+                similarity index 80%\n\
+                rename from a.rs\n\
+                rename to src/a.rs\n\
+                @@ -1 +1 @@\n\
+                -fn a() {}\n\
+                +fn a() -> u8 { 1 }\n";
+    assert!(!is_structural_only(diff, &[rename("a.rs", "src/a.rs")]));
+    assert!(!is_structural_only(diff, &[]));
+  }
+
+  #[test]
+  fn fallback_messages_describe_the_structure() {
+    let cases: Vec<(Vec<StructuralChange>, &str)> = vec![
+      (
+        vec![rename("src/old name.rs", "src/new name.rs")],
+        "refactor: rename old name.rs to new name.rs",
+      ),
+      (
+        vec![rename("src/util.rs", "lib/util.rs")],
+        "refactor: move src/util.rs to lib/util.rs",
+      ),
+      (
+        vec![
+          rename("src/parser/a.rs", "src/syntax/a.rs"),
+          rename("src/parser/b.rs", "src/syntax/b.rs"),
+        ],
+        "refactor: rename module parser to syntax (2 files)",
+      ),
+      (
+        vec![rename("a.rs", "src/a.rs"), rename("b.rs", "src/b.rs")],
+        "refactor: move 2 files to src",
+      ),
+      (
+        vec![rename("a.rs", "c.rs"), rename("b.rs", "d.rs")],
+        "refactor: rename 2 files",
+      ),
+      (
+        vec![delete("docs/old.md", Some(4))],
+        "chore: remove docs/old.md",
+      ),
+      (
+        vec![delete("docs/a/x.md", Some(1)), delete("docs/b/y.md", None)],
+        "chore: remove docs (2 files)",
+      ),
+      (
+        vec![delete("a.md", Some(1)), delete("b/c.md", Some(1))],
+        "chore: remove 2 files",
+      ),
+      (
+        vec![mode("bin/run", "100755")],
+        "chore: make bin/run executable",
+      ),
+      (
+        vec![mode("bin/run", "100644")],
+        "chore: change permissions of 1 file(s)",
+      ),
+      (
+        vec![rename("a.rs", "b.rs"), delete("c.rs", Some(1))],
+        "refactor: restructure 2 files",
+      ),
+    ];
+    for (structure, expected) in cases {
+      assert_eq!(
+        structural_message(&structure).as_deref(),
+        Some(expected),
+        "{:?}",
+        structure
+      );
+    }
+    assert_eq!(structural_message(&[]), None);
+  }
+
+  #[test]
+  fn structural_only_prompts_describe_the_restructuring() {
+    let diff = "diff --git a/a.rs b/src/a.rs\n\
+                similarity index 100%\n\
+                rename from a.rs\n\
+                rename to src/a.rs\n";
+    let prompt = prompt(diff, &[rename("a.rs", "src/a.rs")]);
+    assert!(prompt.contains("only moved, deleted, or had their permissions changed"));
+    assert!(prompt.contains("- renamed a.rs -> src/a.rs (100% similar)\n"));
+    assert!(!prompt.contains("diff --git"));
+  }
+
+  #[test]
+  fn mixed_prompts_list_the_structure_before_the_diff() {
+    let diff = "diff --git a/lib.rs b/lib.rs\n\
+                @@ -1 +1 @@\n\
+                -fn lib() {}\n\
+                +fn lib() -> u8 { 1 }\n\
+                diff --git a/old.rs b/old.rs\n\
+                deleted file mode 100644\n\
+                @@ -1 +0,0 @@\n\
+                -fn old() {}\n";
+    let prompt = prompt(diff, &[delete("old.rs", Some(1))]);
+    let structure = prompt.find("Structural changes:\n- deleted old.rs (1 lines)\n");
+    let hunks = prompt.find("+fn lib() -> u8 { 1 }");
+    assert!(structure.is_some() && hunks.is_some() && structure < hunks);
+    // The deleted file's lines are summarized, not repeated
+    assert!(!prompt.contains("-fn old() {}"));
+  }
+
+  #[test]
+  fn prompts_without_structure_are_unchanged() {
+    let diff = "diff --git a/lib.rs b/lib.rs\n@@ -1 +1 @@\n-a\n+b\n";
+    let prompt = prompt(diff, &[]);
+    assert!(prompt.contains("Generate a commit message for this diff:"));
+    assert!(!prompt.contains("Structural changes"));
+  }
+
+  fn section(path: &str, line: &str, lines: usize) -> String {
+    let mut section = format!(
+      "diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n@@ -0,0 +1,{1} @@\n",
+      path, lines
+    );
+    for _ in 0..lines {
+      section.push_str(&format!("+{}\n", line));
+    }
+    section
+  }
+
+  fn emoji_diff() -> String {
+    [
+      section("src/big.rs", "let s = \"🎉 ünïcødé ✓\";", 400),
+      section("README.md", "Ship it 🚀", 3),
+      section("src/small.rs", "// ✓", 2),
+    ]
+    .concat()
+  }
+
+  #[test]
+  fn small_files_keep_their_share_and_large_ones_split_the_rest() {
+    assert_eq!(share_budget(&[10, 500, 20, 900], 400), [10, 185, 20, 185]);
+    assert_eq!(share_budget(&[10, 20], 400), [10, 20]);
+    assert_eq!(share_budget(&[], 400), Vec::<usize>::new());
+    assert_eq!(share_budget(&[50, 50], 0), [0, 0]);
+  }
+
+  #[test]
+  fn cuts_never_split_a_character() {
+    let section = section("src/emoji.rs", "🎉✓ü", 20);
+    for max in 0..section.len() {
+      let mut out = String::new();
+      push_cut(&mut out, &section, max);
+      assert!(out.starts_with("diff --git a/src/emoji.rs"), "{}", max);
+      assert!(out.ends_with(" more bytes truncated]\n"), "{}", max);
+      for line in out
+        .lines()
+        .filter(|l| l.starts_with('+') && !l.starts_with("+++"))
+      {
+        assert_eq!(line, "+🎉✓ü");
+      }
+    }
+    let mut out = String::new();
+    push_cut(&mut out, &section, section.len());
+    assert_eq!(out, section);
+  }
+
+  #[test]
+  fn every_file_is_represented_in_a_tight_budget() {
+    let diff = emoji_diff();
+    for max_tokens in [0, 30, 100, 400, 1000] {
+      let fitted = fit_diff_with(None, &diff, max_tokens);
+      // Once even the headers don't fit, the rest are named in the closing line
+      let listed = fitted
+        .lines()
+        .find(|l| l.starts_with('[') && l.contains("other file(s) changed"))
+        .unwrap_or_default();
+      for path in ["src/big.rs", "README.md", "src/small.rs"] {
+        assert!(
+          fitted.contains(&format!("diff --git a/{0} b/{0}", path)) || listed.contains(path),
+          "{} missing at {}",
+          path,
+          max_tokens
+        );
+      }
+    }
+    assert!(fit_diff_with(None, &diff, 0).contains("[2 other file(s) changed: "));
+    // The small files fit whole; only the large one is cut
+    let fitted = fit_diff_with(None, &diff, 400);
+    assert!(fitted.contains("+Ship it 🚀\n"));
+    assert!(fitted.contains("+// ✓\n"));
+    assert_eq!(fitted.matches("more bytes truncated]").count(), 1);
+    assert!(fitted.len() < diff.len());
+  }
+
+  #[test]
+  fn diffs_within_budget_are_unchanged() {
+    let diff = emoji_diff();
+    assert_eq!(fit_diff_with(None, &diff, diff.len()), diff);
+  }
+
+  #[test]
+  fn test_changes_are_hinted_only_when_a_body_is_written() {
+    let diff = "diff --git a/lib.rs b/lib.rs\n@@ -1 +1 @@\n-a\n+b\n";
+    let hints = CommitHints {
+      tests: Some("Tests changed: tests/lib.rs"),
+      ..Default::default()
+    };
+    let prompt = |body| {
+      config::with_config(Arc::new(Config::default()), || {
+        commit_prompt(diff, &[], hints, body, 2000)
+      })
+    };
+    assert!(prompt(true)
+      .contains("Tests changed: tests/lib.rs\nThe body may briefly mention the test coverage."));
+    assert!(!prompt(false).contains("Tests changed"));
+  }
+
+  #[test]
+  fn empty_replies_are_regenerated_with_the_next_seed() {
+    let mut seeds = Vec::new();
+    let reply = retry_nonempty(
+      2,
+      41,
+      |seed| {
+        seeds.push(seed);
+        Ok(
+          if seeds.len() == 1 {
+            " \n"
+          } else {
+            "feat: add x"
+          }
+          .to_string(),
+        )
+      },
+      |r| r.trim().to_string(),
+    );
+    assert_eq!(reply.unwrap(), "feat: add x");
+    assert_eq!(seeds, [41, 42]);
+  }
+
+  #[test]
+  fn retries_run_out_with_a_specific_error() {
+    let mut calls = 0;
+    let err = retry_nonempty(
+      2,
+      u32::MAX,
+      |_| {
+        calls += 1;
+        Ok("```\n```".to_string())
+      },
+      // What's left after cleaning decides, not the raw reply
+      |r| r.replace("```", ""),
+    )
+    .unwrap_err();
+    assert_eq!(calls, 3);
+    assert!(
+      err.to_string().contains("empty response 3 time(s)"),
+      "{}",
+      err
+    );
+  }
+
+  #[test]
+  fn generation_errors_are_not_retried() {
+    let mut calls = 0;
+    let err = retry_nonempty(
+      2,
+      0,
+      |_| {
+        calls += 1;
+        Err(anyhow::anyhow!("model not loaded"))
+      },
+      str::to_string,
+    );
+    assert!(err.is_err());
+    assert_eq!(calls, 1);
+  }
+
+  #[test]
+  fn lockfiles_and_minified_assets_are_noise() {
+    for path in [
+      "Cargo.lock",
+      "web/package-lock.json",
+      "yarn.lock",
+      "go.sum",
+      "static/app.min.js",
+      "static/site.min.css",
+      "dist/app.js.map",
+    ] {
+      assert!(is_noise_file(path), "{}", path);
+    }
+    for path in ["Cargo.toml", "src/lock.rs", "static/app.js", "docs/map.md"] {
+      assert!(!is_noise_file(path), "{}", path);
+    }
+  }
+
+  #[test]
+  fn section_paths_come_from_the_new_side() {
+    assert_eq!(
+      section_path("diff --git a/old name.rs b/new name.rs\n"),
+      Some("new name.rs")
+    );
+    assert_eq!(section_path("@@ -1 +1 @@\n"), None);
+  }
+
+  #[test]
+  fn lockfiles_are_summarized_instead_of_included() {
+    let diff = [
+      section("src/lib.rs", "pub fn x() {}", 5),
+      section("Cargo.lock", "checksum = \"abc\"", 500),
+      section("static/app.min.js", "var a=1;", 50),
+    ]
+    .concat();
+    let fitted = fit_diff_with(None, &diff, 10_000);
+    assert!(fitted.starts_with("diff --git a/src/lib.rs b/src/lib.rs\n"));
+    assert!(!fitted.contains("checksum"));
+    assert!(!fitted.contains("var a=1;"));
+    assert!(fitted.ends_with("[2 other file(s) changed: Cargo.lock, static/app.min.js]\n"));
+  }
+
+  #[test]
+  fn a_lockfile_only_diff_is_kept() {
+    let diff = section("Cargo.lock", "version = \"1.0.2\"", 3);
+    assert_eq!(fit_diff_with(None, &diff, 10_000), diff);
+  }
+
+  #[test]
+  fn the_summary_lists_five_files() {
+    let mut diff = section("src/lib.rs", "x", 1);
+    for i in 0..7 {
+      diff.push_str(&section(&format!("pkg{}/yarn.lock", i), "y", 1));
+    }
+    let fitted = fit_diff_with(None, &diff, 10_000);
+    assert!(fitted.ends_with(
+      "[7 other file(s) changed: pkg0/yarn.lock, pkg1/yarn.lock, pkg2/yarn.lock, \
+       pkg3/yarn.lock, pkg4/yarn.lock and 2 more]\n"
+    ));
+  }
+
+  #[test]
+  fn the_token_budget_is_shared_between_files() {
+    let diff = [
+      section("src/a.rs", "let alpha = 1;", 300),
+      section("src/b.rs", "let beta = 2;", 300),
+      section("src/c.rs", "c", 2),
+    ]
+    .concat();
+    let max_tokens = 600;
+    let fitted = fit_diff_with(None, &diff, max_tokens);
+    // Estimated at three characters a token
+    assert!(fitted.len() <= max_tokens * 3 + 200, "{}", fitted.len());
+    let a = fitted.matches("let alpha").count();
+    let b = fitted.matches("let beta").count();
+    assert!(a > 40 && b > 40, "{} / {}", a, b);
+    assert!(a.abs_diff(b) <= 5, "{} / {}", a, b);
+    assert!(fitted.contains("+c\n+c\n"));
+  }
+
+  /// A word a token, with a BOS token, and a prompt of `budget` words
+  fn words(text: &str) -> anyhow::Result<u32> {
+    Ok(text.split_whitespace().count() as u32 + 1)
+  }
+
+  fn prompt_of(budget: usize) -> String {
+    format!("Describe: {}", "w ".repeat(budget))
+  }
+
+  #[test]
+  fn prompts_that_fit_are_built_once() {
+    let builds = std::cell::Cell::new(0);
+    let prompt = fit_prompt_with(1000, words, 100, 200, |budget| {
+      builds.set(builds.get() + 1);
+      prompt_of(budget)
+    })
+    .unwrap();
+    assert_eq!(prompt, prompt_of(100));
+    assert_eq!(builds.get(), 1);
+  }
+
+  #[test]
+  fn budgets_halve_until_the_reply_fits() {
+    let budgets = std::cell::RefCell::new(Vec::new());
+    let prompt = fit_prompt_with(1000, words, 2000, 200, |budget| {
+      budgets.borrow_mut().push(budget);
+      prompt_of(budget)
+    })
+    .unwrap();
+    // 500 words and the 200-token reply fit in 1000 tokens; 1000 words don't
+    assert_eq!(*budgets.borrow(), [2000, 1000, 500]);
+    assert_eq!(prompt, prompt_of(500));
+  }
+
+  #[test]
+  fn prompts_too_large_even_without_input_are_an_error() {
+    let err = fit_prompt_with(100, words, 64, 20, |_| "w ".repeat(95)).unwrap_err();
+    assert_eq!(
+      err.downcast_ref::<PromptTooLarge>(),
+      Some(&PromptTooLarge {
+        prompt_tokens: 96,
+        context_size: 100
+      })
+    );
+  }
+
+  #[test]
+  fn token_counting_errors_are_returned() {
+    let count = |_: &str| Err(anyhow::anyhow!("no model"));
+    assert!(fit_prompt_with(1000, count, 10, 10, prompt_of).is_err());
+  }
+
+  #[test]
+  fn prompts_may_fill_ninety_percent_of_the_context() {
+    assert!(check_prompt_size(900, 1000).is_ok());
+    assert_eq!(
+      check_prompt_size(901, 1000),
+      Err(PromptTooLarge {
+        prompt_tokens: 901,
+        context_size: 1000
+      })
+    );
+    // Large counts don't overflow
+    assert!(check_prompt_size(u32::MAX, u32::MAX).is_err());
+    assert!(check_prompt_size(u32::MAX / 10 * 9, u32::MAX).is_ok());
+  }
+
+  #[test]
+  fn a_fifty_character_cap_cuts_at_a_word() {
+    let message = "feat(auth): add token refresh for expired sessions in the client\n\nBody stays.";
+    let (cut, shortened) = limit_subject(message, 50);
+    assert!(shortened);
+    assert_eq!(
+      cut,
+      "feat(auth): add token refresh for expired sessions\n\nBody stays."
+    );
+    assert!(cut.lines().next().unwrap().chars().count() <= 50);
+  }
+
+  #[test]
+  fn short_subjects_are_left_alone() {
+    let message = "fix: handle empty diff";
+    assert_eq!(limit_subject(message, 50), (message.to_string(), false));
+    // 0 turns the limit off
+    let long = "x".repeat(200);
+    assert_eq!(limit_subject(&long, 0), (long.clone(), false));
+  }
+
+  #[test]
+  fn cuts_drop_dangling_punctuation_and_count_characters() {
+    assert_eq!(
+      limit_subject("fix: parse -- and handle é edge cases", 14).0,
+      "fix: parse"
+    );
+    assert_eq!(limit_subject("fix: ünïcødé ünïcødé", 12).0, "fix: ünïcødé");
+    // A single word longer than the limit is cut mid-word
+    assert_eq!(limit_subject("abcdefghijkl", 5).0, "abcde");
+  }
+
+  #[test]
+  fn the_prompt_asks_for_the_configured_width() {
+    let rules = format_rules(CommitStyle::Conventional, &[], false, 50);
+    assert!(rules.contains("under 50 characters"), "{}", rules);
+    let rules = format_rules(CommitStyle::Conventional, &[], true, 72);
+    assert!(rules.contains("under 72 characters"), "{}", rules);
+  }
+
+  /// Check GBNF the way llama.cpp's parser would reject it: every rule named once, every
+  /// reference defined, and literals, classes, groups and repetitions closed
+  fn check_gbnf(grammar: &str) -> Result<(), String> {
+    let mut defined = Vec::new();
+    let mut referenced = Vec::new();
+    for line in grammar.lines().filter(|l| !l.trim().is_empty()) {
+      let (name, body) = line
+        .split_once(" ::= ")
+        .ok_or_else(|| format!("not a rule: {}", line))?;
+      if defined.contains(&name) {
+        return Err(format!("{} defined twice", name));
+      }
+      defined.push(name);
+
+      let mut chars = body.chars().peekable();
+      let mut depth = 0;
+      while let Some(c) = chars.next() {
+        match c {
+          '"' | '[' => {
+            let close = if c == '"' { '"' } else { ']' };
+            loop {
+              match chars.next() {
+                Some('\\') => {
+                  chars.next();
+                }
+                Some(c) if c == close => break,
+                Some(_) => {}
+                None => return Err(format!("unclosed {} in {}", c, name)),
+              }
+            }
+          }
+          '{' => {
+            let bounds: String = chars.by_ref().take_while(|&c| c != '}').collect();
+            let (min, max) = bounds.split_once(',').unwrap_or((&bounds, &bounds));
+            let min: usize = min.parse().map_err(|_| format!("bad repeat {}", bounds))?;
+            if !max.is_empty() && max.parse::<usize>().map_err(|e| e.to_string())? < min {
+              return Err(format!("bad repeat {}", bounds));
+            }
+          }
+          '(' => depth += 1,
+          ')' if depth == 0 => return Err(format!("unopened ) in {}", name)),
+          ')' => depth -= 1,
+          '|' | '?' | '*' | '+' | ' ' => {}
+          c if c.is_ascii_alphabetic() => {
+            let mut word = c.to_string();
+            while let Some(&c) = chars
+              .peek()
+              .filter(|c| c.is_ascii_alphanumeric() || **c == '-')
+            {
+              word.push(c);
+              chars.next();
+            }
+            referenced.push(word);
+          }
+          other => return Err(format!("unexpected {:?} in {}", other, name)),
+        }
+      }
+      if depth != 0 {
+        return Err(format!("unclosed ( in {}", name));
+      }
+    }
+    if !defined.contains(&"root") {
+      return Err("no root rule".to_string());
+    }
+    match referenced.iter().find(|r| !defined.contains(&r.as_str())) {
+      Some(missing) => Err(format!("{} is used but not defined", missing)),
+      None => Ok(()),
+    }
+  }
+
+  fn grammar_config(style: CommitStyle, grammar: bool) -> Arc<Config> {
+    let mut cfg = Config {
+      commit_style: style,
+      grammar,
+      ..Config::default()
+    };
+    cfg.commit_rules.types = vec!["feat".to_string(), "fix".to_string()];
+    cfg.commit_rules.max_subject_length = 50;
+    Arc::new(cfg)
+  }
+
+  #[test]
+  fn the_checker_rejects_broken_grammars() {
+    assert!(check_gbnf("root ::= word\n").is_err());
+    assert!(check_gbnf("root ::= \"a\nb ::= \"c\"").is_err());
+    assert!(check_gbnf("root ::= ([a-z]\n").is_err());
+    assert!(check_gbnf("root ::= [a-z]{3,1}\n").is_err());
+    assert!(check_gbnf("start ::= \"a\"\n").is_err());
+    assert!(check_gbnf("root ::= \"\\\"\" [^\\n]{0,5}\n").is_ok());
+  }
+
+  #[test]
+  fn the_commit_subject_grammar_compiles() {
+    let grammar = config::with_config(grammar_config(CommitStyle::Conventional, true), || {
+      commit_subject_grammar()
+    })
+    .unwrap();
+    check_gbnf(&grammar).unwrap();
+    assert!(
+      grammar.contains("type ::= \"feat\" | \"fix\"\n"),
+      "{}",
+      grammar
+    );
+    assert!(grammar.contains("[^\\n\\r]{0,50}"), "{}", grammar);
+    // No leading quote or backtick to strip, and no second line of chatter
+    assert!(
+      grammar.contains("description ::= [^\\n\\r\"'`]"),
+      "{}",
+      grammar
+    );
+  }
+
+  #[test]
+  fn the_commit_grammar_is_off_unless_conventional_and_enabled() {
+    for cfg in [
+      grammar_config(CommitStyle::Conventional, false),
+      grammar_config(CommitStyle::Plain, true),
+    ] {
+      assert_eq!(config::with_config(cfg, commit_subject_grammar), None);
+    }
+  }
+
+  #[test]
+  fn the_branch_grammar_compiles() {
+    let prefixes = ["feature/".to_string(), "bugfix/".to_string()];
+    let grammar = branch_name_grammar(&prefixes);
+    check_gbnf(&grammar).unwrap();
+    assert!(grammar.contains("prefix ::= \"feature/\" | \"bugfix/\"\n"));
+    // Kebab-case words only: no spaces or quotes for sanitizing to mangle
+    assert!(grammar.contains("word ::= [a-z0-9]+\n"));
+  }
+
+  #[test]
+  fn safe_context_sizes_come_from_the_ladder() {
+    assert!(available_memory().is_some_and(|bytes| bytes > 0));
+    assert!(CONTEXT_SIZES.contains(&detect_safe_context_size()));
+  }
 }
@@ -1,16 +1,31 @@
 #![allow(dead_code)]
 
 mod cli;
+mod commit_examples;
 mod config;
+mod conflict;
 mod daemon_client;
+mod daemon_log;
+mod daemon_tls;
+mod diff_filter;
+mod diff_truncator;
+mod forge;
 mod git;
 mod llm;
+mod ollama;
+mod prompt_template;
+mod remote;
+mod response_cache;
+mod risk;
+mod tests_changed;
 mod ui;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use std::process::ExitCode;
 
+use cli::dispatch::Dispatch;
+
 #[derive(Parser)]
 #[command(
   name = "alfred",
@@ -22,21 +37,85 @@ struct Cli {
   #[command(subcommand)]
   command: Option<Commands>,
 
-  /// Arguments to pass through to git
-  #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-  git_args: Vec<String>,
+  /// Answer every prompt with its default, for scripts and CI
+  #[arg(short = 'y', long, global = true)]
+  yes: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
   /// Download AI model and configure alfred
-  Setup,
+  Setup {
+    /// Model to install by filename or alias, skipping the menu
+    #[arg(long, conflicts_with = "url")]
+    model: Option<String>,
+
+    /// Download a .gguf model from this URL instead of the built-in list
+    #[arg(long)]
+    url: Option<String>,
+
+    /// Filename to save a --url model as (default: taken from the URL)
+    #[arg(long, requires = "url")]
+    name: Option<String>,
+
+    /// Resume an interrupted setup
+    #[arg(long)]
+    resume: bool,
+
+    /// Offload all model layers to the GPU (CUDA/Metal)
+    #[arg(long)]
+    gpu: bool,
+  },
 
   /// Generate AI commit message from staged changes
   Commit {
     /// Edit the message before committing
     #[arg(short, long)]
     edit: bool,
+
+    /// Generate only a subject line, without a body
+    #[arg(long, alias = "no-body")]
+    short: bool,
+
+    /// Generate N candidate messages to choose from
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=10))]
+    count: u8,
+
+    /// Issue to use as context (default: the number in the branch name)
+    #[arg(long)]
+    issue: Option<String>,
+
+    /// Don't fetch issue context from GitHub/GitLab
+    #[arg(long)]
+    no_forge: bool,
+
+    /// Regenerate the last commit's message (includes staged changes)
+    #[arg(long)]
+    amend: bool,
+
+    /// Add a Signed-off-by trailer from git's user.name and user.email
+    #[arg(short, long)]
+    signoff: bool,
+
+    /// Add a Co-authored-by trailer (repeatable)
+    #[arg(long = "co-author", value_name = "NAME <EMAIL>")]
+    co_authors: Vec<String>,
+
+    /// Sample the example subjects for commit_style: repo from history again
+    #[arg(long)]
+    refresh_style: bool,
+
+    /// Print token counts and how long generation took
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Commit a high-risk change without the extra confirmation, e.g. with --yes or in CI
+    #[arg(long)]
+    allow_high_risk: bool,
+
+    /// Write the message into this file without prompting (for the prepare-commit-msg hook)
+    #[arg(long, hide = true, value_name = "MSG_FILE")]
+    hook: Option<String>,
   },
 
   /// Interactive rebase with AI suggestions
@@ -51,12 +130,106 @@ enum Commands {
     /// Alias for --ai
     #[arg(long)]
     suggest: bool,
+
+    /// Print token counts and how long generation took
+    #[arg(short, long)]
+    verbose: bool,
   },
 
   /// AI-assisted merge conflict resolution
   Resolve {
     /// Specific file to resolve
     file: Option<String>,
+
+    /// Resolve each file in one suggestion instead of one conflict at a time
+    #[arg(long)]
+    whole_file: bool,
+
+    /// Print the suggested resolutions and a summary without changing any file; exits with
+    /// 2 unless every conflict got one
+    #[arg(long, visible_alias = "dry-run")]
+    check: bool,
+  },
+
+  /// Find the commit that introduced a regression, with the model suggesting each verdict
+  Bisect {
+    /// A revision with the regression (default: HEAD)
+    #[arg(long)]
+    bad: Option<String>,
+
+    /// A revision without it (default: the latest tag before --bad)
+    #[arg(long)]
+    good: Option<String>,
+
+    /// What the regression looks like (asked for when omitted)
+    #[arg(long)]
+    symptom: Option<String>,
+  },
+
+  /// Cherry-pick commits or ranges with conflict assistance
+  CherryPick {
+    /// Commits or ranges (a..b) to pick, in order
+    #[arg(required_unless_present = "undo")]
+    refs: Vec<String>,
+
+    /// Check out this branch before picking
+    #[arg(long)]
+    onto: Option<String>,
+
+    /// Don't append "(cherry picked from commit ...)" to messages
+    #[arg(long)]
+    no_record_origin: bool,
+
+    /// Reset to where the last cherry-pick sequence started
+    #[arg(long, conflicts_with_all = ["refs", "onto"])]
+    undo: bool,
+
+    /// Predict which files will conflict first; pick without asking when none will
+    #[arg(long, conflicts_with = "undo")]
+    predict_conflicts: bool,
+  },
+
+  /// Explain what a commit or range does and why
+  Explain {
+    /// Commit (hash, branch, HEAD~2, ...) or range (main..feature)
+    target: String,
+
+    /// One paragraph instead of the full breakdown
+    #[arg(short, long)]
+    short: bool,
+  },
+
+  /// Commit staged changes as a fixup of the branch commit they most likely fix
+  Fixup {
+    /// Branch this one started from (default: main or master)
+    #[arg(long)]
+    base: Option<String>,
+  },
+
+  /// Review uncommitted changes for mistakes before committing
+  Review {
+    /// Review only staged changes
+    #[arg(long)]
+    staged: bool,
+
+    /// Review the whole branch diff since it left this base branch
+    #[arg(long, value_name = "BASE", conflicts_with_all = ["staged", "strict"])]
+    branch: Option<String>,
+
+    /// Lowest severity to report: error, warn or info
+    #[arg(long)]
+    severity: Option<String>,
+
+    /// Fail high-risk changes that don't include test changes, and report one severity lower
+    /// for them
+    #[arg(long)]
+    strict: bool,
+  },
+
+  /// Stash changes with an AI-generated description
+  Stash {
+    #[command(subcommand)]
+    subcmd: Option<StashCommands>,
   },
 
   /// Smart branch management
@@ -65,15 +238,168 @@ enum Commands {
     subcmd: Option<BranchCommands>,
   },
 
+  /// Generate pull request descriptions
+  Pr {
+    #[command(subcommand)]
+    action: PrAction,
+  },
+
+  /// Summarize everything the current branch changes
+  Summarize {
+    /// Branch to compare against (default: main or master)
+    #[arg(long)]
+    base: Option<String>,
+
+    /// Show the diffstat and how the diff was split
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Also write the summary to a file
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+
+  /// Show recent commits with an AI summary of the work (only with --ai; otherwise git log)
+  Log {
+    /// Commits to show and summarize
+    #[arg(short = 'n', long, default_value_t = 10)]
+    count: usize,
+
+    /// Summarize the commits after the log
+    #[arg(long)]
+    ai: bool,
+
+    /// git log --format for the log itself
+    #[arg(long)]
+    format: Option<String>,
+  },
+
+  /// Merge a branch after an AI assessment of what it brings in (only with --ai; otherwise git merge)
+  Merge {
+    /// Branch to merge into the current one
+    branch: String,
+
+    /// Assess the merge before running it
+    #[arg(long)]
+    ai: bool,
+
+    /// Merge strategy, as git merge --strategy
+    #[arg(short, long)]
+    strategy: Option<String>,
+  },
+
+  /// Generate CHANGELOG.md entries from commit history
+  Changelog {
+    /// Commits to include, as <from>..<to> (default: latest tag..HEAD)
+    #[arg(conflicts_with_all = ["from", "to"])]
+    range: Option<String>,
+
+    /// Start of the range (default: latest tag)
+    #[arg(long)]
+    from: Option<String>,
+
+    /// End of the range (default: HEAD)
+    #[arg(long)]
+    to: Option<String>,
+
+    /// keep-a-changelog (default) or conventional
+    #[arg(long)]
+    format: Option<String>,
+
+    /// Print the entry instead of writing CHANGELOG.md
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Add the entry to this file instead of CHANGELOG.md
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+
+    /// Group commits by conventional type without the model
+    #[arg(long)]
+    no_ai: bool,
+  },
+
+  /// Create a release tag, suggesting the next semantic version
+  Tag {
+    /// Tag name (suggested from commits since the last tag when omitted)
+    name: Option<String>,
+
+    /// Annotation message (default: "Release <name>")
+    #[arg(short, long)]
+    message: Option<String>,
+
+    /// GPG-sign the tag
+    #[arg(short, long)]
+    sign: bool,
+  },
+
+  /// Install git hooks that check and draft commit messages
+  #[command(alias = "hook")]
+  Hooks {
+    #[command(subcommand)]
+    subcmd: Option<HooksCommands>,
+  },
+
+  /// Check a commit message file against commit_rules (used by the commit-msg hook)
+  #[command(hide = true)]
+  LintCommit {
+    /// File holding the message, as git passes it to the hook
+    file: String,
+  },
+
   /// Configure alfred settings
   Config {
     /// Set custom model path
     #[arg(long)]
     model: Option<String>,
 
+    /// Number of model layers to offload to the GPU (0 = CPU only)
+    #[arg(long)]
+    gpu_layers: Option<u32>,
+
+    /// Context window in tokens, or 'auto' to pick one that fits in free memory
+    #[arg(long, value_name = "TOKENS|auto")]
+    context_size: Option<cli::commands::config::ContextSizeArg>,
+
+    /// Threads for local inference, or 'auto' for half the logical CPUs
+    #[arg(long, value_name = "N|auto")]
+    threads: Option<cli::commands::config::ThreadsArg>,
+
+    /// Sampling temperature, 0 to 2 (lower is more predictable)
+    #[arg(long)]
+    temperature: Option<f64>,
+
+    /// Sample only from the k most likely tokens
+    #[arg(long)]
+    top_k: Option<u32>,
+
+    /// Sample only from the most likely tokens covering this probability, 0 to 1
+    #[arg(long)]
+    top_p: Option<f64>,
+
+    /// Sampling seed; the same seed and prompt give the same output
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Penalty for repeating recent tokens (1.0 = off)
+    #[arg(long)]
+    repeat_penalty: Option<f64>,
+
+    /// Generate a task with a model alias from `models`, as TASK=ALIAS (repeatable)
+    #[arg(long = "task", value_name = "TASK=ALIAS")]
+    tasks: Vec<cli::commands::config::TaskModelArg>,
+
     /// Reset configuration to defaults
     #[arg(long)]
     reset: bool,
+
+    /// Print the resolved configuration as JSON
+    #[arg(long, conflicts_with_all = ["model", "gpu_layers", "context_size", "threads", "temperature", "top_k", "top_p", "seed", "repeat_penalty", "tasks", "reset"])]
+    json: bool,
+
+    /// Open a prompt template in $EDITOR (commit_message, conflict_resolution, branch_name)
+    #[arg(long, value_name = "NAME", exclusive = true)]
+    edit_prompt: Option<String>,
   },
 
   /// Manage the alfred daemon (keeps model loaded for fast inference)
@@ -81,6 +407,34 @@ enum Commands {
     #[command(subcommand)]
     action: Option<DaemonAction>,
   },
+
+  /// Show or clear the cache of model replies
+  Cache {
+    #[command(subcommand)]
+    action: Option<CacheAction>,
+  },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+  /// Forget every cached reply
+  Clear,
+}
+
+#[derive(Subcommand)]
+enum HooksCommands {
+  /// Write git hooks that run alfred (default: commit-msg)
+  Install {
+    /// Hooks to install
+    #[arg(value_enum)]
+    hooks: Vec<cli::commands::hooks::Hook>,
+
+    /// Replace existing hooks alfred didn't write
+    #[arg(short, long)]
+    force: bool,
+  },
+  /// Remove the hooks alfred installed
+  Uninstall,
 }
 
 #[derive(Subcommand)]
@@ -91,12 +445,119 @@ enum DaemonAction {
   Stop,
   /// Check daemon status
   Status,
+  /// Run a daemon in the foreground with request logging (for development)
+  Run {
+    /// Port to listen on; 0 picks a free one
+    #[arg(long, default_value_t = 0)]
+    port: u16,
+
+    /// Model file to load instead of the configured one
+    #[arg(long)]
+    model: Option<String>,
+
+    /// error, warn, info or debug
+    #[arg(long, default_value = "debug")]
+    log_level: String,
+  },
+  /// Show the daemon log
+  Logs {
+    /// Keep printing entries as they're written
+    #[arg(short, long)]
+    follow: bool,
+
+    /// Entries to show
+    #[arg(short = 'n', long, default_value_t = 50)]
+    lines: usize,
+
+    /// Print the raw JSON entries, one per line
+    #[arg(long)]
+    json: bool,
+  },
+  /// Replace the TLS certificate, restarting a running daemon
+  RegenCert,
+  /// Replace the auth token clients must send
+  RotateToken,
   /// Install as system service (launchd on macOS, systemd on Linux)
   Install,
   /// Uninstall system service
   Uninstall,
 }
 
+#[derive(Subcommand)]
+enum PrAction {
+  /// Generate a description and open the pull request with gh (or glab)
+  Create {
+    /// Branch to merge into (default: main or master)
+    #[arg(long)]
+    base: Option<String>,
+
+    /// Issue to use as context (default: the number in the branch name)
+    #[arg(long)]
+    issue: Option<String>,
+
+    /// Don't fetch issue context from GitHub/GitLab
+    #[arg(long)]
+    no_forge: bool,
+
+    /// Open as a draft
+    #[arg(long)]
+    draft: bool,
+  },
+  /// Print a description for the current branch
+  Describe {
+    /// Branch to compare against (default: main or master)
+    #[arg(long)]
+    base: Option<String>,
+
+    /// Issue to use as context (default: the number in the branch name)
+    #[arg(long)]
+    issue: Option<String>,
+
+    /// Don't fetch issue context from GitHub/GitLab
+    #[arg(long)]
+    no_forge: bool,
+
+    /// Also write the description to a temp file and print its path
+    #[arg(long)]
+    file: bool,
+
+    /// Also copy the description to the clipboard
+    #[arg(long)]
+    copy: bool,
+  },
+}
+
+#[derive(Subcommand)]
+enum StashCommands {
+  /// Stash local changes, describing them with AI unless -m is given
+  Push {
+    /// Use this message instead of generating one
+    #[arg(short, long)]
+    message: Option<String>,
+  },
+
+  /// List stash entries
+  #[command(alias = "ls")]
+  List {
+    /// Summarize what each entry contains
+    #[arg(long)]
+    ai: bool,
+  },
+
+  /// Pick a stash entry and pop it after summarizing it
+  Pop,
+
+  /// Find stash entries whose changes contain some text
+  Find {
+    /// Text to look for in added and removed lines, ignoring case
+    text: String,
+  },
+
+  /// Any other `git stash` subcommand is passed through
+  #[command(external_subcommand)]
+  Git(Vec<String>),
+}
+
 #[derive(Subcommand)]
 enum BranchCommands {
   /// Create new branch with AI-suggested name
@@ -104,6 +565,18 @@ enum BranchCommands {
   New {
     /// Branch name (optional, will prompt if not provided)
     name: Option<String>,
+
+    /// What the branch is for, to suggest a name from without prompting
+    #[arg(short, long, conflicts_with = "name")]
+    description: Option<String>,
+
+    /// Issue to use as context for the suggested name
+    #[arg(long)]
+    issue: Option<String>,
+
+    /// Don't fetch issue context from GitHub/GitLab
+    #[arg(long)]
+    no_forge: bool,
   },
 
   /// Delete merged branches
@@ -112,6 +585,14 @@ enum BranchCommands {
     /// Delete without confirmation
     #[arg(short, long)]
     force: bool,
+
+    /// List the branches that would be deleted, and delete nothing
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Also delete merged branches from their remote
+    #[arg(long)]
+    remote: bool,
   },
 
   /// List branches
@@ -120,6 +601,21 @@ enum BranchCommands {
     /// Show remote branches too
     #[arg(short, long)]
     all: bool,
+
+    /// Order branches by last commit date (newest first) or name
+    #[arg(long, value_enum, default_value = "name")]
+    sort: cli::commands::branch::BranchSort,
+  },
+
+  /// List branches without recent commits
+  Stale {
+    /// Days since the last commit that make a branch stale
+    #[arg(long, default_value = "30")]
+    days: u32,
+
+    /// Offer to delete the stale branches
+    #[arg(long)]
+    clean: bool,
   },
 }
 
@@ -134,57 +630,346 @@ async fn main() -> ExitCode {
   }
 }
 
+/// Subcommand names and aliases alfred handles itself
+fn alfred_commands() -> Vec<String> {
+  let mut names = vec!["help".to_string()];
+  for cmd in Cli::command().get_subcommands() {
+    names.push(cmd.get_name().to_string());
+    names.extend(cmd.get_all_aliases().map(String::from));
+  }
+  names
+}
+
 async fn run() -> Result<i32> {
-  let cli = Cli::parse();
+  let args: Vec<String> = std::env::args_os()
+    .skip(1)
+    .map(|a| a.to_string_lossy().into_owned())
+    .collect();
 
-  // If no subcommand and we have git args, pass through to git
-  if cli.command.is_none() {
-    if cli.git_args.is_empty() {
-      // No args at all, show our custom help
-      cli::commands::help::run();
-      return Ok(0);
+  // Anything that isn't an alfred command goes to git verbatim
+  let cli = match cli::dispatch::dispatch(args, &alfred_commands()) {
+    Dispatch::Git(args) => return git::passthrough(&args),
+    Dispatch::Alfred { args, globals } => {
+      cli::dispatch::apply(&globals)?;
+      Cli::parse_from(std::iter::once("alfred".to_string()).chain(args))
     }
-    // Pass through to git
-    let code = git::passthrough(&cli.git_args)?;
-    return Ok(code);
-  }
+  };
+
+  ui::set_assume_yes(cli.yes);
+  config::check_env()?;
+
+  let Some(command) = cli.command else {
+    // No args at all, show our custom help
+    cli::commands::help::run();
+    return Ok(0);
+  };
 
   // Handle alfred commands
-  match cli.command.unwrap() {
-    Commands::Setup => {
-      cli::commands::setup::run().await?;
+  match command {
+    Commands::Setup {
+      model,
+      url,
+      name,
+      resume,
+      gpu,
+    } => {
+      let opts = cli::commands::setup::SetupOptions {
+        model,
+        yes: cli.yes,
+        resume,
+        gpu,
+        url,
+        name,
+      };
+      return cli::commands::setup::run(opts).await;
     }
-    Commands::Commit { edit } => {
+    Commands::Commit {
+      edit,
+      short,
+      count,
+      issue,
+      no_forge,
+      amend,
+      signoff,
+      co_authors,
+      refresh_style,
+      verbose,
+      allow_high_risk,
+      hook,
+    } => {
       ensure_git_repo()?;
-      cli::commands::commit::run(edit)?;
+      let opts = cli::commands::commit::CommitOptions {
+        edit,
+        short,
+        count: count as usize,
+        issue,
+        forge: !no_forge,
+        amend,
+        signoff,
+        co_authors,
+        refresh_style,
+        verbose,
+        allow_high_risk,
+      };
+      match hook {
+        Some(file) => cli::commands::commit::prepare_message(&file, &opts)?,
+        None => cli::commands::commit::run(opts)?,
+      }
     }
-    Commands::Rebase { onto, ai, suggest } => {
+    Commands::Rebase {
+      onto,
+      ai,
+      suggest,
+      verbose,
+    } => {
       ensure_git_repo()?;
-      cli::commands::rebase::run(onto, ai || suggest)?;
+      cli::commands::rebase::run(onto, ai || suggest, verbose)?;
     }
-    Commands::Resolve { file } => {
+    Commands::Resolve {
+      file,
+      whole_file,
+      check,
+    } => {
       ensure_git_repo()?;
-      cli::commands::resolve::run(file)?;
+      let opts = cli::commands::resolve::ResolveOptions {
+        file,
+        whole_file,
+        check,
+      };
+      return cli::commands::resolve::run(opts);
+    }
+    Commands::Bisect { bad, good, symptom } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::bisect::BisectOptions { bad, good, symptom };
+      cli::commands::bisect::run(opts)?;
+    }
+    Commands::CherryPick {
+      refs,
+      onto,
+      no_record_origin,
+      undo,
+      predict_conflicts,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::cherry_pick::CherryPickOptions {
+        refs,
+        onto,
+        record_origin: !no_record_origin,
+        undo,
+        predict_conflicts,
+      };
+      cli::commands::cherry_pick::run(opts)?;
+    }
+    Commands::Explain { target, short } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::explain::ExplainOptions { target, short };
+      cli::commands::explain::run(opts)?;
+    }
+    Commands::Fixup { base } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::fixup::FixupOptions { base };
+      cli::commands::fixup::run(opts)?;
+    }
+    Commands::Review {
+      staged,
+      branch,
+      severity,
+      strict,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::review::ReviewOptions {
+        staged,
+        branch,
+        severity,
+        strict,
+      };
+      return cli::commands::review::run(opts);
+    }
+    Commands::Stash { subcmd } => {
+      ensure_git_repo()?;
+      match subcmd {
+        Some(StashCommands::Push { message }) => cli::commands::stash::push(message)?,
+        None => cli::commands::stash::push(None)?,
+        Some(StashCommands::List { ai }) => cli::commands::stash::list(ai)?,
+        Some(StashCommands::Pop) => cli::commands::stash::pop()?,
+        Some(StashCommands::Find { text }) => cli::commands::stash::find(&text)?,
+        Some(StashCommands::Git(args)) => {
+          let mut git_args = vec!["stash".to_string()];
+          git_args.extend(args);
+          return git::passthrough(&git_args);
+        }
+      }
     }
     Commands::Branch { subcmd } => {
       ensure_git_repo()?;
       match subcmd {
-        Some(BranchCommands::New { name }) => {
-          cli::commands::branch::new_branch(name)?;
+        Some(BranchCommands::New {
+          name,
+          description,
+          issue,
+          no_forge,
+        }) => {
+          cli::commands::branch::new_branch(name, description, issue, !no_forge)?;
         }
-        Some(BranchCommands::Clean { force }) => {
-          cli::commands::branch::clean(force)?;
+        Some(BranchCommands::Clean {
+          force,
+          dry_run,
+          remote,
+        }) => {
+          cli::commands::branch::clean(force, dry_run, remote)?;
         }
-        Some(BranchCommands::List { all }) => {
-          cli::commands::branch::list(all)?;
+        Some(BranchCommands::List { all, sort }) => {
+          cli::commands::branch::list(all, sort)?;
+        }
+        Some(BranchCommands::Stale { days, clean }) => {
+          cli::commands::branch::stale(days, clean)?;
         }
         None => {
           cli::commands::branch::show_help();
         }
       }
     }
-    Commands::Config { model, reset } => {
-      cli::commands::config::run(model, reset)?;
+    Commands::Pr { action } => {
+      ensure_git_repo()?;
+      match action {
+        PrAction::Create {
+          base,
+          issue,
+          no_forge,
+          draft,
+        } => {
+          let opts = cli::commands::pr::PrOptions {
+            base,
+            issue,
+            forge: !no_forge,
+          };
+          cli::commands::pr::create(opts, draft)?;
+        }
+        PrAction::Describe {
+          base,
+          issue,
+          no_forge,
+          file,
+          copy,
+        } => {
+          let opts = cli::commands::pr::PrOptions {
+            base,
+            issue,
+            forge: !no_forge,
+          };
+          cli::commands::pr::describe(opts, file, copy)?;
+        }
+      }
+    }
+    Commands::Summarize {
+      base,
+      verbose,
+      output,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::summarize::SummarizeOptions {
+        base,
+        verbose,
+        output,
+      };
+      cli::commands::summarize::run(opts)?;
+    }
+    Commands::Log { count, ai, format } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::log::LogOptions { count, ai, format };
+      return cli::commands::log::run(opts);
+    }
+    Commands::Merge {
+      branch,
+      ai,
+      strategy,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::merge::MergeOptions {
+        branch,
+        ai,
+        strategy,
+      };
+      return cli::commands::merge::run(opts);
+    }
+    Commands::Changelog {
+      range,
+      from,
+      to,
+      format,
+      dry_run,
+      output,
+      no_ai,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::changelog::ChangelogOptions {
+        range,
+        from,
+        to,
+        format,
+        dry_run,
+        output,
+        no_ai,
+      };
+      cli::commands::changelog::run(opts)?;
+    }
+    Commands::Tag {
+      name,
+      message,
+      sign,
+    } => {
+      ensure_git_repo()?;
+      let opts = cli::commands::tag::TagOptions {
+        name,
+        message,
+        sign,
+      };
+      cli::commands::tag::run(opts)?;
+    }
+    Commands::Hooks { subcmd } => {
+      ensure_git_repo()?;
+      match subcmd {
+        Some(HooksCommands::Install { hooks, force }) => {
+          cli::commands::hooks::install(hooks, force)?
+        }
+        Some(HooksCommands::Uninstall) => cli::commands::hooks::uninstall()?,
+        None => cli::commands::hooks::show_help(),
+      }
+    }
+    Commands::LintCommit { file } => {
+      return cli::commands::hooks::lint_commit(&file);
+    }
+    Commands::Config {
+      model,
+      gpu_layers,
+      context_size,
+      threads,
+      temperature,
+      top_k,
+      top_p,
+      seed,
+      repeat_penalty,
+      tasks,
+      reset,
+      json,
+      edit_prompt,
+    } => {
+      let opts = cli::commands::config::ConfigOptions {
+        model,
+        gpu_layers,
+        context_size,
+        threads,
+        temperature,
+        top_k,
+        top_p,
+        seed,
+        repeat_penalty,
+        tasks,
+        reset,
+        json,
+        edit_prompt,
+      };
+      cli::commands::config::run(opts)?;
     }
     Commands::Daemon { action } => match action {
       Some(DaemonAction::Start) => {
@@ -196,6 +981,26 @@ async fn run() -> Result<i32> {
       Some(DaemonAction::Status) => {
         cli::commands::daemon::status()?;
       }
+      Some(DaemonAction::Run {
+        port,
+        model,
+        log_level,
+      }) => {
+        return cli::commands::daemon::run_foreground(port, model, &log_level);
+      }
+      Some(DaemonAction::Logs {
+        follow,
+        lines,
+        json,
+      }) => {
+        cli::commands::daemon::logs(lines, follow, json)?;
+      }
+      Some(DaemonAction::RegenCert) => {
+        cli::commands::daemon::regen_cert()?;
+      }
+      Some(DaemonAction::RotateToken) => {
+        cli::commands::daemon::rotate_token()?;
+      }
       Some(DaemonAction::Install) => {
         cli::commands::daemon::install()?;
       }
@@ -206,6 +1011,10 @@ async fn run() -> Result<i32> {
         cli::commands::daemon::show_help();
       }
     },
+    Commands::Cache { action } => match action {
+      Some(CacheAction::Clear) => cli::commands::cache::clear()?,
+      None => cli::commands::cache::status(),
+    },
   }
 
   Ok(0)
@@ -219,3 +1028,228 @@ fn ensure_git_repo() -> Result<()> {
   }
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cli::dispatch::GlobalOption;
+
+  fn parse(args: &[&str]) -> Cli {
+    Cli::try_parse_from(std::iter::once("alfred").chain(args.iter().copied())).unwrap()
+  }
+
+  #[test]
+  fn setup_takes_a_model_and_runs_unattended() {
+    let cli = parse(&["setup", "--model", "qwen-coder", "--yes"]);
+    assert!(cli.yes);
+    let Some(Commands::Setup { model, resume, .. }) = cli.command else {
+      panic!("not setup");
+    };
+    assert_eq!(model.as_deref(), Some("qwen-coder"));
+    assert!(!resume);
+
+    let cli = parse(&["-y", "setup", "--resume"]);
+    assert!(cli.yes);
+    assert!(matches!(
+      cli.command,
+      Some(Commands::Setup {
+        resume: true,
+        model: None,
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn setup_model_and_url_are_exclusive() {
+    let both = [
+      "alfred",
+      "setup",
+      "--model",
+      "phi3",
+      "--url",
+      "https://x/m.gguf",
+    ];
+    assert!(Cli::try_parse_from(both).is_err());
+    assert!(Cli::try_parse_from(["alfred", "setup", "--name", "m.gguf"]).is_err());
+  }
+
+  fn route(line: &str) -> Dispatch {
+    let args = line.split_whitespace().map(String::from).collect();
+    cli::dispatch::dispatch(args, &alfred_commands())
+  }
+
+  fn git(line: &str) -> Dispatch {
+    Dispatch::Git(line.split_whitespace().map(String::from).collect())
+  }
+
+  fn alfred(line: &str, globals: Vec<GlobalOption>) -> Dispatch {
+    Dispatch::Alfred {
+      args: line.split_whitespace().map(String::from).collect(),
+      globals,
+    }
+  }
+
+  #[test]
+  fn git_invocations_reach_git_verbatim() {
+    for line in [
+      "-c core.pager=cat log",
+      "-c core.pager=cat -c color.ui=never log --oneline -5",
+      "--no-pager diff HEAD~1",
+      "-C ../other status -sb",
+      "--git-dir=/srv/x.git --work-tree /srv/x status",
+      "-p log",
+      "--bare rev-parse --git-dir",
+      "log --oneline --graph",
+      "log --oneline -- --ai",
+      "merge feature",
+      "bisect good",
+      "bisect start HEAD v1.0",
+      "push origin main",
+      "frobnicate --all",
+      "-c",
+    ] {
+      assert_eq!(route(line), git(line), "{}", line);
+    }
+  }
+
+  #[test]
+  fn escape_hatches_force_git() {
+    assert_eq!(route("git branch -a"), git("branch -a"));
+    assert_eq!(route("git commit -m wip"), git("commit -m wip"));
+    assert_eq!(route("-- stash list"), git("stash list"));
+    assert_eq!(route("git"), git(""));
+  }
+
+  #[test]
+  fn alfred_commands_always_win() {
+    for line in [
+      "commit",
+      "stash --help",
+      "branch --help",
+      "log --ai",
+      "merge --ai feature",
+      "bisect",
+      "help commit",
+      "--version",
+      "-h",
+      "-y commit",
+      "-y",
+      "",
+    ] {
+      assert_eq!(route(line), alfred(line, vec![]), "{}", line);
+    }
+  }
+
+  #[test]
+  fn global_options_before_alfred_commands_are_split_out() {
+    assert_eq!(
+      route("-C /tmp/repo commit"),
+      alfred("commit", vec![GlobalOption::Dir("/tmp/repo".into())])
+    );
+    assert_eq!(
+      route("-c user.name=Ann --no-pager hook install"),
+      alfred(
+        "hook install",
+        vec![
+          GlobalOption::Config("user.name=Ann".into()),
+          GlobalOption::NoPager
+        ]
+      )
+    );
+    assert_eq!(
+      route("-y --git-dir=/r/.git --work-tree /r commit"),
+      alfred(
+        "-y commit",
+        vec![
+          GlobalOption::GitDir("/r/.git".into()),
+          GlobalOption::WorkTree("/r".into())
+        ]
+      )
+    );
+    assert_eq!(
+      route("--namespace=ns commit"),
+      alfred(
+        "commit",
+        vec![GlobalOption::Other(vec!["--namespace=ns".into()])]
+      )
+    );
+    // Alfred's own flag isn't a git option
+    assert_eq!(route("-y push"), git("push"));
+  }
+
+  #[test]
+  fn routed_alfred_arguments_parse() {
+    for line in [
+      "-y -C /r commit",
+      "-c a=b log --ai",
+      "--no-pager hook install",
+    ] {
+      let Dispatch::Alfred { args, .. } = route(line) else {
+        panic!("{} went to git", line);
+      };
+      let args: Vec<&str> = args.iter().map(String::as_str).collect();
+      parse(&args);
+    }
+  }
+
+  #[test]
+  fn unsupported_globals_point_at_the_escape_hatch() {
+    let err = cli::dispatch::apply(&[GlobalOption::Other(vec!["--bare".into()])])
+      .unwrap_err()
+      .to_string();
+    assert!(err.contains("alfred git --bare"), "{}", err);
+  }
+
+  #[test]
+  fn resolve_check_has_a_dry_run_alias() {
+    for flag in ["--check", "--dry-run"] {
+      let Some(Commands::Resolve { file, check, .. }) = parse(&["resolve", flag, "a.rs"]).command
+      else {
+        panic!("not resolve");
+      };
+      assert!(check);
+      assert_eq!(file.as_deref(), Some("a.rs"));
+    }
+  }
+
+  #[test]
+  fn stash_subcommands_parse_and_others_pass_through() {
+    let stash = |args: &[&str]| {
+      let Some(Commands::Stash { subcmd }) = parse(args).command else {
+        panic!("not stash");
+      };
+      subcmd
+    };
+    assert!(stash(&["stash"]).is_none());
+    assert!(matches!(
+      stash(&["stash", "push", "-m", "wip"]),
+      Some(StashCommands::Push { message: Some(m) }) if m == "wip"
+    ));
+    assert!(matches!(
+      stash(&["stash", "ls", "--ai"]),
+      Some(StashCommands::List { ai: true })
+    ));
+    assert!(matches!(
+      stash(&["stash", "find", "check_token"]),
+      Some(StashCommands::Find { text }) if text == "check_token"
+    ));
+    assert!(matches!(
+      stash(&["stash", "drop", "stash@{1}"]),
+      Some(StashCommands::Git(args)) if args == ["drop", "stash@{1}"]
+    ));
+  }
+
+  #[test]
+  fn commit_takes_allow_high_risk_with_yes() {
+    let cli = parse(&["commit", "--yes", "--allow-high-risk"]);
+    assert!(cli.yes);
+    assert!(matches!(
+      cli.command,
+      Some(Commands::Commit {
+        allow_high_risk: true,
+        ..
+      })
+    ));
+  }
+}
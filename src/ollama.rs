@@ -0,0 +1,107 @@
+//! Generation through an existing Ollama server
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::config::{Config, SamplingConfig};
+use crate::llm::{blocking, split_chat};
+
+/// Generation can take a while on CPU-only machines
+const GENERATE_TIMEOUT: Duration = Duration::from_secs(600);
+
+const LIST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+  response: String,
+}
+
+#[derive(Deserialize)]
+struct TagsResponse {
+  models: Vec<TagModel>,
+}
+
+#[derive(Deserialize)]
+struct TagModel {
+  name: String,
+}
+
+fn unreachable(url: &str, e: impl std::fmt::Display) -> anyhow::Error {
+  anyhow!(
+    "Failed to reach Ollama at {}: {}\nCheck that Ollama is running ('ollama serve')",
+    url,
+    e
+  )
+}
+
+pub fn generate(
+  cfg: &Config,
+  sampling: &SamplingConfig,
+  prompt: &str,
+  max_tokens: u32,
+) -> Result<String> {
+  let model = cfg
+    .ollama_model
+    .clone()
+    .ok_or_else(|| anyhow!("No Ollama model configured. Run 'alfred setup'"))?;
+  let url = cfg.ollama_url.trim_end_matches('/').to_string();
+  let (system, prompt) = split_chat(prompt);
+
+  let body = serde_json::json!({
+      "model": model,
+      "prompt": prompt,
+      "system": system,
+      "stream": false,
+      "options": {
+          "num_predict": max_tokens,
+          "num_ctx": cfg.context_size,
+          "seed": sampling.seed,
+          "temperature": sampling.temperature,
+          "top_k": sampling.top_k,
+          "top_p": sampling.top_p,
+          "repeat_penalty": sampling.repeat_penalty
+      }
+  });
+
+  blocking(move || {
+    let client = reqwest::blocking::Client::builder()
+      .timeout(GENERATE_TIMEOUT)
+      .build()?;
+    let response = client
+      .post(format!("{}/api/generate", url))
+      .json(&body)
+      .send()
+      .map_err(|e| unreachable(&url, e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+      let text = response.text().unwrap_or_default();
+      return Err(anyhow!("Ollama returned {}: {}", status, text.trim()));
+    }
+
+    let parsed: GenerateResponse = response
+      .json()
+      .with_context(|| "Invalid response from Ollama")?;
+    Ok(parsed.response.trim().to_string())
+  })
+}
+
+/// Names of the models the Ollama server has pulled
+pub fn list_models(url: &str) -> Result<Vec<String>> {
+  let url = url.trim_end_matches('/').to_string();
+
+  blocking(move || {
+    let client = reqwest::blocking::Client::builder()
+      .timeout(LIST_TIMEOUT)
+      .build()?;
+    let tags: TagsResponse = client
+      .get(format!("{}/api/tags", url))
+      .send()
+      .map_err(|e| unreachable(&url, e))?
+      .error_for_status()?
+      .json()
+      .with_context(|| "Invalid response from Ollama")?;
+    Ok(tags.models.into_iter().map(|m| m.name).collect())
+  })
+}
@@ -0,0 +1,123 @@
+//! Prompt templates, overridable from `~/.alfred/prompts/`
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+
+/// Templates alfred ships, by name
+const BUILTIN: &[(&str, &str)] = &[
+  ("commit_message", include_str!("prompts/commit_message.txt")),
+  (
+    "conflict_resolution",
+    include_str!("prompts/conflict_resolution.txt"),
+  ),
+  ("branch_name", include_str!("prompts/branch_name.txt")),
+];
+
+/// Names of the templates that can be overridden
+pub fn names() -> impl Iterator<Item = &'static str> {
+  BUILTIN.iter().map(|(name, _)| *name)
+}
+
+pub fn prompts_dir() -> PathBuf {
+  config::alfred_dir().join("prompts")
+}
+
+/// The built-in text of a template
+pub fn builtin(name: &str) -> Result<&'static str> {
+  BUILTIN
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|(_, text)| *text)
+    .ok_or_else(|| {
+      anyhow!(
+        "Unknown prompt template '{}'. Available: {}",
+        name,
+        names().collect::<Vec<_>>().join(", ")
+      )
+    })
+}
+
+/// `~/.alfred/prompts/<name>.txt` or `.md`, whichever exists first
+pub fn override_path(name: &str) -> Option<PathBuf> {
+  ["txt", "md"]
+    .iter()
+    .map(|ext| prompts_dir().join(format!("{}.{}", name, ext)))
+    .find(|path| path.exists())
+}
+
+/// The template to use for `name`: the user's override, else the built-in one
+pub fn load_template(name: &str) -> Result<String> {
+  let builtin = builtin(name)?;
+  match override_path(name) {
+    Some(path) => {
+      let text = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read prompt template {}", path.display()))?;
+      // Editors add a final newline the built-in templates don't have
+      Ok(text.trim_end_matches(['\n', '\r']).to_string())
+    }
+    None => Ok(builtin.to_string()),
+  }
+}
+
+/// The `{{name}}` placeholders a template uses, in order of first appearance
+pub fn placeholders(template: &str) -> Vec<&str> {
+  let mut names = Vec::new();
+  let mut rest = template;
+  while let Some(start) = rest.find("{{") {
+    rest = &rest[start + 2..];
+    let Some(end) = rest.find("}}") else {
+      break;
+    };
+    let name = rest[..end].trim();
+    if !names.contains(&name) {
+      names.push(name);
+    }
+    rest = &rest[end + 2..];
+  }
+  names
+}
+
+/// Replace each `{{name}}` with its value in one pass, so values are never substituted
+/// into themselves. Unknown placeholders are left as they are
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+  let mut out = String::with_capacity(template.len());
+  let mut rest = template;
+
+  while let Some(start) = rest.find("{{") {
+    out.push_str(&rest[..start]);
+    let after = &rest[start + 2..];
+    let value = after.find("}}").and_then(|end| {
+      let name = after[..end].trim();
+      vars
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| (*value, end))
+    });
+    match value {
+      Some((value, end)) => {
+        out.push_str(value);
+        rest = &after[end + 2..];
+      }
+      None => {
+        out.push_str("{{");
+        rest = after;
+      }
+    }
+  }
+
+  out.push_str(rest);
+  out
+}
+
+/// Load and render a template; a broken override falls back to the built-in one
+pub fn prompt(name: &str, vars: &[(&str, &str)]) -> String {
+  let template = load_template(name).unwrap_or_else(|_| {
+    builtin(name)
+      .expect("prompt names are built in")
+      .to_string()
+  });
+  render(&template, vars)
+}
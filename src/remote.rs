@@ -0,0 +1,98 @@
+//! Generation through an OpenAI-compatible chat-completions API
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::config::{RemoteConfig, SamplingConfig, API_KEY_ENV};
+use crate::llm::{blocking, split_chat};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Deserialize)]
+struct ChatResponse {
+  choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+  message: Message,
+}
+
+#[derive(Deserialize)]
+struct Message {
+  content: Option<String>,
+}
+
+/// Chat-completions APIs have no top-k or repeat penalty, so only temperature, top-p and
+/// the seed are sent
+pub fn generate(
+  cfg: &RemoteConfig,
+  sampling: &SamplingConfig,
+  prompt: &str,
+  max_tokens: u32,
+) -> Result<String> {
+  let api_base = cfg
+    .api_base
+    .as_deref()
+    .ok_or_else(|| {
+      anyhow!("No API base configured for the remote backend. Set remote.api_base in config.yaml")
+    })?
+    .trim_end_matches('/')
+    .to_string();
+  let model = cfg.model.clone().ok_or_else(|| {
+    anyhow!("No model configured for the remote backend. Set remote.model in config.yaml")
+  })?;
+  let key = cfg.key().ok_or_else(|| {
+    anyhow!(
+      "No API key for the remote backend. Set {} or remote.api_key in config.yaml",
+      API_KEY_ENV
+    )
+  })?;
+
+  let (system, user) = split_chat(prompt);
+  let mut messages = Vec::new();
+  if let Some(system) = system {
+    messages.push(serde_json::json!({ "role": "system", "content": system }));
+  }
+  messages.push(serde_json::json!({ "role": "user", "content": user }));
+
+  let body = serde_json::json!({
+      "model": model,
+      "messages": messages,
+      "max_tokens": max_tokens,
+      "temperature": sampling.temperature,
+      "top_p": sampling.top_p,
+      "seed": sampling.seed
+  });
+
+  blocking(move || {
+    let client = reqwest::blocking::Client::builder()
+      .timeout(REQUEST_TIMEOUT)
+      .build()?;
+    let url = format!("{}/chat/completions", api_base);
+    let response = client
+      .post(&url)
+      .bearer_auth(&key)
+      .json(&body)
+      .send()
+      .with_context(|| format!("Failed to reach {}", url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+      let text = response.text().unwrap_or_default();
+      return Err(anyhow!("Remote API returned {}: {}", status, text.trim()));
+    }
+
+    let parsed: ChatResponse = response
+      .json()
+      .with_context(|| "Invalid response from remote API")?;
+    let content = parsed
+      .choices
+      .into_iter()
+      .next()
+      .and_then(|c| c.message.content)
+      .ok_or_else(|| anyhow!("Remote API returned no choices"))?;
+    Ok(content.trim().to_string())
+  })
+}
@@ -0,0 +1,195 @@
+//! Replies the model already gave, so the same prompt isn't run through it twice
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{self, SamplingConfig};
+
+/// Replies kept at most; the oldest are dropped first
+const MAX_ENTRIES: usize = 500;
+
+/// Daemon requests run on several threads; only one may rewrite the file at a time
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cache {
+  entries: HashMap<u64, (String, SystemTime)>,
+}
+
+pub fn cache_path() -> PathBuf {
+  config::alfred_dir().join("cache.json")
+}
+
+/// FxHash, the hasher rustc uses. Unlike std's `DefaultHasher`, its output is the same
+/// across runs and Rust versions, which a key stored on disk needs
+struct FxHasher {
+  hash: u64,
+}
+
+const FX_SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl FxHasher {
+  fn add(&mut self, word: u64) {
+    self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_SEED);
+  }
+}
+
+impl Hasher for FxHasher {
+  fn write(&mut self, bytes: &[u8]) {
+    for chunk in bytes.chunks(8) {
+      let mut word = [0u8; 8];
+      word[..chunk.len()].copy_from_slice(chunk);
+      self.add(u64::from_le_bytes(word));
+    }
+  }
+
+  fn write_u32(&mut self, n: u32) {
+    self.add(n as u64);
+  }
+
+  fn write_u64(&mut self, n: u64) {
+    self.add(n);
+  }
+
+  fn finish(&self) -> u64 {
+    self.hash
+  }
+}
+
+/// Cache key for one generation. The model, grammar and every sampling setting are part of
+/// it, so switching models or seeds never returns a reply that was made differently
+pub fn key(
+  model: &str,
+  prompt: &str,
+  max_tokens: u32,
+  grammar: Option<&str>,
+  sampling: &SamplingConfig,
+) -> u64 {
+  let mut hasher = FxHasher { hash: 0 };
+  model.hash(&mut hasher);
+  prompt.hash(&mut hasher);
+  max_tokens.hash(&mut hasher);
+  grammar.hash(&mut hasher);
+  sampling.temperature.to_bits().hash(&mut hasher);
+  sampling.top_k.hash(&mut hasher);
+  sampling.top_p.to_bits().hash(&mut hasher);
+  sampling.seed.hash(&mut hasher);
+  sampling.repeat_penalty.to_bits().hash(&mut hasher);
+  hasher.finish()
+}
+
+/// How long replies stay valid; `None` when `cache_ttl_hours` is 0
+fn ttl() -> Option<Duration> {
+  match config::get_cache_ttl_hours() {
+    0 => None,
+    hours => Some(Duration::from_secs(hours as u64 * 3600)),
+  }
+}
+
+fn is_fresh(stored: SystemTime, ttl: Duration) -> bool {
+  stored.elapsed().is_ok_and(|age| age < ttl)
+}
+
+fn read() -> Cache {
+  fs::read_to_string(cache_path())
+    .ok()
+    .and_then(|content| serde_json::from_str(&content).ok())
+    .unwrap_or_default()
+}
+
+/// Write through a temp file so a crash never leaves half a cache behind
+fn write(cache: &Cache) -> Result<()> {
+  let path = cache_path();
+  fs::create_dir_all(config::alfred_dir())?;
+  let tmp = path.with_extension("json.tmp");
+  fs::write(&tmp, serde_json::to_string(cache)?)
+    .with_context(|| format!("Failed to write {}", tmp.display()))?;
+  fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+  Ok(())
+}
+
+/// The stored reply for `key`, if caching is on and it hasn't expired
+pub fn get(key: u64) -> Option<String> {
+  let ttl = ttl()?;
+  let _lock = LOCK.lock().ok()?;
+  read()
+    .entries
+    .remove(&key)
+    .filter(|(_, stored)| is_fresh(*stored, ttl))
+    .map(|(reply, _)| reply)
+}
+
+/// Remember a reply. Failing to write the cache never fails the generation
+pub fn put(key: u64, reply: &str) {
+  let Some(ttl) = ttl() else {
+    return;
+  };
+  let Ok(_lock) = LOCK.lock() else {
+    return;
+  };
+
+  let mut cache = read();
+  cache
+    .entries
+    .retain(|_, (_, stored)| is_fresh(*stored, ttl));
+  cache
+    .entries
+    .insert(key, (reply.to_string(), SystemTime::now()));
+
+  if cache.entries.len() > MAX_ENTRIES {
+    let mut by_age: Vec<(u64, SystemTime)> = cache
+      .entries
+      .iter()
+      .map(|(key, (_, stored))| (*key, *stored))
+      .collect();
+    by_age.sort_by_key(|&(_, stored)| stored);
+    for (key, _) in by_age.iter().take(cache.entries.len() - MAX_ENTRIES) {
+      cache.entries.remove(key);
+    }
+  }
+
+  let _ = write(&cache);
+}
+
+/// Number of stored replies, expired ones included
+pub fn len() -> usize {
+  read().entries.len()
+}
+
+/// Delete every stored reply; returns how many there were
+pub fn clear() -> Result<usize> {
+  let _lock = LOCK
+    .lock()
+    .map_err(|_| anyhow::anyhow!("Cache lock poisoned"))?;
+  let count = read().entries.len();
+  let path = cache_path();
+  if path.exists() {
+    fs::remove_file(&path).with_context(|| format!("Failed to delete {}", path.display()))?;
+  }
+  Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::key;
+  use crate::config::SamplingConfig;
+
+  #[test]
+  fn grammar_is_part_of_the_key() {
+    let sampling = SamplingConfig::default();
+    let plain = key("m.gguf", "prompt", 30, None, &sampling);
+    let constrained = key("m.gguf", "prompt", 30, Some("root ::= \"a\""), &sampling);
+    assert_ne!(plain, constrained);
+    assert_ne!(
+      constrained,
+      key("m.gguf", "prompt", 30, Some("root ::= \"b\""), &sampling)
+    );
+    assert_eq!(plain, key("m.gguf", "prompt", 30, None, &sampling));
+  }
+}
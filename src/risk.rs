@@ -0,0 +1,284 @@
+//! Deterministic risk scoring for a set of changes
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::config::RiskConfig;
+use crate::git::FileChange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+  Low,
+  Medium,
+  High,
+}
+
+impl RiskLevel {
+  /// `low`, `medium` or `high`, in any case
+  pub fn parse(name: &str) -> Option<Self> {
+    match name.trim().to_lowercase().as_str() {
+      "low" => Some(RiskLevel::Low),
+      "medium" => Some(RiskLevel::Medium),
+      "high" => Some(RiskLevel::High),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for RiskLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let label = match self {
+      RiskLevel::Low => "low".green(),
+      RiskLevel::Medium => "medium".yellow(),
+      RiskLevel::High => "high".red().bold(),
+    };
+    write!(f, "{}", label)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct RiskAssessment {
+  pub score: u32,
+  pub level: RiskLevel,
+  /// Human-readable reasons, each with the points it contributed
+  pub factors: Vec<String>,
+}
+
+/// Points for a value against ascending thresholds (one point per threshold exceeded)
+fn tiered(value: usize, thresholds: &[usize]) -> u32 {
+  thresholds.iter().filter(|t| value > **t).count() as u32
+}
+
+fn top_level_dir(path: &str) -> &str {
+  match path.split_once('/') {
+    Some((dir, _)) => dir,
+    None => ".",
+  }
+}
+
+fn is_sensitive(path: &str, patterns: &[glob::Pattern]) -> bool {
+  patterns.iter().any(|p| p.matches(path))
+}
+
+/// Score a set of file changes; the same input always yields the same result
+pub fn assess(changes: &[FileChange], cfg: &RiskConfig) -> RiskAssessment {
+  let mut score = 0;
+  let mut factors = Vec::new();
+  let mut add = |points: u32, reason: String| {
+    if points > 0 {
+      score += points;
+      factors.push(format!("{} (+{})", reason, points));
+    }
+  };
+
+  let added: usize = changes.iter().map(|c| c.added).sum();
+  let deleted: usize = changes.iter().map(|c| c.deleted).sum();
+  let lines = added + deleted;
+
+  add(
+    tiered(lines, &[50, 200, 500, 1000]),
+    format!("{} lines changed", lines),
+  );
+  add(
+    tiered(changes.len(), &[5, 15, 40]),
+    format!("{} files changed", changes.len()),
+  );
+
+  let dirs: HashSet<&str> = changes.iter().map(|c| top_level_dir(&c.path)).collect();
+  add(
+    tiered(dirs.len(), &[2, 4]),
+    format!("spans {} top-level directories", dirs.len()),
+  );
+
+  let patterns: Vec<glob::Pattern> = cfg
+    .sensitive_paths
+    .iter()
+    .filter_map(|p| glob::Pattern::new(p).ok())
+    .collect();
+  let sensitive: Vec<&str> = changes
+    .iter()
+    .filter(|c| is_sensitive(&c.path, &patterns))
+    .map(|c| c.path.as_str())
+    .collect();
+  if !sensitive.is_empty() {
+    let shown: Vec<&str> = sensitive.iter().take(3).copied().collect();
+    let more = if sensitive.len() > 3 {
+      format!(" and {} more", sensitive.len() - 3)
+    } else {
+      String::new()
+    };
+    add(
+      3,
+      format!("touches sensitive paths: {}{}", shown.join(", "), more),
+    );
+  }
+
+  if deleted > 50 && deleted > added * 2 {
+    add(2, format!("deletion-heavy (-{} / +{})", deleted, added));
+  }
+
+  let removed = changes.iter().filter(|c| c.removed).count();
+  add(
+    removed.min(2) as u32,
+    format!("{} file(s) deleted", removed),
+  );
+
+  let binary = changes.iter().filter(|c| c.binary).count();
+  if binary > 0 {
+    add(1, format!("{} binary file(s)", binary));
+  }
+
+  let modes = changes.iter().filter(|c| c.mode_changed).count();
+  if modes > 0 {
+    add(1, format!("{} permission change(s)", modes));
+  }
+
+  let level = if score >= cfg.high_threshold {
+    RiskLevel::High
+  } else if score >= cfg.medium_threshold {
+    RiskLevel::Medium
+  } else {
+    RiskLevel::Low
+  };
+
+  RiskAssessment {
+    score,
+    level,
+    factors,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn change(path: &str, added: usize, deleted: usize) -> FileChange {
+    FileChange {
+      path: path.to_string(),
+      added,
+      deleted,
+      ..Default::default()
+    }
+  }
+
+  fn level(changes: &[FileChange]) -> RiskLevel {
+    assess(changes, &RiskConfig::default()).level
+  }
+
+  #[test]
+  fn fixtures_classify_as_expected() {
+    let many_files: Vec<FileChange> = (0..20)
+      .map(|i| change(&format!("dir{}/file{}.rs", i % 6, i), 30, 10))
+      .collect();
+    let fixtures: Vec<(&str, Vec<FileChange>, RiskLevel)> = vec![
+      ("typo fix", vec![change("src/lib.rs", 1, 1)], RiskLevel::Low),
+      (
+        "a feature in one place",
+        vec![change("src/a.rs", 120, 10), change("src/b.rs", 40, 0)],
+        RiskLevel::Low,
+      ),
+      (
+        "CI config",
+        vec![change(".github/workflows/ci.yml", 3, 1)],
+        RiskLevel::Medium,
+      ),
+      ("wide refactor", many_files, RiskLevel::High),
+      (
+        "auth rewrite",
+        vec![
+          change("src/auth/session.rs", 400, 380),
+          change("db/migrations/002.sql", 20, 0),
+        ],
+        RiskLevel::High,
+      ),
+    ];
+    for (name, changes, expected) in fixtures {
+      let assessment = assess(&changes, &RiskConfig::default());
+      assert_eq!(assessment.level, expected, "{}: {:?}", name, assessment);
+    }
+  }
+
+  #[test]
+  fn factors_explain_the_score() {
+    let mut removed = change("old/module.rs", 0, 300);
+    removed.removed = true;
+    let mut script = change("bin/run.sh", 0, 0);
+    script.mode_changed = true;
+    let mut image = change("assets/logo.png", 0, 0);
+    image.binary = true;
+
+    let assessment = assess(&[removed, script, image], &RiskConfig::default());
+    let points: u32 = assessment
+      .factors
+      .iter()
+      .filter_map(|f| {
+        f.rsplit_once("(+")?
+          .1
+          .trim_end_matches(")")
+          .parse::<u32>()
+          .ok()
+      })
+      .sum();
+    assert_eq!(points, assessment.score);
+    for expected in [
+      "deletion-heavy",
+      "1 file(s) deleted",
+      "1 binary file(s)",
+      "1 permission change(s)",
+    ] {
+      assert!(
+        assessment.factors.iter().any(|f| f.contains(expected)),
+        "{:?} lacks {}",
+        assessment.factors,
+        expected
+      );
+    }
+  }
+
+  #[test]
+  fn scoring_is_deterministic_and_order_independent() {
+    let changes = vec![
+      change(".github/ci.yml", 10, 2),
+      change("src/a.rs", 200, 50),
+      change("docs/a.md", 5, 5),
+    ];
+    let mut reversed = changes.clone();
+    reversed.reverse();
+    let a = assess(&changes, &RiskConfig::default());
+    let b = assess(&reversed, &RiskConfig::default());
+    assert_eq!((a.score, a.level), (b.score, b.level));
+  }
+
+  #[test]
+  fn sensitive_paths_and_thresholds_are_configurable() {
+    let changes = [change("payments/ledger.rs", 2, 2)];
+    assert_eq!(level(&changes), RiskLevel::Low);
+
+    let cfg = RiskConfig {
+      sensitive_paths: vec!["payments/**".to_string()],
+      medium_threshold: 2,
+      high_threshold: 3,
+    };
+    let assessment = assess(&changes, &cfg);
+    assert_eq!(assessment.level, RiskLevel::High);
+    assert!(assessment.factors[0].contains("payments/ledger.rs"));
+  }
+
+  #[test]
+  fn empty_changes_are_low_risk() {
+    let assessment = assess(&[], &RiskConfig::default());
+    assert_eq!((assessment.score, assessment.level), (0, RiskLevel::Low));
+    assert!(assessment.factors.is_empty());
+  }
+
+  #[test]
+  fn levels_parse_and_order() {
+    assert_eq!(RiskLevel::parse(" High "), Some(RiskLevel::High));
+    assert_eq!(RiskLevel::parse("medium"), Some(RiskLevel::Medium));
+    assert_eq!(RiskLevel::parse("severe"), None);
+    assert!(RiskLevel::Low < RiskLevel::Medium && RiskLevel::Medium < RiskLevel::High);
+  }
+}
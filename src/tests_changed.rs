@@ -0,0 +1,400 @@
+//! Classify changed files as tests or production code, and match them up
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::TestsConfig;
+use crate::git;
+
+/// Directories whose contents are tests in most ecosystems
+const TEST_DIRS: &[&str] = &["test", "tests", "__tests__", "spec", "specs", "testdata"];
+
+/// Extensions counted as production code; docs, configs and assets don't need tests
+const SOURCE_EXTENSIONS: &[&str] = &[
+  "rs", "py", "js", "jsx", "ts", "tsx", "mjs", "cjs", "go", "java", "kt", "rb", "c", "cc", "cpp",
+  "h", "hpp", "cs", "swift", "php", "scala", "ex", "exs",
+];
+
+/// Markers that make a new file a test, whatever its name
+const TEST_MARKERS: &[&str] = &[
+  "#[test]",
+  "#[cfg(test)]",
+  "describe(",
+  "it(\"",
+  "test(\"",
+  "def test_",
+  "func Test",
+  "@Test",
+  "[Fact]",
+];
+
+/// Test-file affixes, stripped to find the name of the code under test
+const TEST_PREFIXES: &[&str] = &["test_", "test-"];
+const TEST_SUFFIXES: &[&str] = &[
+  "_test", "_tests", "-test", "_spec", "-spec", ".test", ".spec", "Tests", "Test", "Spec",
+];
+
+/// What a set of changes does to production code and tests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestSummary {
+  pub production: Vec<String>,
+  pub tests: Vec<String>,
+  /// Production files with a changed test of the same name
+  pub covered: Vec<String>,
+}
+
+impl TestSummary {
+  /// "tests: 2 files updated", "no test changes", or nothing when no code changed
+  pub fn line(&self) -> Option<String> {
+    if !self.tests.is_empty() {
+      Some(format!(
+        "tests: {} file{} updated",
+        self.tests.len(),
+        if self.tests.len() == 1 { "" } else { "s" }
+      ))
+    } else if !self.production.is_empty() {
+      Some("no test changes".to_string())
+    } else {
+      None
+    }
+  }
+
+  /// Context for commit message prompts
+  pub fn prompt_hint(&self) -> Option<String> {
+    if self.tests.is_empty() {
+      return None;
+    }
+    let mut hint = format!("Tests changed: {}", self.tests.join(", "));
+    if !self.covered.is_empty() {
+      hint.push_str(&format!("\nCovering: {}", self.covered.join(", ")));
+    }
+    Some(hint)
+  }
+}
+
+fn file_name(path: &str) -> &str {
+  path.rsplit('/').next().unwrap_or(path)
+}
+
+/// File name without its extension (and without `.test`-style double extensions' last part)
+fn stem(path: &str) -> &str {
+  let name = file_name(path);
+  Path::new(name)
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or(name)
+}
+
+/// Whether a path follows a test naming convention, built in or from `tests.patterns`
+pub fn is_test_path(path: &str, extra: &[glob::Pattern]) -> bool {
+  if extra.iter().any(|p| p.matches(path)) {
+    return true;
+  }
+
+  let mut dirs = path.split('/').rev().skip(1);
+  if dirs.any(|dir| TEST_DIRS.contains(&dir)) {
+    return true;
+  }
+
+  let stem = stem(path);
+  TEST_PREFIXES.iter().any(|p| stem.starts_with(p))
+    || TEST_SUFFIXES
+      .iter()
+      .any(|s| stem.len() > s.len() && stem.ends_with(s))
+    || stem == "test"
+    || stem == "tests"
+}
+
+/// Whether a new file's content looks like tests
+pub fn has_test_markers(content: &str) -> bool {
+  TEST_MARKERS.iter().any(|m| content.contains(m))
+}
+
+fn is_source(path: &str) -> bool {
+  Path::new(path)
+    .extension()
+    .and_then(|e| e.to_str())
+    .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e))
+}
+
+/// The name of the code a test file exercises: `test_login.py` and `login.spec.ts` give `login`
+pub fn tested_stem(path: &str) -> String {
+  let mut stem = stem(path);
+  for prefix in TEST_PREFIXES {
+    stem = stem.strip_prefix(prefix).unwrap_or(stem);
+  }
+  for suffix in TEST_SUFFIXES {
+    if stem.len() > suffix.len() {
+      stem = stem.strip_suffix(suffix).unwrap_or(stem);
+    }
+  }
+  stem.to_lowercase()
+}
+
+/// Split `files` into tests and production code. `is_test_content` is asked about added
+/// files that don't follow a naming convention
+pub fn summarize(
+  files: &[String],
+  added: &[String],
+  is_test_content: impl Fn(&str) -> bool,
+  cfg: &TestsConfig,
+) -> TestSummary {
+  let extra: Vec<glob::Pattern> = cfg
+    .patterns
+    .iter()
+    .filter_map(|p| glob::Pattern::new(p).ok())
+    .collect();
+
+  let mut summary = TestSummary::default();
+  for file in files {
+    if is_test_path(file, &extra) || (added.contains(file) && is_test_content(file)) {
+      summary.tests.push(file.clone());
+    } else if is_source(file) {
+      summary.production.push(file.clone());
+    }
+  }
+
+  let tested: Vec<String> = summary.tests.iter().map(|t| tested_stem(t)).collect();
+  summary.covered = summary
+    .production
+    .iter()
+    .filter(|p| tested.contains(&stem(p).to_lowercase()))
+    .cloned()
+    .collect();
+  summary
+}
+
+/// Test summary of the staged changes, or of everything uncommitted
+pub fn changes(staged: bool, cfg: &TestsConfig) -> Result<TestSummary> {
+  let files = if staged {
+    git::staged_files()?
+  } else {
+    git::uncommitted_files()?
+  };
+  let added = git::added_files(staged).unwrap_or_default();
+  let content = |path: &str| {
+    let text = if staged {
+      git::staged_content(path)
+    } else {
+      std::fs::read_to_string(path).ok()
+    };
+    text.is_some_and(|t| has_test_markers(&t))
+  };
+  Ok(summarize(&files, &added, content, cfg))
+}
+
+/// Test files a diff deletes outright
+pub fn deleted_tests(diff: &str, cfg: &TestsConfig) -> Vec<String> {
+  let extra: Vec<glob::Pattern> = cfg
+    .patterns
+    .iter()
+    .filter_map(|p| glob::Pattern::new(p).ok())
+    .collect();
+
+  let mut deleted = Vec::new();
+  let mut path = None;
+  for line in diff.lines() {
+    if let Some(header) = line.strip_prefix("diff --git a/") {
+      path = header.rsplit_once(" b/").map(|(old, _)| old.to_string());
+    } else if line.starts_with("deleted file mode") {
+      if let Some(path) = path.take().filter(|p| is_test_path(p, &extra)) {
+        deleted.push(path);
+      }
+    }
+  }
+  deleted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+  }
+
+  fn config(patterns: &[&str]) -> TestsConfig {
+    TestsConfig {
+      patterns: strings(patterns),
+    }
+  }
+
+  #[test]
+  fn test_paths_across_ecosystems() {
+    for path in [
+      "tests/cli.rs",
+      "crates/core/tests/parse.rs",
+      "src/parser_test.rs",
+      "pkg/server/handler_test.go",
+      "tests/test_login.py",
+      "app/test_models.py",
+      "lib/models_tests.py",
+      "src/components/Button.test.tsx",
+      "src/api/client.spec.ts",
+      "src/__tests__/App.jsx",
+      "spec/models/user_spec.rb",
+      "src/test/java/com/acme/UserServiceTest.java",
+      "Tests/AppTests/LoginTests.swift",
+      "test/my_app_test.exs",
+      "UserSpec.scala",
+      "testdata/fixture.json",
+    ] {
+      assert!(is_test_path(path, &[]), "{} should be a test", path);
+    }
+  }
+
+  #[test]
+  fn production_paths_across_ecosystems() {
+    for path in [
+      "src/main.rs",
+      "src/testing.rs",
+      "src/attest.rs",
+      "pkg/server/handler.go",
+      "app/models.py",
+      "src/components/Button.tsx",
+      "lib/contest/results.rb",
+      "src/main/java/com/acme/UserService.java",
+      "Test.swift",
+      "README.md",
+    ] {
+      assert!(!is_test_path(path, &[]), "{} isn't a test", path);
+    }
+  }
+
+  #[test]
+  fn configured_globs_extend_the_conventions() {
+    let extra = [glob::Pattern::new("qa/**/*.rs").unwrap()];
+    assert!(is_test_path("qa/smoke/boot.rs", &extra));
+    assert!(!is_test_path("src/boot.rs", &extra));
+
+    let files = strings(&["qa/smoke/boot.rs", "src/boot.rs"]);
+    let summary = summarize(&files, &[], |_| false, &config(&["qa/**"]));
+    assert_eq!(summary.tests, ["qa/smoke/boot.rs"]);
+    assert_eq!(summary.covered, ["src/boot.rs"]);
+    // Invalid globs are ignored rather than failing the commit
+    let summary = summarize(&files, &[], |_| false, &config(&["[", "qa/**"]));
+    assert_eq!(summary.tests, ["qa/smoke/boot.rs"]);
+  }
+
+  #[test]
+  fn markers_identify_tests_in_each_language() {
+    for content in [
+      "#[test]\nfn parses() {}",
+      "#[cfg(test)]\nmod tests {}",
+      "describe('Button', () => {})",
+      "def test_login(client):",
+      "func TestHandler(t *testing.T) {",
+      "@Test\npublic void saves() {}",
+      "[Fact]\npublic void Saves() {}",
+    ] {
+      assert!(has_test_markers(content), "{}", content);
+    }
+    assert!(!has_test_markers("fn main() {}\n// test later"));
+  }
+
+  #[test]
+  fn tested_stems_strip_test_affixes() {
+    for (path, stem) in [
+      ("tests/test_login.py", "login"),
+      ("src/login.spec.ts", "login"),
+      ("src/Login.test.tsx", "login"),
+      ("pkg/auth/token_test.go", "token"),
+      ("spec/user_spec.rb", "user"),
+      ("UserServiceTest.java", "userservice"),
+      ("LoginTests.swift", "login"),
+      ("tests/cli.rs", "cli"),
+    ] {
+      assert_eq!(tested_stem(path), stem, "{}", path);
+    }
+  }
+
+  #[test]
+  fn changed_tests_cover_production_files_of_the_same_name() {
+    let files = strings(&[
+      "src/auth/login.ts",
+      "src/auth/logout.ts",
+      "src/auth/login.spec.ts",
+      "app/UserService.java",
+      "test/UserServiceTest.java",
+      "docs/auth.md",
+    ]);
+    let summary = summarize(&files, &[], |_| false, &TestsConfig::default());
+    assert_eq!(
+      summary.production,
+      [
+        "src/auth/login.ts",
+        "src/auth/logout.ts",
+        "app/UserService.java"
+      ]
+    );
+    assert_eq!(
+      summary.tests,
+      ["src/auth/login.spec.ts", "test/UserServiceTest.java"]
+    );
+    assert_eq!(
+      summary.covered,
+      ["src/auth/login.ts", "app/UserService.java"]
+    );
+  }
+
+  #[test]
+  fn only_new_files_are_checked_for_markers() {
+    let files = strings(&["src/checks.rs", "src/lib.rs"]);
+    let added = strings(&["src/checks.rs"]);
+    let summary = summarize(&files, &added, |_| true, &TestsConfig::default());
+    assert_eq!(summary.tests, ["src/checks.rs"]);
+    assert_eq!(summary.production, ["src/lib.rs"]);
+  }
+
+  #[test]
+  fn summary_lines() {
+    let summary = |production: &[&str], tests: &[&str]| TestSummary {
+      production: strings(production),
+      tests: strings(tests),
+      covered: Vec::new(),
+    };
+    assert_eq!(
+      summary(&["a.rs"], &["a_test.rs", "b_test.rs"])
+        .line()
+        .as_deref(),
+      Some("tests: 2 files updated")
+    );
+    assert_eq!(
+      summary(&[], &["a_test.rs"]).line().as_deref(),
+      Some("tests: 1 file updated")
+    );
+    assert_eq!(
+      summary(&["a.rs"], &[]).line().as_deref(),
+      Some("no test changes")
+    );
+    assert_eq!(summary(&[], &[]).line(), None);
+  }
+
+  #[test]
+  fn prompt_hints_name_the_tests_and_what_they_cover() {
+    let summary = TestSummary {
+      production: strings(&["src/login.ts"]),
+      tests: strings(&["src/login.spec.ts"]),
+      covered: strings(&["src/login.ts"]),
+    };
+    assert_eq!(
+      summary.prompt_hint().as_deref(),
+      Some("Tests changed: src/login.spec.ts\nCovering: src/login.ts")
+    );
+    assert_eq!(TestSummary::default().prompt_hint(), None);
+  }
+
+  #[test]
+  fn deleted_tests_are_found_in_the_diff() {
+    let diff = "diff --git a/tests/old_test.rs b/tests/old_test.rs\n\
+                deleted file mode 100644\n\
+                diff --git a/src/old.rs b/src/old.rs\n\
+                deleted file mode 100644\n\
+                diff --git a/tests/kept_test.rs b/tests/kept_test.rs\n\
+                index 1111111..2222222 100644\n";
+    assert_eq!(
+      deleted_tests(diff, &TestsConfig::default()),
+      ["tests/old_test.rs"]
+    );
+  }
+}
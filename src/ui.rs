@@ -1,6 +1,57 @@
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
-use dialoguer::{Confirm, Input, Select};
+use console::{Key, Term};
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+use similar::{ChangeTag, TextDiff};
 use std::fmt::Display;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Items shown before a long list is collapsed
+pub const PAGE_SIZE: usize = 15;
+
+/// Bulk operations over more items than this need the full list viewable before confirming
+pub const BULK_CONFIRM_THRESHOLD: usize = 15;
+
+/// Set by `--yes`: answer every prompt with its default instead of asking
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_assume_yes(yes: bool) {
+  ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+pub fn assume_yes() -> bool {
+  ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// No one to ask: `--yes` was given, or stdin isn't a terminal (CI, scripts, pipes)
+pub fn non_interactive() -> bool {
+  #[cfg(test)]
+  if let Some(interactive) = script::interactive() {
+    return !interactive;
+  }
+  assume_yes() || !std::io::stdin().is_terminal()
+}
+
+/// Show the question a `--yes` run answered, so logs say what happened
+fn auto_answer(question: &str, answer: &str) {
+  #[cfg(test)]
+  script::record(question);
+  println!(
+    "{} {} {}",
+    "?".cyan(),
+    question,
+    format!(
+      "{} ({})",
+      answer,
+      if assume_yes() { "--yes" } else { "no terminal" }
+    )
+    .dimmed()
+  );
+}
 
 pub fn info<T: Display>(msg: T) {
   println!("{} {}", "i".blue(), msg);
@@ -31,6 +82,13 @@ pub fn separator() {
 }
 
 pub fn prompt(question: &str) -> Option<String> {
+  if non_interactive() {
+    return None;
+  }
+  #[cfg(test)]
+  if let Some(answer) = script::answer(question) {
+    return answer.text();
+  }
   Input::<String>::new()
     .with_prompt(format!("{} {}", "?".cyan(), question))
     .allow_empty(true)
@@ -40,6 +98,14 @@ pub fn prompt(question: &str) -> Option<String> {
 }
 
 pub fn confirm(question: &str, default: bool) -> bool {
+  if non_interactive() {
+    auto_answer(question, if default { "yes" } else { "no" });
+    return default;
+  }
+  #[cfg(test)]
+  if let Some(answer) = script::answer(question) {
+    return answer.confirm();
+  }
   Confirm::new()
     .with_prompt(format!("{} {}", "?".cyan(), question))
     .default(default)
@@ -47,6 +113,15 @@ pub fn confirm(question: &str, default: bool) -> bool {
     .unwrap_or(default)
 }
 
+/// Like `confirm`, but answers no when it can't ask: destructive operations need their own flag
+pub fn confirm_destructive(question: &str, default: bool) -> bool {
+  if non_interactive() {
+    auto_answer(question, "no");
+    return false;
+  }
+  confirm(question, default)
+}
+
 pub fn select<T: ToString + Clone>(question: &str, options: &[T]) -> Option<T> {
   if options.is_empty() {
     return None;
@@ -54,6 +129,15 @@ pub fn select<T: ToString + Clone>(question: &str, options: &[T]) -> Option<T> {
 
   let items: Vec<String> = options.iter().map(|o| o.to_string()).collect();
 
+  if non_interactive() {
+    auto_answer(question, &items[0]);
+    return options.first().cloned();
+  }
+  #[cfg(test)]
+  if let Some(answer) = script::answer(question) {
+    return options.get(answer.select()).cloned();
+  }
+
   Select::new()
     .with_prompt(format!("{} {}", "?".cyan(), question))
     .items(&items)
@@ -63,6 +147,45 @@ pub fn select<T: ToString + Clone>(question: &str, options: &[T]) -> Option<T> {
     .and_then(|i| options.get(i).cloned())
 }
 
+/// Let the user tick items off a list. Returns the indices of the checked items, or `None`
+/// if the prompt was cancelled. Without a terminal the items in `checked` are taken as they are
+pub fn multi_select<T: Display>(
+  question: &str,
+  items: &[T],
+  checked: &[bool],
+) -> Option<Vec<usize>> {
+  if items.is_empty() {
+    return Some(Vec::new());
+  }
+
+  if non_interactive() {
+    let chosen: Vec<usize> = (0..items.len())
+      .filter(|&i| checked.get(i).copied().unwrap_or(false))
+      .collect();
+    auto_answer(question, &format!("{} of {}", chosen.len(), items.len()));
+    return Some(chosen);
+  }
+  #[cfg(test)]
+  if let Some(answer) = script::answer(question) {
+    return answer.multi_select();
+  }
+
+  let labels: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+  MultiSelect::new()
+    .with_prompt(format!(
+      "{} {} {}",
+      "?".cyan(),
+      question,
+      "(space to toggle, enter to confirm)".dimmed()
+    ))
+    .items(&labels)
+    .defaults(checked)
+    .max_length(PAGE_SIZE)
+    .interact_opt()
+    .ok()
+    .flatten()
+}
+
 pub fn list_item<T: Display>(item: T) {
   println!("  {} {}", "•".dimmed(), item);
 }
@@ -78,3 +201,435 @@ pub fn list_item_colored<T: Display>(marker: &str, color: &str, item: T) {
   };
   println!("  {} {}", colored_marker, item);
 }
+
+/// Run `f`, printing the text the model generates dimmed as it arrives when stdout is a
+/// terminal
+pub fn with_streamed_output<T>(f: impl FnOnce() -> T) -> T {
+  if !std::io::stdout().is_terminal() {
+    return f();
+  }
+  let printed = std::rc::Rc::new(std::cell::Cell::new(false));
+  let seen = printed.clone();
+  let result = crate::llm::with_token_callback(
+    move |piece| {
+      seen.set(true);
+      print!("{}", piece.dimmed());
+      let _ = std::io::Write::flush(&mut std::io::stdout());
+    },
+    f,
+  );
+  if printed.get() {
+    println!();
+  }
+  result
+}
+
+fn is_interactive() -> bool {
+  #[cfg(test)]
+  if let Some(interactive) = script::interactive() {
+    return interactive;
+  }
+  std::io::stdout().is_terminal() && std::io::stdin().is_terminal() && !assume_yes()
+}
+
+/// Print `items` one per line, collapsing everything after the first `page_size`
+/// until the user presses space. Piped output always gets the full list.
+///
+/// Returns true when every item was printed.
+pub fn list_paged<T: Display>(items: &[T], page_size: usize) -> bool {
+  let term = Term::stdout();
+  write_paged(
+    &mut std::io::stdout(),
+    items,
+    page_size,
+    is_interactive(),
+    || {
+      #[cfg(test)]
+      if let Some(answer) = script::answer("expand") {
+        return answer.confirm();
+      }
+      if term.read_key().ok() != Some(Key::Char(' ')) {
+        return false;
+      }
+      let _ = term.clear_last_lines(1);
+      true
+    },
+  )
+}
+
+/// `list_paged` into `out`; `expand` waits for the user and says whether to show the rest
+fn write_paged<T: Display>(
+  out: &mut impl Write,
+  items: &[T],
+  page_size: usize,
+  interactive: bool,
+  expand: impl FnOnce() -> bool,
+) -> bool {
+  let shown = if interactive {
+    items.len().min(page_size)
+  } else {
+    items.len()
+  };
+
+  for item in &items[..shown] {
+    let _ = writeln!(out, "  {}", item);
+  }
+
+  let hidden = items.len() - shown;
+  if hidden == 0 {
+    return true;
+  }
+
+  let _ = writeln!(
+    out,
+    "{}",
+    format!("  … and {} more (press space to expand)", hidden).dimmed()
+  );
+  let _ = out.flush();
+  if !expand() {
+    return false;
+  }
+
+  for item in &items[shown..] {
+    let _ = writeln!(out, "  {}", item);
+  }
+  true
+}
+
+/// Print a colored unified diff from `old` to `new`, with 3 lines of context
+pub fn print_diff(old_label: &str, new_label: &str, old: &str, new: &str) {
+  if old == new {
+    dim(format!("No changes from {}", old_label));
+    return;
+  }
+
+  println!("{}", format!("--- {}", old_label).red());
+  println!("{}", format!("+++ {}", new_label).green());
+  let diff = TextDiff::from_lines(old, new);
+  for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+    println!("{}", hunk.header().to_string().cyan());
+    for change in hunk.iter_changes() {
+      let text = change.value().trim_end_matches(['\n', '\r']);
+      match change.tag() {
+        ChangeTag::Delete => println!("{}", format!("-{}", text).red()),
+        ChangeTag::Insert => println!("{}", format!("+{}", text).green()),
+        ChangeTag::Equal => println!(" {}", text),
+      }
+    }
+  }
+}
+
+/// Open `path` in `$EDITOR` (vi when unset) and wait for it to close
+pub fn open_in_editor(path: &Path) -> Result<()> {
+  // Through the shell, so EDITOR can carry arguments like "code --wait"
+  let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+  let status = Command::new("sh")
+    .arg("-c")
+    .arg(format!("{} \"$1\"", editor))
+    .arg("sh")
+    .arg(path)
+    .status()
+    .with_context(|| format!("Failed to run {}", editor))?;
+  if !status.success() {
+    bail!("{} exited with {}", editor, status);
+  }
+  Ok(())
+}
+
+/// Let the user edit `text` in `$EDITOR`. The temp file ends in `suffix`, such as `.rs`, so
+/// the editor highlights it
+pub fn edit_text(text: &str, suffix: &str) -> Result<String> {
+  if !std::io::stdin().is_terminal() {
+    bail!("No terminal to edit in");
+  }
+  let path = std::env::temp_dir().join(format!("alfred-edit-{}{}", std::process::id(), suffix));
+  fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+  let edited = open_in_editor(&path).and_then(|()| Ok(fs::read_to_string(&path)?));
+  let _ = fs::remove_file(&path);
+  edited
+}
+
+/// Write a list to a temp file so it can be reviewed outside the terminal
+fn write_list<T: Display>(dir: &Path, items: &[T], name: &str) -> Option<PathBuf> {
+  let path = dir.join(format!("alfred-{}-{}.txt", name, std::process::id()));
+  let content: String = items.iter().map(|i| format!("{}\n", i)).collect();
+  fs::write(&path, content).ok().map(|_| path)
+}
+
+/// Confirm a bulk operation. Above the safety threshold, a list that was only
+/// partly shown is written to a temp file first so the user can see every item.
+pub fn confirm_bulk<T: Display>(
+  question: &str,
+  items: &[T],
+  fully_shown: bool,
+  default: bool,
+) -> bool {
+  confirm_bulk_in(&std::env::temp_dir(), question, items, fully_shown, default)
+}
+
+fn confirm_bulk_in<T: Display>(
+  dir: &Path,
+  question: &str,
+  items: &[T],
+  fully_shown: bool,
+  default: bool,
+) -> bool {
+  if !fully_shown && items.len() > BULK_CONFIRM_THRESHOLD {
+    match write_list(dir, items, "list") {
+      Some(path) => info(format!(
+        "Full list of {} written to {}",
+        items.len(),
+        path.display()
+      )),
+      None => {
+        warn("Could not write the full list for review; not continuing");
+        return false;
+      }
+    }
+  }
+  confirm(question, default)
+}
+
+/// Scripted answers in place of the terminal, so tests can drive prompts and see which
+/// questions were asked
+#[cfg(test)]
+pub mod script {
+  use std::cell::RefCell;
+  use std::collections::VecDeque;
+
+  /// One answer to the next prompt
+  #[derive(Debug, Clone)]
+  pub enum Answer {
+    /// `confirm`, or whether to expand a collapsed `list_paged` listing
+    Yes,
+    No,
+    /// `prompt`; `None` is an empty answer
+    Text(Option<String>),
+    /// Index of the option `select` picks
+    Pick(usize),
+    /// Indices `multi_select` returns, or `None` when cancelled
+    Picks(Option<Vec<usize>>),
+  }
+
+  impl Answer {
+    pub(super) fn confirm(self) -> bool {
+      match self {
+        Answer::Yes => true,
+        Answer::No => false,
+        other => panic!("expected a yes or no, scripted {:?}", other),
+      }
+    }
+
+    pub(super) fn text(self) -> Option<String> {
+      match self {
+        Answer::Text(text) => text,
+        other => panic!("expected text, scripted {:?}", other),
+      }
+    }
+
+    pub(super) fn select(self) -> usize {
+      match self {
+        Answer::Pick(index) => index,
+        other => panic!("expected a pick, scripted {:?}", other),
+      }
+    }
+
+    pub(super) fn multi_select(self) -> Option<Vec<usize>> {
+      match self {
+        Answer::Picks(indices) => indices,
+        other => panic!("expected picks, scripted {:?}", other),
+      }
+    }
+  }
+
+  struct Script {
+    interactive: bool,
+    answers: VecDeque<Answer>,
+    asked: Vec<String>,
+  }
+
+  thread_local! {
+    static SCRIPT: RefCell<Option<Script>> = const { RefCell::new(None) };
+  }
+
+  /// Run `f` with prompts on this thread answered from `answers` in order, as if a terminal
+  /// were attached when `interactive`, or as with `--yes` when not. Returns what `f` returned
+  /// and every question asked, including the ones answered with their default.
+  ///
+  /// Panics when a prompt has no answer left, or when answers are left over
+  pub fn run<T>(
+    interactive: bool,
+    answers: Vec<Answer>,
+    f: impl FnOnce() -> T,
+  ) -> (T, Vec<String>) {
+    let previous = SCRIPT.with(|cell| {
+      cell.replace(Some(Script {
+        interactive,
+        answers: answers.into(),
+        asked: Vec::new(),
+      }))
+    });
+    let result = f();
+    let script = SCRIPT.with(|cell| cell.replace(previous)).unwrap();
+    assert!(
+      script.answers.is_empty(),
+      "unused answers {:?} after {:?}",
+      script.answers,
+      script.asked
+    );
+    (result, script.asked)
+  }
+
+  pub(super) fn interactive() -> Option<bool> {
+    SCRIPT.with(|cell| cell.borrow().as_ref().map(|s| s.interactive))
+  }
+
+  pub(super) fn record(question: &str) {
+    SCRIPT.with(|cell| {
+      if let Some(script) = cell.borrow_mut().as_mut() {
+        script.asked.push(question.to_string());
+      }
+    });
+  }
+
+  /// The scripted answer to `question`, or `None` outside `run`
+  pub(super) fn answer(question: &str) -> Option<Answer> {
+    SCRIPT.with(|cell| {
+      let mut cell = cell.borrow_mut();
+      let script = cell.as_mut()?;
+      script.asked.push(question.to_string());
+      let answer = script.answers.pop_front();
+      assert!(answer.is_some(), "no answer scripted for {:?}", question);
+      answer
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn paged(count: usize, interactive: bool, expand: bool) -> (bool, Vec<String>, bool) {
+    let items: Vec<String> = (1..=count).map(|i| format!("item {}", i)).collect();
+    let mut out = Vec::new();
+    let mut asked = false;
+    let complete = write_paged(&mut out, &items, 3, interactive, || {
+      asked = true;
+      expand
+    });
+    let lines = String::from_utf8(out)
+      .unwrap()
+      .lines()
+      .map(|l| l.trim().to_string())
+      .collect();
+    (complete, lines, asked)
+  }
+
+  #[test]
+  fn piped_output_gets_every_item() {
+    let (complete, lines, asked) = paged(10, false, false);
+    assert!(complete);
+    assert!(!asked);
+    assert_eq!(lines.len(), 10);
+    assert_eq!(lines[9], "item 10");
+  }
+
+  #[test]
+  fn short_lists_are_never_collapsed() {
+    let (complete, lines, asked) = paged(3, true, false);
+    assert!(complete);
+    assert!(!asked);
+    assert_eq!(lines, ["item 1", "item 2", "item 3"]);
+  }
+
+  #[test]
+  fn long_lists_show_a_page_until_expanded() {
+    let (complete, lines, asked) = paged(10, true, false);
+    assert!(asked);
+    assert!(!complete);
+    assert_eq!(&lines[..3], ["item 1", "item 2", "item 3"]);
+    assert!(lines[3].contains("and 7 more"), "{:?}", lines);
+    assert_eq!(lines.len(), 4);
+
+    let (complete, lines, _) = paged(10, true, true);
+    assert!(complete);
+    assert_eq!(lines.last().map(String::as_str), Some("item 10"));
+    assert_eq!(lines.iter().filter(|l| l.starts_with("item")).count(), 10);
+  }
+
+  #[test]
+  fn bulk_lists_are_written_whole_for_review() {
+    let items: Vec<String> = (1..=20).map(|i| format!("branch-{}", i)).collect();
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_list(dir.path(), &items, "test-review").unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().collect::<Vec<_>>(), items);
+  }
+
+  /// Run `confirm_bulk_in` answering yes, returning its answer, the questions asked and
+  /// the list written for review, if any
+  fn bulk(count: usize, fully_shown: bool) -> (bool, Vec<String>, Option<Vec<String>>) {
+    let dir = tempfile::tempdir().unwrap();
+    let items: Vec<String> = (1..=count).map(|i| format!("branch-{}", i)).collect();
+    let (confirmed, asked) = script::run(true, vec![script::Answer::Yes], || {
+      confirm_bulk_in(dir.path(), "Delete them?", &items, fully_shown, false)
+    });
+    let written = fs::read_dir(dir.path()).unwrap().next().map(|entry| {
+      fs::read_to_string(entry.unwrap().path())
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+    });
+    (confirmed, asked, written)
+  }
+
+  #[test]
+  fn bulk_confirms_up_to_the_threshold_just_ask() {
+    let (confirmed, asked, written) = bulk(BULK_CONFIRM_THRESHOLD, false);
+    assert!(confirmed);
+    assert_eq!(asked, ["Delete them?"]);
+    assert_eq!(written, None);
+  }
+
+  #[test]
+  fn bulk_confirms_over_the_threshold_write_a_collapsed_list_first() {
+    let (confirmed, asked, written) = bulk(BULK_CONFIRM_THRESHOLD + 1, false);
+    assert!(confirmed);
+    assert_eq!(asked, ["Delete them?"]);
+    let written = written.expect("the full list should be written");
+    assert_eq!(written.len(), BULK_CONFIRM_THRESHOLD + 1);
+    assert_eq!(written.last().unwrap(), "branch-16");
+
+    let (_, _, written) = bulk(BULK_CONFIRM_THRESHOLD + 1, true);
+    assert_eq!(written, None, "a list already shown whole needs no file");
+  }
+
+  #[test]
+  fn bulk_confirms_stop_when_the_list_cannot_be_written() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+    let items: Vec<String> = (1..=20).map(|i| format!("branch-{}", i)).collect();
+    let (confirmed, asked) = script::run(true, vec![], || {
+      confirm_bulk_in(&missing, "Delete them?", &items, false, true)
+    });
+    assert!(!confirmed);
+    assert!(asked.is_empty(), "asked {:?}", asked);
+  }
+
+  #[test]
+  fn list_paged_asks_to_expand_only_in_a_terminal() {
+    let items: Vec<String> = (1..=10).map(|i| format!("item {}", i)).collect();
+
+    let (complete, asked) = script::run(true, vec![script::Answer::No], || list_paged(&items, 3));
+    assert!(!complete);
+    assert_eq!(asked, ["expand"]);
+
+    let (complete, _) = script::run(true, vec![script::Answer::Yes], || list_paged(&items, 3));
+    assert!(complete);
+
+    let (complete, asked) = script::run(false, vec![], || list_paged(&items, 3));
+    assert!(complete);
+    assert!(asked.is_empty());
+  }
+}